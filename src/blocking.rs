@@ -0,0 +1,105 @@
+use std::{
+    future::Future,
+    ops::RangeBounds,
+};
+
+use futures::{
+    executor,
+    stream::BoxStream,
+    StreamExt,
+};
+
+use tokio::runtime;
+
+use crate::{
+    kv,
+    Pid,
+    Inserted,
+    InsertError,
+    LookupError,
+    Removed,
+    RemoveError,
+    Flushed,
+    FlushError,
+    LookupRangeError,
+    StreamError,
+};
+
+// every call blocks the calling thread until the request is served, so it should never be made from
+// within an async context: the store tasks would not make progress if they share that very thread
+#[derive(Clone)]
+pub struct BlockingPid {
+    pid: Pid,
+    executor: Executor,
+}
+
+#[derive(Clone)]
+enum Executor {
+    Local,
+    Handle(runtime::Handle),
+}
+
+impl Executor {
+    fn block_on<F>(&self, future: F) -> F::Output where F: Future {
+        match self {
+            Executor::Local =>
+                executor::block_on(future),
+            Executor::Handle(handle) =>
+                handle.block_on(future),
+        }
+    }
+}
+
+impl BlockingPid {
+    // requests are driven by `futures::executor::block_on` on the calling thread
+    pub fn new(pid: Pid) -> BlockingPid {
+        BlockingPid { pid, executor: Executor::Local, }
+    }
+
+    // requests are driven by the runtime of `handle`
+    pub fn with_handle(pid: Pid, handle: runtime::Handle) -> BlockingPid {
+        BlockingPid { pid, executor: Executor::Handle(handle), }
+    }
+
+    pub fn into_pid(self) -> Pid {
+        self.pid
+    }
+
+    pub fn insert(&mut self, key: kv::Key, value: kv::Value) -> Result<Inserted, InsertError> {
+        self.executor.block_on(self.pid.insert(key, value))
+    }
+
+    pub fn lookup(&mut self, key: kv::Key) -> Result<Option<kv::ValueCell<kv::Value>>, LookupError> {
+        self.executor.block_on(self.pid.lookup(key))
+    }
+
+    pub fn remove(&mut self, key: kv::Key) -> Result<Removed, RemoveError> {
+        self.executor.block_on(self.pid.remove(key))
+    }
+
+    pub fn flush(&mut self) -> Result<Flushed, FlushError> {
+        self.executor.block_on(self.pid.flush())
+    }
+
+    pub fn lookup_range<R>(&mut self, range: R) -> Result<LookupRangeIter, LookupRangeError> where R: RangeBounds<kv::Key> {
+        let lookup_range = self.executor.block_on(self.pid.lookup_range(range))?;
+        Ok(LookupRangeIter {
+            key_values: lookup_range.into_stream().boxed(),
+            executor: self.executor.clone(),
+        })
+    }
+}
+
+// the same items as `LookupRange::into_stream`: a failed or interrupted scan yields one error and then ends
+pub struct LookupRangeIter {
+    key_values: BoxStream<'static, Result<kv::KeyValuePair<kv::Value>, StreamError>>,
+    executor: Executor,
+}
+
+impl Iterator for LookupRangeIter {
+    type Item = Result<kv::KeyValuePair<kv::Value>, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.executor.block_on(self.key_values.next())
+    }
+}
@@ -0,0 +1,40 @@
+use std::{
+    fmt,
+    cmp::Ordering,
+    sync::Arc,
+};
+
+// `id` is recorded in every search tree root, trees written with another id are refused on load:
+// their blocks are ordered differently, so every comparator must be given its own id
+pub trait KeyComparator {
+    fn compare(&self, key_a: &[u8], key_b: &[u8]) -> Ordering;
+
+    fn id(&self) -> u32;
+}
+
+// trees written before comparator ids were recorded are taken as bytewise ordered
+pub const BYTEWISE_ID: u32 = 0;
+
+pub type KeyComparatorRef = Arc<dyn KeyComparator + Send + Sync>;
+
+pub struct Bytewise;
+
+impl KeyComparator for Bytewise {
+    fn compare(&self, key_a: &[u8], key_b: &[u8]) -> Ordering {
+        key_a.cmp(key_b)
+    }
+
+    fn id(&self) -> u32 {
+        BYTEWISE_ID
+    }
+}
+
+pub fn bytewise() -> KeyComparatorRef {
+    Arc::new(Bytewise)
+}
+
+impl fmt::Debug for dyn KeyComparator + Send + Sync {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("KeyComparator")
+    }
+}
@@ -1,17 +1,18 @@
 use std::{
     cmp,
+    sync::Arc,
     ops::{
         Deref,
         DerefMut,
         Bound,
         RangeBounds,
     },
-    borrow::Borrow,
     collections::BTreeMap,
 };
 
 use futures::{
     channel::{
+        mpsc,
         oneshot,
     },
 };
@@ -19,6 +20,7 @@ use futures::{
 use super::{
     kv,
     storage,
+    comparator::KeyComparatorRef,
     wheels::{
         BlockRef,
     },
@@ -27,6 +29,24 @@ use super::{
     Removed,
     Flushed,
     LookupRange,
+    LookupRangeRefs,
+    LookupRangeKeys,
+    KeyStreamItem,
+    KeyValueStreamItem,
+    KeyValueRefStreamItem,
+    KeyFilter,
+    VersionWindow,
+    ValueLocation,
+    RawBlocks,
+    VerifyReport,
+    Health,
+    Histograms,
+    Consistency,
+    DetailedInfo,
+    Warmed,
+    CompactionEvent,
+    OrphansCollected,
+    StoragePreference,
 };
 
 pub mod manager;
@@ -34,9 +54,13 @@ pub mod butcher;
 pub mod search_tree;
 pub mod merger;
 pub mod bin_merger;
+pub mod level_merger;
+pub mod backoff;
+pub mod block_sizer;
 
 #[derive(Debug)]
 pub struct RequestInfo {
+    range: SearchRangeBounds,
     reply_tx: oneshot::Sender<Info>,
 }
 
@@ -44,24 +68,93 @@ pub struct RequestInfo {
 pub struct RequestInsert {
     key: kv::Key,
     value: kv::Value,
-    reply_tx: oneshot::Sender<Inserted>,
+    version: Option<u64>,
+    storage_preference: StoragePreference,
+    reply_tx: oneshot::Sender<Result<Inserted, manager::InsertError>>,
+}
+
+#[derive(Debug)]
+pub struct RequestInsertNoReply {
+    key: kv::Key,
+    value: kv::Value,
 }
 
 #[derive(Debug)]
 pub struct RequestLookup {
     key: kv::Key,
+    consistency: Consistency,
     reply_tx: oneshot::Sender<Option<kv::ValueCell<kv::Value>>>,
 }
 
+pub struct RequestLookupLocated {
+    key: kv::Key,
+    reply_tx: oneshot::Sender<Option<kv::ValueCell<ValueLocation>>>,
+}
+
 pub struct RequestLookupRange {
     range: SearchRangeBounds,
+    buffer: Option<usize>,
+    key_filter: Option<KeyFilter>,
+    version_window: Option<VersionWindow>,
     reply_tx: oneshot::Sender<LookupRange>,
 }
 
+pub struct RequestLookupRangeRefs {
+    range: SearchRangeBounds,
+    buffer: Option<usize>,
+    reply_tx: oneshot::Sender<LookupRangeRefs>,
+}
+
+pub struct RequestLookupRangeKeys {
+    range: SearchRangeBounds,
+    buffer: Option<usize>,
+    reply_tx: oneshot::Sender<LookupRangeKeys>,
+}
+
+// range lookup results sink: retrieved values, value refs as is or alive keys only
+pub enum KeyValuesTx {
+    Values(mpsc::Sender<KeyValueStreamItem>),
+    Refs(mpsc::Sender<KeyValueRefStreamItem>),
+    Keys(mpsc::Sender<KeyStreamItem>),
+}
+
 #[derive(Debug)]
 pub struct RequestRemove {
     key: kv::Key,
-    reply_tx: oneshot::Sender<Removed>,
+    meta: Option<kv::Value>,
+    reply_tx: oneshot::Sender<Result<Removed, manager::RemoveError>>,
+}
+
+#[derive(Debug)]
+pub struct RequestRemoveMany {
+    keys: Vec<kv::Key>,
+    reply_tx: oneshot::Sender<Result<Vec<Removed>, manager::RemoveError>>,
+}
+
+#[derive(Debug)]
+pub struct RequestRemoveReturning {
+    key: kv::Key,
+    reply_tx: oneshot::Sender<Result<(Removed, Option<kv::ValueCell<kv::Value>>), manager::RemoveError>>,
+}
+
+#[derive(Debug)]
+pub struct RequestRemoveRange {
+    range: SearchRangeBounds,
+    reply_tx: oneshot::Sender<Result<Removed, manager::RemoveError>>,
+}
+
+#[derive(Debug)]
+pub struct RequestSplitOff {
+    range: SearchRangeBounds,
+    reply_tx: oneshot::Sender<Result<Vec<kv::KeyValuePair<kv::Value>>, manager::SplitOffError>>,
+}
+
+#[derive(Debug)]
+pub struct RequestBulkLoad {
+    // last key of the previous chunk of the same bulk load
+    prev_key: Option<kv::Key>,
+    items: Arc<Vec<(kv::Key, kv::Value)>>,
+    reply_tx: oneshot::Sender<Result<Inserted, manager::BulkLoadError>>,
 }
 
 #[derive(Debug)]
@@ -69,31 +162,149 @@ pub struct RequestFlush {
     reply_tx: oneshot::Sender<Flushed>,
 }
 
+#[derive(Debug)]
+pub struct RequestGcOrphans {
+    reply_tx: oneshot::Sender<OrphansCollected>,
+}
+
+#[derive(Debug)]
+pub struct RequestCurrentVersion {
+    reply_tx: oneshot::Sender<u64>,
+}
+
+#[derive(Debug)]
+pub struct RequestDetailedInfo {
+    reply_tx: oneshot::Sender<DetailedInfo>,
+}
+
+#[derive(Debug)]
+pub struct RequestWarmRange {
+    range: SearchRangeBounds,
+    reply_tx: oneshot::Sender<Warmed>,
+}
+
+pub struct RequestSubscribeCompactions {
+    reply_tx: oneshot::Sender<mpsc::Receiver<CompactionEvent>>,
+}
+
+pub struct RequestIterRawBlocks {
+    reply_tx: oneshot::Sender<RawBlocks>,
+}
+
+#[derive(Debug)]
+pub struct RequestVerify {
+    reply_tx: oneshot::Sender<VerifyReport>,
+}
+
+#[derive(Debug)]
+pub struct RequestHealth {
+    reply_tx: oneshot::Sender<Health>,
+}
+
+#[derive(Debug)]
+pub struct RequestSizeHistograms {
+    reply_tx: oneshot::Sender<Histograms>,
+}
+
+#[derive(Debug)]
+pub struct RequestEstimateCount {
+    range: SearchRangeBounds,
+    reply_tx: oneshot::Sender<usize>,
+}
+
+#[derive(Debug)]
+pub struct RequestChangedBlocks {
+    version_floor: u64,
+    reply_tx: oneshot::Sender<Vec<BlockRef>>,
+}
+
 pub struct MemCache {
     cache: BTreeMap<OrdKey, kv::ValueCell<kv::Value>>,
+    // only non-`Auto` preferences are kept; they are not written to wal, so replayed cells fall back to `Auto`
+    storage_preferences: BTreeMap<OrdKey, StoragePreference>,
+    key_comparator: KeyComparatorRef,
 }
 
 impl MemCache {
-    fn new() -> MemCache {
+    pub fn max_version(&self) -> Option<u64> {
+        self.cache.values()
+            .map(|value_cell| value_cell.version)
+            .max()
+    }
+
+    pub fn min_version(&self) -> Option<u64> {
+        self.cache.values()
+            .map(|value_cell| value_cell.version)
+            .min()
+    }
+
+    // the same accounting as the butcher memtable size: key and value (or tombstone meta) bytes
+    pub fn bytes(&self) -> usize {
+        self.cache.iter()
+            .map(|(ord_key, value_cell)| {
+                let value_bytes = match value_cell.cell {
+                    kv::Cell::Value(ref value) | kv::Cell::Tombstone { meta: Some(ref value), } =>
+                        value.value_bytes.len(),
+                    kv::Cell::Tombstone { meta: None, } =>
+                        0,
+                };
+                ord_key.as_ref().key_bytes.len() + value_bytes
+            })
+            .sum()
+    }
+
+    fn new(key_comparator: KeyComparatorRef) -> MemCache {
         MemCache {
             cache: BTreeMap::new(),
+            storage_preferences: BTreeMap::new(),
+            key_comparator,
         }
     }
 
+    fn storage_preference(&self, ord_key: &OrdKey) -> StoragePreference {
+        self.storage_preferences.get(ord_key)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn set_storage_preference(&mut self, ord_key: OrdKey, storage_preference: StoragePreference) -> StoragePreference {
+        let maybe_prev = match storage_preference {
+            StoragePreference::Auto =>
+                self.storage_preferences.remove(&ord_key),
+            storage_preference =>
+                self.storage_preferences.insert(ord_key, storage_preference),
+        };
+        maybe_prev.unwrap_or_default()
+    }
+
+    fn ord_key(&self, key: kv::Key) -> OrdKey {
+        OrdKey::new(key, self.key_comparator.clone())
+    }
+
+    fn lookup(&self, key: &kv::Key) -> Option<&kv::ValueCell<kv::Value>> {
+        self.cache.get(&self.ord_key(key.clone()))
+    }
+
     fn range(&self, range: SearchRangeBounds) -> impl Iterator<Item = kv::KeyValuePair<kv::Value>> + '_ {
-        fn ord_key_map(bound: Bound<kv::Key>) -> Bound<OrdKey> {
-            match bound {
-                Bound::Unbounded =>
-                    Bound::Unbounded,
-                Bound::Included(key) =>
-                    Bound::Included(OrdKey::new(key)),
-                Bound::Excluded(key) =>
-                    Bound::Excluded(OrdKey::new(key)),
-            }
-        }
+        // `BTreeMap::range` panics on these while search tree blocks just yield nothing
+        let is_empty = range.is_empty(&self.key_comparator);
+        let ord_key_map = |bound: Bound<kv::Key>| match bound {
+            Bound::Unbounded =>
+                Bound::Unbounded,
+            Bound::Included(key) =>
+                Bound::Included(self.ord_key(key)),
+            Bound::Excluded(key) =>
+                Bound::Excluded(self.ord_key(key)),
+        };
 
-        let ord_key_range = (ord_key_map(range.range_from), ord_key_map(range.range_to));
-        self.cache.range(ord_key_range)
+        let maybe_ord_key_range = if is_empty {
+            None
+        } else {
+            Some((ord_key_map(range.range_from), ord_key_map(range.range_to)))
+        };
+        maybe_ord_key_range
+            .into_iter()
+            .flat_map(move |ord_key_range| self.cache.range(ord_key_range))
             .map(|(ord_key, value_cell)| kv::KeyValuePair {
                 key: ord_key.as_ref().clone(),
                 value_cell: value_cell.clone(),
@@ -120,7 +331,7 @@ pub enum KeyValueRef {
         key: kv::Key,
         value_cell: kv::ValueCell<storage::OwnedValueBlockRef>,
     },
-    BlockFinish(BlockRef),
+    BlockFinish { block_ref: BlockRef, depth: usize, block_size: usize, },
     NoMore,
 }
 
@@ -137,8 +348,70 @@ impl SearchRangeBounds {
             range_to: Bound::Unbounded,
         }
     }
+
+    fn is_empty(&self, key_comparator: &KeyComparatorRef) -> bool {
+        match (&self.range_from, &self.range_to) {
+            (Bound::Unbounded, _) | (_, Bound::Unbounded) =>
+                false,
+            (Bound::Included(from), Bound::Included(to)) =>
+                key_comparator.compare(&from.key_bytes, &to.key_bytes) == cmp::Ordering::Greater,
+            (Bound::Included(from), Bound::Excluded(to)) |
+            (Bound::Excluded(from), Bound::Included(to)) |
+            (Bound::Excluded(from), Bound::Excluded(to)) =>
+                key_comparator.compare(&from.key_bytes, &to.key_bytes) != cmp::Ordering::Less,
+        }
+    }
+
+    fn contains(&self, key: &kv::Key, key_comparator: &KeyComparatorRef) -> bool {
+        let from_ok = match self.range_from {
+            Bound::Unbounded =>
+                true,
+            Bound::Included(ref from) =>
+                key_comparator.compare(&from.key_bytes, &key.key_bytes) != cmp::Ordering::Greater,
+            Bound::Excluded(ref from) =>
+                key_comparator.compare(&from.key_bytes, &key.key_bytes) == cmp::Ordering::Less,
+        };
+        from_ok && match self.range_to {
+            Bound::Unbounded =>
+                true,
+            Bound::Included(ref to) =>
+                key_comparator.compare(&key.key_bytes, &to.key_bytes) != cmp::Ordering::Greater,
+            Bound::Excluded(ref to) =>
+                key_comparator.compare(&key.key_bytes, &to.key_bytes) == cmp::Ordering::Less,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RangeTombstone {
+    range: SearchRangeBounds,
+    version: u64,
+    // deleted once the tombstone is discarded
+    block_ref: BlockRef,
 }
 
+// a range tombstone is discarded after a merge applying it, once no cell it shadows may be left in
+// butcher cache or in any other search tree (see `manager::range_tombstone_collectable`)
+pub type RangeTombstones = Arc<Vec<RangeTombstone>>;
+
+// version of the newest range tombstone shadowing the cell of `key` with `version`, if any
+fn range_tombstoned(
+    range_tombstones: &[RangeTombstone],
+    key: &kv::Key,
+    version: u64,
+    key_comparator: &KeyComparatorRef,
+)
+    -> Option<u64>
+{
+    range_tombstones.iter()
+        .filter(|range_tombstone| range_tombstone.version > version)
+        .filter(|range_tombstone| range_tombstone.range.contains(key, key_comparator))
+        .map(|range_tombstone| range_tombstone.version)
+        .max()
+}
+
+// key bytes are refcounted so cloning bounds (e.g. on request retry) does not copy them;
+// borrowed bounds like `&a .. &b` or `(Bound<&kv::Key>, Bound<&kv::Key>)` are accepted as well
 impl<R> From<R> for SearchRangeBounds where R: RangeBounds<kv::Key> {
     fn from(range: R) -> SearchRangeBounds {
         SearchRangeBounds {
@@ -166,11 +439,12 @@ impl<R> From<R> for SearchRangeBounds where R: RangeBounds<kv::Key> {
 #[derive(Clone, Debug)]
 pub struct OrdKey {
     inner: kv::Key,
+    key_comparator: KeyComparatorRef,
 }
 
 impl OrdKey {
-    fn new(inner: kv::Key) -> OrdKey {
-        OrdKey { inner, }
+    fn new(inner: kv::Key, key_comparator: KeyComparatorRef) -> OrdKey {
+        OrdKey { inner, key_comparator, }
     }
 }
 
@@ -192,7 +466,7 @@ impl Deref for OrdKey {
 
 impl PartialEq for OrdKey {
     fn eq(&self, other: &OrdKey) -> bool {
-        self.inner.key_bytes == other.inner.key_bytes
+        self.cmp(other) == cmp::Ordering::Equal
     }
 }
 
@@ -206,12 +480,119 @@ impl PartialOrd for OrdKey {
 
 impl Ord for OrdKey {
     fn cmp(&self, other: &OrdKey) -> cmp::Ordering {
-        self.inner.key_bytes.cmp(&other.inner.key_bytes)
+        self.key_comparator.compare(&self.inner.key_bytes, &other.inner.key_bytes)
     }
 }
 
-impl Borrow<[u8]> for OrdKey {
-    fn borrow(&self) -> &[u8] {
-        &self.inner.key_bytes
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use alloc_pool::bytes::BytesPool;
+
+    use crate::{
+        kv,
+        comparator,
+    };
+
+    use super::{
+        MemCache,
+        SearchRangeBounds,
+    };
+
+    fn make_key(blocks_pool: &BytesPool, key: &[u8]) -> kv::Key {
+        let mut key_bytes = blocks_pool.lend();
+        key_bytes.extend_from_slice(key);
+        key_bytes.into()
+    }
+
+    fn same_bytes(a: &Bound<kv::Key>, b: &Bound<kv::Key>) -> bool {
+        match (a, b) {
+            (Bound::Unbounded, Bound::Unbounded) =>
+                true,
+            (Bound::Included(a), Bound::Included(b)) | (Bound::Excluded(a), Bound::Excluded(b)) =>
+                a.key_bytes.as_ptr() == b.key_bytes.as_ptr(),
+            _ =>
+                false,
+        }
+    }
+
+    #[test]
+    fn range_bounds_owned_and_borrowed() {
+        let blocks_pool = BytesPool::new();
+        let key_a = make_key(&blocks_pool, b"alpha");
+        let key_b = make_key(&blocks_pool, b"beta");
+
+        let bounds_list = vec![
+            SearchRangeBounds::from(..),
+            SearchRangeBounds::from(key_a.clone() ..),
+            SearchRangeBounds::from(.. key_b.clone()),
+            SearchRangeBounds::from(..= key_b.clone()),
+            SearchRangeBounds::from(key_a.clone() .. key_b.clone()),
+            SearchRangeBounds::from(key_a.clone() ..= key_b.clone()),
+            SearchRangeBounds::from(&key_a ..),
+            SearchRangeBounds::from(.. &key_b),
+            SearchRangeBounds::from(..= &key_b),
+            SearchRangeBounds::from(&key_a .. &key_b),
+            SearchRangeBounds::from(&key_a ..= &key_b),
+            SearchRangeBounds::from((Bound::Excluded(&key_a), Bound::Included(&key_b))),
+            SearchRangeBounds::from((Bound::Excluded(&key_a), Bound::Excluded(&key_b))),
+            SearchRangeBounds::from((Bound::Excluded(&key_a), Bound::<&kv::Key>::Unbounded)),
+        ];
+        let expected = vec![
+            (Bound::Unbounded, Bound::Unbounded),
+            (Bound::Included(key_a.clone()), Bound::Unbounded),
+            (Bound::Unbounded, Bound::Excluded(key_b.clone())),
+            (Bound::Unbounded, Bound::Included(key_b.clone())),
+            (Bound::Included(key_a.clone()), Bound::Excluded(key_b.clone())),
+            (Bound::Included(key_a.clone()), Bound::Included(key_b.clone())),
+            (Bound::Included(key_a.clone()), Bound::Unbounded),
+            (Bound::Unbounded, Bound::Excluded(key_b.clone())),
+            (Bound::Unbounded, Bound::Included(key_b.clone())),
+            (Bound::Included(key_a.clone()), Bound::Excluded(key_b.clone())),
+            (Bound::Included(key_a.clone()), Bound::Included(key_b.clone())),
+            (Bound::Excluded(key_a.clone()), Bound::Included(key_b.clone())),
+            (Bound::Excluded(key_a.clone()), Bound::Excluded(key_b.clone())),
+            (Bound::Excluded(key_a.clone()), Bound::Unbounded),
+        ];
+        assert_eq!(bounds_list.len(), expected.len());
+
+        for (bounds, (range_from, range_to)) in bounds_list.into_iter().zip(expected) {
+            // neither conversion nor clone copies key bytes
+            let cloned = bounds.clone();
+            assert!(same_bytes(&bounds.range_from, &range_from), "{:?} vs {:?}", bounds.range_from, range_from);
+            assert!(same_bytes(&bounds.range_to, &range_to), "{:?} vs {:?}", bounds.range_to, range_to);
+            assert!(same_bytes(&cloned.range_from, &range_from));
+            assert!(same_bytes(&cloned.range_to, &range_to));
+        }
+    }
+
+    #[test]
+    fn mem_cache_range_excluded_bounds() {
+        let blocks_pool = BytesPool::new();
+        let key_a = make_key(&blocks_pool, b"alpha");
+        let key_b = make_key(&blocks_pool, b"beta");
+        let key_c = make_key(&blocks_pool, b"gamma");
+
+        let mut mem_cache = MemCache::new(comparator::bytewise());
+        for (version, key) in [&key_a, &key_b, &key_c].iter().enumerate() {
+            let ord_key = mem_cache.ord_key((*key).clone());
+            mem_cache.insert(ord_key, kv::ValueCell { version: version as u64, cell: kv::Cell::Tombstone { meta: None, }, });
+        }
+
+        let range_keys = |range: (Bound<&kv::Key>, Bound<&kv::Key>)| -> Vec<Vec<u8>> {
+            mem_cache.range(SearchRangeBounds::from(range))
+                .map(|kv_pair| kv_pair.key.key_bytes.to_vec())
+                .collect()
+        };
+
+        assert_eq!(range_keys((Bound::Excluded(&key_a), Bound::Unbounded)), vec![b"beta".to_vec(), b"gamma".to_vec()]);
+        assert_eq!(range_keys((Bound::Excluded(&key_b), Bound::Included(&key_c))), vec![b"gamma".to_vec()]);
+        assert_eq!(range_keys((Bound::Excluded(&key_a), Bound::Excluded(&key_c))), vec![b"beta".to_vec()]);
+        // empty and inverted ranges
+        assert!(range_keys((Bound::Excluded(&key_b), Bound::Excluded(&key_b))).is_empty());
+        assert!(range_keys((Bound::Included(&key_b), Bound::Excluded(&key_b))).is_empty());
+        assert!(range_keys((Bound::Included(&key_c), Bound::Included(&key_a))).is_empty());
+        assert_eq!(range_keys((Bound::Included(&key_b), Bound::Included(&key_b))), vec![b"beta".to_vec()]);
     }
 }
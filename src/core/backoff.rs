@@ -0,0 +1,114 @@
+use std::time::{
+    Instant,
+    Duration,
+};
+
+use rand::Rng;
+
+use tokio::time;
+
+use ero::RestartStrategy;
+
+use crate::RestartBackoff;
+
+pub struct Backoff {
+    restart_backoff: Option<RestartBackoff>,
+    attempt: u32,
+    last_start: Option<Instant>,
+}
+
+impl Backoff {
+    pub fn new(restart_backoff: Option<RestartBackoff>) -> Backoff {
+        Backoff {
+            restart_backoff,
+            attempt: 0,
+            last_start: None,
+        }
+    }
+
+    // with backoff enabled ero restarts immediately and the delay is applied by `wait`
+    pub fn restart_strategy(&self, task_restart_sec: usize) -> RestartStrategy {
+        RestartStrategy::Delay {
+            restart_after: match self.restart_backoff {
+                None =>
+                    Duration::from_secs(task_restart_sec as u64),
+                Some(..) =>
+                    Duration::from_secs(0),
+            },
+        }
+    }
+
+    pub async fn wait(&mut self, name: &str) {
+        if let Some(delay) = self.next_delay(Instant::now()) {
+            log::warn!("{} restart #{}: backing off for {:?}", name, self.attempt, delay);
+            time::sleep(delay).await;
+        }
+    }
+
+    fn next_delay(&mut self, now: Instant) -> Option<Duration> {
+        let restart_backoff = self.restart_backoff?;
+        let last_start = self.last_start.replace(now)?;
+        // a run which lasted longer than the longest delay counts as a success
+        if now.duration_since(last_start) >= restart_backoff.max {
+            self.attempt = 0;
+        }
+        let delay = 1u32.checked_shl(self.attempt)
+            .and_then(|factor| restart_backoff.base.checked_mul(factor))
+            .map_or(restart_backoff.max, |delay| delay.min(restart_backoff.max));
+        self.attempt = self.attempt.saturating_add(1);
+        let jitter = if restart_backoff.jitter == Duration::from_secs(0) {
+            restart_backoff.jitter
+        } else {
+            rand::thread_rng().gen_range(Duration::from_secs(0) ..= restart_backoff.jitter)
+        };
+        Some(delay + jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{
+        Instant,
+        Duration,
+    };
+
+    use crate::RestartBackoff;
+
+    use super::Backoff;
+
+    #[test]
+    fn doubles_up_to_max_and_resets() {
+        let mut backoff = Backoff::new(Some(RestartBackoff {
+            base: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            jitter: Duration::from_secs(0),
+        }));
+        let mut now = Instant::now();
+        assert_eq!(backoff.next_delay(now), None);
+
+        let mut delays = Vec::new();
+        for _ in 0 .. 6 {
+            now += Duration::from_millis(10);
+            delays.push(backoff.next_delay(now).unwrap());
+        }
+        assert_eq!(delays, vec![
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+            Duration::from_millis(400),
+            Duration::from_millis(800),
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+        ]);
+
+        now += Duration::from_secs(1);
+        assert_eq!(backoff.next_delay(now), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn disabled_without_params() {
+        let mut backoff = Backoff::new(None);
+        let now = Instant::now();
+        assert_eq!(backoff.next_delay(now), None);
+        assert_eq!(backoff.next_delay(now + Duration::from_secs(1)), None);
+    }
+}
@@ -35,6 +35,13 @@ impl<B> BinMerger<B> {
         }
     }
 
+    // the pair `pop` would hand out, the merger is left intact
+    pub fn peek(&self) -> Option<(&B, &B)> {
+        let power_of_2 = self.need_merge.iter().next()?;
+        let powers = &self.powers[power_of_2];
+        Some((&powers[powers.len() - 1], &powers[powers.len() - 2]))
+    }
+
     pub fn pop(&mut self) -> Option<(B, B)> {
         let power_of_2 = self.need_merge.iter().next().cloned()?;
         let powers = self.powers.get_mut(&power_of_2).unwrap();
@@ -45,6 +52,19 @@ impl<B> BinMerger<B> {
         }
         Some((bucket_a, bucket_b))
     }
+
+    pub fn remove_by<P>(&mut self, pred: P) -> Option<B> where P: Fn(&B) -> bool {
+        for (&power_of_2, powers) in self.powers.iter_mut() {
+            if let Some(index) = powers.iter().position(&pred) {
+                let bucket = powers.remove(index);
+                if powers.len() < 2 {
+                    self.need_merge.remove(&power_of_2);
+                }
+                return Some(bucket);
+            }
+        }
+        None
+    }
 }
 
 #[cfg(test)]
@@ -88,4 +108,37 @@ mod tests {
         assert_eq!(total_count_check, total_count);
         assert!(bin_merger.need_merge.is_empty());
     }
+
+    #[test]
+    fn removed_bucket_is_not_merged() {
+        let mut bin_merger = BinMerger::new();
+        bin_merger.push(Bucket { count: 3, }, 3);
+        bin_merger.push(Bucket { count: 4, }, 4);
+
+        let removed = bin_merger.remove_by(|bucket| bucket.count == 3).unwrap();
+        assert_eq!(removed.count, 3);
+        assert!(bin_merger.remove_by(|bucket| bucket.count == 3).is_none());
+        assert!(bin_merger.pop().is_none());
+    }
+
+    #[test]
+    fn peek_matches_pop() {
+        let mut bin_merger = BinMerger::new();
+        let mut rng = rand::thread_rng();
+        let mut next_id = 0;
+        for _ in 0 .. 1024 {
+            let count = rng.gen_range(0 .. 64);
+            bin_merger.push((next_id, count), count);
+            next_id += 1;
+        }
+
+        while let Some((&peeked_a, &peeked_b)) = bin_merger.peek() {
+            let (bucket_a, bucket_b) = bin_merger.pop().unwrap();
+            assert_eq!((bucket_a, bucket_b), (peeked_a, peeked_b));
+            let count = bucket_a.1 + bucket_b.1;
+            bin_merger.push((next_id, count), count);
+            next_id += 1;
+        }
+        assert!(bin_merger.need_merge.is_empty());
+    }
 }
@@ -0,0 +1,122 @@
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlockBudget {
+    Entries(usize),
+    // serialized entry bytes, values stored out of line count by their refs only
+    Bytes(usize),
+}
+
+impl BlockBudget {
+    pub fn new(tree_block_size: usize, target_block_bytes: Option<usize>) -> BlockBudget {
+        match target_block_bytes {
+            None =>
+                BlockBudget::Entries(tree_block_size),
+            Some(target_block_bytes) =>
+                BlockBudget::Bytes(target_block_bytes),
+        }
+    }
+
+    pub fn is_full(&self, entries_count: usize, entries_bytes: usize) -> bool {
+        match *self {
+            BlockBudget::Entries(tree_block_size) =>
+                entries_count >= tree_block_size,
+            BlockBudget::Bytes(target_block_bytes) =>
+                entries_bytes >= target_block_bytes,
+        }
+    }
+}
+
+// Cuts an ascending entries stream into an in order tree: a block is cut as soon as it is full, and the
+// next entry goes one level up with a jump to it, so only one block per level is open at a time. The
+// blocks still open in the end are concatenated from the top level down into the root, which keeps every
+// jump right before the keys it covers.
+pub struct BlockCutter {
+    budget: BlockBudget,
+    levels: Vec<LevelBlock>,
+    next_level: usize,
+}
+
+#[derive(Clone, Copy, Default)]
+struct LevelBlock {
+    entries_count: usize,
+    entries_bytes: usize,
+}
+
+impl BlockCutter {
+    pub fn new(budget: BlockBudget) -> BlockCutter {
+        BlockCutter {
+            budget,
+            levels: Vec::new(),
+            next_level: 0,
+        }
+    }
+
+    pub fn next_level(&self) -> usize {
+        self.next_level
+    }
+
+    // true when the entry fills its block up: the block is cut and the next entry jumps to it
+    pub fn push(&mut self, entry_bytes: usize) -> bool {
+        let level_index = self.next_level;
+        if self.levels.len() <= level_index {
+            self.levels.resize(level_index + 1, LevelBlock::default());
+        }
+        let level = &mut self.levels[level_index];
+        level.entries_count += 1;
+        level.entries_bytes += entry_bytes;
+        if self.budget.is_full(level.entries_count, level.entries_bytes) {
+            *level = LevelBlock::default();
+            self.next_level = level_index + 1;
+            true
+        } else {
+            self.next_level = 0;
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BlockBudget,
+        BlockCutter,
+    };
+
+    #[test]
+    fn entries_budget() {
+        let mut cutter = BlockCutter::new(BlockBudget::new(2, None));
+        assert_eq!(cutter.next_level(), 0);
+        assert!(!cutter.push(1024));
+        assert!(cutter.push(1));
+        // jumps to the cut block
+        assert_eq!(cutter.next_level(), 1);
+        assert!(!cutter.push(1));
+        assert_eq!(cutter.next_level(), 0);
+        assert!(!cutter.push(1));
+        assert!(cutter.push(1));
+        assert_eq!(cutter.next_level(), 1);
+        // second entry of the level above cuts it as well
+        assert!(cutter.push(1));
+        assert_eq!(cutter.next_level(), 2);
+        assert!(!cutter.push(1));
+        assert_eq!(cutter.next_level(), 0);
+    }
+
+    #[test]
+    fn bytes_budget() {
+        let mut cutter = BlockCutter::new(BlockBudget::new(32, Some(100)));
+        for _ in 0 .. 9 {
+            assert!(!cutter.push(10));
+        }
+        assert!(cutter.push(10));
+        assert_eq!(cutter.next_level(), 1);
+        assert!(!cutter.push(10));
+
+        // an entry larger than the budget gets a block of its own
+        assert!(cutter.push(4096));
+        assert_eq!(cutter.next_level(), 1);
+        assert!(!cutter.push(10));
+        assert_eq!(cutter.next_level(), 0);
+        assert!(!cutter.push(90));
+        assert!(cutter.push(10));
+    }
+}
@@ -1,10 +1,13 @@
 use std::{
+    cmp,
     mem,
     sync::Arc,
+    ops::Bound,
     time::Duration,
 };
 
 use futures::{
+    select,
     stream,
     channel::{
         mpsc,
@@ -12,10 +15,16 @@ use futures::{
     },
     StreamExt,
     SinkExt,
+    FutureExt,
 };
 
+use rand::Rng;
+
+use tokio::time;
+
 use alloc_pool::{
     pool,
+    bytes::BytesPool,
     Shared,
 };
 
@@ -27,35 +36,63 @@ use ero::{
 
 use crate::{
     kv,
+    wheels,
+    storage,
     version,
+    comparator,
+    blockwheel,
     core::{
         manager,
+        block_sizer,
         OrdKey,
         MemCache,
         RequestInfo,
         RequestInsert,
-        RequestLookup,
-        RequestRemove,
         RequestFlush,
         SearchRangeBounds,
+        BlockRef,
     },
     Info,
     Inserted,
     Removed,
     Flushed,
+    PoolingMode,
+    StoragePreference,
+    NAMESPACE_MARKER,
+    NAMESPACE_PREFIX_LEN,
 };
 
 #[derive(Clone, Debug)]
 pub struct Params {
     pub task_restart_sec: usize,
-    pub tree_block_size: usize,
+    pub block_budget: block_sizer::BlockBudget,
+    pub flush_threshold_bytes: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub flush_interval: Option<Duration>,
+    pub flush_coalesce_window: Option<Duration>,
+    pub wal: bool,
+    pub max_key_size: Option<usize>,
+    pub namespaces: bool,
+    pub pooling: PoolingMode,
+    pub store_id: u32,
+    pub key_comparator: comparator::KeyComparatorRef,
 }
 
 impl Default for Params {
     fn default() -> Params {
         Params {
             task_restart_sec: 4,
-            tree_block_size: 32,
+            block_budget: block_sizer::BlockBudget::Entries(32),
+            flush_threshold_bytes: None,
+            max_bytes: None,
+            flush_interval: None,
+            flush_coalesce_window: None,
+            wal: false,
+            max_key_size: None,
+            namespaces: false,
+            pooling: PoolingMode::Full,
+            store_id: 0,
+            key_comparator: comparator::bytewise(),
         }
     }
 }
@@ -88,7 +125,10 @@ impl GenServer {
     pub async fn run(
         self,
         version_provider: version::Provider,
+        version_watermark: version::Watermark,
         manager_pid: manager::Pid,
+        wheels_pid: wheels::Pid,
+        blocks_pool: BytesPool,
         params: Params,
     )
     {
@@ -103,7 +143,11 @@ impl GenServer {
                 fused_request_rx: self.fused_request_rx,
                 version_provider,
                 manager_pid,
+                wheels_pid,
+                blocks_pool,
                 params,
+                flush_generation: 0,
+                version_watermark,
             },
             |state| busyloop(state),
         ).await;
@@ -114,10 +158,10 @@ impl GenServer {
 }
 
 impl Pid {
-    pub async fn info(&mut self) -> Result<Info, ero::NoProcError> {
+    pub async fn info(&mut self, range: SearchRangeBounds) -> Result<Info, ero::NoProcError> {
         loop {
             let (reply_tx, reply_rx) = oneshot::channel();
-            self.request_tx.send(Request::Info(RequestInfo { reply_tx, })).await
+            self.request_tx.send(Request::Info(RequestInfo { range: range.clone(), reply_tx, })).await
                 .map_err(|_send_error| ero::NoProcError)?;
             match reply_rx.await {
                 Ok(info) =>
@@ -128,10 +172,34 @@ impl Pid {
         }
     }
 
-    pub async fn insert(&mut self, key: kv::Key, value: kv::Value) -> Result<Inserted, ero::NoProcError> {
+    pub async fn insert(&mut self, key: kv::Key, value: kv::Value) -> Result<Result<Inserted, manager::InsertError>, ero::NoProcError> {
+        self.insert_cell(key, value, None, StoragePreference::Auto).await
+    }
+
+    pub async fn insert_versioned(
+        &mut self,
+        key: kv::Key,
+        value: kv::Value,
+        version: u64,
+    )
+        -> Result<Result<Inserted, manager::InsertError>, ero::NoProcError>
+    {
+        self.insert_cell(key, value, Some(version), StoragePreference::Auto).await
+    }
+
+    pub async fn insert_cell(
+        &mut self,
+        key: kv::Key,
+        value: kv::Value,
+        version: Option<u64>,
+        storage_preference: StoragePreference,
+    )
+        -> Result<Result<Inserted, manager::InsertError>, ero::NoProcError>
+    {
         loop {
             let (reply_tx, reply_rx) = oneshot::channel();
-            self.request_tx.send(Request::Insert(RequestInsert { key: key.clone(), value: value.clone(), reply_tx, })).await
+            let request = RequestInsert { key: key.clone(), value: value.clone(), version, storage_preference, reply_tx, };
+            self.request_tx.send(Request::Insert(request)).await
                 .map_err(|_send_error| ero::NoProcError)?;
 
             match reply_rx.await {
@@ -143,10 +211,10 @@ impl Pid {
         }
     }
 
-    pub async fn lookup(&mut self, key: kv::Key) -> Result<Option<kv::ValueCell<kv::Value>>, ero::NoProcError> {
+    pub async fn lookup(&mut self, key: kv::Key) -> Result<Found, ero::NoProcError> {
         loop {
             let (reply_tx, reply_rx) = oneshot::channel();
-            self.request_tx.send(Request::Lookup(RequestLookup { key: key.clone(), reply_tx, })).await
+            self.request_tx.send(Request::Lookup { key: key.clone(), reply_tx, }).await
                 .map_err(|_send_error| ero::NoProcError)?;
 
             match reply_rx.await {
@@ -182,15 +250,85 @@ impl Pid {
         }
     }
 
-    pub async fn remove(&mut self, key: kv::Key) -> Result<Removed, ero::NoProcError> {
+    pub async fn remove(&mut self, key: kv::Key, meta: Option<kv::Value>) -> Result<Result<Removed, manager::RemoveError>, ero::NoProcError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let request = Request::Remove { key: key.clone(), meta: meta.clone(), reply: RemoveReply::Unconditional { reply_tx, }, };
+            self.request_tx.send(request).await
+                .map_err(|_send_error| ero::NoProcError)?;
+
+            match reply_rx.await {
+                Ok(result) =>
+                    return Ok(result),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    // keys are validated and versions obtained for the whole batch before anything is written
+    pub async fn remove_many(&mut self, keys: Vec<kv::Key>) -> Result<Result<Vec<Removed>, manager::RemoveError>, ero::NoProcError> {
         loop {
             let (reply_tx, reply_rx) = oneshot::channel();
-            self.request_tx.send(Request::Remove(RequestRemove { key: key.clone(), reply_tx, })).await
+            self.request_tx.send(Request::RemoveMany { keys: keys.clone(), reply_tx, }).await
                 .map_err(|_send_error| ero::NoProcError)?;
 
             match reply_rx.await {
-                Ok(removed) =>
-                    return Ok(removed),
+                Ok(result) =>
+                    return Ok(result),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    pub async fn remove_if(
+        &mut self,
+        key: kv::Key,
+        condition: RemoveCondition,
+    )
+        -> Result<Result<Option<Removed>, manager::RemoveError>, ero::NoProcError>
+    {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let reply = RemoveReply::Conditional { condition: condition.clone(), reply_tx, };
+            let request = Request::Remove { key: key.clone(), meta: None, reply, };
+            self.request_tx.send(request).await
+                .map_err(|_send_error| ero::NoProcError)?;
+
+            match reply_rx.await {
+                Ok(result) =>
+                    return Ok(result),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    pub async fn flush_generation(&mut self) -> Result<u64, ero::NoProcError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx.send(Request::FlushGeneration { reply_tx, }).await
+                .map_err(|_send_error| ero::NoProcError)?;
+
+            match reply_rx.await {
+                Ok(flush_generation) =>
+                    return Ok(flush_generation),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    pub async fn replay_wal(&mut self, wal_records: Vec<WalRecord>) -> Result<(), ero::NoProcError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx.send(Request::ReplayWal { wal_records: wal_records.clone(), reply_tx, }).await
+                .map_err(|_send_error| ero::NoProcError)?;
+
+            match reply_rx.await {
+                Ok(()) =>
+                    return Ok(()),
                 Err(oneshot::Canceled) =>
                     (),
             }
@@ -204,8 +342,8 @@ impl Pid {
                 .map_err(|_send_error| ero::NoProcError)?;
 
             match reply_rx.await {
-                Ok(Flushed) =>
-                    return Ok(Flushed),
+                Ok(flushed) =>
+                    return Ok(flushed),
                 Err(oneshot::Canceled) =>
                     (),
             }
@@ -217,140 +355,661 @@ struct State {
     fused_request_rx: stream::Fuse<mpsc::Receiver<Request>>,
     version_provider: version::Provider,
     manager_pid: manager::Pid,
+    wheels_pid: wheels::Pid,
+    blocks_pool: BytesPool,
     params: Params,
+    // bumped on every cache flush, survives restarts
+    flush_generation: u64,
+    version_watermark: version::Watermark,
+}
+
+// remove only if no flush happened since `flush_generation` was observed by manager
+// and no cell newer than `expected_version` has been cached since then
+#[derive(Clone, Debug)]
+pub struct RemoveCondition {
+    pub expected_version: Option<u64>,
+    pub flush_generation: u64,
+}
+
+// only a conditional remove can conflict, so only it is answered with `None`
+enum RemoveReply {
+    Unconditional {
+        reply_tx: oneshot::Sender<Result<Removed, manager::RemoveError>>,
+    },
+    Conditional {
+        condition: RemoveCondition,
+        reply_tx: oneshot::Sender<Result<Option<Removed>, manager::RemoveError>>,
+    },
+}
+
+impl RemoveReply {
+    fn send(self, result: Result<Removed, manager::RemoveError>) -> Result<(), ()> {
+        match self {
+            RemoveReply::Unconditional { reply_tx, } =>
+                reply_tx.send(result).map_err(|_result| ()),
+            RemoveReply::Conditional { reply_tx, .. } =>
+                reply_tx.send(result.map(Some)).map_err(|_result| ()),
+        }
+    }
+}
+
+// caches flushed up to `flush_generation` are not covered by `found`
+#[derive(Clone, Debug)]
+pub struct Found {
+    pub found: Option<kv::ValueCell<kv::Value>>,
+    pub flush_generation: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct WalRecord {
+    pub key: kv::Key,
+    pub value_cell: kv::ValueCell<kv::Value>,
+    pub block_ref: BlockRef,
 }
 
 #[derive(Debug)]
 enum Request {
     Info(RequestInfo),
     Insert(RequestInsert),
-    Lookup(RequestLookup),
+    Lookup {
+        key: kv::Key,
+        reply_tx: oneshot::Sender<Found>,
+    },
     LookupRange {
         range: SearchRangeBounds,
         reply_tx: oneshot::Sender<Shared<Vec<kv::KeyValuePair<kv::Value>>>>,
         iter_items_pool: pool::Pool<Vec<kv::KeyValuePair<kv::Value>>>,
     },
-    Remove(RequestRemove),
+    Remove {
+        key: kv::Key,
+        meta: Option<kv::Value>,
+        reply: RemoveReply,
+    },
+    RemoveMany {
+        keys: Vec<kv::Key>,
+        reply_tx: oneshot::Sender<Result<Vec<Removed>, manager::RemoveError>>,
+    },
+    FlushGeneration {
+        reply_tx: oneshot::Sender<u64>,
+    },
+    ReplayWal {
+        wal_records: Vec<WalRecord>,
+        reply_tx: oneshot::Sender<()>,
+    },
     Flush(RequestFlush),
 }
 
 #[derive(Debug)]
 enum Error {
+    WalSerialize(storage::Error),
+    WheelsGone,
+    WheelsEmpty,
+    WheelNotFound {
+        blockwheel_filename: wheels::WheelFilename,
+    },
+    WalWriteBlock(blockwheel::WriteBlockError),
+    WalDeleteBlock(blockwheel::DeleteBlockError),
 }
 
 async fn busyloop(mut state: State) -> Result<(), ErrorSeverity<State, Error>> {
-    let mut memcache = MemCache::new();
+    let mut memcache = MemCache::new(state.params.key_comparator.clone());
+    let mut memcache_bytes = 0;
     let mut current_info = Info::default();
+    let mut flush_deadline = None;
+    let mut coalesce_deadline = None;
+    let mut wal_block_refs = Vec::new();
+
+    loop {
+        enum Event<R> {
+            Request(Option<R>),
+            FlushTimeout,
+        }
+
+        // whichever of the interval and the coalescing deadlines comes first triggers the flush
+        let maybe_deadline = match (flush_deadline, coalesce_deadline) {
+            (Some(flush_deadline), Some(coalesce_deadline)) =>
+                Some(cmp::min(flush_deadline, coalesce_deadline)),
+            (maybe_flush_deadline, maybe_coalesce_deadline) =>
+                maybe_flush_deadline.or(maybe_coalesce_deadline),
+        };
+        let event = match maybe_deadline {
+            None =>
+                Event::Request(state.fused_request_rx.next().await),
+            Some(deadline) =>
+                select! {
+                    result = state.fused_request_rx.next() =>
+                        Event::Request(result),
+                    () = time::sleep_until(deadline).fuse() =>
+                        Event::FlushTimeout,
+                },
+        };
+
+        match event {
+            Event::Request(None) =>
+                break,
+
+            Event::FlushTimeout => {
+                log::debug!("flush deadline elapsed: performing flush_cache");
+                let cache = Arc::new(mem::replace(&mut memcache, MemCache::new(state.params.key_comparator.clone())));
+                memcache_bytes = 0;
+                current_info.reset();
+                state.flush_generation += 1;
+                if let Err(ero::NoProcError) = state.manager_pid.flush_cache(cache, mem::take(&mut wal_block_refs), state.flush_generation).await {
+                    log::warn!("manager has gone during flush, terminating");
+                    break;
+                }
+            },
 
-    while let Some(request) = state.fused_request_rx.next().await {
-        match request {
-            Request::Info(RequestInfo { reply_tx, }) => {
-                if let Err(_send_error) = reply_tx.send(current_info) {
+            Event::Request(Some(Request::Info(RequestInfo { range, reply_tx, }))) => {
+                let info = match range {
+                    SearchRangeBounds { range_from: Bound::Unbounded, range_to: Bound::Unbounded, } =>
+                        Info {
+                            butcher_entries: memcache.len(),
+                            butcher_bytes: memcache_bytes,
+                            ..current_info
+                        },
+                    range =>
+                        memcache.range(range)
+                            .fold(Info::default(), |mut info, kv::KeyValuePair { key, value_cell, }| {
+                                info.butcher_entries += 1;
+                                info.butcher_bytes += key.key_bytes.len();
+                                match value_cell.cell {
+                                    kv::Cell::Value(value) => {
+                                        info.alive_cells_count += 1;
+                                        info.butcher_bytes += value.value_bytes.len();
+                                    },
+                                    kv::Cell::Tombstone { .. } =>
+                                        info.tombstones_count += 1,
+                                }
+                                info
+                            }),
+                };
+                if let Err(_send_error) = reply_tx.send(info) {
                     log::warn!("client canceled info request");
                 }
             },
 
-            Request::Insert(RequestInsert { key, value, reply_tx, }) => {
-                let ord_key = OrdKey::new(key);
-                let version = state.version_provider.obtain();
+            // rejected before any version is obtained
+            Event::Request(Some(Request::Insert(RequestInsert { key, reply_tx, .. }))) if key_too_large(&key, &state.params) => {
+                let error = manager::InsertError::KeyTooLarge {
+                    size: key_size(&key, &state.params),
+                    limit: state.params.max_key_size.unwrap(),
+                };
+                if let Err(_send_error) = reply_tx.send(Err(error)) {
+                    log::warn!("client canceled insert request");
+                }
+            },
+
+            Event::Request(Some(Request::Insert(RequestInsert { key, version: Some(version), reply_tx, .. })))
+                if memcache.lookup(&key).map_or(false, |value_cell| value_cell.version >= version) =>
+            {
+                // an equal or newer cell is already cached: keep it
+                if let Err(_send_error) = reply_tx.send(Ok(Inserted { version, })) {
+                    log::warn!("client canceled insert request");
+                }
+            },
+
+            Event::Request(Some(Request::Insert(RequestInsert { key, value, version, storage_preference, reply_tx, }))) => {
+                let version = match version {
+                    Some(version) =>
+                        version,
+                    None =>
+                        match obtain_version(&state) {
+                            Ok(version) =>
+                                version,
+                            Err(version::Regression { version, last_version, }) => {
+                                let error = manager::InsertError::VersionRegression { version, last_version, };
+                                if let Err(_send_error) = reply_tx.send(Err(error)) {
+                                    log::warn!("client canceled insert request");
+                                }
+                                continue;
+                            },
+                        },
+                };
+                let ord_key = memcache.ord_key(key);
                 let value_cell = kv::ValueCell {
                     version,
                     cell: kv::Cell::Value(value),
                 };
+                let value_cell_bytes = entry_bytes(&ord_key, &value_cell);
+                if max_bytes_exceeded(&memcache, memcache_bytes, value_cell_bytes, &state.params) {
+                    // flush before the entry is added so the memtable stays within `max_bytes`
+                    let cache = Arc::new(mem::replace(&mut memcache, MemCache::new(state.params.key_comparator.clone())));
+                    memcache_bytes = 0;
+                    current_info.reset();
+                    state.flush_generation += 1;
+                    if let Err(ero::NoProcError) = state.manager_pid.flush_cache(cache, mem::take(&mut wal_block_refs), state.flush_generation).await {
+                        log::warn!("manager has gone during flush, terminating");
+                        break;
+                    }
+                }
+                let maybe_wal_block_ref = wal_append(&mut state, &ord_key, &value_cell, memcache_bytes).await
+                    .map_err(ErrorSeverity::Fatal)?;
                 let maybe_prev = memcache.insert(ord_key.clone(), value_cell);
+                let prev_storage_preference = memcache.set_storage_preference(ord_key.clone(), storage_preference);
+                memcache_bytes += value_cell_bytes;
+                if let Some(ref prev_value_cell) = maybe_prev {
+                    memcache_bytes -= entry_bytes(&ord_key, prev_value_cell);
+                }
                 if maybe_prev.is_none() {
                     current_info.alive_cells_count += 1;
                 }
-                if let Err(_send_error) = reply_tx.send(Inserted { version, }) {
+                if let Err(_send_error) = reply_tx.send(Ok(Inserted { version, })) {
                     log::warn!("client canceled insert request");
+                    if let Some(wal_block_ref) = maybe_wal_block_ref {
+                        wal_discard(&mut state, wal_block_ref).await
+                            .map_err(ErrorSeverity::Fatal)?;
+                    }
+                    memcache.set_storage_preference(ord_key.clone(), prev_storage_preference);
+                    memcache_bytes -= value_cell_bytes;
                     match maybe_prev {
                         None => {
                             memcache.remove(&ord_key);
-                            current_info.alive_cells_count -= 1;
+                            current_info.alive_cells_count = current_info.alive_cells_count.saturating_sub(1);
                         },
                         Some(prev_value_cell) => {
+                            memcache_bytes += entry_bytes(&ord_key, &prev_value_cell);
                             memcache.insert(ord_key, prev_value_cell);
                         },
                     }
-                } else if memcache.len() >= state.params.tree_block_size {
-                    // flush tree block
-                    let cache = Arc::new(mem::replace(&mut memcache, MemCache::new()));
-                    current_info.reset();
-                    if let Err(ero::NoProcError) = state.manager_pid.flush_cache(cache).await {
-                        log::warn!("manager has gone during flush, terminating");
-                        break;
+                } else {
+                    wal_block_refs.extend(maybe_wal_block_ref);
+                    if flush_required(&memcache, memcache_bytes, &state.params) && !flush_deferred(&mut coalesce_deadline, memcache_bytes, &state.params) {
+                        // flush tree block
+                        let cache = Arc::new(mem::replace(&mut memcache, MemCache::new(state.params.key_comparator.clone())));
+                        memcache_bytes = 0;
+                        current_info.reset();
+                        state.flush_generation += 1;
+                        if let Err(ero::NoProcError) = state.manager_pid.flush_cache(cache, mem::take(&mut wal_block_refs), state.flush_generation).await {
+                            log::warn!("manager has gone during flush, terminating");
+                            break;
+                        }
                     }
                 }
             },
 
-            Request::Lookup(RequestLookup { key, reply_tx, }) => {
-                let lookup_result = memcache.get(&*key.key_bytes)
+            Event::Request(Some(Request::Lookup { key, reply_tx, })) => {
+                let found = memcache.lookup(&key)
                     .cloned();
-                if let Err(_send_error) = reply_tx.send(lookup_result) {
+                if let Err(_send_error) = reply_tx.send(Found { found, flush_generation: state.flush_generation, }) {
                     log::warn!("client canceled lookup request");
                 }
             },
 
-            Request::LookupRange { range, reply_tx, iter_items_pool, } => {
+            Event::Request(Some(Request::LookupRange { range, reply_tx, iter_items_pool, })) => {
                 let mut iter_items = iter_items_pool.lend(Vec::new);
                 iter_items.clear();
                 iter_items.extend(memcache.range(range));
-                iter_items.shrink_to_fit();
                 if let Err(_send_error) = reply_tx.send(iter_items.freeze()) {
                     log::warn!("client canceled lookup range request");
                 }
             },
 
-            Request::Remove(RequestRemove { key, reply_tx, }) => {
-                let ord_key = OrdKey::new(key);
-                let version = state.version_provider.obtain();
+            Event::Request(Some(Request::Remove { key, reply, .. })) if key_too_large(&key, &state.params) => {
+                let error = manager::RemoveError::KeyTooLarge {
+                    size: key_size(&key, &state.params),
+                    limit: state.params.max_key_size.unwrap(),
+                };
+                if let Err(()) = reply.send(Err(error)) {
+                    log::warn!("client canceled remove request");
+                }
+            },
+
+            Event::Request(Some(Request::Remove { key, reply: RemoveReply::Conditional { condition, reply_tx, }, .. }))
+                if condition.flush_generation != state.flush_generation ||
+                    memcache.lookup(&key).map_or(false, |value_cell| condition.expected_version.map_or(true, |version| value_cell.version > version)) =>
+            {
+                // either a flush or a concurrent write happened after manager lookup
+                if let Err(_send_error) = reply_tx.send(Ok(None)) {
+                    log::warn!("client canceled remove request");
+                }
+            },
+
+            Event::Request(Some(Request::FlushGeneration { reply_tx, })) => {
+                if let Err(_send_error) = reply_tx.send(state.flush_generation) {
+                    log::warn!("client canceled flush generation request");
+                }
+            },
+
+            Event::Request(Some(Request::Remove { key, meta, reply, })) => {
+                let version = match obtain_version(&state) {
+                    Ok(version) =>
+                        version,
+                    Err(version::Regression { version, last_version, }) => {
+                        let error = manager::RemoveError::VersionRegression { version, last_version, };
+                        if let Err(()) = reply.send(Err(error)) {
+                            log::warn!("client canceled remove request");
+                        }
+                        continue;
+                    },
+                };
+                let ord_key = memcache.ord_key(key);
                 let value_cell = kv::ValueCell {
                     version,
-                    cell: kv::Cell::Tombstone,
+                    cell: kv::Cell::Tombstone { meta, },
                 };
+                let value_cell_bytes = entry_bytes(&ord_key, &value_cell);
+                if max_bytes_exceeded(&memcache, memcache_bytes, value_cell_bytes, &state.params) {
+                    // flush before the entry is added so the memtable stays within `max_bytes`
+                    let cache = Arc::new(mem::replace(&mut memcache, MemCache::new(state.params.key_comparator.clone())));
+                    memcache_bytes = 0;
+                    current_info.reset();
+                    state.flush_generation += 1;
+                    if let Err(ero::NoProcError) = state.manager_pid.flush_cache(cache, mem::take(&mut wal_block_refs), state.flush_generation).await {
+                        log::warn!("manager has gone during flush, terminating");
+                        break;
+                    }
+                }
+                let maybe_wal_block_ref = wal_append(&mut state, &ord_key, &value_cell, memcache_bytes).await
+                    .map_err(ErrorSeverity::Fatal)?;
                 let maybe_prev = memcache.insert(ord_key.clone(), value_cell);
+                memcache_bytes += value_cell_bytes;
+                if let Some(ref prev_value_cell) = maybe_prev {
+                    memcache_bytes -= entry_bytes(&ord_key, prev_value_cell);
+                }
                 if maybe_prev.is_none() {
                     current_info.tombstones_count += 1;
                 }
-                if let Err(_send_error) = reply_tx.send(Removed { version, }) {
+                if let Err(()) = reply.send(Ok(Removed { version, })) {
                     log::warn!("client canceled remove request");
+                    if let Some(wal_block_ref) = maybe_wal_block_ref {
+                        wal_discard(&mut state, wal_block_ref).await
+                            .map_err(ErrorSeverity::Fatal)?;
+                    }
+                    memcache_bytes -= value_cell_bytes;
                     match maybe_prev {
                         None => {
                             memcache.remove(&ord_key);
-                            current_info.tombstones_count -= 1;
+                            current_info.tombstones_count = current_info.tombstones_count.saturating_sub(1);
                         },
                         Some(prev_value_cell) => {
+                            memcache_bytes += entry_bytes(&ord_key, &prev_value_cell);
                             memcache.insert(ord_key, prev_value_cell);
                         },
                     }
-                } else if memcache.len() >= state.params.tree_block_size {
+                } else {
+                    wal_block_refs.extend(maybe_wal_block_ref);
+                    if flush_required(&memcache, memcache_bytes, &state.params) && !flush_deferred(&mut coalesce_deadline, memcache_bytes, &state.params) {
+                        // flush tree block
+                        let cache = Arc::new(mem::replace(&mut memcache, MemCache::new(state.params.key_comparator.clone())));
+                        memcache_bytes = 0;
+                        current_info.reset();
+                        state.flush_generation += 1;
+                        if let Err(ero::NoProcError) = state.manager_pid.flush_cache(cache, mem::take(&mut wal_block_refs), state.flush_generation).await {
+                            log::warn!("manager has gone during flush, terminating");
+                            break;
+                        }
+                    }
+                }
+            },
+
+            Event::Request(Some(Request::RemoveMany { keys, reply_tx, })) => {
+                if let Some(key) = keys.iter().find(|key| key_too_large(key, &state.params)) {
+                    let error = manager::RemoveError::KeyTooLarge {
+                        size: key_size(key, &state.params),
+                        limit: state.params.max_key_size.unwrap(),
+                    };
+                    if let Err(_send_error) = reply_tx.send(Err(error)) {
+                        log::warn!("client canceled remove many request");
+                    }
+                    continue;
+                }
+                // versions are obtained up front so a regression rejects the whole batch before anything is written
+                let mut removed = Vec::with_capacity(keys.len());
+                let mut maybe_regression = None;
+                for _ in 0 .. keys.len() {
+                    match obtain_version(&state) {
+                        Ok(version) =>
+                            removed.push(Removed { version, }),
+                        Err(regression) => {
+                            maybe_regression = Some(regression);
+                            break;
+                        },
+                    }
+                }
+                if let Some(version::Regression { version, last_version, }) = maybe_regression {
+                    let error = manager::RemoveError::VersionRegression { version, last_version, };
+                    if let Err(_send_error) = reply_tx.send(Err(error)) {
+                        log::warn!("client canceled remove many request");
+                    }
+                    continue;
+                }
+                let mut manager_gone = false;
+                for (key, &Removed { version, }) in keys.into_iter().zip(removed.iter()) {
+                    let ord_key = memcache.ord_key(key);
+                    let value_cell = kv::ValueCell {
+                        version,
+                        cell: kv::Cell::Tombstone { meta: None, },
+                    };
+                    let value_cell_bytes = entry_bytes(&ord_key, &value_cell);
+                    // the threshold check is deferred to the end of the batch, but the memtable is still
+                    // kept within `max_bytes` and a single tree block
+                    if max_bytes_exceeded(&memcache, memcache_bytes, value_cell_bytes, &state.params) ||
+                        state.params.block_budget.is_full(memcache.len(), memcache_bytes)
+                    {
+                        let cache = Arc::new(mem::replace(&mut memcache, MemCache::new(state.params.key_comparator.clone())));
+                        memcache_bytes = 0;
+                        current_info.reset();
+                        state.flush_generation += 1;
+                        if let Err(ero::NoProcError) = state.manager_pid.flush_cache(cache, mem::take(&mut wal_block_refs), state.flush_generation).await {
+                            manager_gone = true;
+                            break;
+                        }
+                    }
+                    let maybe_wal_block_ref = wal_append(&mut state, &ord_key, &value_cell, memcache_bytes).await
+                        .map_err(ErrorSeverity::Fatal)?;
+                    let maybe_prev = memcache.insert(ord_key.clone(), value_cell);
+                    memcache_bytes += value_cell_bytes;
+                    if let Some(ref prev_value_cell) = maybe_prev {
+                        memcache_bytes -= entry_bytes(&ord_key, prev_value_cell);
+                    }
+                    if maybe_prev.is_none() {
+                        current_info.tombstones_count += 1;
+                    }
+                    wal_block_refs.extend(maybe_wal_block_ref);
+                }
+                if manager_gone {
+                    log::warn!("manager has gone during flush, terminating");
+                    break;
+                }
+                // unlike a single remove the batch is not reverted when the client cancels
+                if let Err(_send_error) = reply_tx.send(Ok(removed)) {
+                    log::warn!("client canceled remove many request");
+                }
+                if flush_required(&memcache, memcache_bytes, &state.params) && !flush_deferred(&mut coalesce_deadline, memcache_bytes, &state.params) {
                     // flush tree block
-                    let cache = Arc::new(mem::replace(&mut memcache, MemCache::new()));
+                    let cache = Arc::new(mem::replace(&mut memcache, MemCache::new(state.params.key_comparator.clone())));
+                    memcache_bytes = 0;
                     current_info.reset();
-                    if let Err(ero::NoProcError) = state.manager_pid.flush_cache(cache).await {
+                    state.flush_generation += 1;
+                    if let Err(ero::NoProcError) = state.manager_pid.flush_cache(cache, mem::take(&mut wal_block_refs), state.flush_generation).await {
                         log::warn!("manager has gone during flush, terminating");
                         break;
                     }
                 }
             },
 
-            Request::Flush(RequestFlush { reply_tx, }) => {
+            Event::Request(Some(Request::ReplayWal { wal_records, reply_tx, })) => {
+                for WalRecord { key, value_cell, block_ref, } in wal_records {
+                    state.version_watermark.observe(value_cell.version);
+                    // record is adopted even if shadowed: it is deleted along with the rest after flush
+                    if !wal_block_refs.contains(&block_ref) {
+                        wal_block_refs.push(block_ref);
+                    }
+                    let ord_key = memcache.ord_key(key);
+                    if memcache.get(&ord_key).map_or(false, |cached_value_cell| cached_value_cell.version >= value_cell.version) {
+                        continue;
+                    }
+                    let value_cell_bytes = entry_bytes(&ord_key, &value_cell);
+                    let is_tombstone = matches!(value_cell.cell, kv::Cell::Tombstone { .. });
+                    match memcache.insert(ord_key.clone(), value_cell) {
+                        None if is_tombstone =>
+                            current_info.tombstones_count += 1,
+                        None =>
+                            current_info.alive_cells_count += 1,
+                        Some(prev_value_cell) =>
+                            memcache_bytes -= entry_bytes(&ord_key, &prev_value_cell),
+                    }
+                    memcache_bytes += value_cell_bytes;
+                }
+                log::info!("wal replayed: {} cells in memcache, {} wal records", memcache.len(), wal_block_refs.len());
+                if let Err(_send_error) = reply_tx.send(()) {
+                    log::warn!("client canceled replay wal request");
+                }
+                if flush_required(&memcache, memcache_bytes, &state.params) && !flush_deferred(&mut coalesce_deadline, memcache_bytes, &state.params) {
+                    let cache = Arc::new(mem::replace(&mut memcache, MemCache::new(state.params.key_comparator.clone())));
+                    memcache_bytes = 0;
+                    current_info.reset();
+                    state.flush_generation += 1;
+                    if let Err(ero::NoProcError) = state.manager_pid.flush_cache(cache, mem::take(&mut wal_block_refs), state.flush_generation).await {
+                        log::warn!("manager has gone during flush, terminating");
+                        break;
+                    }
+                }
+            },
+
+            Event::Request(Some(Request::Flush(RequestFlush { reply_tx, }))) => {
+                let mut flushed = Flushed::default();
                 if !memcache.is_empty() {
                     log::debug!("Request::Flush: actually performing flush_cache");
-                    let cache = Arc::new(mem::replace(&mut memcache, MemCache::new()));
+                    let cache = Arc::new(mem::replace(&mut memcache, MemCache::new(state.params.key_comparator.clone())));
+                    memcache_bytes = 0;
                     current_info.reset();
-                    if let Err(ero::NoProcError) = state.manager_pid.flush_cache(cache).await {
+                    flushed.cache_entries_flushed = cache.len();
+                    flushed.new_search_trees = 1;
+                    state.flush_generation += 1;
+                    if let Err(ero::NoProcError) = state.manager_pid.flush_cache(cache, mem::take(&mut wal_block_refs), state.flush_generation).await {
                         log::warn!("manager has gone during flush, terminating");
                         break;
                     }
                 } else {
                     log::debug!("Request::Flush: no need to perform flush_cache");
                 }
-                if let Err(_send_error) = reply_tx.send(Flushed) {
+                if let Err(_send_error) = reply_tx.send(flushed) {
                     log::warn!("client canceled flush request");
                 }
             },
         }
+
+        if memcache.is_empty() {
+            flush_deadline = None;
+        } else if flush_deadline.is_none() {
+            flush_deadline = state.params.flush_interval
+                .map(|flush_interval| time::Instant::now() + flush_interval);
+        }
+        // the window belongs to the burst which armed it: a flush in between (e.g. on `max_bytes`) disarms it
+        if !flush_required(&memcache, memcache_bytes, &state.params) {
+            coalesce_deadline = None;
+        }
     }
     Ok(())
 }
+
+fn obtain_version(state: &State) -> Result<u64, version::Regression> {
+    state.version_watermark.obtain(&state.version_provider)
+        .map_err(|regression| {
+            log::error!("version provider regressed: {:?}, write rejected", regression);
+            regression
+        })
+}
+
+fn entry_bytes(ord_key: &OrdKey, value_cell: &kv::ValueCell<kv::Value>) -> usize {
+    let value_bytes = match value_cell.cell {
+        kv::Cell::Value(ref value) | kv::Cell::Tombstone { meta: Some(ref value), } =>
+            value.value_bytes.len(),
+        kv::Cell::Tombstone { meta: None, } =>
+            0,
+    };
+    ord_key.as_ref().key_bytes.len() + value_bytes
+}
+
+async fn wal_append(
+    state: &mut State,
+    ord_key: &OrdKey,
+    value_cell: &kv::ValueCell<kv::Value>,
+    memcache_bytes: usize,
+)
+    -> Result<Option<BlockRef>, Error>
+{
+    if !state.params.wal {
+        return Ok(None);
+    }
+    let mut block_bytes = state.params.pooling
+        .pool_for(memcache_bytes + entry_bytes(ord_key, value_cell), &state.blocks_pool)
+        .lend();
+    storage::wal_record_serialize(ord_key.as_ref(), value_cell, state.params.store_id, &mut block_bytes)
+        .map_err(Error::WalSerialize)?;
+    let mut wheel_ref = state.wheels_pid.acquire().await
+        .map_err(|ero::NoProcError| Error::WheelsGone)?
+        .ok_or(Error::WheelsEmpty)?;
+    let block_id = wheel_ref.blockwheel_pid.write_block(block_bytes.freeze()).await
+        .map_err(Error::WalWriteBlock)?;
+    Ok(Some(BlockRef {
+        blockwheel_filename: wheel_ref.blockwheel_filename,
+        block_id,
+    }))
+}
+
+async fn wal_discard(state: &mut State, block_ref: BlockRef) -> Result<(), Error> {
+    let mut wheel_ref = state.wheels_pid.get(block_ref.blockwheel_filename.clone()).await
+        .map_err(|ero::NoProcError| Error::WheelsGone)?
+        .ok_or_else(|| Error::WheelNotFound {
+            blockwheel_filename: block_ref.blockwheel_filename.clone(),
+        })?;
+    let blockwheel::Deleted = wheel_ref.blockwheel_pid.delete_block(block_ref.block_id).await
+        .map_err(Error::WalDeleteBlock)?;
+    Ok(())
+}
+
+fn key_too_large(key: &kv::Key, params: &Params) -> bool {
+    params.max_key_size.map_or(false, |max_key_size| key_size(key, params) > max_key_size)
+}
+
+// plain keys never start with the marker while namespaces are enabled, so the prefix of a namespaced key is not counted
+fn key_size(key: &kv::Key, params: &Params) -> usize {
+    if params.namespaces && key.key_bytes.first() == Some(&NAMESPACE_MARKER) {
+        key.key_bytes.len().saturating_sub(NAMESPACE_PREFIX_LEN)
+    } else {
+        key.key_bytes.len()
+    }
+}
+
+// a flushed memtable becomes a single root block; values later stored out of line still count in full,
+// so the block ends up within budget either way
+fn flush_required(memcache: &MemCache, memcache_bytes: usize, params: &Params) -> bool {
+    if params.block_budget.is_full(memcache.len(), memcache_bytes) {
+        return true;
+    }
+    if params.max_bytes.is_some_and(|max_bytes| memcache_bytes >= max_bytes) {
+        return true;
+    }
+    match params.flush_threshold_bytes {
+        Some(flush_threshold_bytes) =>
+            memcache_bytes >= flush_threshold_bytes,
+        None =>
+            false,
+    }
+}
+
+// arms the coalescing window on the first threshold crossing and defers the flush until it elapses,
+// unless `max_bytes` is already reached
+fn flush_deferred(coalesce_deadline: &mut Option<time::Instant>, memcache_bytes: usize, params: &Params) -> bool {
+    let window = match params.flush_coalesce_window {
+        None =>
+            return false,
+        Some(window) =>
+            window,
+    };
+    if params.max_bytes.is_some_and(|max_bytes| memcache_bytes >= max_bytes) {
+        return false;
+    }
+    if coalesce_deadline.is_none() {
+        let delay = rand::thread_rng().gen_range(window / 2 ..= window);
+        *coalesce_deadline = Some(time::Instant::now() + delay);
+    }
+    true
+}
+
+fn max_bytes_exceeded(memcache: &MemCache, memcache_bytes: usize, entry_bytes: usize, params: &Params) -> bool {
+    !memcache.is_empty() && params.max_bytes.is_some_and(|max_bytes| memcache_bytes + entry_bytes > max_bytes)
+}
@@ -0,0 +1,237 @@
+use std::cmp::Ordering;
+
+use crate::{
+    kv,
+    comparator::KeyComparatorRef,
+};
+
+#[derive(Clone, Debug)]
+pub struct KeyRange {
+    pub first: kv::Key,
+    pub last: kv::Key,
+}
+
+impl KeyRange {
+    pub fn union(&self, other: &KeyRange, key_comparator: &KeyComparatorRef) -> KeyRange {
+        KeyRange {
+            first: match key_comparator.compare(&self.first.key_bytes, &other.first.key_bytes) {
+                Ordering::Less | Ordering::Equal =>
+                    self.first.clone(),
+                Ordering::Greater =>
+                    other.first.clone(),
+            },
+            last: match key_comparator.compare(&self.last.key_bytes, &other.last.key_bytes) {
+                Ordering::Greater | Ordering::Equal =>
+                    self.last.clone(),
+                Ordering::Less =>
+                    other.last.clone(),
+            },
+        }
+    }
+}
+
+struct Run<B> {
+    bucket: B,
+    bytes: usize,
+    key_range: Option<KeyRange>,
+}
+
+struct InFlight<B> {
+    run_a: Run<B>,
+    run_b: Run<B>,
+    level: usize,
+}
+
+pub struct LevelMerger<B> {
+    level_multiplier: usize,
+    base_level_bytes: usize,
+    levels: Vec<Vec<Run<B>>>,
+    in_flight: Vec<InFlight<B>>,
+    key_comparator: KeyComparatorRef,
+}
+
+impl<B> LevelMerger<B> where B: Clone {
+    pub fn new(level_multiplier: usize, base_level_bytes: usize, key_comparator: KeyComparatorRef) -> LevelMerger<B> {
+        assert!(level_multiplier >= 2);
+        assert!(base_level_bytes > 0);
+        LevelMerger {
+            level_multiplier,
+            base_level_bytes,
+            levels: vec![Vec::new()],
+            in_flight: Vec::new(),
+            key_comparator,
+        }
+    }
+
+    // unknown key range (`None`) is treated as overlapping with everything
+    pub fn push(&mut self, bucket: B, bytes: usize, key_range: Option<KeyRange>) {
+        self.levels[0].push(Run { bucket, bytes, key_range, });
+    }
+
+    pub fn pop(&mut self) -> Option<(B, B)> {
+        'outer: loop {
+            for level in 0 .. self.levels.len() {
+                // runs within every level except zero should not overlap
+                if level > 0 {
+                    if let Some((index_a, index_b)) = find_overlapping_pair(&self.levels[level], &self.key_comparator) {
+                        let run_b = self.levels[level].remove(index_b);
+                        let run_a = self.levels[level].remove(index_a);
+                        return Some(self.start_merge(run_a, run_b, level));
+                    }
+                }
+
+                let level_bytes: usize = self.levels[level].iter().map(|run| run.bytes).sum();
+                if level_bytes <= self.level_capacity(level) || self.levels[level].is_empty() {
+                    continue;
+                }
+
+                if level + 1 >= self.levels.len() {
+                    self.levels.push(Vec::new());
+                }
+                let run = self.levels[level].remove(0);
+                let key_comparator = &self.key_comparator;
+                let next_level = &mut self.levels[level + 1];
+                match next_level.iter().position(|next_run| overlaps(&run.key_range, &next_run.key_range, key_comparator)) {
+                    Some(index) => {
+                        let next_run = next_level.remove(index);
+                        return Some(self.start_merge(run, next_run, level + 1));
+                    },
+                    None => {
+                        next_level.push(run);
+                        continue 'outer;
+                    },
+                }
+            }
+            return None;
+        }
+    }
+
+    // buckets in flight are not removed
+    pub fn remove_by<P>(&mut self, pred: P) -> Option<(B, usize, Option<KeyRange>)> where P: Fn(&B) -> bool {
+        for runs in self.levels.iter_mut() {
+            if let Some(index) = runs.iter().position(|run| pred(&run.bucket)) {
+                let Run { bucket, bytes, key_range, } = runs.remove(index);
+                return Some((bucket, bytes, key_range));
+            }
+        }
+        None
+    }
+
+    pub fn merged<P>(&mut self, pred: P, maybe_bucket: Option<B>) where P: Fn(&B, &B) -> bool {
+        let index = self.in_flight.iter()
+            .position(|in_flight| pred(&in_flight.run_a.bucket, &in_flight.run_b.bucket))
+            .expect("merged buckets should be in flight");
+        let InFlight { run_a, run_b, level, } = self.in_flight.swap_remove(index);
+        if let Some(bucket) = maybe_bucket {
+            let key_range = match (run_a.key_range, run_b.key_range) {
+                (Some(key_range_a), Some(key_range_b)) =>
+                    Some(key_range_a.union(&key_range_b, &self.key_comparator)),
+                _ =>
+                    None,
+            };
+            self.levels[level].push(Run {
+                bucket,
+                bytes: run_a.bytes + run_b.bytes,
+                key_range,
+            });
+        }
+    }
+
+    fn level_capacity(&self, level: usize) -> usize {
+        (0 .. level).fold(self.base_level_bytes, |capacity, _| capacity.saturating_mul(self.level_multiplier))
+    }
+
+    fn start_merge(&mut self, run_a: Run<B>, run_b: Run<B>, level: usize) -> (B, B) {
+        let buckets = (run_a.bucket.clone(), run_b.bucket.clone());
+        self.in_flight.push(InFlight { run_a, run_b, level, });
+        buckets
+    }
+}
+
+fn overlaps(key_range_a: &Option<KeyRange>, key_range_b: &Option<KeyRange>, key_comparator: &KeyComparatorRef) -> bool {
+    match (key_range_a, key_range_b) {
+        (Some(a), Some(b)) =>
+            key_comparator.compare(&a.first.key_bytes, &b.last.key_bytes) != Ordering::Greater &&
+                key_comparator.compare(&b.first.key_bytes, &a.last.key_bytes) != Ordering::Greater,
+        _ =>
+            true,
+    }
+}
+
+fn find_overlapping_pair<B>(runs: &[Run<B>], key_comparator: &KeyComparatorRef) -> Option<(usize, usize)> {
+    for index_a in 0 .. runs.len() {
+        for index_b in index_a + 1 .. runs.len() {
+            if overlaps(&runs[index_a].key_range, &runs[index_b].key_range, key_comparator) {
+                return Some((index_a, index_b));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use alloc_pool::bytes::BytesPool;
+
+    use crate::{
+        kv,
+        comparator,
+    };
+
+    use super::{
+        overlaps,
+        KeyRange,
+        LevelMerger,
+    };
+
+    fn make_key(blocks_pool: &BytesPool, value: u32) -> kv::Key {
+        let mut key_bytes = blocks_pool.lend();
+        key_bytes.extend_from_slice(&value.to_be_bytes());
+        key_bytes.into()
+    }
+
+    #[test]
+    fn levels_do_not_overlap() {
+        let blocks_pool = BytesPool::new();
+        let key_comparator = comparator::bytewise();
+        let mut level_merger = LevelMerger::new(4, 1024, key_comparator.clone());
+        let mut rng = rand::thread_rng();
+
+        let mut next_id = 0;
+        let mut total_bytes = 0;
+        for _ in 0 .. 1024 {
+            let first = rng.gen_range(0 .. 65536);
+            let last = first + rng.gen_range(0 .. 256);
+            let bytes = rng.gen_range(1 .. 128);
+            let key_range = KeyRange {
+                first: make_key(&blocks_pool, first),
+                last: make_key(&blocks_pool, last),
+            };
+            level_merger.push(next_id, bytes, Some(key_range));
+            next_id += 1;
+            total_bytes += bytes;
+
+            while let Some((bucket_a, bucket_b)) = level_merger.pop() {
+                level_merger.merged(|a, b| *a == bucket_a && *b == bucket_b, Some(next_id));
+                next_id += 1;
+            }
+        }
+
+        assert!(level_merger.in_flight.is_empty());
+        let mut total_bytes_check = 0;
+        for (level, runs) in level_merger.levels.iter().enumerate() {
+            total_bytes_check += runs.iter().map(|run| run.bytes).sum::<usize>();
+            if level == 0 {
+                continue;
+            }
+            for (index_a, run_a) in runs.iter().enumerate() {
+                for run_b in &runs[index_a + 1 ..] {
+                    assert!(!overlaps(&run_a.key_range, &run_b.key_range, &key_comparator));
+                }
+            }
+        }
+        assert_eq!(total_bytes_check, total_bytes);
+    }
+}
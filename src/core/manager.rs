@@ -1,7 +1,13 @@
 use std::{
+    cmp,
     mem,
     sync::Arc,
-    time::Duration,
+    collections::{
+        BTreeMap,
+        HashSet,
+        VecDeque,
+    },
+    time::Instant,
     ops::RangeBounds,
 };
 
@@ -15,7 +21,9 @@ use futures::{
         mpsc,
         oneshot,
     },
+    future,
     StreamExt,
+    Sink,
     SinkExt,
 };
 
@@ -27,7 +35,6 @@ use o1::set::{
 use ero::{
     restart,
     ErrorSeverity,
-    RestartStrategy,
     supervisor::SupervisorPid,
 };
 
@@ -42,34 +49,107 @@ use crate::{
     kv,
     job,
     wheels,
+    trace,
     storage,
+    metrics,
+    version,
+    comparator,
     core::{
         merger,
         butcher,
+        backoff,
+        block_sizer,
         bin_merger,
+        level_merger,
         search_tree,
         MemCache,
         RequestInfo,
         RequestInsert,
+        RequestInsertNoReply,
         RequestLookup,
+        RequestLookupLocated,
         RequestLookupRange,
+        RequestLookupRangeRefs,
+        RequestLookupRangeKeys,
         RequestRemove,
+        RequestRemoveMany,
+        RequestRemoveReturning,
+        RequestRemoveRange,
+        RequestSplitOff,
         RequestFlush,
+        RequestBulkLoad,
+        RequestGcOrphans,
+        RequestChangedBlocks,
+        RequestIterRawBlocks,
+        RequestVerify,
+        RequestHealth,
+        RequestSizeHistograms,
+        RequestEstimateCount,
+        RequestSubscribeCompactions,
+        RequestCurrentVersion,
+        RequestDetailedInfo,
+        RequestWarmRange,
+        RangeTombstone,
+        RangeTombstones,
+        KeyValuesTx,
         SearchRangeBounds,
+        range_tombstoned,
     },
     Info,
     Flushed,
     Removed,
     Inserted,
+    KeyFilter,
+    VersionWindow,
+    ValueLocation,
+    RawBlocks,
+    VerifyReport,
+    Health,
+    Histograms,
+    Consistency,
+    DetailedInfo,
+    SearchTreeId,
+    SearchTreeDetails,
+    QuarantinedSearchTree,
+    Warmed,
+    CompactionEvent,
+    CompactionTimings,
+    StreamError,
     LookupRange,
-    KeyValueStreamItem,
+    LookupRangeRefs,
+    LookupRangeKeys,
+    OrphansCollected,
+    StoragePreference,
+    Compression,
+    RestartBackoff,
+    CompactionStrategy,
+    PlacementPolicy,
 };
 
 pub mod task;
 
+const REQUEST_RETRIES_LIMIT: usize = 16;
+const COMPACTION_EVENTS_BUFFER: usize = 64;
+// requests parked while flushing or collecting, the request queue is not read past it
+const DEFERRED_REQUESTS_LIMIT: usize = 64;
+
 #[derive(Clone, Debug)]
 pub struct Params {
     pub task_restart_sec: usize,
+    pub task_restart_backoff: Option<RestartBackoff>,
+    pub skip_corrupt_trees: bool,
+    pub max_concurrent_merges: usize,
+    pub background_jobs_limit: Option<usize>,
+    pub lookup_range_prefetch_depth: usize,
+    pub max_pending_trees: Option<usize>,
+    pub lookup_fanout_limit: Option<usize>,
+    pub lookup_range_retries_limit: usize,
+    pub compaction_strategy: CompactionStrategy,
+    pub merge_placement: PlacementPolicy,
+    pub gc_orphans_on_start: bool,
+    pub warm_range_bytes_limit: Option<usize>,
+    pub no_reply_inserts_limit: usize,
+    pub metrics: metrics::MetricsRef,
     pub search_tree_params: search_tree::Params,
 }
 
@@ -77,6 +157,20 @@ impl Default for Params {
     fn default() -> Params {
         Params {
             task_restart_sec: 4,
+            task_restart_backoff: None,
+            skip_corrupt_trees: false,
+            max_concurrent_merges: 4,
+            background_jobs_limit: None,
+            lookup_range_prefetch_depth: 4,
+            max_pending_trees: None,
+            lookup_fanout_limit: None,
+            lookup_range_retries_limit: 4,
+            compaction_strategy: CompactionStrategy::SizeTiered,
+            merge_placement: PlacementPolicy::Random,
+            gc_orphans_on_start: false,
+            warm_range_bytes_limit: None,
+            no_reply_inserts_limit: 64,
+            metrics: metrics::no_metrics(),
             search_tree_params: Default::default(),
         }
     }
@@ -93,12 +187,13 @@ pub struct GenServer {
 pub struct Pid {
     request_tx: mpsc::Sender<Request>,
     flush_cache_tx: mpsc::Sender<ButcherFlush>,
+    request_retries_limit: usize,
 }
 
 impl GenServer {
-    pub fn new() -> GenServer {
-        let (request_tx, request_rx) = mpsc::channel(0);
-        let (flush_cache_tx, flush_cache_rx) = mpsc::channel(0);
+    pub fn new(request_queue_depth: usize, flush_queue_depth: usize) -> GenServer {
+        let (request_tx, request_rx) = mpsc::channel(request_queue_depth);
+        let (flush_cache_tx, flush_cache_rx) = mpsc::channel(flush_queue_depth);
         GenServer {
             request_tx,
             fused_request_rx: request_rx.fuse(),
@@ -111,6 +206,7 @@ impl GenServer {
         Pid {
             request_tx: self.request_tx.clone(),
             flush_cache_tx: self.flush_cache_tx.clone(),
+            request_retries_limit: REQUEST_RETRIES_LIMIT,
         }
     }
 
@@ -119,6 +215,8 @@ impl GenServer {
         parent_supervisor: SupervisorPid,
         thread_pool: edeltraud::Edeltraud<J>,
         blocks_pool: BytesPool,
+        version_provider: version::Provider,
+        version_watermark: version::Watermark,
         butcher_pid: butcher::Pid,
         wheels_pid: wheels::Pid,
         params: Params,
@@ -127,24 +225,30 @@ impl GenServer {
           J::Output: From<job::JobOutput>,
           job::JobOutput: From<J::Output>,
     {
+        let restart_backoff = backoff::Backoff::new(params.task_restart_backoff);
         let terminate_result = restart::restartable(
             ero::Params {
                 name: "ero-blockwheel-kv manager task",
-                restart_strategy: RestartStrategy::Delay {
-                    restart_after: Duration::from_secs(params.task_restart_sec as u64),
-                },
+                restart_strategy: restart_backoff.restart_strategy(params.task_restart_sec),
             },
             State {
                 fused_request_rx: self.fused_request_rx,
                 fused_flush_cache_rx: self.fused_flush_cache_rx,
                 parent_supervisor,
                 thread_pool,
+                background_gate: job::BackgroundGate::new(params.background_jobs_limit),
                 blocks_pool,
+                version_provider,
+                version_watermark,
                 butcher_pid,
                 wheels_pid,
                 params,
+                restart_backoff,
+                last_error: None,
             },
             |mut state| async move {
+                state.restart_backoff.wait("ero-blockwheel-kv manager task").await;
+
                 let child_supervisor_gen_server = state.parent_supervisor.child_supervisor();
                 let child_supervisor_pid = child_supervisor_gen_server.pid();
                 state.parent_supervisor.spawn_link_temporary(
@@ -165,19 +269,36 @@ struct State<J> where J: edeltraud::Job {
     fused_flush_cache_rx: stream::Fuse<mpsc::Receiver<ButcherFlush>>,
     parent_supervisor: SupervisorPid,
     thread_pool: edeltraud::Edeltraud<J>,
+    background_gate: job::BackgroundGate,
     blocks_pool: BytesPool,
+    version_provider: version::Provider,
+    // shared with butcher, seeded from restored trees, range tombstones and wal records
+    version_watermark: version::Watermark,
     butcher_pid: butcher::Pid,
     wheels_pid: wheels::Pid,
     params: Params,
+    restart_backoff: backoff::Backoff,
+    // latest non fatal failure: a skipped corrupt block or a failed health probe
+    last_error: Option<String>,
 }
 
 struct ButcherFlush {
     cache: Arc<MemCache>,
+    wal_block_refs: Vec<wheels::BlockRef>,
+    flush_generation: u64,
 }
 
 #[derive(Debug)]
 pub enum InsertError {
     GenServer(ero::NoProcError),
+    KeyTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    VersionRegression {
+        version: u64,
+        last_version: u64,
+    },
 }
 
 #[derive(Debug)]
@@ -193,6 +314,14 @@ pub enum LookupRangeError {
 #[derive(Debug)]
 pub enum RemoveError {
     GenServer(ero::NoProcError),
+    KeyTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    VersionRegression {
+        version: u64,
+        last_version: u64,
+    },
 }
 
 #[derive(Debug)]
@@ -200,121 +329,361 @@ pub enum FlushError {
     GenServer(ero::NoProcError),
 }
 
+#[derive(Debug)]
+pub enum GcOrphansError {
+    GenServer(ero::NoProcError),
+}
+
+#[derive(Debug)]
+pub enum ChangedBlocksError {
+    GenServer(ero::NoProcError),
+}
+
+#[derive(Debug)]
+pub enum IterRawBlocksError {
+    GenServer(ero::NoProcError),
+}
+
+#[derive(Debug)]
+pub enum VerifyError {
+    GenServer(ero::NoProcError),
+}
+
+#[derive(Debug)]
+pub enum SplitOffError {
+    GenServer(ero::NoProcError),
+    Stream(StreamError),
+    VersionRegression {
+        version: u64,
+        last_version: u64,
+    },
+}
+
+#[derive(Debug)]
+pub enum BulkLoadError {
+    GenServer(ero::NoProcError),
+    KeysNotAscending {
+        prev_key: kv::Key,
+        key: kv::Key,
+    },
+    VersionRegression {
+        version: u64,
+        last_version: u64,
+    },
+}
+
 impl Pid {
-    pub async fn flush_cache(&mut self, cache: Arc<MemCache>) -> Result<Flushed, ero::NoProcError> {
-        self.flush_cache_tx.send(ButcherFlush { cache: cache.clone(), }).await
+    pub fn set_request_retries_limit(&mut self, request_retries_limit: usize) {
+        self.request_retries_limit = request_retries_limit;
+    }
+
+    pub async fn flush_cache(
+        &mut self,
+        cache: Arc<MemCache>,
+        wal_block_refs: Vec<wheels::BlockRef>,
+        flush_generation: u64,
+    )
+        -> Result<(), ero::NoProcError>
+    {
+        self.flush_cache_tx.send(ButcherFlush { cache, wal_block_refs, flush_generation, }).await
             .map_err(|_send_error| ero::NoProcError)?;
-        Ok(Flushed)
+        Ok(())
     }
 
     pub async fn info(&mut self) -> Result<Info, ero::NoProcError> {
-        loop {
-            let (reply_tx, reply_rx) = oneshot::channel();
-            self.request_tx.send(Request::Info(RequestInfo { reply_tx, })).await
-                .map_err(|_send_error| ero::NoProcError)?;
-            match reply_rx.await {
-                Ok(info) =>
-                    return Ok(info),
-                Err(oneshot::Canceled) =>
-                    (),
-            }
-        }
+        self.info_range(..).await
+    }
+
+    pub async fn info_range<R>(&mut self, range: R) -> Result<Info, ero::NoProcError> where R: RangeBounds<kv::Key> {
+        let bounds: SearchRangeBounds = range.into();
+        self.request_with_retries(|reply_tx| Request::Info(RequestInfo {
+            range: bounds.clone(),
+            reply_tx,
+        }))
+            .await
     }
 
     pub async fn insert(&mut self, key: kv::Key, value: kv::Value) -> Result<Inserted, InsertError> {
-        loop {
-            let (reply_tx, reply_rx) = oneshot::channel();
-            self.request_tx
-                .send(Request::Insert(RequestInsert {
-                    key: key.clone(),
-                    value: value.clone(),
-                    reply_tx,
-                }))
-                .await
-                .map_err(|_send_error| InsertError::GenServer(ero::NoProcError))?;
+        self.request_with_retries(|reply_tx| Request::Insert(RequestInsert {
+            key: key.clone(),
+            value: value.clone(),
+            version: None,
+            storage_preference: StoragePreference::Auto,
+            reply_tx,
+        }))
+            .await
+            .map_err(InsertError::GenServer)?
+    }
 
-            match reply_rx.await {
-                Ok(inserted) =>
-                    return Ok(inserted),
-                Err(oneshot::Canceled) =>
-                    (),
-            }
-        }
+    pub async fn insert_with_storage_preference(
+        &mut self,
+        key: kv::Key,
+        value: kv::Value,
+        storage_preference: StoragePreference,
+    )
+        -> Result<Inserted, InsertError>
+    {
+        self.request_with_retries(|reply_tx| Request::Insert(RequestInsert {
+            key: key.clone(),
+            value: value.clone(),
+            version: None,
+            storage_preference,
+            reply_tx,
+        }))
+            .await
+            .map_err(InsertError::GenServer)?
+    }
+
+    pub async fn insert_versioned(&mut self, key: kv::Key, value: kv::Value, version: u64) -> Result<Inserted, InsertError> {
+        self.request_with_retries(|reply_tx| Request::Insert(RequestInsert {
+            key: key.clone(),
+            value: value.clone(),
+            version: Some(version),
+            storage_preference: StoragePreference::Auto,
+            reply_tx,
+        }))
+            .await
+            .map_err(InsertError::GenServer)?
+    }
+
+    // inserts are not acknowledged, only a closed manager is reported
+    pub fn insert_sink(&self) -> impl Sink<(kv::Key, kv::Value), Error = ero::NoProcError> {
+        self.request_tx.clone()
+            .sink_map_err(|_send_error| ero::NoProcError)
+            .with(|(key, value): (kv::Key, kv::Value)| {
+                future::ready(Ok::<_, ero::NoProcError>(Request::InsertNoReply(RequestInsertNoReply { key, value, })))
+            })
     }
 
     pub async fn lookup(&mut self, key: kv::Key) -> Result<Option<kv::ValueCell<kv::Value>>, LookupError> {
-        loop {
-            let (reply_tx, reply_rx) = oneshot::channel();
-            self.request_tx
-                .send(Request::Lookup(RequestLookup {
-                    key: key.clone(),
-                    reply_tx,
-                }))
-                .await
-                .map_err(|_send_error| LookupError::GenServer(ero::NoProcError))?;
+        self.lookup_with_consistency(key, Consistency::Strong).await
+    }
 
-            match reply_rx.await {
-                Ok(result) =>
-                    return Ok(result),
-                Err(oneshot::Canceled) =>
-                    (),
-            }
-        }
+    pub async fn lookup_with_consistency(
+        &mut self,
+        key: kv::Key,
+        consistency: Consistency,
+    )
+        -> Result<Option<kv::ValueCell<kv::Value>>, LookupError>
+    {
+        self.request_with_retries(|reply_tx| Request::Lookup(RequestLookup {
+            key: key.clone(),
+            consistency,
+            reply_tx,
+        }))
+            .await
+            .map_err(LookupError::GenServer)
+    }
+
+    pub async fn lookup_located(&mut self, key: kv::Key) -> Result<Option<kv::ValueCell<ValueLocation>>, LookupError> {
+        self.request_with_retries(|reply_tx| Request::LookupLocated(RequestLookupLocated {
+            key: key.clone(),
+            reply_tx,
+        }))
+            .await
+            .map_err(LookupError::GenServer)
     }
 
-    pub async fn lookup_range<R>(&mut self, range: R) -> Result<LookupRange, LookupRangeError> where R: RangeBounds<kv::Key> {
+    pub async fn lookup_range<R>(
+        &mut self,
+        range: R,
+        buffer: Option<usize>,
+        key_filter: Option<KeyFilter>,
+        version_window: Option<VersionWindow>,
+    )
+        -> Result<LookupRange, LookupRangeError>
+    where R: RangeBounds<kv::Key>
+    {
         let bounds: SearchRangeBounds = range.into();
-        loop {
-            let (reply_tx, reply_rx) = oneshot::channel();
-            self.request_tx
-                .send(Request::LookupRange(RequestLookupRange {
-                    range: bounds.clone(),
-                    reply_tx,
-                }))
-                .await
-                .map_err(|_send_error| LookupRangeError::GenServer(ero::NoProcError))?;
+        self.request_with_retries(|reply_tx| Request::LookupRange(RequestLookupRange {
+            range: bounds.clone(),
+            buffer,
+            key_filter: key_filter.clone(),
+            version_window,
+            reply_tx,
+        }))
+            .await
+            .map_err(LookupRangeError::GenServer)
+    }
 
-            match reply_rx.await {
-                Ok(result) =>
-                    return Ok(result),
-                Err(oneshot::Canceled) =>
-                    (),
-            }
-        }
+    pub async fn lookup_range_refs<R>(&mut self, range: R, buffer: Option<usize>) -> Result<LookupRangeRefs, LookupRangeError> where R: RangeBounds<kv::Key> {
+        let bounds: SearchRangeBounds = range.into();
+        self.request_with_retries(|reply_tx| Request::LookupRangeRefs(RequestLookupRangeRefs {
+            range: bounds.clone(),
+            buffer,
+            reply_tx,
+        }))
+            .await
+            .map_err(LookupRangeError::GenServer)
     }
 
-    pub async fn remove(&mut self, key: kv::Key) -> Result<Removed, RemoveError> {
-        loop {
-            let (reply_tx, reply_rx) = oneshot::channel();
-            self.request_tx
-                .send(Request::Remove(RequestRemove {
-                    key: key.clone(),
-                    reply_tx,
-                }))
-                .await
-                .map_err(|_send_error| RemoveError::GenServer(ero::NoProcError))?;
+    pub async fn lookup_range_keys_only<R>(&mut self, range: R, buffer: Option<usize>) -> Result<LookupRangeKeys, LookupRangeError> where R: RangeBounds<kv::Key> {
+        let bounds: SearchRangeBounds = range.into();
+        self.request_with_retries(|reply_tx| Request::LookupRangeKeys(RequestLookupRangeKeys {
+            range: bounds.clone(),
+            buffer,
+            reply_tx,
+        }))
+            .await
+            .map_err(LookupRangeError::GenServer)
+    }
 
-            match reply_rx.await {
-                Ok(result) =>
-                    return Ok(result),
-                Err(oneshot::Canceled) =>
-                    (),
-            }
-        }
+    pub async fn remove(&mut self, key: kv::Key, meta: Option<kv::Value>) -> Result<Removed, RemoveError> {
+        self.request_with_retries(|reply_tx| Request::Remove(RequestRemove {
+            key: key.clone(),
+            meta: meta.clone(),
+            reply_tx,
+        }))
+            .await
+            .map_err(RemoveError::GenServer)?
+    }
+
+    pub async fn remove_many(&mut self, keys: Vec<kv::Key>) -> Result<Vec<Removed>, RemoveError> {
+        self.request_with_retries(|reply_tx| Request::RemoveMany(RequestRemoveMany {
+            keys: keys.clone(),
+            reply_tx,
+        }))
+            .await
+            .map_err(RemoveError::GenServer)?
+    }
+
+    pub async fn remove_returning(&mut self, key: kv::Key) -> Result<(Removed, Option<kv::ValueCell<kv::Value>>), RemoveError> {
+        self.request_with_retries(|reply_tx| Request::RemoveReturning(RequestRemoveReturning {
+            key: key.clone(),
+            reply_tx,
+        }))
+            .await
+            .map_err(RemoveError::GenServer)?
+    }
+
+    pub async fn remove_range<R>(&mut self, range: R) -> Result<Removed, RemoveError> where R: RangeBounds<kv::Key> {
+        let bounds: SearchRangeBounds = range.into();
+        self.request_with_retries(|reply_tx| Request::RemoveRange(RequestRemoveRange {
+            range: bounds.clone(),
+            reply_tx,
+        }))
+            .await
+            .map_err(RemoveError::GenServer)?
+    }
+
+    pub async fn split_off<R>(&mut self, range: R) -> Result<Vec<kv::KeyValuePair<kv::Value>>, SplitOffError> where R: RangeBounds<kv::Key> {
+        let bounds: SearchRangeBounds = range.into();
+        self.request_with_retries(|reply_tx| Request::SplitOff(RequestSplitOff {
+            range: bounds.clone(),
+            reply_tx,
+        }))
+            .await
+            .map_err(SplitOffError::GenServer)?
+    }
+
+    pub async fn bulk_load(&mut self, prev_key: Option<kv::Key>, items: Vec<(kv::Key, kv::Value)>) -> Result<Inserted, BulkLoadError> {
+        let items = Arc::new(items);
+        self.request_with_retries(|reply_tx| Request::BulkLoad(RequestBulkLoad {
+            prev_key: prev_key.clone(),
+            items: items.clone(),
+            reply_tx,
+        }))
+            .await
+            .map_err(BulkLoadError::GenServer)?
+    }
+
+    pub async fn shutdown(&mut self) -> Result<(), ero::NoProcError> {
+        let Flushed { .. } = self.request_with_retries(|reply_tx| Request::Shutdown(RequestFlush { reply_tx, })).await?;
+        Ok(())
     }
 
     pub async fn flush_all(&mut self) -> Result<Flushed, FlushError> {
+        self.request_with_retries(|reply_tx| Request::FlushAll(RequestFlush { reply_tx, }))
+            .await
+            .map_err(FlushError::GenServer)
+    }
+
+    pub async fn flush_butcher_only(&mut self) -> Result<Flushed, FlushError> {
+        self.request_with_retries(|reply_tx| Request::FlushButcherOnly(RequestFlush { reply_tx, }))
+            .await
+            .map_err(FlushError::GenServer)
+    }
+
+    pub async fn gc_orphans(&mut self) -> Result<OrphansCollected, GcOrphansError> {
+        self.request_with_retries(|reply_tx| Request::GcOrphans(RequestGcOrphans { reply_tx, }))
+            .await
+            .map_err(GcOrphansError::GenServer)
+    }
+
+    pub async fn changed_blocks_since(&mut self, version_floor: u64) -> Result<Vec<wheels::BlockRef>, ChangedBlocksError> {
+        self.request_with_retries(|reply_tx| Request::ChangedBlocks(RequestChangedBlocks { version_floor, reply_tx, }))
+            .await
+            .map_err(ChangedBlocksError::GenServer)
+    }
+
+    pub async fn iter_raw_blocks(&mut self) -> Result<RawBlocks, IterRawBlocksError> {
+        self.request_with_retries(|reply_tx| Request::IterRawBlocks(RequestIterRawBlocks { reply_tx, }))
+            .await
+            .map_err(IterRawBlocksError::GenServer)
+    }
+
+    pub async fn verify(&mut self) -> Result<VerifyReport, VerifyError> {
+        self.request_with_retries(|reply_tx| Request::Verify(RequestVerify { reply_tx, }))
+            .await
+            .map_err(VerifyError::GenServer)
+    }
+
+    pub async fn health(&mut self) -> Result<Health, ero::NoProcError> {
+        self.request_with_retries(|reply_tx| Request::Health(RequestHealth { reply_tx, })).await
+    }
+
+    pub async fn size_histograms(&mut self) -> Result<Histograms, ero::NoProcError> {
+        self.request_with_retries(|reply_tx| Request::SizeHistograms(RequestSizeHistograms { reply_tx, })).await
+    }
+
+    pub async fn estimate_count<R>(&mut self, range: R) -> Result<usize, ero::NoProcError> where R: RangeBounds<kv::Key> {
+        let bounds: SearchRangeBounds = range.into();
+        self.request_with_retries(|reply_tx| Request::EstimateCount(RequestEstimateCount {
+            range: bounds.clone(),
+            reply_tx,
+        }))
+            .await
+    }
+
+    pub async fn subscribe_compactions(&mut self) -> Result<mpsc::Receiver<CompactionEvent>, ero::NoProcError> {
+        self.request_with_retries(|reply_tx| Request::SubscribeCompactions(RequestSubscribeCompactions { reply_tx, })).await
+    }
+
+    pub async fn current_version(&mut self) -> Result<u64, ero::NoProcError> {
+        self.request_with_retries(|reply_tx| Request::CurrentVersion(RequestCurrentVersion { reply_tx, })).await
+    }
+
+    pub async fn detailed_info(&mut self) -> Result<DetailedInfo, ero::NoProcError> {
+        self.request_with_retries(|reply_tx| Request::DetailedInfo(RequestDetailedInfo { reply_tx, })).await
+    }
+
+    pub async fn warm_range<R>(&mut self, range: R) -> Result<Warmed, ero::NoProcError> where R: RangeBounds<kv::Key> {
+        let bounds: SearchRangeBounds = range.into();
+        self.request_with_retries(|reply_tx| Request::WarmRange(RequestWarmRange {
+            range: bounds.clone(),
+            reply_tx,
+        }))
+            .await
+    }
+
+    async fn request_with_retries<T, F>(&mut self, mut make_request: F) -> Result<T, ero::NoProcError>
+    where F: FnMut(oneshot::Sender<T>) -> Request
+    {
+        let mut retries_left = self.request_retries_limit;
         loop {
             let (reply_tx, reply_rx) = oneshot::channel();
-            self.request_tx
-                .send(Request::FlushAll(RequestFlush { reply_tx, })).await
-                .map_err(|_send_error| FlushError::GenServer(ero::NoProcError))?;
+            self.request_tx.send(make_request(reply_tx)).await
+                .map_err(|_send_error| ero::NoProcError)?;
 
             match reply_rx.await {
-                Ok(Flushed) =>
-                    return Ok(Flushed),
+                Ok(reply) =>
+                    return Ok(reply),
+                Err(oneshot::Canceled) if retries_left == 0 => {
+                    log::warn!("request reply canceled {} times in a row, giving up", self.request_retries_limit + 1);
+                    return Err(ero::NoProcError);
+                },
                 Err(oneshot::Canceled) =>
-                    (),
+                    retries_left -= 1,
             }
         }
     }
@@ -323,10 +692,67 @@ impl Pid {
 enum Request {
     Info(RequestInfo),
     Insert(RequestInsert),
+    InsertNoReply(RequestInsertNoReply),
     Lookup(RequestLookup),
+    LookupLocated(RequestLookupLocated),
     LookupRange(RequestLookupRange),
+    LookupRangeRefs(RequestLookupRangeRefs),
+    LookupRangeKeys(RequestLookupRangeKeys),
     Remove(RequestRemove),
+    RemoveMany(RequestRemoveMany),
+    RemoveReturning(RequestRemoveReturning),
+    RemoveRange(RequestRemoveRange),
+    SplitOff(RequestSplitOff),
+    BulkLoad(RequestBulkLoad),
     FlushAll(RequestFlush),
+    FlushButcherOnly(RequestFlush),
+    Shutdown(RequestFlush),
+    GcOrphans(RequestGcOrphans),
+    ChangedBlocks(RequestChangedBlocks),
+    IterRawBlocks(RequestIterRawBlocks),
+    Verify(RequestVerify),
+    Health(RequestHealth),
+    SizeHistograms(RequestSizeHistograms),
+    EstimateCount(RequestEstimateCount),
+    SubscribeCompactions(RequestSubscribeCompactions),
+    CurrentVersion(RequestCurrentVersion),
+    DetailedInfo(RequestDetailedInfo),
+    WarmRange(RequestWarmRange),
+}
+
+impl Request {
+    fn kind(&self) -> &'static str {
+        match self {
+            Request::Info(..) => "Info",
+            Request::Insert(..) => "Insert",
+            Request::InsertNoReply(..) => "InsertNoReply",
+            Request::Lookup(..) => "Lookup",
+            Request::LookupLocated(..) => "LookupLocated",
+            Request::LookupRange(..) => "LookupRange",
+            Request::LookupRangeRefs(..) => "LookupRangeRefs",
+            Request::LookupRangeKeys(..) => "LookupRangeKeys",
+            Request::Remove(..) => "Remove",
+            Request::RemoveMany(..) => "RemoveMany",
+            Request::RemoveReturning(..) => "RemoveReturning",
+            Request::RemoveRange(..) => "RemoveRange",
+            Request::SplitOff(..) => "SplitOff",
+            Request::BulkLoad(..) => "BulkLoad",
+            Request::FlushAll(..) => "FlushAll",
+            Request::FlushButcherOnly(..) => "FlushButcherOnly",
+            Request::Shutdown(..) => "Shutdown",
+            Request::GcOrphans(..) => "GcOrphans",
+            Request::ChangedBlocks(..) => "ChangedBlocks",
+            Request::IterRawBlocks(..) => "IterRawBlocks",
+            Request::Verify(..) => "Verify",
+            Request::Health(..) => "Health",
+            Request::SizeHistograms(..) => "SizeHistograms",
+            Request::EstimateCount(..) => "EstimateCount",
+            Request::SubscribeCompactions(..) => "SubscribeCompactions",
+            Request::CurrentVersion(..) => "CurrentVersion",
+            Request::DetailedInfo(..) => "DetailedInfo",
+            Request::WarmRange(..) => "WarmRange",
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -334,10 +760,31 @@ enum Error {
     Task(task::Error),
     WheelsIterBlocks(wheels::IterBlocksError),
     WheelsIterBlocksRxDropped,
+    ButcherReplayWal,
+    ButcherFlushGeneration,
     DeserializeBlock {
         block_ref: wheels::BlockRef,
+        node_type: Option<storage::NodeType>,
+        first_key: Option<kv::Key>,
         error: storage::Error,
     },
+    DeleteMergeRecords(task::delete_range_tombstones::Error),
+    // a tree ordered by another comparator cannot be searched with this one
+    ComparatorMismatch {
+        block_ref: wheels::BlockRef,
+        expected: u32,
+        found: u32,
+    },
+}
+
+fn skip_corrupt_block<S>(error: Error, skip_corrupt_trees: bool, last_error: &mut Option<String>) -> Result<(), ErrorSeverity<S, Error>> {
+    if skip_corrupt_trees {
+        log::error!("skipping corrupt block: {:?}", error);
+        *last_error = Some(format!("skipped corrupt block: {:?}", error));
+        Ok(())
+    } else {
+        Err(ErrorSeverity::Fatal(error))
+    }
 }
 
 struct InfoRequest {
@@ -348,29 +795,219 @@ struct InfoRequest {
 
 struct LookupRequest {
     key: kv::Key,
-    reply_tx: oneshot::Sender<Option<kv::ValueCell<kv::Value>>>,
+    reply_tx: LookupReplyTx,
+    started_at: Instant,
+    span: trace::Span,
+    flush_generation: u64,
     butcher_status: LookupRequestButcherStatus,
     pending_count: usize,
     found_fold: Option<kv::ValueCell<storage::OwnedValueBlockRef>>,
 }
 
+enum LookupReplyTx {
+    Value(oneshot::Sender<Option<kv::ValueCell<kv::Value>>>),
+    // replied right away with the value location, skipping the value block read
+    Located(oneshot::Sender<Option<kv::ValueCell<ValueLocation>>>),
+}
+
+impl LookupReplyTx {
+    // the caller dropped its future: there is nobody to reply to
+    fn is_canceled(&self) -> bool {
+        match self {
+            LookupReplyTx::Value(reply_tx) =>
+                reply_tx.is_canceled(),
+            LookupReplyTx::Located(reply_tx) =>
+                reply_tx.is_canceled(),
+        }
+    }
+}
+
 enum LookupRequestButcherStatus {
     NotReady,
+    AwaitingFlush { flush_generation: u64, },
     Done,
-    Invalidated,
+}
+
+// caches flushed after some pending lookup has been launched: butcher replies
+// do not cover them and search trees built from them are not queried
+#[derive(Default)]
+struct FlushedCaches {
+    caches: VecDeque<(u64, Arc<MemCache>)>,
+    lookups_launched: BTreeMap<u64, usize>,
+}
+
+impl FlushedCaches {
+    fn lookup_launched(&mut self, flush_generation: u64) {
+        *self.lookups_launched.entry(flush_generation).or_insert(0) += 1;
+    }
+
+    fn lookup_finished(&mut self, flush_generation: u64) {
+        let count = self.lookups_launched.get_mut(&flush_generation).unwrap();
+        assert!(*count > 0);
+        *count -= 1;
+        if *count == 0 {
+            self.lookups_launched.remove(&flush_generation);
+        }
+        self.prune();
+    }
+
+    fn flushed(&mut self, flush_generation: u64, cache: Arc<MemCache>) {
+        if !self.lookups_launched.is_empty() {
+            self.caches.push_back((flush_generation, cache));
+        }
+    }
+
+    fn prune(&mut self) {
+        match self.lookups_launched.keys().next() {
+            None =>
+                self.caches.clear(),
+            Some(&min_generation) =>
+                while let Some(&(flush_generation, _)) = self.caches.front() {
+                    if flush_generation > min_generation {
+                        break;
+                    }
+                    self.caches.pop_front();
+                },
+        }
+    }
+
+    fn fold(
+        &self,
+        key: &kv::Key,
+        after_generation: u64,
+        upto_generation: u64,
+        found_fold: &mut Option<kv::ValueCell<storage::OwnedValueBlockRef>>,
+    )
+    {
+        let caches = self.caches.iter()
+            .filter(|(flush_generation, _)| *flush_generation > after_generation && *flush_generation <= upto_generation);
+        for (_flush_generation, cache) in caches {
+            let found = cache.lookup(key).cloned().map(Into::into);
+            if replace_fold_found(found_fold, &found) {
+                *found_fold = found;
+            }
+        }
+    }
 }
 
 struct LookupRangeRequest {
     range: SearchRangeBounds,
-    key_values_tx: mpsc::Sender<KeyValueStreamItem>,
+    key_values_tx: KeyValuesTx,
+    key_filter: Option<KeyFilter>,
+    version_window: Option<VersionWindow>,
+    cancel_rx: oneshot::Receiver<()>,
     butcher_iter_items: Shared<Vec<kv::KeyValuePair<kv::Value>>>,
     merger_iters: Unique<Vec<merger::KeyValuesIter>>,
+    retries: usize,
+    last_key: Option<kv::Key>,
+    late_trees_rx: mpsc::UnboundedReceiver<search_tree::Pid>,
     pending_count: usize,
 }
 
+// scans deprecated more than `lookup_range_retries_limit` times in a row are restarted with merges paused:
+// value blocks are freed by merges only, so such a scan sees a stable set of trees and cannot be deprecated again
+#[derive(Default)]
+struct PinnedLookupRanges {
+    running: usize,
+    // waiting for running merges to finish
+    pending: Vec<task::lookup_range_butcher::Args>,
+}
+
+impl PinnedLookupRanges {
+    fn max_concurrent_merges(&self, max_concurrent_merges: usize) -> usize {
+        if self.running > 0 || !self.pending.is_empty() {
+            0
+        } else {
+            max_concurrent_merges
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LookupRangeRetry {
+    Retry,
+    Pinned,
+    WaitMerges,
+}
+
+// `retries` is the count for the scan about to be restarted
+fn lookup_range_retry(retries: usize, retries_limit: usize, merge_search_trees_tasks_count: usize) -> LookupRangeRetry {
+    if retries <= retries_limit {
+        LookupRangeRetry::Retry
+    } else if merge_search_trees_tasks_count == 0 {
+        LookupRangeRetry::Pinned
+    } else {
+        LookupRangeRetry::WaitMerges
+    }
+}
+
 struct FlushRequest {
     butcher_done: bool,
     search_trees_pending_count: usize,
+    flushed: Flushed,
+}
+
+// flush requests arriving while a flush is running are served together by the single next pass: attaching
+// them to the running one would miss inserts the butcher accepted after it had already been flushed
+#[derive(Default)]
+struct CoalescedFlush {
+    reply_txs: Vec<oneshot::Sender<Flushed>>,
+    terminate: bool,
+}
+
+fn flush_butcher_task_args(flush_requests: &mut Set<FlushRequest>, butcher_pid: &butcher::Pid) -> task::TaskArgs {
+    let request_ref = flush_requests.insert(FlushRequest {
+        butcher_done: false,
+        search_trees_pending_count: 0,
+        flushed: Flushed::default(),
+    });
+    task::TaskArgs::FlushButcher(
+        task::flush_butcher::Args {
+            request_ref,
+            butcher_pid: butcher_pid.clone(),
+        },
+    )
+}
+
+// a paused queue is never ready, so requests stay in the channel and senders get its backpressure
+fn next_request(
+    fused_request_rx: &mut stream::Fuse<mpsc::Receiver<Request>>,
+    paused: bool,
+)
+    -> future::Either<future::Pending<Option<Request>>, stream::Next<'_, stream::Fuse<mpsc::Receiver<Request>>>>
+{
+    if paused {
+        future::Either::Left(future::pending())
+    } else {
+        future::Either::Right(fused_request_rx.next())
+    }
+}
+
+struct ButcherFlushWaiter {
+    flush_generation: u64,
+    flushed: Flushed,
+    reply_tx: oneshot::Sender<Flushed>,
+}
+
+fn mask_range_tombstoned(
+    found_fold: Option<kv::ValueCell<storage::OwnedValueBlockRef>>,
+    key: &kv::Key,
+    range_tombstones: &[RangeTombstone],
+    key_comparator: &comparator::KeyComparatorRef,
+)
+    -> Option<kv::ValueCell<storage::OwnedValueBlockRef>>
+{
+    match found_fold {
+        Some(value_cell) =>
+            match range_tombstoned(range_tombstones, key, value_cell.version, key_comparator) {
+                None =>
+                    Some(value_cell),
+                Some(version) =>
+                    Some(kv::ValueCell { version, cell: kv::Cell::Tombstone { meta: None, }, }),
+            },
+        None =>
+            None,
+    }
 }
 
 fn replace_fold_found(
@@ -400,8 +1037,23 @@ where J: edeltraud::Job + From<job::Job>,
 {
     let search_tree_pools = search_tree::Pools::new(state.blocks_pool.clone());
     let mut search_trees = Set::new();
-    let mut search_tree_refs = bin_merger::BinMerger::new();
+    let mut search_tree_max_versions = BTreeMap::new();
+    let mut search_tree_created_versions = BTreeMap::new();
+    let (search_tree_failures_tx, search_tree_failures_rx) = mpsc::unbounded();
+    let search_tree_refs = SearchTreeRefs::new(
+        &state.params.compaction_strategy,
+        &state.params.search_tree_params.key_comparator,
+    );
+    let mut restored_search_trees = Vec::new();
     let mut blocks_total = 0;
+    let mut wal_records = Vec::new();
+    let mut range_tombstones = Vec::new();
+    // roots are restored after every merge record is read, so superseded merge inputs are skipped
+    let mut found_roots = Vec::new();
+    let mut merge_records = Vec::new();
+    // the highest cell version over blocks written before trees recorded their version range
+    let mut legacy_max_version = None;
+    let store_id = state.params.search_tree_params.store_id;
 
     log::info!("loading search_tree roots from wheels");
 
@@ -415,43 +1067,160 @@ where J: edeltraud::Job + From<job::Job>,
                 return Err(ErrorSeverity::Fatal(Error::WheelsIterBlocksRxDropped)),
             Some(wheels::IterBlocksItem::Block { block_ref, block_bytes, }) => {
                 blocks_total += 1;
+                let block_bytes = match storage::block_decompress(block_bytes, &state.blocks_pool) {
+                    Ok(block_bytes) =>
+                        block_bytes,
+                    Err(error) => {
+                        skip_corrupt_block(
+                            Error::DeserializeBlock { block_ref, node_type: None, first_key: None, error, },
+                            state.params.skip_corrupt_trees,
+                            &mut state.last_error,
+                        )?;
+                        continue;
+                    },
+                };
+                match storage::block_verify_checksum(&block_bytes, &block_ref) {
+                    Ok(()) =>
+                        (),
+                    Err(storage::Error::UnsupportedFormatVersion { found, supported, }) => {
+                        log::warn!("skipping block {:?} (unsupported format version: {}, supported: {})", block_ref, found, supported);
+                        continue;
+                    },
+                    Err(error) => {
+                        skip_corrupt_block(
+                            Error::DeserializeBlock { block_ref, node_type: None, first_key: None, error, },
+                            state.params.skip_corrupt_trees,
+                            &mut state.last_error,
+                        )?;
+                        continue;
+                    },
+                }
                 let deserializer = match storage::block_deserialize_iter(&block_bytes) {
                     Ok(deserializer) =>
                         deserializer,
+                    Err(storage::Error::InvalidBlockMagic { provided, .. }) if storage::magic_store_id(provided, storage::WAL_RECORD_MAGIC) == Some(store_id) => {
+                        match storage::wal_record_deserialize(&block_bytes) {
+                            Ok((key, value_cell)) =>
+                                wal_records.push(butcher::WalRecord { key, value_cell, block_ref, }),
+                            Err(error) =>
+                                skip_corrupt_block(
+                                    Error::DeserializeBlock { block_ref, node_type: None, first_key: None, error, },
+                                    state.params.skip_corrupt_trees,
+                                    &mut state.last_error,
+                                )?,
+                        }
+                        continue;
+                    },
+                    Err(storage::Error::InvalidBlockMagic { provided, .. }) if storage::magic_store_id(provided, storage::RANGE_TOMBSTONE_MAGIC) == Some(store_id) => {
+                        match storage::range_tombstone_deserialize(&block_bytes) {
+                            Ok((range_from, range_to, version)) =>
+                                range_tombstones.push(RangeTombstone {
+                                    range: SearchRangeBounds { range_from, range_to, },
+                                    version,
+                                    block_ref,
+                                }),
+                            Err(error) =>
+                                skip_corrupt_block(
+                                    Error::DeserializeBlock { block_ref, node_type: None, first_key: None, error, },
+                                    state.params.skip_corrupt_trees,
+                                    &mut state.last_error,
+                                )?,
+                        }
+                        continue;
+                    },
+                    Err(storage::Error::InvalidBlockMagic { provided, .. }) if storage::magic_store_id(provided, storage::MERGE_RECORD_MAGIC) == Some(store_id) => {
+                        match storage::merge_record_deserialize(&block_bytes) {
+                            Ok((_output_root, input_roots)) =>
+                                merge_records.push((block_ref, input_roots)),
+                            Err(error) =>
+                                skip_corrupt_block(
+                                    Error::DeserializeBlock { block_ref, node_type: None, first_key: None, error, },
+                                    state.params.skip_corrupt_trees,
+                                    &mut state.last_error,
+                                )?,
+                        }
+                        continue;
+                    },
                     Err(storage::Error::InvalidBlockMagic { expected, provided, }) => {
                         log::debug!("skipping block {:?} (invalid magic provided: {}, expected: {})", block_ref, provided, expected);
                         continue;
                     },
-                    Err(error) =>
-                        return Err(ErrorSeverity::Fatal(Error::DeserializeBlock {
-                            block_ref,
-                            error,
-                        })),
+                    Err(storage::Error::UnsupportedFormatVersion { found, supported, }) => {
+                        log::warn!("skipping block {:?} (unsupported format version: {}, supported: {})", block_ref, found, supported);
+                        continue;
+                    },
+                    Err(error) => {
+                        skip_corrupt_block(
+                            Error::DeserializeBlock { block_ref, node_type: None, first_key: None, error, },
+                            state.params.skip_corrupt_trees,
+                            &mut state.last_error,
+                        )?;
+                        continue;
+                    },
                 };
-                match deserializer.block_header().node_type {
-                    storage::NodeType::Root { tree_entries_count, } => {
+                if deserializer.store_id() != store_id {
+                    log::debug!("skipping block {:?} (written by store {}, this one is {})", block_ref, deserializer.store_id(), store_id);
+                    continue;
+                }
+                let node_type = deserializer.block_header().node_type;
+                let max_version_recorded = deserializer.max_version_recorded();
+                let max_version = deserializer.root_max_version();
+                match node_type {
+                    storage::NodeType::Root { tree_entries_count, created_at_version, comparator_id, .. } => {
+                        let expected_comparator_id = state.params.search_tree_params.key_comparator.id();
+                        if comparator_id != expected_comparator_id {
+                            return Err(ErrorSeverity::Fatal(Error::ComparatorMismatch {
+                                block_ref,
+                                expected: expected_comparator_id,
+                                found: comparator_id,
+                            }));
+                        }
+                        let mut first_key = None;
+                        let mut prev_key: Option<&[u8]> = None;
+                        let mut maybe_error = None;
+                        for maybe_entry in deserializer {
+                            if let (false, Ok(entry)) = (max_version_recorded, &maybe_entry) {
+                                legacy_max_version = cmp::max(legacy_max_version, Some(entry.value_cell.version));
+                            }
+                            match maybe_entry {
+                                // lookups walk a block assuming strictly ascending keys
+                                Ok(entry) if prev_key.map_or(false, |prev_key| {
+                                    state.params.search_tree_params.key_comparator.compare(prev_key, entry.key) != cmp::Ordering::Less
+                                }) => {
+                                    maybe_error = Some(storage::Error::NonMonotonicBlock { block_ref: block_ref.clone(), });
+                                    break;
+                                },
+                                Ok(entry) if first_key.is_none() => {
+                                    let mut key_bytes = state.blocks_pool.lend();
+                                    key_bytes.extend_from_slice(entry.key);
+                                    first_key = Some(kv::Key::from(key_bytes));
+                                    prev_key = Some(entry.key);
+                                },
+                                Ok(entry) =>
+                                    prev_key = Some(entry.key),
+                                Err(error) => {
+                                    maybe_error = Some(error);
+                                    break;
+                                },
+                            }
+                        }
+                        if let Some(error) = maybe_error {
+                            skip_corrupt_block(
+                                Error::DeserializeBlock { block_ref, node_type: Some(node_type), first_key, error, },
+                                state.params.skip_corrupt_trees,
+                                &mut state.last_error,
+                            )?;
+                            continue;
+                        }
+
                         log::debug!("root search_tree found with {:?} entries in {:?}", tree_entries_count, block_ref);
-                        let search_tree_gen_server = search_tree::GenServer::new();
-                        let search_tree_pid = search_tree_gen_server.pid();
-                        child_supervisor_pid.spawn_link_temporary(
-                            search_tree_gen_server.run(
-                                child_supervisor_pid.clone(),
-                                state.thread_pool.clone(),
-                                search_tree_pools.clone(),
-                                state.wheels_pid.clone(),
-                                state.params.search_tree_params.clone(),
-                                search_tree::Mode::Regular { root_block: block_ref, },
-                            ),
-                        );
-                        let search_tree_ref = search_trees.insert(search_tree_pid);
-                        search_tree_refs.push(
-                            SearchTreeRef {
-                                search_tree_ref,
-                                items_count: tree_entries_count,
-                            },
-                            tree_entries_count,
-                        );
+                        found_roots.push((block_ref, max_version, created_at_version));
                     },
+                    // legacy roots carry no version range, so their leaves are scanned for the watermark instead
+                    storage::NodeType::Leaf if !max_version_recorded =>
+                        for entry in deserializer.flatten() {
+                            legacy_max_version = cmp::max(legacy_max_version, Some(entry.value_cell.version));
+                        },
                     storage::NodeType::Leaf =>
                         (),
                 }
@@ -461,22 +1230,118 @@ where J: edeltraud::Job + From<job::Job>,
         }
     }
 
-    log::info!("loading done, {} search_trees restored within {} blocks", search_trees.len(), blocks_total);
-
-    busyloop(
-        child_supervisor_pid,
-        search_trees,
-        search_tree_refs,
-        search_tree_pools,
-        state,
+    // a record is written after the merged root, so its inputs are fully covered by a tree on disk
+    // (the merged one or whatever it has been merged into later), while their deprecated values are gone
+    let superseded_roots: HashSet<_> = merge_records.iter()
+        .flat_map(|(_record_ref, input_roots)| input_roots.iter().cloned())
+        .collect();
+    // versions obtained from now on must be above everything already on disk
+    for (_block_ref, max_version, _created_at_version) in &found_roots {
+        if let Some(max_version) = max_version {
+            state.version_watermark.observe(*max_version);
+        }
+    }
+    if let Some(max_version) = legacy_max_version {
+        state.version_watermark.observe(max_version);
+    }
+    for range_tombstone in &range_tombstones {
+        state.version_watermark.observe(range_tombstone.version);
+    }
+    for (block_ref, max_version, created_at_version) in found_roots.iter().cloned() {
+        if superseded_roots.contains(&block_ref) {
+            log::info!("skipping root {:?}: superseded by a merge interrupted before its inputs were demolished", block_ref);
+            continue;
+        }
+        let search_tree_gen_server = search_tree::GenServer::new();
+        let search_tree_pid = search_tree_gen_server.pid();
+        child_supervisor_pid.spawn_link_temporary(
+            search_tree_gen_server.run(
+                child_supervisor_pid.clone(),
+                state.thread_pool.clone(),
+                search_tree_pools.clone(),
+                state.wheels_pid.clone(),
+                state.params.search_tree_params.clone(),
+                search_tree::Mode::Regular { root_block: block_ref, },
+                search_tree_failures_tx.clone(),
+            ),
+        );
+        let search_tree_ref = search_trees.insert(search_tree_pid);
+        // an unknown max version is left out, so the tree counts as changed for any floor
+        if let Some(max_version) = max_version {
+            search_tree_max_versions.insert(search_tree_ref, max_version);
+        }
+        search_tree_created_versions.insert(search_tree_ref, created_at_version);
+        restored_search_trees.push(search_tree_ref);
+    }
+
+    // superseded roots left are freed by `gc_orphans`, a record is dropped once none of its inputs is found
+    let stale_merge_records: Vec<_> = merge_records.into_iter()
+        .filter(|(_record_ref, input_roots)| {
+            !input_roots.iter().any(|input_root| found_roots.iter().any(|(block_ref, ..)| block_ref == input_root))
+        })
+        .map(|(record_ref, _input_roots)| record_ref)
+        .collect();
+    if !stale_merge_records.is_empty() {
+        let task::delete_range_tombstones::Done { blocks_count, } = task::delete_range_tombstones::run(
+            task::delete_range_tombstones::Args {
+                inputs_demolished_rxs: Vec::new(),
+                block_refs: stale_merge_records,
+                wheels_pid: state.wheels_pid.clone(),
+            },
+        ).await
+            .map_err(Error::DeleteMergeRecords)
+            .map_err(ErrorSeverity::Fatal)?;
+        log::debug!("{} stale merge records deleted", blocks_count);
+    }
+
+    log::info!(
+        "loading done, {} search_trees and {} range tombstones restored within {} blocks",
+        search_trees.len(),
+        range_tombstones.len(),
+        blocks_total,
+    );
+
+    if !wal_records.is_empty() {
+        log::info!("replaying {} wal records into butcher", wal_records.len());
+        state.butcher_pid.replay_wal(wal_records).await
+            .map_err(|ero::NoProcError| ErrorSeverity::Fatal(Error::ButcherReplayWal))?;
+    }
+
+    let butcher_flush_generation = state.butcher_pid.flush_generation().await
+        .map_err(|ero::NoProcError| ErrorSeverity::Fatal(Error::ButcherFlushGeneration))?;
+
+    busyloop(
+        child_supervisor_pid,
+        search_trees,
+        search_tree_max_versions,
+        search_tree_created_versions,
+        search_tree_failures_tx,
+        search_tree_failures_rx,
+        search_tree_refs,
+        restored_search_trees,
+        search_tree_pools,
+        Arc::new(range_tombstones),
+        butcher_flush_generation,
+        state,
     ).await
 }
 
 async fn busyloop<J>(
     mut child_supervisor_pid: SupervisorPid,
     mut search_trees: Set<search_tree::Pid>,
-    mut search_tree_refs: bin_merger::BinMerger<SearchTreeRef>,
+    // trees missing here have unknown versions and are always queried
+    mut search_tree_max_versions: BTreeMap<Ref, u64>,
+    // versions the trees were built at, kept in their root headers
+    mut search_tree_created_versions: BTreeMap<Ref, u64>,
+    // trees stopped on a fatal error report here, to be quarantined
+    search_tree_failures_tx: mpsc::UnboundedSender<search_tree::Failure>,
+    search_tree_failures_rx: mpsc::UnboundedReceiver<search_tree::Failure>,
+    mut search_tree_refs: SearchTreeRefs,
+    // queued for merges once their size and keys range are known
+    restored_search_trees: Vec<Ref>,
     search_tree_pools: search_tree::Pools,
+    mut range_tombstones: RangeTombstones,
+    mut butcher_flush_generation: u64,
     mut state: State<J>,
 )
     -> Result<(), ErrorSeverity<State<J>, Error>>
@@ -487,14 +1352,49 @@ where J: edeltraud::Job + From<job::Job>,
     let merger_iters_pool = pool::Pool::new();
     let iter_items_pool = pool::Pool::new();
     let merge_blocks_pool = pool::Pool::new();
+    let block_budget = block_sizer::BlockBudget::new(
+        state.params.search_tree_params.tree_block_size,
+        state.params.search_tree_params.target_block_bytes,
+    );
+
+    let mut fused_search_tree_failures_rx = search_tree_failures_rx.fuse();
+    // lowest cell version of the trees built by this run, trees missing here may hold any older cells
+    let mut search_tree_min_versions: BTreeMap<Ref, u64> = BTreeMap::new();
+    // butcher versions are obtained in order, so once a flushed cache holds a cell newer than some version,
+    // every butcher cell older than that version is in a search tree
+    let mut butcher_flushed_max_version: Option<u64> = None;
+    // failed trees are kept out of lookups and merges until `verify` finds them intact
+    let mut quarantined_search_trees: Vec<QuarantinedTree> = Vec::new();
 
     let mut info_requests = Set::new();
+    // range scans running or about to start, told about trees adding cells they have not seen,
+    // a watcher is dropped on the first send after its scan is over
+    let mut lookup_range_watchers: Vec<mpsc::UnboundedSender<search_tree::Pid>> = Vec::new();
     let mut lookup_requests = Set::new();
+    let mut flushed_caches = FlushedCaches::default();
+    let mut compaction_subscribers: Vec<mpsc::Sender<CompactionEvent>> = Vec::new();
     let mut lookup_range_requests = Set::new();
     let mut flush_requests = Set::new();
+    let mut coalesced_flush = CoalescedFlush::default();
+    let mut deferred_requests = VecDeque::new();
+    // inserts and removes held while trees pending merges exceed `max_pending_trees`
+    let mut throttled_writes = VecDeque::new();
+    let mut butcher_flush_waiters: Vec<ButcherFlushWaiter> = Vec::new();
 
     let mut tasks = FuturesUnordered::new();
     let mut tasks_count = 0;
+    let mut no_reply_inserts_count = 0;
+    for search_tree_ref in restored_search_trees {
+        tasks.push(task::run_args(task::TaskArgs::TreeStats(
+            task::tree_stats::Args {
+                search_tree_ref,
+                search_tree_pid: search_trees.get(search_tree_ref).unwrap().clone(),
+            },
+        )));
+        tasks_count += 1;
+    }
+    // every range scan started ends with exactly one merge lookup range success or cancel
+    let mut active_range_scans = 0;
 
     let (bg_tasks_tx, bg_tasks_rx) = mpsc::channel(0);
     let mut fused_bg_tasks_rx = bg_tasks_rx.fuse();
@@ -511,24 +1411,45 @@ where J: edeltraud::Job + From<job::Job>,
     };
 
     let mut merge_search_trees_tasks_count = 0;
+    let mut pinned_lookup_ranges = PinnedLookupRanges::default();
 
     enum Mode {
         Regular,
-        Flushing { done_reply_tx: oneshot::Sender<Flushed>, },
+        Flushing { done_reply_txs: Vec<oneshot::Sender<Flushed>>, terminate: bool, flushed: Flushed, },
+        Collecting { done_reply_tx: Option<oneshot::Sender<OrphansCollected>>, collect_started: bool, },
     }
 
-    let mut current_mode = Mode::Regular;
+    // blocks of a merge interrupted by a cancellation or a crash are unreachable from any root,
+    // so collecting orphans before anything else frees them
+    let mut current_mode = if state.params.gc_orphans_on_start {
+        Mode::Collecting { done_reply_tx: None, collect_started: false, }
+    } else {
+        Mode::Regular
+    };
 
     loop {
         let maybe_task_args = maybe_merge_search_trees(
             &mut search_tree_refs,
             &search_trees,
+            &search_tree_min_versions,
+            &search_tree_max_versions,
+            !quarantined_search_trees.is_empty(),
+            merge_search_trees_tasks_count,
+            pinned_lookup_ranges.max_concurrent_merges(state.params.max_concurrent_merges),
             &state.thread_pool,
+            &state.background_gate,
             &state.blocks_pool,
             &merge_blocks_pool,
             &merger_iters_pool,
             &state.wheels_pid,
-            state.params.search_tree_params.tree_block_size,
+            block_budget,
+            state.params.merge_placement,
+            state.params.search_tree_params.block_compression,
+            state.params.search_tree_params.store_id,
+            state.version_provider.current(),
+            &state.params.metrics,
+            &state.params.search_tree_params.key_comparator,
+            &range_tombstones,
         );
         if let Some(task_args) = maybe_task_args {
             bg_tasks_push(task_args);
@@ -540,17 +1461,41 @@ where J: edeltraud::Job + From<job::Job>,
     }
 
     loop {
-        enum Event<R, F, T> {
+        enum Event<R, F, T, S> {
             Request(Option<R>),
             FlushCache(Option<F>),
             Task(T),
+            SearchTreeFailure(S),
         }
 
+        // writes are parked unacknowledged until running merges catch up: butcher cache flushes are still
+        // taken, so butcher keeps serving lookups while its cache stops growing
+        let writes_throttled = match state.params.max_pending_trees {
+            Some(max_pending_trees) =>
+                search_trees.len() > max_pending_trees && merge_search_trees_tasks_count > 0,
+            None =>
+                false,
+        };
+        // fire-and-forget inserts are not awaited by clients, so the request queue stops being read
+        // until some of them are applied, same for requests parked until the mode is left
+        let requests_paused = no_reply_inserts_count >= state.params.no_reply_inserts_limit
+            || deferred_requests.len() >= DEFERRED_REQUESTS_LIMIT;
+
         let event = match mem::replace(&mut current_mode, Mode::Regular) {
+            Mode::Regular if !deferred_requests.is_empty() =>
+                Event::Request(deferred_requests.pop_front()),
+            Mode::Regular if !writes_throttled && !throttled_writes.is_empty() =>
+                Event::Request(throttled_writes.pop_front()),
             Mode::Regular if tasks_count == 0 =>
                 select! {
-                    result = state.fused_request_rx.next() =>
+                    result = next_request(&mut state.fused_request_rx, requests_paused) =>
                         Event::Request(result),
+                    result = fused_search_tree_failures_rx.next() => match result {
+                        None =>
+                            unreachable!(),
+                        Some(failure) =>
+                            Event::SearchTreeFailure(failure),
+                    },
                     result = state.fused_flush_cache_rx.next() =>
                         Event::FlushCache(result),
                     result = fused_bg_tasks_rx.next() => match result {
@@ -564,8 +1509,14 @@ where J: edeltraud::Job + From<job::Job>,
                 },
             Mode::Regular =>
                 select! {
-                    result = state.fused_request_rx.next() =>
+                    result = next_request(&mut state.fused_request_rx, requests_paused) =>
                         Event::Request(result),
+                    result = fused_search_tree_failures_rx.next() => match result {
+                        None =>
+                            unreachable!(),
+                        Some(failure) =>
+                            Event::SearchTreeFailure(failure),
+                    },
                     result = state.fused_flush_cache_rx.next() =>
                         Event::FlushCache(result),
                     result = fused_bg_tasks_rx.next() => match result {
@@ -585,17 +1536,46 @@ where J: edeltraud::Job + From<job::Job>,
                         },
                     },
                 },
-            Mode::Flushing { done_reply_tx, } if tasks_count + bg_tasks_count == 0 => {
-                log::debug!("Mode::Flushing: all tasks finished, responding Flushed and switching mode");
-                if let Err(_send_error) = done_reply_tx.send(Flushed) {
-                    log::warn!("client canceled flush request");
+            Mode::Flushing { done_reply_txs, terminate: false, flushed, } if tasks_count + bg_tasks_count == 0 => {
+                log::debug!("Mode::Flushing: all tasks finished, responding {:?} to {} clients and switching mode", flushed, done_reply_txs.len());
+                for done_reply_tx in done_reply_txs {
+                    if let Err(_send_error) = done_reply_tx.send(flushed) {
+                        log::warn!("client canceled flush request");
+                    }
+                }
+                if !coalesced_flush.reply_txs.is_empty() {
+                    let CoalescedFlush { reply_txs, terminate, } = mem::take(&mut coalesced_flush);
+                    log::debug!("starting next flush for {} coalesced requests", reply_txs.len());
+                    tasks.push(task::run_args(flush_butcher_task_args(&mut flush_requests, &state.butcher_pid)));
+                    tasks_count += 1;
+                    current_mode = Mode::Flushing { done_reply_txs: reply_txs, terminate, flushed: Flushed::default(), };
                 }
                 continue;
             },
-            Mode::Flushing { done_reply_tx, } if tasks_count == 0 => {
+            Mode::Flushing { done_reply_txs, terminate: true, flushed, } if tasks_count + bg_tasks_count == 0 => {
+                log::info!("Mode::Flushing: all tasks finished, shutdown complete: terminating");
+                // nothing can be inserted after shutdown, so coalesced requests are satisfied as well
+                let reply_txs = done_reply_txs.into_iter()
+                    .chain(mem::take(&mut coalesced_flush.reply_txs));
+                for done_reply_tx in reply_txs {
+                    if let Err(_send_error) = done_reply_tx.send(flushed) {
+                        log::warn!("client canceled shutdown request");
+                    }
+                }
+                return Ok(());
+            },
+            Mode::Flushing { done_reply_txs, terminate, flushed, } if tasks_count == 0 => {
                 log::debug!("FlushMode::InProgress: {} tasks left", bg_tasks_count);
-                current_mode = Mode::Flushing { done_reply_tx, };
+                current_mode = Mode::Flushing { done_reply_txs, terminate, flushed, };
                 select! {
+                    result = next_request(&mut state.fused_request_rx, requests_paused) =>
+                        Event::Request(result),
+                    result = fused_search_tree_failures_rx.next() => match result {
+                        None =>
+                            unreachable!(),
+                        Some(failure) =>
+                            Event::SearchTreeFailure(failure),
+                    },
                     result = state.fused_flush_cache_rx.next() =>
                         Event::FlushCache(result),
                     result = fused_bg_tasks_rx.next() => match result {
@@ -608,10 +1588,18 @@ where J: edeltraud::Job + From<job::Job>,
                     },
                 }
             },
-            Mode::Flushing { done_reply_tx, } if bg_tasks_count == 0 => {
+            Mode::Flushing { done_reply_txs, terminate, flushed, } if bg_tasks_count == 0 => {
                 log::debug!("FlushMode::InProgress: {} tasks left", tasks_count);
-                current_mode = Mode::Flushing { done_reply_tx, };
+                current_mode = Mode::Flushing { done_reply_txs, terminate, flushed, };
                 select! {
+                    result = next_request(&mut state.fused_request_rx, requests_paused) =>
+                        Event::Request(result),
+                    result = fused_search_tree_failures_rx.next() => match result {
+                        None =>
+                            unreachable!(),
+                        Some(failure) =>
+                            Event::SearchTreeFailure(failure),
+                    },
                     result = state.fused_flush_cache_rx.next() =>
                         Event::FlushCache(result),
                     result = tasks.next() => match result {
@@ -624,10 +1612,98 @@ where J: edeltraud::Job + From<job::Job>,
                     },
                 }
             },
-            Mode::Flushing { done_reply_tx, } => {
+            Mode::Flushing { done_reply_txs, terminate, flushed, } => {
                 log::debug!("FlushMode::InProgress: {} tasks left", tasks_count + bg_tasks_count);
-                current_mode = Mode::Flushing { done_reply_tx, };
+                current_mode = Mode::Flushing { done_reply_txs, terminate, flushed, };
+                select! {
+                    result = next_request(&mut state.fused_request_rx, requests_paused) =>
+                        Event::Request(result),
+                    result = fused_search_tree_failures_rx.next() => match result {
+                        None =>
+                            unreachable!(),
+                        Some(failure) =>
+                            Event::SearchTreeFailure(failure),
+                    },
+                    result = state.fused_flush_cache_rx.next() =>
+                        Event::FlushCache(result),
+                    result = fused_bg_tasks_rx.next() => match result {
+                        None =>
+                            unreachable!(),
+                        Some(task) => {
+                            bg_tasks_count -= 1;
+                            Event::Task(task)
+                        },
+                    },
+                    result = tasks.next() => match result {
+                        None =>
+                            unreachable!(),
+                        Some(task) => {
+                            tasks_count -= 1;
+                            Event::Task(task)
+                        },
+                    },
+                }
+            },
+            Mode::Collecting { done_reply_tx, collect_started: false, } if tasks_count + bg_tasks_count == 0 => {
+                log::debug!("Mode::Collecting: all tasks finished, collecting orphans for {} search_trees", search_trees.len());
+                tasks.push(task::run_args(task::TaskArgs::GcOrphans(
+                    task::gc_orphans::Args {
+                        search_tree_pids: search_trees.iter()
+                            .map(|(_search_tree_ref, search_tree_pid)| search_tree_pid.clone())
+                            .collect(),
+                        quarantined_roots: quarantined_search_trees.iter()
+                            .flat_map(|quarantined| quarantined.root_block.clone())
+                            .collect(),
+                        wheels_pid: state.wheels_pid.clone(),
+                        blocks_pool: state.blocks_pool.clone(),
+                        store_id: state.params.search_tree_params.store_id,
+                    },
+                )));
+                tasks_count += 1;
+                current_mode = Mode::Collecting { done_reply_tx, collect_started: true, };
+                continue;
+            },
+            Mode::Collecting { done_reply_tx, collect_started: true, } => {
+                // no cache flushes are accepted here: a tree bootstrapped in the middle of collection
+                // would have its blocks treated as orphans
+                current_mode = Mode::Collecting { done_reply_tx, collect_started: true, };
+                select! {
+                    result = next_request(&mut state.fused_request_rx, requests_paused) =>
+                        Event::Request(result),
+                    result = tasks.next() => match result {
+                        None =>
+                            unreachable!(),
+                        Some(task) => {
+                            tasks_count -= 1;
+                            Event::Task(task)
+                        },
+                    },
+                }
+            },
+            Mode::Collecting { done_reply_tx, collect_started: false, } if tasks_count == 0 => {
+                log::debug!("Mode::Collecting: {} tasks left", bg_tasks_count);
+                current_mode = Mode::Collecting { done_reply_tx, collect_started: false, };
+                select! {
+                    result = next_request(&mut state.fused_request_rx, requests_paused) =>
+                        Event::Request(result),
+                    result = state.fused_flush_cache_rx.next() =>
+                        Event::FlushCache(result),
+                    result = fused_bg_tasks_rx.next() => match result {
+                        None =>
+                            unreachable!(),
+                        Some(task) => {
+                            bg_tasks_count -= 1;
+                            Event::Task(task)
+                        },
+                    },
+                }
+            },
+            Mode::Collecting { done_reply_tx, collect_started: false, } => {
+                log::debug!("Mode::Collecting: {} tasks left", tasks_count + bg_tasks_count);
+                current_mode = Mode::Collecting { done_reply_tx, collect_started: false, };
                 select! {
+                    result = next_request(&mut state.fused_request_rx, requests_paused) =>
+                        Event::Request(result),
                     result = state.fused_flush_cache_rx.next() =>
                         Event::FlushCache(result),
                     result = fused_bg_tasks_rx.next() => match result {
@@ -650,14 +1726,94 @@ where J: edeltraud::Job + From<job::Job>,
             },
         };
 
+        let event_span = match &event {
+            Event::Request(Some(request)) =>
+                trace::request_span(request.kind()),
+            _ =>
+                trace::Span::current(),
+        };
+        let _event_span_entered = event_span.enter();
+
         match event {
+            // only flush requests are taken while flushing, the rest wait until it is finished
+            Event::Request(Some(request)) if matches!(current_mode, Mode::Flushing { .. }) =>
+                match request {
+                    Request::FlushAll(RequestFlush { reply_tx, }) =>
+                        coalesced_flush.reply_txs.push(reply_tx),
+                    Request::Shutdown(RequestFlush { reply_tx, }) => {
+                        coalesced_flush.reply_txs.push(reply_tx);
+                        coalesced_flush.terminate = true;
+                    },
+                    request =>
+                        deferred_requests.push_back(request),
+                },
+
+            Event::Request(Some(request)) if writes_throttled && matches!(current_mode, Mode::Regular) && matches!(
+                request,
+                Request::Insert(..) | Request::InsertNoReply(..) | Request::Remove(..) | Request::RemoveMany(..) | Request::RemoveReturning(..)
+            ) =>
+                throttled_writes.push_back(request),
+
+            // reads, butcher writes and range tombstones are served while collecting, requests writing or
+            // demolishing trees (or leaving the mode) wait until collection is done
+            Event::Request(Some(request)) if matches!(current_mode, Mode::Collecting { .. }) && matches!(
+                request,
+                Request::BulkLoad(..) | Request::FlushAll(..) | Request::Shutdown(..) | Request::GcOrphans(..) | Request::Verify(..)
+            ) =>
+                deferred_requests.push_back(request),
+
+            Event::SearchTreeFailure(search_tree::Failure { search_tree_pid, root_block, error, }) => {
+                let maybe_search_tree_ref = search_trees.iter()
+                    .find(|(_search_tree_ref, pid)| pid.same_tree(&search_tree_pid))
+                    .map(|(search_tree_ref, _pid)| search_tree_ref);
+                let search_tree_ref = match maybe_search_tree_ref {
+                    Some(search_tree_ref) =>
+                        search_tree_ref,
+                    None => {
+                        // merged or demolished trees are not addressed anyway
+                        log::warn!("retired search tree failed: {}", error);
+                        continue;
+                    },
+                };
+                log::error!("search tree {:?} failed, quarantined: {}", root_block, error);
+                state.last_error = Some(format!("search tree quarantined: {}", error));
+                search_trees.remove(search_tree_ref);
+                let max_version = search_tree_max_versions.remove(&search_tree_ref);
+                search_tree_min_versions.remove(&search_tree_ref);
+                let created_at_version = search_tree_created_versions.remove(&search_tree_ref).unwrap_or(0);
+                // a tree taking part in a running merge is not queued for merges anymore
+                let maybe_queued = search_tree_refs.remove(search_tree_ref);
+                quarantined_search_trees.push(QuarantinedTree {
+                    root_block,
+                    created_at_version,
+                    max_version,
+                    maybe_queued,
+                    error,
+                });
+            },
+
             Event::FlushCache(None) => {
                 log::info!("butcher channel depleted: terminating");
                 return Ok(());
             },
 
-            Event::FlushCache(Some(ButcherFlush { cache, })) => {
+            Event::FlushCache(Some(ButcherFlush { cache, wal_block_refs, flush_generation, })) => {
+                butcher_flush_generation = flush_generation;
                 let items_count = cache.len();
+                state.params.metrics.on_flush(items_count);
+                let bootstrap_done_tx = if wal_block_refs.is_empty() {
+                    None
+                } else {
+                    let (bootstrap_done_tx, bootstrap_done_rx) = oneshot::channel();
+                    bg_tasks_push(task::TaskArgs::TruncateWal(task::truncate_wal::Args {
+                        bootstrap_done_rx,
+                        wal_block_refs,
+                        wheels_pid: state.wheels_pid.clone(),
+                    }));
+                    bg_tasks_count += 1;
+                    Some(bootstrap_done_tx)
+                };
+                let created_at_version = state.version_provider.current();
                 let search_tree_gen_server = search_tree::GenServer::new();
                 let search_tree_pid = search_tree_gen_server.pid();
                 child_supervisor_pid.spawn_link_temporary(
@@ -667,20 +1823,53 @@ where J: edeltraud::Job + From<job::Job>,
                         search_tree_pools.clone(),
                         state.wheels_pid.clone(),
                         state.params.search_tree_params.clone(),
-                        search_tree::Mode::CacheBootstrap { cache: cache.clone(), },
+                        search_tree::Mode::CacheBootstrap { cache: cache.clone(), created_at_version, bootstrap_done_tx, },
+                        search_tree_failures_tx.clone(),
                     ),
                 );
+                lookup_range_watchers.retain(|late_trees_tx| late_trees_tx.unbounded_send(search_tree_pid.clone()).is_ok());
                 let search_tree_ref = search_trees.insert(search_tree_pid.clone());
-                search_tree_refs.push(SearchTreeRef { search_tree_ref, items_count, }, items_count);
+                search_tree_created_versions.insert(search_tree_ref, created_at_version);
+                if let Some(max_version) = cache.max_version() {
+                    search_tree_max_versions.insert(search_tree_ref, max_version);
+                }
+                if let Some(min_version) = cache.min_version() {
+                    search_tree_min_versions.insert(search_tree_ref, min_version);
+                }
+                butcher_flushed_max_version = cmp::max(butcher_flushed_max_version, cache.max_version());
+                let (cache_bytes, cache_key_range) = cache_bytes_and_key_range(&cache);
+                search_tree_refs.push(SearchTreeRef { search_tree_ref, items_count, }, cache_bytes, cache_key_range);
+                let (ready_waiters, pending_waiters): (Vec<_>, Vec<_>) = mem::take(&mut butcher_flush_waiters)
+                    .into_iter()
+                    .partition(|waiter| waiter.flush_generation <= flush_generation);
+                butcher_flush_waiters = pending_waiters;
+                for ButcherFlushWaiter { flushed, reply_tx, .. } in ready_waiters {
+                    if let Err(_send_error) = reply_tx.send(flushed) {
+                        log::warn!("client canceled flush butcher only request");
+                    }
+                }
                 let maybe_task_args = maybe_merge_search_trees(
                     &mut search_tree_refs,
                     &search_trees,
+                    &search_tree_min_versions,
+                    &search_tree_max_versions,
+                    !quarantined_search_trees.is_empty(),
+                    merge_search_trees_tasks_count,
+                    pinned_lookup_ranges.max_concurrent_merges(state.params.max_concurrent_merges),
                     &state.thread_pool,
+                    &state.background_gate,
                     &state.blocks_pool,
                     &merge_blocks_pool,
                     &merger_iters_pool,
                     &state.wheels_pid,
-                    state.params.search_tree_params.tree_block_size,
+                    block_budget,
+                    state.params.merge_placement,
+                    state.params.search_tree_params.block_compression,
+                    state.params.search_tree_params.store_id,
+                    state.version_provider.current(),
+                    &state.params.metrics,
+                    &state.params.search_tree_params.key_comparator,
+                    &range_tombstones,
                 );
                 if let Some(task_args) = maybe_task_args {
                     bg_tasks_push(task_args);
@@ -688,40 +1877,56 @@ where J: edeltraud::Job + From<job::Job>,
                     merge_search_trees_tasks_count += 1;
                 }
 
-                let mut invalidated_count = 0;
+                flushed_caches.flushed(flush_generation, cache.clone());
 
-                // maybe invalidate on-fly butcher requests
-                for (request_ref, LookupRequest { key, butcher_status, pending_count, .. }) in lookup_requests.iter_mut() {
-                    if let LookupRequestButcherStatus::NotReady = butcher_status {
-                        log::debug!("lookup request for {:?} invalidated due to cache flush", key);
-                        *butcher_status = LookupRequestButcherStatus::Invalidated;
-                        tasks.push(task::run_args::<J>(task::TaskArgs::LookupSearchTree(
-                            task::lookup_search_tree::Args {
-                                key: key.clone(),
-                                request_ref: request_ref.clone(),
-                                search_tree_pid: search_tree_pid.clone(),
-                            },
-                        )));
+                // complete butcher lookups which have been answered from a newer cache
+                let mut lookups_done = Vec::new();
+                for (request_ref, lookup_request) in lookup_requests.iter_mut() {
+                    if let LookupRequestButcherStatus::AwaitingFlush { flush_generation: awaiting_generation, } = lookup_request.butcher_status {
+                        let found = cache.lookup(&lookup_request.key).cloned().map(Into::into);
+                        if replace_fold_found(&lookup_request.found_fold, &found) {
+                            lookup_request.found_fold = found;
+                        }
+                        if flush_generation >= awaiting_generation {
+                            lookup_request.butcher_status = LookupRequestButcherStatus::Done;
+                            assert!(lookup_request.pending_count > 0);
+                            lookup_request.pending_count -= 1;
+                            if lookup_request.pending_count == 0 {
+                                lookups_done.push(request_ref.clone());
+                            }
+                        }
+                    }
+                }
+                for request_ref in lookups_done {
+                    let lookup_request = lookup_requests.remove(request_ref).unwrap();
+                    if let Some(task_args) = finish_lookup_request(lookup_request, &mut flushed_caches, &range_tombstones, &state) {
+                        tasks.push(task::run_args(task_args));
                         tasks_count += 1;
-                        *pending_count += 1;
-                        invalidated_count += 1;
                     }
                 }
 
                 log::info!(
-                    "cache flushed: {} invalidated, currently {} in action, {} merging",
-                    invalidated_count,
+                    "cache flushed: generation {}, currently {} in action, {} merging",
+                    flush_generation,
                     search_trees.len(),
                     merge_search_trees_tasks_count,
                 );
             },
 
-            Event::Request(None) => {
-                log::info!("requests sink channel depleted: terminating");
-                return Ok(());
-            },
+            Event::Request(None) =>
+                match mem::replace(&mut current_mode, Mode::Regular) {
+                    // inserts acknowledged before the running flush are persisted anyway
+                    Mode::Flushing { done_reply_txs, flushed, .. } => {
+                        log::info!("requests sink channel depleted: terminating after the running flush");
+                        current_mode = Mode::Flushing { done_reply_txs, terminate: true, flushed, };
+                    },
+                    _ => {
+                        log::info!("requests sink channel depleted: terminating");
+                        return Ok(());
+                    },
+                },
 
-            Event::Request(Some(Request::Info(RequestInfo { reply_tx, }))) => {
+            Event::Request(Some(Request::Info(RequestInfo { range, reply_tx, }))) => {
                 let request_ref = info_requests.insert(InfoRequest {
                     reply_tx,
                     pending_count: 1 + search_trees.len(),
@@ -730,6 +1935,7 @@ where J: edeltraud::Job + From<job::Job>,
                 tasks.push(task::run_args(task::TaskArgs::InfoButcher(
                     task::info_butcher::Args {
                         request_ref: request_ref.clone(),
+                        range: range.clone(),
                         butcher_pid: state.butcher_pid.clone(),
                     },
                 )));
@@ -738,6 +1944,7 @@ where J: edeltraud::Job + From<job::Job>,
                     tasks.push(task::run_args(task::TaskArgs::InfoSearchTree(
                         task::info_search_tree::Args {
                             request_ref: request_ref.clone(),
+                            range: range.clone(),
                             search_tree_pid: search_tree_pid.clone(),
                         },
                     )));
@@ -745,22 +1952,66 @@ where J: edeltraud::Job + From<job::Job>,
                 }
             },
 
-            Event::Request(Some(Request::Insert(request))) => {
+            Event::Request(Some(Request::Insert(RequestInsert { key, value, version, storage_preference, reply_tx, }))) => {
+                state.params.metrics.on_insert();
                 tasks.push(task::run_args(task::TaskArgs::InsertButcher(
                     task::insert_butcher::Args {
-                        request,
+                        key,
+                        value,
+                        version,
+                        storage_preference,
+                        reply_tx: Some(reply_tx),
+                        butcher_pid: state.butcher_pid.clone(),
+                    },
+                )));
+                tasks_count += 1;
+            },
+
+            Event::Request(Some(Request::InsertNoReply(RequestInsertNoReply { key, value, }))) => {
+                state.params.metrics.on_insert();
+                tasks.push(task::run_args(task::TaskArgs::InsertButcher(
+                    task::insert_butcher::Args {
+                        key,
+                        value,
+                        version: None,
+                        storage_preference: StoragePreference::Auto,
+                        reply_tx: None,
                         butcher_pid: state.butcher_pid.clone(),
                     },
                 )));
                 tasks_count += 1;
+                no_reply_inserts_count += 1;
             },
 
-            Event::Request(Some(Request::Lookup(RequestLookup { key, reply_tx, }))) =>
+            Event::Request(Some(Request::Lookup(RequestLookup { key, consistency, reply_tx, }))) =>
                 launch_lookup_request(
                     key,
-                    reply_tx,
+                    consistency,
+                    LookupReplyTx::Value(reply_tx),
+                    Instant::now(),
+                    butcher_flush_generation,
+                    &mut lookup_requests,
+                    &mut flushed_caches,
+                    &search_trees,
+                    state.params.lookup_fanout_limit,
+                    &state.butcher_pid,
+                    |args| {
+                        tasks.push(task::run_args(args));
+                        tasks_count += 1;
+                    },
+                ),
+
+            Event::Request(Some(Request::LookupLocated(RequestLookupLocated { key, reply_tx, }))) =>
+                launch_lookup_request(
+                    key,
+                    Consistency::Strong,
+                    LookupReplyTx::Located(reply_tx),
+                    Instant::now(),
+                    butcher_flush_generation,
                     &mut lookup_requests,
+                    &mut flushed_caches,
                     &search_trees,
+                    state.params.lookup_fanout_limit,
                     &state.butcher_pid,
                     |args| {
                         tasks.push(task::run_args(args));
@@ -768,22 +2019,79 @@ where J: edeltraud::Job + From<job::Job>,
                     },
                 ),
 
-            Event::Request(Some(Request::LookupRange(RequestLookupRange { range, reply_tx, }))) => {
+            Event::Request(Some(Request::LookupRange(RequestLookupRange { range, buffer, key_filter, version_window, reply_tx, }))) => {
                 let (key_values_tx, key_values_rx) =
-                    mpsc::channel(state.params.search_tree_params.iter_send_buffer);
-                let lookup_range = LookupRange { key_values_rx, };
+                    mpsc::channel(buffer.unwrap_or(state.params.search_tree_params.iter_send_buffer));
+                let (cancel_tx, cancel_rx) = oneshot::channel();
+                let lookup_range = LookupRange { key_values_rx, cancel_tx, };
                 if let Err(_send_error) = reply_tx.send(lookup_range) {
                     log::warn!("client canceled lookup_range request");
                 }
                 tasks.push(task::run_args(task::TaskArgs::LookupRangeButcher(
                     task::lookup_range_butcher::Args {
                         range,
-                        key_values_tx,
+                        key_values_tx: KeyValuesTx::Values(key_values_tx),
+                        key_filter,
+                        version_window,
+                        cancel_rx,
+                        retries: 0,
+                        last_key: None,
+                        iter_items_pool: iter_items_pool.clone(),
+                        butcher_pid: state.butcher_pid.clone(),
+                    },
+                )));
+                tasks_count += 1;
+                active_range_scans += 1;
+            },
+
+            Event::Request(Some(Request::LookupRangeRefs(RequestLookupRangeRefs { range, buffer, reply_tx, }))) => {
+                let (key_values_tx, key_values_rx) =
+                    mpsc::channel(buffer.unwrap_or(state.params.search_tree_params.iter_send_buffer));
+                let (cancel_tx, cancel_rx) = oneshot::channel();
+                let lookup_range_refs = LookupRangeRefs { key_values_rx, cancel_tx, };
+                if let Err(_send_error) = reply_tx.send(lookup_range_refs) {
+                    log::warn!("client canceled lookup_range_refs request");
+                }
+                tasks.push(task::run_args(task::TaskArgs::LookupRangeButcher(
+                    task::lookup_range_butcher::Args {
+                        range,
+                        key_values_tx: KeyValuesTx::Refs(key_values_tx),
+                        key_filter: None,
+                        version_window: None,
+                        cancel_rx,
+                        retries: 0,
+                        last_key: None,
                         iter_items_pool: iter_items_pool.clone(),
                         butcher_pid: state.butcher_pid.clone(),
                     },
                 )));
                 tasks_count += 1;
+                active_range_scans += 1;
+            },
+
+            Event::Request(Some(Request::LookupRangeKeys(RequestLookupRangeKeys { range, buffer, reply_tx, }))) => {
+                let (keys_tx, keys_rx) =
+                    mpsc::channel(buffer.unwrap_or(state.params.search_tree_params.iter_send_buffer));
+                let (cancel_tx, cancel_rx) = oneshot::channel();
+                let lookup_range_keys = LookupRangeKeys { keys_rx, cancel_tx, };
+                if let Err(_send_error) = reply_tx.send(lookup_range_keys) {
+                    log::warn!("client canceled lookup_range_keys_only request");
+                }
+                tasks.push(task::run_args(task::TaskArgs::LookupRangeButcher(
+                    task::lookup_range_butcher::Args {
+                        range,
+                        key_values_tx: KeyValuesTx::Keys(keys_tx),
+                        key_filter: None,
+                        version_window: None,
+                        cancel_rx,
+                        retries: 0,
+                        last_key: None,
+                        iter_items_pool: iter_items_pool.clone(),
+                        butcher_pid: state.butcher_pid.clone(),
+                    },
+                )));
+                tasks_count += 1;
+                active_range_scans += 1;
             },
 
             Event::Request(Some(Request::Remove(request))) => {
@@ -796,73 +2104,386 @@ where J: edeltraud::Job + From<job::Job>,
                 tasks_count += 1;
             },
 
-            Event::Request(Some(Request::FlushAll(RequestFlush { reply_tx, }))) => {
-                log::debug!("Request::FlushAll for butcher first");
-
-                let request_ref = flush_requests.insert(FlushRequest {
-                    butcher_done: false,
-                    search_trees_pending_count: 0,
-                });
-                tasks.push(task::run_args(task::TaskArgs::FlushButcher(
-                    task::flush_butcher::Args {
-                        request_ref,
+            Event::Request(Some(Request::RemoveMany(request))) => {
+                tasks.push(task::run_args(task::TaskArgs::RemoveManyButcher(
+                    task::remove_many_butcher::Args {
+                        request,
                         butcher_pid: state.butcher_pid.clone(),
                     },
                 )));
                 tasks_count += 1;
-                current_mode = Mode::Flushing { done_reply_tx: reply_tx, };
             },
 
-            Event::Task(Ok(task::TaskDone::InfoButcher(task::info_butcher::Done { request_ref, info, }))) |
-            Event::Task(Ok(task::TaskDone::InfoSearchTree(task::info_search_tree::Done { request_ref, info, }))) => {
-                let info_request = info_requests.get_mut(request_ref).unwrap();
-                assert!(info_request.pending_count > 0);
-                info_request.pending_count -= 1;
-                info_request.info_fold += info;
-                if info_request.pending_count == 0 {
-                    let info_request = info_requests.remove(request_ref).unwrap();
-                    let info = info_request.info_fold;
-                    if let Err(_send_error) = info_request.reply_tx.send(info) {
-                        log::warn!("client canceled info request");
-                    }
-                }
+            Event::Request(Some(Request::RemoveReturning(RequestRemoveReturning { key, reply_tx, }))) =>
+                launch_remove_returning_request(
+                    key,
+                    reply_tx,
+                    butcher_flush_generation,
+                    &mut lookup_requests,
+                    &mut flushed_caches,
+                    &search_trees,
+                    state.params.lookup_fanout_limit,
+                    &state.butcher_pid,
+                    |args| {
+                        tasks.push(task::run_args(args));
+                        tasks_count += 1;
+                    },
+                ),
+
+            Event::Request(Some(Request::RemoveRange(RequestRemoveRange { range, reply_tx, }))) => {
+                let version = match obtain_version(&state) {
+                    Ok(version) =>
+                        version,
+                    Err(version::Regression { version, last_version, }) => {
+                        if let Err(_send_error) = reply_tx.send(Err(RemoveError::VersionRegression { version, last_version, })) {
+                            log::warn!("client canceled remove range request");
+                        }
+                        continue;
+                    },
+                };
+                tasks.push(task::run_args(task::TaskArgs::WriteRangeTombstone(
+                    task::write_range_tombstone::Args {
+                        range,
+                        version,
+                        reply_tx,
+                        store_id: state.params.search_tree_params.store_id,
+                        blocks_pool: state.blocks_pool.clone(),
+                        wheels_pid: state.wheels_pid.clone(),
+                    },
+                )));
+                tasks_count += 1;
             },
 
-            Event::Task(Ok(task::TaskDone::InsertButcher(task::insert_butcher::Done))) =>
-                (),
+            // cells below the reserved version are already in butcher when the scan starts, while later writes
+            // get higher versions and are neither moved nor shadowed (externally versioned writes are not covered)
+            Event::Request(Some(Request::SplitOff(RequestSplitOff { range, reply_tx, }))) => {
+                let version = match obtain_version(&state) {
+                    Ok(version) =>
+                        version,
+                    Err(version::Regression { version, last_version, }) => {
+                        if let Err(_send_error) = reply_tx.send(Err(SplitOffError::VersionRegression { version, last_version, })) {
+                            log::warn!("client canceled split off request");
+                        }
+                        continue;
+                    },
+                };
+                let (key_values_tx, key_values_rx) = mpsc::channel(state.params.search_tree_params.iter_send_buffer);
+                let (cancel_tx, cancel_rx) = oneshot::channel();
+                tasks.push(task::run_args(task::TaskArgs::LookupRangeButcher(
+                    task::lookup_range_butcher::Args {
+                        range: range.clone(),
+                        key_values_tx: KeyValuesTx::Values(key_values_tx),
+                        key_filter: None,
+                        version_window: None,
+                        cancel_rx,
+                        retries: 0,
+                        last_key: None,
+                        iter_items_pool: iter_items_pool.clone(),
+                        butcher_pid: state.butcher_pid.clone(),
+                    },
+                )));
+                active_range_scans += 1;
+                tasks.push(task::run_args(task::TaskArgs::SplitOff(
+                    task::split_off::Args {
+                        range,
+                        version,
+                        key_values_rx,
+                        cancel_tx,
+                        reply_tx,
+                        store_id: state.params.search_tree_params.store_id,
+                        blocks_pool: state.blocks_pool.clone(),
+                        wheels_pid: state.wheels_pid.clone(),
+                    },
+                )));
+                tasks_count += 2;
+            },
 
-            Event::Task(Ok(task::TaskDone::LookupButcher(task::lookup_butcher::Done { request_ref, found, }))) => {
-                let lookup_request = lookup_requests.get_mut(request_ref).unwrap();
-                assert!(lookup_request.pending_count > 0);
-                lookup_request.pending_count -= 1;
-                match lookup_request.butcher_status {
-                    LookupRequestButcherStatus::NotReady => {
-                        lookup_request.butcher_status = LookupRequestButcherStatus::Done;
-                        if replace_fold_found(&lookup_request.found_fold, &found) {
-                            lookup_request.found_fold = found;
+            Event::Request(Some(Request::BulkLoad(RequestBulkLoad { prev_key, items, reply_tx, }))) => {
+                let version = match obtain_version(&state) {
+                    Ok(version) =>
+                        version,
+                    Err(version::Regression { version, last_version, }) => {
+                        if let Err(_send_error) = reply_tx.send(Err(BulkLoadError::VersionRegression { version, last_version, })) {
+                            log::warn!("client canceled bulk load request");
                         }
+                        continue;
                     },
-                    LookupRequestButcherStatus::Done =>
-                        unreachable!(),
-                    LookupRequestButcherStatus::Invalidated =>
-                        log::debug!("invalidating butcher lookup reply"),
-                }
-                if lookup_request.pending_count == 0 {
-                    let lookup_request = lookup_requests.remove(request_ref).unwrap();
-                    tasks.push(task::run_args(task::TaskArgs::RetrieveValue(
-                        task::retrieve_value::Args {
-                            key: lookup_request.key,
-                            found_fold: lookup_request.found_fold,
-                            reply_tx: lookup_request.reply_tx,
+                };
+                if items.is_empty() {
+                    if let Err(_send_error) = reply_tx.send(Ok(Inserted { version, })) {
+                        log::warn!("client canceled bulk load request");
+                    }
+                } else {
+                    bg_tasks_push(task::TaskArgs::BulkLoad(
+                        task::bulk_load::Args {
+                            prev_key,
+                            items,
+                            version,
+                            reply_tx,
+                            thread_pool: state.thread_pool.clone(),
+                            background_gate: state.background_gate.clone(),
+                            blocks_pool: state.blocks_pool.clone(),
+                            merge_blocks_pool: merge_blocks_pool.clone(),
                             wheels_pid: state.wheels_pid.clone(),
+                            block_budget,
+                            values_inline_size_limit: state.params.search_tree_params.values_inline_size_limit,
+                            block_compression: state.params.search_tree_params.block_compression,
+                            store_id: state.params.search_tree_params.store_id,
+                            key_comparator: state.params.search_tree_params.key_comparator.clone(),
+                            metrics: state.params.metrics.clone(),
                         },
-                    )));
-                    tasks_count += 1;
+                    ));
+                    bg_tasks_count += 1;
                 }
             },
 
-            Event::Task(Ok(task::TaskDone::LookupSearchTree(task::lookup_search_tree::Done { request_ref, found, }))) => {
-                let lookup_request = lookup_requests.get_mut(request_ref).unwrap();
+            Event::Request(Some(request @ Request::FlushAll(..))) |
+            Event::Request(Some(request @ Request::Shutdown(..))) => {
+                let (reply_tx, terminate) = match request {
+                    Request::FlushAll(RequestFlush { reply_tx, }) => {
+                        log::debug!("Request::FlushAll for butcher first");
+                        (reply_tx, false)
+                    },
+                    Request::Shutdown(RequestFlush { reply_tx, }) => {
+                        log::info!("Request::Shutdown: flushing butcher and waiting for all tasks before terminating");
+                        (reply_tx, true)
+                    },
+                    _ =>
+                        unreachable!(),
+                };
+
+                tasks.push(task::run_args(flush_butcher_task_args(&mut flush_requests, &state.butcher_pid)));
+                tasks_count += 1;
+                current_mode = Mode::Flushing { done_reply_txs: vec![reply_tx], terminate, flushed: Flushed::default(), };
+            },
+
+            Event::Request(Some(Request::FlushButcherOnly(RequestFlush { reply_tx, }))) => {
+                tasks.push(task::run_args(task::TaskArgs::FlushButcherOnly(
+                    task::flush_butcher_only::Args {
+                        reply_tx,
+                        butcher_pid: state.butcher_pid.clone(),
+                    },
+                )));
+                tasks_count += 1;
+            },
+
+            Event::Request(Some(Request::GcOrphans(RequestGcOrphans { reply_tx, }))) => {
+                log::info!("Request::GcOrphans: waiting for all tasks before collecting orphaned blocks");
+                current_mode = Mode::Collecting { done_reply_tx: Some(reply_tx), collect_started: false, };
+            },
+
+            Event::Request(Some(Request::ChangedBlocks(RequestChangedBlocks { version_floor, reply_tx, }))) => {
+                // trees without a known max version always count as changed
+                tasks.push(task::run_args(task::TaskArgs::ChangedBlocks(
+                    task::changed_blocks::Args {
+                        search_tree_pids: search_trees.iter()
+                            .filter(|(search_tree_ref, _search_tree_pid)| {
+                                search_tree_max_versions.get(search_tree_ref)
+                                    .map_or(true, |&max_version| max_version > version_floor)
+                            })
+                            .map(|(_search_tree_ref, search_tree_pid)| search_tree_pid.clone())
+                            .collect(),
+                        reply_tx,
+                    },
+                )));
+                tasks_count += 1;
+            },
+
+            Event::Request(Some(Request::IterRawBlocks(RequestIterRawBlocks { reply_tx, }))) => {
+                let (blocks_tx, blocks_rx) = mpsc::channel(state.params.search_tree_params.iter_send_buffer);
+                if let Err(_send_error) = reply_tx.send(RawBlocks { blocks_rx, }) {
+                    log::warn!("client canceled iter raw blocks request");
+                }
+                tasks.push(task::run_args(task::TaskArgs::IterRawBlocks(
+                    task::iter_raw_blocks::Args {
+                        blocks_tx,
+                        wheels_pid: state.wheels_pid.clone(),
+                        thread_pool: state.thread_pool.clone(),
+                        background_gate: state.background_gate.clone(),
+                        blocks_pool: state.blocks_pool.clone(),
+                        key_comparator: state.params.search_tree_params.key_comparator.clone(),
+                    },
+                )));
+                tasks_count += 1;
+            },
+
+            Event::Request(Some(Request::Verify(RequestVerify { reply_tx, }))) => {
+                tasks.push(task::run_args(task::TaskArgs::Verify(
+                    task::verify::Args {
+                        reply_tx,
+                        wheels_pid: state.wheels_pid.clone(),
+                        thread_pool: state.thread_pool.clone(),
+                        background_gate: state.background_gate.clone(),
+                        blocks_pool: state.blocks_pool.clone(),
+                        key_comparator: state.params.search_tree_params.key_comparator.clone(),
+                        store_id: state.params.search_tree_params.store_id,
+                    },
+                )));
+                tasks_count += 1;
+            },
+
+            Event::Request(Some(Request::SizeHistograms(RequestSizeHistograms { reply_tx, }))) => {
+                tasks.push(task::run_args(task::TaskArgs::SizeHistograms(
+                    task::size_histograms::Args {
+                        reply_tx,
+                        butcher_pid: state.butcher_pid.clone(),
+                        search_tree_pids: search_trees.iter()
+                            .map(|(_search_tree_ref, search_tree_pid)| search_tree_pid.clone())
+                            .collect(),
+                        iter_items_pool: iter_items_pool.clone(),
+                    },
+                )));
+                tasks_count += 1;
+            },
+
+            Event::Request(Some(Request::EstimateCount(RequestEstimateCount { range, reply_tx, }))) => {
+                tasks.push(task::run_args(task::TaskArgs::EstimateCount(
+                    task::estimate_count::Args {
+                        reply_tx,
+                        range,
+                        butcher_pid: state.butcher_pid.clone(),
+                        search_tree_pids: search_trees.iter()
+                            .map(|(_search_tree_ref, search_tree_pid)| search_tree_pid.clone())
+                            .collect(),
+                    },
+                )));
+                tasks_count += 1;
+            },
+
+            Event::Request(Some(Request::WarmRange(RequestWarmRange { range, reply_tx, }))) => {
+                tasks.push(task::run_args(task::TaskArgs::WarmRange(
+                    task::warm_range::Args {
+                        reply_tx,
+                        range,
+                        search_tree_pids: search_trees.iter()
+                            .map(|(_search_tree_ref, search_tree_pid)| search_tree_pid.clone())
+                            .collect(),
+                        bytes_limit: state.params.warm_range_bytes_limit,
+                    },
+                )));
+                tasks_count += 1;
+            },
+
+            Event::Request(Some(Request::Health(RequestHealth { reply_tx, }))) => {
+                tasks.push(task::run_args(task::TaskArgs::Health(
+                    task::health::Args {
+                        reply_tx,
+                        search_trees_count: search_trees.len(),
+                        quarantined_search_trees: quarantined_search_trees.len(),
+                        butcher_pid: state.butcher_pid.clone(),
+                        search_tree_pid: search_trees.iter()
+                            .map(|(_search_tree_ref, search_tree_pid)| search_tree_pid.clone())
+                            .next(),
+                    },
+                )));
+                tasks_count += 1;
+            },
+
+            Event::Request(Some(Request::SubscribeCompactions(RequestSubscribeCompactions { reply_tx, }))) => {
+                let (events_tx, events_rx) = mpsc::channel(COMPACTION_EVENTS_BUFFER);
+                if let Err(_send_error) = reply_tx.send(events_rx) {
+                    log::warn!("client canceled subscribe compactions request");
+                } else {
+                    compaction_subscribers.push(events_tx);
+                }
+            },
+
+            // butcher obtains versions from the same provider, so no round trip to it is needed
+            Event::Request(Some(Request::CurrentVersion(RequestCurrentVersion { reply_tx, }))) =>
+                if let Err(_send_error) = reply_tx.send(state.version_provider.current()) {
+                    log::warn!("client canceled current version request");
+                },
+
+            Event::Request(Some(Request::DetailedInfo(RequestDetailedInfo { reply_tx, }))) => {
+                let current_version = state.version_provider.current();
+                let mut search_trees_details: Vec<_> = search_tree_created_versions.iter()
+                    .filter_map(|(&search_tree_ref, &created_at_version)| {
+                        let search_tree_pid = search_trees.get(search_tree_ref)?.clone();
+                        let details = SearchTreeDetails {
+                            id: SearchTreeId(search_tree_ref),
+                            created_at_version,
+                            age: current_version.saturating_sub(created_at_version),
+                            max_version: search_tree_max_versions.get(&search_tree_ref).copied(),
+                            // measured by the task
+                            disk_bytes: 0,
+                        };
+                        Some((details, search_tree_pid))
+                    })
+                    .collect();
+                search_trees_details.sort_by_key(|(details, _search_tree_pid)| details.created_at_version);
+                let (search_trees_details, search_tree_pids) = search_trees_details.into_iter().unzip();
+                tasks.push(task::run_args(task::TaskArgs::DetailedInfo(
+                    task::detailed_info::Args {
+                        reply_tx,
+                        detailed_info: DetailedInfo {
+                            current_version,
+                            search_trees: search_trees_details,
+                            quarantined_search_trees: quarantined_search_trees.iter()
+                                .map(|quarantined| QuarantinedSearchTree {
+                                    root_block: quarantined.root_block.clone(),
+                                    created_at_version: quarantined.created_at_version,
+                                    error: quarantined.error.clone(),
+                                })
+                                .collect(),
+                        },
+                        search_tree_pids,
+                    },
+                )));
+                tasks_count += 1;
+            },
+
+            Event::Task(Ok(task::TaskDone::InfoButcher(task::info_butcher::Done { request_ref, info, }))) |
+            Event::Task(Ok(task::TaskDone::InfoSearchTree(task::info_search_tree::Done { request_ref, info, }))) => {
+                let info_request = info_requests.get_mut(request_ref).unwrap();
+                assert!(info_request.pending_count > 0);
+                info_request.pending_count -= 1;
+                info_request.info_fold += info;
+                if info_request.pending_count == 0 {
+                    let info_request = info_requests.remove(request_ref).unwrap();
+                    let mut info = info_request.info_fold;
+                    info.active_range_scans = active_range_scans;
+                    if let Err(_send_error) = info_request.reply_tx.send(info) {
+                        log::warn!("client canceled info request");
+                    }
+                }
+            },
+
+            Event::Task(Ok(task::TaskDone::InsertButcher(task::insert_butcher::Done { no_reply, }))) =>
+                if no_reply {
+                    no_reply_inserts_count -= 1;
+                },
+
+            Event::Task(Ok(task::TaskDone::LookupButcher(task::lookup_butcher::Done { request_ref, found, flush_generation, }))) => {
+                let lookup_request = lookup_requests.get_mut(request_ref).unwrap();
+                assert!(matches!(lookup_request.butcher_status, LookupRequestButcherStatus::NotReady));
+                if replace_fold_found(&lookup_request.found_fold, &found) {
+                    lookup_request.found_fold = found;
+                }
+                flushed_caches.fold(
+                    &lookup_request.key,
+                    lookup_request.flush_generation,
+                    cmp::min(flush_generation, butcher_flush_generation),
+                    &mut lookup_request.found_fold,
+                );
+                if flush_generation > butcher_flush_generation {
+                    // caches flushed meanwhile have not been received yet
+                    lookup_request.butcher_status = LookupRequestButcherStatus::AwaitingFlush { flush_generation, };
+                } else {
+                    lookup_request.butcher_status = LookupRequestButcherStatus::Done;
+                    assert!(lookup_request.pending_count > 0);
+                    lookup_request.pending_count -= 1;
+                    if lookup_request.pending_count == 0 {
+                        let lookup_request = lookup_requests.remove(request_ref).unwrap();
+                        if let Some(task_args) = finish_lookup_request(lookup_request, &mut flushed_caches, &range_tombstones, &state) {
+                            tasks.push(task::run_args(task_args));
+                            tasks_count += 1;
+                        }
+                    }
+                }
+            },
+
+            Event::Task(Ok(task::TaskDone::LookupSearchTree(task::lookup_search_tree::Done { request_ref, found, }))) => {
+                let lookup_request = lookup_requests.get_mut(request_ref).unwrap();
                 assert!(lookup_request.pending_count > 0);
                 lookup_request.pending_count -= 1;
                 if replace_fold_found(&lookup_request.found_fold, &found) {
@@ -870,29 +2491,57 @@ where J: edeltraud::Job + From<job::Job>,
                 }
                 if lookup_request.pending_count == 0 {
                     let lookup_request = lookup_requests.remove(request_ref).unwrap();
-                    tasks.push(task::run_args(task::TaskArgs::RetrieveValue(
-                        task::retrieve_value::Args {
-                            key: lookup_request.key,
-                            found_fold: lookup_request.found_fold,
-                            reply_tx: lookup_request.reply_tx,
-                            wheels_pid: state.wheels_pid.clone(),
-                        },
-                    )));
-                    tasks_count += 1;
+                    if let Some(task_args) = finish_lookup_request(lookup_request, &mut flushed_caches, &range_tombstones, &state) {
+                        tasks.push(task::run_args(task_args));
+                        tasks_count += 1;
+                    }
                 }
             },
 
-            Event::Task(Ok(task::TaskDone::LookupRangeButcher(task::lookup_range_butcher::Done { range, key_values_tx, iter_items, }))) => {
+            Event::Task(Ok(task::TaskDone::LookupRangeButcher(task::lookup_range_butcher::Done {
+                range,
+                key_values_tx,
+                key_filter,
+                version_window,
+                cancel_rx,
+                retries,
+                last_key,
+                iter_items,
+            }))) => {
                 let mut merger_iters = merger_iters_pool.lend(Vec::new);
                 merger_iters.clear();
+                let (late_trees_tx, late_trees_rx) = mpsc::unbounded();
+                lookup_range_watchers.retain(|late_trees_tx| !late_trees_tx.is_closed());
+                lookup_range_watchers.push(late_trees_tx);
 
-                if search_trees.is_empty() {
+                // trees entirely older than the window cannot contribute a cell to it
+                let queried_search_trees: Vec<_> = search_trees.iter()
+                    .filter(|(search_tree_ref, _search_tree_pid)| match version_window {
+                        None =>
+                            true,
+                        Some(window) =>
+                            search_tree_max_versions.get(search_tree_ref)
+                                .map_or(true, |&max_version| max_version >= window.lo),
+                    })
+                    .map(|(_search_tree_ref, search_tree_pid)| search_tree_pid.clone())
+                    .collect();
+
+                if queried_search_trees.is_empty() {
                     bg_tasks_push(task::TaskArgs::MergeLookupRange(
                         task::merge_lookup_range::Args {
                             range,
                             key_values_tx,
+                            key_filter,
+                            version_window,
+                            cancel_rx,
                             butcher_iter_items: iter_items,
                             merger_iters,
+                            key_comparator: state.params.search_tree_params.key_comparator.clone(),
+                            range_tombstones: range_tombstones.clone(),
+                            prefetch_depth: state.params.lookup_range_prefetch_depth,
+                            retries,
+                            last_key,
+                            late_trees_rx,
                             wheels_pid: state.wheels_pid.clone(),
                         },
                     ));
@@ -901,17 +2550,23 @@ where J: edeltraud::Job + From<job::Job>,
                     let lookup_range_request = LookupRangeRequest {
                         range: range.clone(),
                         key_values_tx,
+                        key_filter,
+                        version_window,
+                        cancel_rx,
                         butcher_iter_items: iter_items,
                         merger_iters,
-                        pending_count: search_trees.len(),
+                        retries,
+                        last_key,
+                        late_trees_rx,
+                        pending_count: queried_search_trees.len(),
                     };
                     let request_ref = lookup_range_requests.insert(lookup_range_request);
-                    for (_search_tree_ref, search_tree_pid) in search_trees.iter() {
+                    for search_tree_pid in queried_search_trees {
                         tasks.push(task::run_args(task::TaskArgs::LookupRangeSearchTree(
                             task::lookup_range_search_tree::Args {
                                 range: range.clone(),
                                 request_ref: request_ref.clone(),
-                                search_tree_pid: search_tree_pid.clone(),
+                                search_tree_pid,
                             },
                         )));
                         tasks_count += 1;
@@ -923,15 +2578,31 @@ where J: edeltraud::Job + From<job::Job>,
                 let lookup_range_request = lookup_range_requests.get_mut(request_ref).unwrap();
                 assert!(lookup_range_request.pending_count > 0);
                 lookup_range_request.pending_count -= 1;
-                lookup_range_request.merger_iters.push(merger::KeyValuesIter::new(items_iter.items_rx));
+                let search_tree::SearchTreeIterItemsRx { items_rx, demand_tx, } = items_iter;
+                let merger_iter = match demand_tx {
+                    Some(demand_tx) =>
+                        merger::KeyValuesIter::demand_driven(items_rx, demand_tx),
+                    None =>
+                        merger::KeyValuesIter::new(items_rx),
+                };
+                lookup_range_request.merger_iters.push(merger_iter);
                 if lookup_range_request.pending_count == 0 {
                     let lookup_range_request = lookup_range_requests.remove(request_ref).unwrap();
                     bg_tasks_push(task::TaskArgs::MergeLookupRange(
                         task::merge_lookup_range::Args {
                             range: lookup_range_request.range,
                             key_values_tx: lookup_range_request.key_values_tx,
+                            key_filter: lookup_range_request.key_filter,
+                            version_window: lookup_range_request.version_window,
+                            cancel_rx: lookup_range_request.cancel_rx,
                             butcher_iter_items: lookup_range_request.butcher_iter_items,
                             merger_iters: lookup_range_request.merger_iters,
+                            key_comparator: state.params.search_tree_params.key_comparator.clone(),
+                            range_tombstones: range_tombstones.clone(),
+                            prefetch_depth: state.params.lookup_range_prefetch_depth,
+                            retries: lookup_range_request.retries,
+                            last_key: lookup_range_request.last_key,
+                            late_trees_rx: lookup_range_request.late_trees_rx,
                             wheels_pid: state.wheels_pid.clone(),
                         },
                     ));
@@ -939,38 +2610,155 @@ where J: edeltraud::Job + From<job::Job>,
                 }
             },
 
-            Event::Task(Ok(task::TaskDone::MergeLookupRange(task::merge_lookup_range::Done::MergeSuccess))) =>
-                (),
+            Event::Task(Ok(task::TaskDone::MergeLookupRange(task::merge_lookup_range::Done::MergeSuccess { retries, }))) |
+            Event::Task(Ok(task::TaskDone::MergeLookupRange(task::merge_lookup_range::Done::Canceled { retries, }))) => {
+                assert!(active_range_scans > 0);
+                active_range_scans -= 1;
+                if retries > state.params.lookup_range_retries_limit {
+                    pinned_lookup_ranges.running -= 1;
+                    // resume merges paused for pinned scans
+                    while let Some(task_args) = maybe_merge_search_trees(
+                        &mut search_tree_refs,
+                        &search_trees,
+                        &search_tree_min_versions,
+                        &search_tree_max_versions,
+                        !quarantined_search_trees.is_empty(),
+                        merge_search_trees_tasks_count,
+                        pinned_lookup_ranges.max_concurrent_merges(state.params.max_concurrent_merges),
+                        &state.thread_pool,
+                        &state.background_gate,
+                        &state.blocks_pool,
+                        &merge_blocks_pool,
+                        &merger_iters_pool,
+                        &state.wheels_pid,
+                        block_budget,
+                        state.params.merge_placement,
+                        state.params.search_tree_params.block_compression,
+                        state.params.search_tree_params.store_id,
+                        state.version_provider.current(),
+                        &state.params.metrics,
+                        &state.params.search_tree_params.key_comparator,
+                        &range_tombstones,
+                    ) {
+                        bg_tasks_push(task_args);
+                        bg_tasks_count += 1;
+                        merge_search_trees_tasks_count += 1;
+                    }
+                }
+            },
 
             Event::Task(Ok(task::TaskDone::MergeLookupRange(task::merge_lookup_range::Done::DeprecatedResults {
                 modified_range,
                 key_values_tx,
+                key_filter,
+                version_window,
+                cancel_rx,
+                retries,
+                last_key,
             }))) => {
-                log::debug!("task::TaskDone::MergeLookupRange deprecated results: retrying LOOKUP RANGE request");
-                tasks.push(task::run_args(task::TaskArgs::LookupRangeButcher(
-                    task::lookup_range_butcher::Args {
-                        range: modified_range,
-                        key_values_tx,
-                        iter_items_pool: iter_items_pool.clone(),
-                        butcher_pid: state.butcher_pid.clone(),
+                let retries_limit = state.params.lookup_range_retries_limit;
+                if retries > retries_limit {
+                    pinned_lookup_ranges.running -= 1;
+                }
+                let lookup_range_args = task::lookup_range_butcher::Args {
+                    range: modified_range,
+                    key_values_tx,
+                    key_filter,
+                    version_window,
+                    cancel_rx,
+                    retries: retries + 1,
+                    last_key,
+                    iter_items_pool: iter_items_pool.clone(),
+                    butcher_pid: state.butcher_pid.clone(),
+                };
+                match lookup_range_retry(lookup_range_args.retries, retries_limit, merge_search_trees_tasks_count) {
+                    LookupRangeRetry::Retry => {
+                        log::debug!("task::TaskDone::MergeLookupRange deprecated results: retrying LOOKUP RANGE request");
+                        tasks.push(task::run_args(task::TaskArgs::LookupRangeButcher(lookup_range_args)));
+                        tasks_count += 1;
                     },
-                )));
-                tasks_count += 1;
+                    LookupRangeRetry::Pinned => {
+                        log::info!("LOOKUP RANGE request deprecated {} times in a row: retrying with merges paused", retries + 1);
+                        tasks.push(task::run_args(task::TaskArgs::LookupRangeButcher(lookup_range_args)));
+                        tasks_count += 1;
+                        pinned_lookup_ranges.running += 1;
+                    },
+                    LookupRangeRetry::WaitMerges => {
+                        log::info!(
+                            "LOOKUP RANGE request deprecated {} times in a row: waiting for {} merges to finish",
+                            retries + 1,
+                            merge_search_trees_tasks_count,
+                        );
+                        pinned_lookup_ranges.pending.push(lookup_range_args);
+                    },
+                }
             },
 
             Event::Task(Ok(task::TaskDone::RemoveButcher(task::remove_butcher::Done))) =>
                 (),
 
-            Event::Task(Ok(task::TaskDone::FlushButcher(task::flush_butcher::Done { request_ref, }))) => {
+            Event::Task(Ok(task::TaskDone::RemoveManyButcher(task::remove_many_butcher::Done))) =>
+                (),
+
+            Event::Task(Ok(task::TaskDone::RemoveReturningButcher(task::remove_returning_butcher::Done::Removed))) =>
+                (),
+
+            Event::Task(Ok(task::TaskDone::WriteRangeTombstone(task::write_range_tombstone::Done { range_tombstone, reply_tx, }))) => {
+                let version = range_tombstone.version;
+                // running lookups and merges keep their own snapshot
+                Arc::make_mut(&mut range_tombstones).push(range_tombstone);
+                if let Err(_send_error) = reply_tx.send(Ok(Removed { version, })) {
+                    log::warn!("client canceled remove range request");
+                }
+            },
+
+            Event::Task(Ok(task::TaskDone::SplitOff(task::split_off::Done::Moved { range_tombstone, items, reply_tx, }))) => {
+                log::debug!("{} items split off", items.len());
+                Arc::make_mut(&mut range_tombstones).push(range_tombstone);
+                if let Err(_send_error) = reply_tx.send(Ok(items)) {
+                    log::warn!("client canceled split off request");
+                }
+            },
+
+            Event::Task(Ok(task::TaskDone::SplitOff(task::split_off::Done::ScanFailed))) =>
+                (),
+
+            Event::Task(Ok(task::TaskDone::IterRawBlocks(task::iter_raw_blocks::Done::Finished))) |
+            Event::Task(Ok(task::TaskDone::IterRawBlocks(task::iter_raw_blocks::Done::Canceled))) =>
+                (),
+
+            Event::Task(Ok(task::TaskDone::RemoveReturningButcher(task::remove_returning_butcher::Done::Conflict { key, reply_tx, }))) => {
+                log::debug!("task::TaskDone::RemoveReturningButcher conflict: retrying REMOVE_RETURNING request");
+                launch_remove_returning_request(
+                    key,
+                    reply_tx,
+                    butcher_flush_generation,
+                    &mut lookup_requests,
+                    &mut flushed_caches,
+                    &search_trees,
+                    state.params.lookup_fanout_limit,
+                    &state.butcher_pid,
+                    |args| {
+                        tasks.push(task::run_args(args));
+                        tasks_count += 1;
+                    },
+                );
+            },
+
+            Event::Task(Ok(task::TaskDone::FlushButcher(task::flush_butcher::Done { request_ref, flushed, }))) => {
                 log::debug!("task::TaskDone::FlushButcher received, proceeding with {} search_trees", search_trees.len());
                 assert!(matches!(current_mode, Mode::Flushing { .. }));
                 let flush_request = flush_requests.get_mut(request_ref).unwrap();
                 assert!(!flush_request.butcher_done);
                 flush_request.butcher_done = true;
+                flush_request.flushed = flushed;
 
                 if search_trees.is_empty() {
                     log::debug!("task::TaskDone::FlushSearchTree finished, waiting for all tasks to be done");
-                    flush_requests.remove(request_ref).unwrap();
+                    let FlushRequest { flushed, .. } = flush_requests.remove(request_ref).unwrap();
+                    if let Mode::Flushing { flushed: mode_flushed, .. } = &mut current_mode {
+                        *mode_flushed = flushed;
+                    }
                 } else {
                     for (_search_tree_ref, search_tree_pid) in search_trees.iter() {
                         tasks.push(task::run_args(task::TaskArgs::FlushSearchTree(
@@ -985,6 +2773,16 @@ where J: edeltraud::Job + From<job::Job>,
                 }
             },
 
+            Event::Task(Ok(task::TaskDone::FlushButcherOnly(task::flush_butcher_only::Done { flushed, flush_generation, reply_tx, }))) =>
+                // the resulting tree is registered once its cache arrives through the flush channel
+                if flushed.new_search_trees == 0 || butcher_flush_generation >= flush_generation {
+                    if let Err(_send_error) = reply_tx.send(flushed) {
+                        log::warn!("client canceled flush butcher only request");
+                    }
+                } else {
+                    butcher_flush_waiters.push(ButcherFlushWaiter { flush_generation, flushed, reply_tx, });
+                },
+
             Event::Task(Ok(task::TaskDone::FlushSearchTree(task::flush_search_tree::Done { request_ref, }))) => {
                 assert!(matches!(current_mode, Mode::Flushing { .. }));
                 let flush_request = flush_requests.get_mut(request_ref).unwrap();
@@ -994,27 +2792,199 @@ where J: edeltraud::Job + From<job::Job>,
                 log::debug!("task::TaskDone::FlushSearchTree received ({} left)", flush_request.search_trees_pending_count);
                 if flush_request.search_trees_pending_count == 0 {
                     log::debug!("task::TaskDone::FlushSearchTree finished, waiting for all tasks to be done");
-                    flush_requests.remove(request_ref).unwrap();
+                    let FlushRequest { flushed, .. } = flush_requests.remove(request_ref).unwrap();
+                    if let Mode::Flushing { flushed: mode_flushed, .. } = &mut current_mode {
+                        *mode_flushed = flushed;
+                    }
                 }
             },
 
             Event::Task(Ok(task::TaskDone::MergeSearchTrees(done))) => {
-                let search_tree_a_pid = search_trees.remove(done.search_tree_a_ref).unwrap();
-                tasks.push(task::run_args(task::TaskArgs::DemolishSearchTree(
-                    task::demolish_search_tree::Args {
-                        search_tree_pid: search_tree_a_pid,
-                    },
-                )));
-                tasks_count += 1;
+                let merged_min_version = match (
+                    search_tree_min_versions.get(&done.search_tree_a_ref),
+                    search_tree_min_versions.get(&done.search_tree_b_ref),
+                ) {
+                    (Some(&min_version_a), Some(&min_version_b)) =>
+                        Some(cmp::min(min_version_a, min_version_b)),
+                    _ =>
+                        None,
+                };
+                // a tree quarantined meanwhile keeps its blocks until `verify` decides on it
+                let mut inputs_demolished_rxs = Vec::new();
+                if search_trees.get(done.search_tree_a_ref).map_or(false, |pid| pid.same_tree(&done.search_tree_a_pid)) {
+                    let search_tree_a_pid = search_trees.remove(done.search_tree_a_ref).unwrap();
+                    search_tree_max_versions.remove(&done.search_tree_a_ref);
+                    search_tree_min_versions.remove(&done.search_tree_a_ref);
+                    search_tree_created_versions.remove(&done.search_tree_a_ref);
+                    let (demolished_tx, demolished_rx) = oneshot::channel();
+                    inputs_demolished_rxs.push(demolished_rx);
+                    tasks.push(task::run_args(task::TaskArgs::DemolishSearchTree(
+                        task::demolish_search_tree::Args {
+                            search_tree_pid: search_tree_a_pid,
+                            demolished_tx: Some(demolished_tx),
+                        },
+                    )));
+                    tasks_count += 1;
+                }
 
-                let search_tree_b_pid = search_trees.remove(done.search_tree_b_ref).unwrap();
-                tasks.push(task::run_args(task::TaskArgs::DemolishSearchTree(
-                    task::demolish_search_tree::Args {
-                        search_tree_pid: search_tree_b_pid,
-                    },
-                )));
-                tasks_count += 1;
+                if search_trees.get(done.search_tree_b_ref).map_or(false, |pid| pid.same_tree(&done.search_tree_b_pid)) {
+                    let search_tree_b_pid = search_trees.remove(done.search_tree_b_ref).unwrap();
+                    search_tree_max_versions.remove(&done.search_tree_b_ref);
+                    search_tree_min_versions.remove(&done.search_tree_b_ref);
+                    search_tree_created_versions.remove(&done.search_tree_b_ref);
+                    let (demolished_tx, demolished_rx) = oneshot::channel();
+                    inputs_demolished_rxs.push(demolished_rx);
+                    tasks.push(task::run_args(task::TaskArgs::DemolishSearchTree(
+                        task::demolish_search_tree::Args {
+                            search_tree_pid: search_tree_b_pid,
+                            demolished_tx: Some(demolished_tx),
+                        },
+                    )));
+                    tasks_count += 1;
+                }
+
+                let maybe_search_tree_ref = if let Some(root_block) = done.root_block {
+                    let search_tree_gen_server = search_tree::GenServer::new();
+                    let search_tree_pid = search_tree_gen_server.pid();
+                    child_supervisor_pid.spawn_link_temporary(
+                        search_tree_gen_server.run(
+                            child_supervisor_pid.clone(),
+                            state.thread_pool.clone(),
+                            search_tree_pools.clone(),
+                            state.wheels_pid.clone(),
+                            state.params.search_tree_params.clone(),
+                            search_tree::Mode::Regular { root_block, },
+                            search_tree_failures_tx.clone(),
+                        ),
+                    );
+                    // not sent to running range scans: a merged tree holds no cells its inputs did not
+                    let search_tree_ref = search_trees.insert(search_tree_pid);
+                    search_tree_max_versions.insert(search_tree_ref, done.max_version);
+                    if let Some(min_version) = merged_min_version {
+                        search_tree_min_versions.insert(search_tree_ref, min_version);
+                    }
+                    search_tree_created_versions.insert(search_tree_ref, done.created_at_version);
+                    Some(SearchTreeRef { search_tree_ref, items_count: done.items_count, })
+                } else {
+                    None
+                };
+                let output_ref = maybe_search_tree_ref.as_ref()
+                    .map(|search_tree_ref| SearchTreeId(search_tree_ref.search_tree_ref));
+                let merged_search_tree_ref = maybe_search_tree_ref.as_ref()
+                    .map(|search_tree_ref| search_tree_ref.search_tree_ref);
+                search_tree_refs.merged(done.search_tree_a_ref, done.search_tree_b_ref, maybe_search_tree_ref);
+
+                let (collected, kept): (Vec<_>, Vec<_>) = range_tombstones.iter()
+                    .cloned()
+                    .partition(|range_tombstone| range_tombstone_collectable(
+                        range_tombstone,
+                        &done.range_tombstones,
+                        merged_search_tree_ref,
+                        &search_trees,
+                        &search_tree_min_versions,
+                        butcher_flushed_max_version,
+                        !quarantined_search_trees.is_empty() || inputs_demolished_rxs.len() < 2,
+                    ));
+                if !collected.is_empty() {
+                    log::debug!("{} range tombstones applied by the merge are discarded", collected.len());
+                    // running lookups and merges keep their own snapshot
+                    range_tombstones = Arc::new(kept);
+                }
+                let block_refs: Vec<_> = collected.into_iter()
+                    .map(|range_tombstone| range_tombstone.block_ref)
+                    // a quarantined input is not demolished, the record keeps it from being restored
+                    .chain(done.merge_record.filter(|_| inputs_demolished_rxs.len() == 2))
+                    .collect();
+                if !block_refs.is_empty() {
+                    tasks.push(task::run_args(task::TaskArgs::DeleteRangeTombstones(
+                        task::delete_range_tombstones::Args {
+                            inputs_demolished_rxs,
+                            block_refs,
+                            wheels_pid: state.wheels_pid.clone(),
+                        },
+                    )));
+                    tasks_count += 1;
+                }
 
+                merge_search_trees_tasks_count -= 1;
+                // offer deferred pairs as well
+                while let Some(task_args) = maybe_merge_search_trees(
+                    &mut search_tree_refs,
+                    &search_trees,
+                    &search_tree_min_versions,
+                    &search_tree_max_versions,
+                    !quarantined_search_trees.is_empty(),
+                    merge_search_trees_tasks_count,
+                    pinned_lookup_ranges.max_concurrent_merges(state.params.max_concurrent_merges),
+                    &state.thread_pool,
+                    &state.background_gate,
+                    &state.blocks_pool,
+                    &merge_blocks_pool,
+                    &merger_iters_pool,
+                    &state.wheels_pid,
+                    block_budget,
+                    state.params.merge_placement,
+                    state.params.search_tree_params.block_compression,
+                    state.params.search_tree_params.store_id,
+                    state.version_provider.current(),
+                    &state.params.metrics,
+                    &state.params.search_tree_params.key_comparator,
+                    &range_tombstones,
+                ) {
+                    bg_tasks_push(task_args);
+                    bg_tasks_count += 1;
+                    merge_search_trees_tasks_count += 1;
+                }
+                if merge_search_trees_tasks_count == 0 {
+                    let pending = mem::take(&mut pinned_lookup_ranges.pending);
+                    pinned_lookup_ranges.running += pending.len();
+                    for lookup_range_args in pending {
+                        tasks.push(task::run_args(task::TaskArgs::LookupRangeButcher(lookup_range_args)));
+                        tasks_count += 1;
+                    }
+                }
+
+                state.params.metrics.on_merge_end(done.items_count, done.timings.total);
+                log::info!(
+                    "two search_tree of {} merged in {:?}: currently {} in action, {} merging",
+                    done.items_count,
+                    done.timings,
+                    search_trees.len(),
+                    merge_search_trees_tasks_count,
+                );
+
+                if !compaction_subscribers.is_empty() {
+                    let event = CompactionEvent {
+                        items_count: done.items_count,
+                        timings: CompactionTimings {
+                            count: done.timings.count,
+                            merge: done.timings.merge,
+                            merge_next: done.timings.merge_next,
+                            block_prepare: done.timings.block_prepare,
+                            block_write: done.timings.block_write,
+                            total: done.timings.total,
+                        },
+                        input_refs: [SearchTreeId(done.search_tree_a_ref), SearchTreeId(done.search_tree_b_ref)],
+                        output_ref,
+                    };
+                    // busyloop never waits for subscribers: a full channel loses the event, a closed one is dropped
+                    compaction_subscribers.retain_mut(|events_tx| match events_tx.try_send(event.clone()) {
+                        Ok(()) =>
+                            true,
+                        Err(error) if error.is_full() => {
+                            log::warn!("compaction subscriber lags behind: event dropped");
+                            true
+                        },
+                        Err(..) =>
+                            false,
+                    });
+                }
+            },
+
+            Event::Task(Ok(task::TaskDone::BulkLoad(task::bulk_load::Done::KeysNotAscending))) =>
+                (),
+
+            Event::Task(Ok(task::TaskDone::BulkLoad(task::bulk_load::Done::Loaded(done)))) => {
                 let search_tree_gen_server = search_tree::GenServer::new();
                 let search_tree_pid = search_tree_gen_server.pid();
                 child_supervisor_pid.spawn_link_temporary(
@@ -1025,19 +2995,37 @@ where J: edeltraud::Job + From<job::Job>,
                         state.wheels_pid.clone(),
                         state.params.search_tree_params.clone(),
                         search_tree::Mode::Regular { root_block: done.root_block, },
+                        search_tree_failures_tx.clone(),
                     ),
                 );
+                lookup_range_watchers.retain(|late_trees_tx| late_trees_tx.unbounded_send(search_tree_pid.clone()).is_ok());
                 let search_tree_ref = search_trees.insert(search_tree_pid);
-                search_tree_refs.push(SearchTreeRef { search_tree_ref, items_count: done.items_count, }, done.items_count);
+                search_tree_max_versions.insert(search_tree_ref, done.version);
+                search_tree_min_versions.insert(search_tree_ref, done.version);
+                search_tree_created_versions.insert(search_tree_ref, done.version);
+                search_tree_refs.push(SearchTreeRef { search_tree_ref, items_count: done.items_count, }, done.items_bytes, done.key_range);
                 let maybe_task_args = maybe_merge_search_trees(
                     &mut search_tree_refs,
                     &search_trees,
+                    &search_tree_min_versions,
+                    &search_tree_max_versions,
+                    !quarantined_search_trees.is_empty(),
+                    merge_search_trees_tasks_count,
+                    pinned_lookup_ranges.max_concurrent_merges(state.params.max_concurrent_merges),
                     &state.thread_pool,
+                    &state.background_gate,
                     &state.blocks_pool,
                     &merge_blocks_pool,
                     &merger_iters_pool,
                     &state.wheels_pid,
-                    state.params.search_tree_params.tree_block_size,
+                    block_budget,
+                    state.params.merge_placement,
+                    state.params.search_tree_params.block_compression,
+                    state.params.search_tree_params.store_id,
+                    state.version_provider.current(),
+                    &state.params.metrics,
+                    &state.params.search_tree_params.key_comparator,
+                    &range_tombstones,
                 );
                 if let Some(task_args) = maybe_task_args {
                     bg_tasks_push(task_args);
@@ -1045,30 +3033,182 @@ where J: edeltraud::Job + From<job::Job>,
                     merge_search_trees_tasks_count += 1;
                 }
 
-                merge_search_trees_tasks_count -= 1;
                 log::info!(
-                    "two search_tree of {} merged in {:?}: currently {} in action, {} merging",
+                    "bulk loaded search_tree of {} items in {:?}: currently {} in action, {} merging",
                     done.items_count,
-                    done.timings,
+                    done.elapsed,
                     search_trees.len(),
                     merge_search_trees_tasks_count,
                 );
+                if let Err(_send_error) = done.reply_tx.send(Ok(Inserted { version: done.version, })) {
+                    log::warn!("client canceled bulk load request");
+                }
             },
 
             Event::Task(Ok(task::TaskDone::DemolishSearchTree(task::demolish_search_tree::Done))) => {
                 log::debug!("search tree DEMOLISHED");
             },
 
-            Event::Task(Ok(task::TaskDone::RetrieveValue(task::retrieve_value::Done::RetrieveSuccess))) =>
-                (),
+            Event::Task(Ok(task::TaskDone::DeleteRangeTombstones(task::delete_range_tombstones::Done { blocks_count, }))) => {
+                log::debug!("range tombstones and merge records deleted: {} blocks removed", blocks_count);
+            },
+
+            Event::Task(Ok(task::TaskDone::TruncateWal(task::truncate_wal::Done { records_count, }))) => {
+                log::debug!("wal truncated: {} records removed", records_count);
+            },
+
+            Event::Task(Ok(task::TaskDone::GcOrphans(task::gc_orphans::Done { orphans_collected, }))) => {
+                log::info!(
+                    "orphans collected: {} blocks deleted out of {}",
+                    orphans_collected.blocks_deleted,
+                    orphans_collected.blocks_total,
+                );
+                match mem::replace(&mut current_mode, Mode::Regular) {
+                    Mode::Collecting { done_reply_tx: Some(done_reply_tx), collect_started: true, } =>
+                        if let Err(_send_error) = done_reply_tx.send(orphans_collected) {
+                            log::warn!("client canceled gc orphans request");
+                        },
+                    Mode::Collecting { done_reply_tx: None, collect_started: true, } =>
+                        (),
+                    _ =>
+                        unreachable!(),
+                }
+            },
+
+            Event::Task(Ok(task::TaskDone::ChangedBlocks(task::changed_blocks::Done { changed_blocks, reply_tx, }))) => {
+                log::debug!("{} changed blocks found", changed_blocks.len());
+                if let Err(_send_error) = reply_tx.send(changed_blocks) {
+                    log::warn!("client canceled changed blocks request");
+                }
+            },
+
+            Event::Task(Ok(task::TaskDone::Verify(task::verify::Done { verify_report, reply_tx, }))) => {
+                log::info!(
+                    "verify done: {} blocks total, {} corrupt blocks, {} corrupt trees, {} missing blocks",
+                    verify_report.blocks_total,
+                    verify_report.corrupt_blocks.len(),
+                    verify_report.corrupt_trees.len(),
+                    verify_report.missing_blocks.len(),
+                );
+                // quarantine is lifted for trees with every block found intact, the others stay out
+                let (restored, still_quarantined): (Vec<_>, Vec<_>) = mem::take(&mut quarantined_search_trees)
+                    .into_iter()
+                    .partition(|quarantined| match &quarantined.root_block {
+                        Some(root_block) =>
+                            !verify_report.corrupt_trees.contains(root_block),
+                        None =>
+                            false,
+                    });
+                quarantined_search_trees = still_quarantined;
+                for QuarantinedTree { root_block, created_at_version, max_version, maybe_queued, .. } in restored {
+                    let root_block = root_block.unwrap();
+                    log::info!("search tree {:?} verified intact, quarantine lifted", root_block);
+                    let search_tree_gen_server = search_tree::GenServer::new();
+                    let search_tree_pid = search_tree_gen_server.pid();
+                    child_supervisor_pid.spawn_link_temporary(
+                        search_tree_gen_server.run(
+                            child_supervisor_pid.clone(),
+                            state.thread_pool.clone(),
+                            search_tree_pools.clone(),
+                            state.wheels_pid.clone(),
+                            state.params.search_tree_params.clone(),
+                            search_tree::Mode::Regular { root_block, },
+                            search_tree_failures_tx.clone(),
+                        ),
+                    );
+                    let search_tree_ref = search_trees.insert(search_tree_pid);
+                    if let Some(max_version) = max_version {
+                        search_tree_max_versions.insert(search_tree_ref, max_version);
+                    }
+                    search_tree_created_versions.insert(search_tree_ref, created_at_version);
+                    match maybe_queued {
+                        Some((items_count, bytes, key_range)) =>
+                            search_tree_refs.push(SearchTreeRef { search_tree_ref, items_count, }, bytes, key_range),
+                        // quarantined in the middle of a merge or before its stats were taken
+                        None => {
+                            tasks.push(task::run_args(task::TaskArgs::TreeStats(
+                                task::tree_stats::Args {
+                                    search_tree_ref,
+                                    search_tree_pid: search_trees.get(search_tree_ref).unwrap().clone(),
+                                },
+                            )));
+                            tasks_count += 1;
+                        },
+                    }
+                }
+                if let Err(_send_error) = reply_tx.send(verify_report) {
+                    log::warn!("client canceled verify request");
+                }
+            },
 
-            Event::Task(Ok(task::TaskDone::RetrieveValue(task::retrieve_value::Done::DeprecatedResults { key, reply_tx, }))) => {
+            Event::Task(Ok(task::TaskDone::SizeHistograms(task::size_histograms::Done { histograms, reply_tx, }))) =>
+                if let Err(_send_error) = reply_tx.send(histograms) {
+                    log::warn!("client canceled size histograms request");
+                },
+
+            Event::Task(Ok(task::TaskDone::EstimateCount(task::estimate_count::Done { estimate, reply_tx, }))) =>
+                if let Err(_send_error) = reply_tx.send(estimate) {
+                    log::warn!("client canceled estimate count request");
+                },
+
+            Event::Task(Ok(task::TaskDone::TreeStats(task::tree_stats::Done {
+                search_tree_ref,
+                search_tree_pid,
+                items_count,
+                bytes,
+                key_range,
+            }))) =>
+                // a tree quarantined meanwhile is queued again once lifted
+                match search_trees.get(search_tree_ref) {
+                    Some(current_pid) if current_pid.same_tree(&search_tree_pid) =>
+                        search_tree_refs.push(SearchTreeRef { search_tree_ref, items_count, }, bytes, key_range),
+                    _ =>
+                        log::debug!("search tree {:?} gone before its stats were taken", search_tree_ref),
+                },
+
+            Event::Task(Ok(task::TaskDone::WarmRange(task::warm_range::Done { warmed, reply_tx, }))) =>
+                if let Err(_send_error) = reply_tx.send(warmed) {
+                    log::warn!("client canceled warm range request");
+                },
+
+            Event::Task(Ok(task::TaskDone::DetailedInfo(task::detailed_info::Done { detailed_info, reply_tx, }))) =>
+                if let Err(_send_error) = reply_tx.send(detailed_info) {
+                    log::warn!("client canceled detailed info request");
+                },
+
+            Event::Task(Ok(task::TaskDone::Health(task::health::Done { mut health, reply_tx, }))) => {
+                match &health.last_error {
+                    Some(error) => {
+                        log::warn!("health probe failed: {}", error);
+                        state.last_error = Some(error.clone());
+                    },
+                    None =>
+                        health.last_error = state.last_error.clone(),
+                }
+                if let Err(_send_error) = reply_tx.send(health) {
+                    log::warn!("client canceled health request");
+                }
+            },
+
+            Event::Task(Ok(task::TaskDone::RetrieveValue(task::retrieve_value::Done::RetrieveSuccess { found: true, started_at, }))) =>
+                state.params.metrics.on_lookup_hit(started_at.elapsed()),
+
+            Event::Task(Ok(task::TaskDone::RetrieveValue(task::retrieve_value::Done::RetrieveSuccess { found: false, started_at, }))) =>
+                state.params.metrics.on_lookup_miss(started_at.elapsed()),
+
+            Event::Task(Ok(task::TaskDone::RetrieveValue(task::retrieve_value::Done::DeprecatedResults { key, reply_tx, started_at, }))) => {
                 log::debug!("task::TaskDone::RetrieveValue deprecated results: retrying LOOKUP request");
+                // retries are rare enough to always be done in full
                 launch_lookup_request(
                     key,
-                    reply_tx,
+                    Consistency::Strong,
+                    LookupReplyTx::Value(reply_tx),
+                    started_at,
+                    butcher_flush_generation,
                     &mut lookup_requests,
+                    &mut flushed_caches,
                     &search_trees,
+                    state.params.lookup_fanout_limit,
                     &state.butcher_pid,
                     |args| {
                         tasks.push(task::run_args(args));
@@ -1085,62 +3225,400 @@ where J: edeltraud::Job + From<job::Job>,
 
 fn launch_lookup_request<T, J>(
     key: kv::Key,
-    reply_tx: oneshot::Sender<Option<kv::ValueCell<kv::Value>>>,
+    consistency: Consistency,
+    reply_tx: LookupReplyTx,
+    started_at: Instant,
+    butcher_flush_generation: u64,
     lookup_requests: &mut Set<LookupRequest>,
+    flushed_caches: &mut FlushedCaches,
     search_trees: &Set<search_tree::Pid>,
+    lookup_fanout_limit: Option<usize>,
     butcher_pid: &butcher::Pid,
     mut tasks_push: T,
 )
 where T: FnMut(task::TaskArgs<J>),
       J: edeltraud::Job,
 {
+    if reply_tx.is_canceled() {
+        log::debug!("client canceled lookup request before launch");
+        return;
+    }
+    flushed_caches.lookup_launched(butcher_flush_generation);
+    // each shard task looks up its trees sequentially
+    let shards_count = lookup_fanout_limit
+        .map_or(search_trees.len(), |limit| limit.min(search_trees.len()));
+    // without the butcher task at least one (maybe empty) shard is needed for the request to be finished
+    let shards_count = match consistency {
+        Consistency::Strong =>
+            shards_count,
+        Consistency::Trees =>
+            shards_count.max(1),
+    };
+    let mut shards: Vec<Vec<search_tree::Pid>> = (0 .. shards_count).map(|_| Vec::new()).collect();
+    for (index, (_search_tree_ref, search_tree_pid)) in search_trees.iter().enumerate() {
+        shards[index % shards_count].push(search_tree_pid.clone());
+    }
     let request_ref = lookup_requests.insert(LookupRequest {
         key: key.clone(),
         reply_tx,
-        butcher_status: LookupRequestButcherStatus::NotReady,
-        pending_count: 1 + search_trees.len(),
+        started_at,
+        span: trace::Span::current(),
+        flush_generation: butcher_flush_generation,
+        butcher_status: match consistency {
+            Consistency::Strong =>
+                LookupRequestButcherStatus::NotReady,
+            Consistency::Trees =>
+                LookupRequestButcherStatus::Done,
+        },
+        pending_count: match consistency {
+            Consistency::Strong =>
+                1 + shards_count,
+            Consistency::Trees =>
+                shards_count,
+        },
         found_fold: None,
     });
-    tasks_push(task::TaskArgs::LookupButcher(
-        task::lookup_butcher::Args {
-            key: key.clone(),
-            request_ref: request_ref.clone(),
-            butcher_pid: butcher_pid.clone(),
-        },
-    ));
-    for (_search_tree_ref, search_tree_pid) in search_trees.iter() {
+    if let Consistency::Strong = consistency {
+        tasks_push(task::TaskArgs::LookupButcher(
+            task::lookup_butcher::Args {
+                key: key.clone(),
+                request_ref: request_ref.clone(),
+                butcher_pid: butcher_pid.clone(),
+            },
+        ));
+    }
+    for search_tree_pids in shards {
         tasks_push(task::TaskArgs::LookupSearchTree(
             task::lookup_search_tree::Args {
                 key: key.clone(),
                 request_ref: request_ref.clone(),
-                search_tree_pid: search_tree_pid.clone(),
+                search_tree_pids,
             },
         ));
     }
 }
 
+fn finish_lookup_request<J>(
+    lookup_request: LookupRequest,
+    flushed_caches: &mut FlushedCaches,
+    range_tombstones: &RangeTombstones,
+    state: &State<J>,
+)
+    -> Option<task::TaskArgs<J>>
+where J: edeltraud::Job,
+{
+    flushed_caches.lookup_finished(lookup_request.flush_generation);
+    let _lookup_span_entered = lookup_request.span.enter();
+    // search tree tasks already ran to completion, but the value block read is still saved
+    if lookup_request.reply_tx.is_canceled() {
+        log::debug!("client canceled lookup request: skipping value retrieval");
+        return None;
+    }
+    let found_fold = mask_range_tombstoned(
+        lookup_request.found_fold,
+        &lookup_request.key,
+        range_tombstones,
+        &state.params.search_tree_params.key_comparator,
+    );
+    match lookup_request.reply_tx {
+        LookupReplyTx::Value(reply_tx) =>
+            Some(task::TaskArgs::RetrieveValue(
+                task::retrieve_value::Args {
+                    key: lookup_request.key,
+                    found_fold,
+                    reply_tx,
+                    started_at: lookup_request.started_at,
+                    wheels_pid: state.wheels_pid.clone(),
+                },
+            )),
+        LookupReplyTx::Located(reply_tx) => {
+            let located = found_fold.map(|kv::ValueCell { version, cell, }| kv::ValueCell {
+                version,
+                cell: match cell {
+                    kv::Cell::Value(storage::OwnedValueBlockRef::Inline(value)) =>
+                        kv::Cell::Value(ValueLocation::Inline(value)),
+                    kv::Cell::Value(storage::OwnedValueBlockRef::Ref(block_ref)) =>
+                        kv::Cell::Value(ValueLocation::Block(block_ref)),
+                    kv::Cell::Tombstone { meta, } =>
+                        kv::Cell::Tombstone { meta, },
+                },
+            });
+            if let Err(_send_error) = reply_tx.send(located) {
+                log::warn!("client canceled lookup located request");
+            }
+            None
+        },
+    }
+}
+
+fn launch_remove_returning_request<T, J>(
+    key: kv::Key,
+    reply_tx: oneshot::Sender<Result<(Removed, Option<kv::ValueCell<kv::Value>>), RemoveError>>,
+    butcher_flush_generation: u64,
+    lookup_requests: &mut Set<LookupRequest>,
+    flushed_caches: &mut FlushedCaches,
+    search_trees: &Set<search_tree::Pid>,
+    lookup_fanout_limit: Option<usize>,
+    butcher_pid: &butcher::Pid,
+    mut tasks_push: T,
+)
+where T: FnMut(task::TaskArgs<J>),
+      J: edeltraud::Job,
+{
+    let (lookup_reply_tx, lookup_reply_rx) = oneshot::channel();
+    launch_lookup_request(
+        key.clone(),
+        Consistency::Strong,
+        LookupReplyTx::Value(lookup_reply_tx),
+        Instant::now(),
+        butcher_flush_generation,
+        lookup_requests,
+        flushed_caches,
+        search_trees,
+        lookup_fanout_limit,
+        butcher_pid,
+        &mut tasks_push,
+    );
+    tasks_push(task::TaskArgs::RemoveReturningButcher(
+        task::remove_returning_butcher::Args {
+            key,
+            lookup_reply_rx,
+            flush_generation: butcher_flush_generation,
+            reply_tx,
+            butcher_pid: butcher_pid.clone(),
+        },
+    ));
+}
+
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 struct SearchTreeRef {
     items_count: usize,
     search_tree_ref: Ref,
 }
 
+struct QuarantinedTree {
+    root_block: Option<wheels::BlockRef>,
+    created_at_version: u64,
+    max_version: Option<u64>,
+    // merge queue entry to restore it with: items count, bytes and key range
+    maybe_queued: Option<(usize, usize, Option<level_merger::KeyRange>)>,
+    error: String,
+}
+
+enum SearchTreeRefs {
+    SizeTiered(bin_merger::BinMerger<SearchTreeRef>),
+    Leveled(level_merger::LevelMerger<SearchTreeRef>),
+}
+
+impl SearchTreeRefs {
+    fn new(compaction_strategy: &CompactionStrategy, key_comparator: &comparator::KeyComparatorRef) -> SearchTreeRefs {
+        match compaction_strategy {
+            CompactionStrategy::SizeTiered =>
+                SearchTreeRefs::SizeTiered(bin_merger::BinMerger::new()),
+            CompactionStrategy::Leveled { level_multiplier, base_level_bytes, } =>
+                SearchTreeRefs::Leveled(level_merger::LevelMerger::new(
+                    *level_multiplier,
+                    *base_level_bytes,
+                    key_comparator.clone(),
+                )),
+        }
+    }
+
+    fn push(&mut self, search_tree_ref: SearchTreeRef, bytes: usize, key_range: Option<level_merger::KeyRange>) {
+        match self {
+            SearchTreeRefs::SizeTiered(bin_merger) => {
+                let items_count = search_tree_ref.items_count;
+                bin_merger.push(search_tree_ref, items_count);
+            },
+            SearchTreeRefs::Leveled(level_merger) =>
+                level_merger.push(search_tree_ref, bytes, key_range),
+        }
+    }
+
+    fn pop(&mut self) -> Option<(SearchTreeRef, SearchTreeRef)> {
+        match self {
+            SearchTreeRefs::SizeTiered(bin_merger) => {
+                let (search_tree_a_ref, search_tree_b_ref) = choose_merge(bin_merger)?;
+                let pair = bin_merger.pop();
+                debug_assert!(matches!(
+                    &pair,
+                    Some((a, b)) if a.search_tree_ref == search_tree_a_ref && b.search_tree_ref == search_tree_b_ref,
+                ));
+                pair
+            },
+            SearchTreeRefs::Leveled(level_merger) =>
+                level_merger.pop(),
+        }
+    }
+
+    // trees already handed out for a merge are not found
+    fn remove(&mut self, search_tree_ref: Ref) -> Option<(usize, usize, Option<level_merger::KeyRange>)> {
+        match self {
+            SearchTreeRefs::SizeTiered(bin_merger) =>
+                bin_merger.remove_by(|bucket| bucket.search_tree_ref == search_tree_ref)
+                    .map(|bucket| (bucket.items_count, 0, None)),
+            SearchTreeRefs::Leveled(level_merger) =>
+                level_merger.remove_by(|bucket| bucket.search_tree_ref == search_tree_ref)
+                    .map(|(bucket, bytes, key_range)| (bucket.items_count, bytes, key_range)),
+        }
+    }
+
+    fn merged(&mut self, search_tree_a_ref: Ref, search_tree_b_ref: Ref, maybe_search_tree_ref: Option<SearchTreeRef>) {
+        match self {
+            SearchTreeRefs::SizeTiered(bin_merger) =>
+                if let Some(search_tree_ref) = maybe_search_tree_ref {
+                    let items_count = search_tree_ref.items_count;
+                    bin_merger.push(search_tree_ref, items_count);
+                },
+            SearchTreeRefs::Leveled(level_merger) =>
+                level_merger.merged(
+                    |a, b| a.search_tree_ref == search_tree_a_ref && b.search_tree_ref == search_tree_b_ref,
+                    maybe_search_tree_ref,
+                ),
+        }
+    }
+}
+
+fn obtain_version<J>(state: &State<J>) -> Result<u64, version::Regression> where J: edeltraud::Job {
+    state.version_watermark.obtain(&state.version_provider)
+        .map_err(|regression| {
+            log::error!("version provider regressed: {:?}, write rejected", regression);
+            regression
+        })
+}
+
+fn cache_bytes_and_key_range(cache: &MemCache) -> (usize, Option<level_merger::KeyRange>) {
+    let bytes = cache.iter()
+        .map(|(ord_key, value_cell)| {
+            let value_bytes = match &value_cell.cell {
+                kv::Cell::Value(value) | kv::Cell::Tombstone { meta: Some(value), } =>
+                    value.value_bytes.len(),
+                kv::Cell::Tombstone { meta: None, } =>
+                    0,
+            };
+            ord_key.key_bytes.len() + value_bytes
+        })
+        .sum();
+    let key_range = match (cache.keys().next(), cache.keys().next_back()) {
+        (Some(first), Some(last)) =>
+            Some(level_merger::KeyRange { first: first.as_ref().clone(), last: last.as_ref().clone(), }),
+        _ =>
+            None,
+    };
+    (bytes, key_range)
+}
+
+// size tiered compaction policy without side effects: trees of the same power of two size are paired
+fn choose_merge(search_tree_refs: &bin_merger::BinMerger<SearchTreeRef>) -> Option<(Ref, Ref)> {
+    search_tree_refs.peek()
+        .map(|(search_tree_a_ref, search_tree_b_ref)| (search_tree_a_ref.search_tree_ref, search_tree_b_ref.search_tree_ref))
+}
+
+// tombstones may be discarded only when every other tree holds strictly newer cells than both merge inputs,
+// so nothing they shadow exists anywhere; a tree of unknown versions (e.g. loaded from disk) or a quarantined
+// one which may come back counts as older
+fn merge_is_bottom<T>(
+    search_tree_a_ref: Ref,
+    search_tree_b_ref: Ref,
+    search_trees: &Set<T>,
+    search_tree_min_versions: &BTreeMap<Ref, u64>,
+    search_tree_max_versions: &BTreeMap<Ref, u64>,
+    any_quarantined: bool,
+)
+    -> bool
+{
+    if any_quarantined {
+        return false;
+    }
+    let inputs_max_version = match (search_tree_max_versions.get(&search_tree_a_ref), search_tree_max_versions.get(&search_tree_b_ref)) {
+        (Some(&max_version_a), Some(&max_version_b)) =>
+            cmp::max(max_version_a, max_version_b),
+        _ =>
+            return false,
+    };
+    search_trees.iter()
+        .map(|(search_tree_ref, _pid)| search_tree_ref)
+        .filter(|&search_tree_ref| search_tree_ref != search_tree_a_ref && search_tree_ref != search_tree_b_ref)
+        .all(|search_tree_ref| {
+            search_tree_min_versions.get(&search_tree_ref)
+                .map_or(false, |&min_version| min_version > inputs_max_version)
+        })
+}
+
+// a range tombstone applied by a merge is not needed anymore when nothing it shadows is left elsewhere: every
+// other tree holds newer cells only and butcher has flushed every older cell; `blocked` is for the same reasons
+// as in `merge_is_bottom`, or for a merge input kept on disk (quarantined meanwhile) which a restart would load
+fn range_tombstone_collectable<T>(
+    range_tombstone: &RangeTombstone,
+    applied: &[RangeTombstone],
+    merged_search_tree_ref: Option<Ref>,
+    search_trees: &Set<T>,
+    search_tree_min_versions: &BTreeMap<Ref, u64>,
+    butcher_flushed_max_version: Option<u64>,
+    blocked: bool,
+)
+    -> bool
+{
+    if blocked {
+        return false;
+    }
+    if !applied.iter().any(|applied| applied.block_ref == range_tombstone.block_ref) {
+        return false;
+    }
+    if butcher_flushed_max_version.map_or(true, |max_version| max_version <= range_tombstone.version) {
+        return false;
+    }
+    search_trees.iter()
+        .map(|(search_tree_ref, _pid)| search_tree_ref)
+        .filter(|&search_tree_ref| Some(search_tree_ref) != merged_search_tree_ref)
+        .all(|search_tree_ref| {
+            search_tree_min_versions.get(&search_tree_ref)
+                .map_or(false, |&min_version| min_version > range_tombstone.version)
+        })
+}
+
 fn maybe_merge_search_trees<J>(
-    search_tree_refs: &mut bin_merger::BinMerger<SearchTreeRef>,
+    search_tree_refs: &mut SearchTreeRefs,
     search_trees: &Set<search_tree::Pid>,
+    search_tree_min_versions: &BTreeMap<Ref, u64>,
+    search_tree_max_versions: &BTreeMap<Ref, u64>,
+    any_quarantined: bool,
+    merge_search_trees_tasks_count: usize,
+    max_concurrent_merges: usize,
     thread_pool: &edeltraud::Edeltraud<J>,
+    background_gate: &job::BackgroundGate,
     blocks_pool: &BytesPool,
     merge_blocks_pool: &pool::Pool<Vec<storage::OwnedEntry>>,
     merger_iters_pool: &pool::Pool<Vec<merger::KeyValuesIter>>,
     wheels_pid: &wheels::Pid,
-    tree_block_size: usize,
+    block_budget: block_sizer::BlockBudget,
+    merge_placement: PlacementPolicy,
+    block_compression: Option<Compression>,
+    store_id: u32,
+    created_at_version: u64,
+    metrics: &metrics::MetricsRef,
+    key_comparator: &comparator::KeyComparatorRef,
+    range_tombstones: &RangeTombstones,
 )
     -> Option<task::TaskArgs<J>>
 where J: edeltraud::Job
 {
+    if merge_search_trees_tasks_count >= max_concurrent_merges {
+        // pair stays in bin merger until some merge task finishes
+        return None;
+    }
     let (search_tree_a_ref, search_tree_b_ref) = search_tree_refs.pop()?;
+    metrics.on_merge_start(search_tree_a_ref.items_count + search_tree_b_ref.items_count);
     let search_tree_a_pid = search_trees.get(search_tree_a_ref.search_tree_ref).unwrap().clone();
     let search_tree_b_pid = search_trees.get(search_tree_b_ref.search_tree_ref).unwrap().clone();
+    let is_bottom = merge_is_bottom(
+        search_tree_a_ref.search_tree_ref,
+        search_tree_b_ref.search_tree_ref,
+        search_trees,
+        search_tree_min_versions,
+        search_tree_max_versions,
+        any_quarantined,
+    );
     Some(task::TaskArgs::MergeSearchTrees(
         task::merge_search_trees::Args {
             search_tree_a_ref: search_tree_a_ref.search_tree_ref,
@@ -1148,11 +3626,217 @@ where J: edeltraud::Job
             search_tree_a_pid,
             search_tree_b_pid,
             thread_pool: thread_pool.clone(),
+            background_gate: background_gate.clone(),
             blocks_pool: blocks_pool.clone(),
             merge_blocks_pool: merge_blocks_pool.clone(),
             merger_iters_pool: merger_iters_pool.clone(),
             wheels_pid: wheels_pid.clone(),
-            tree_block_size,
+            block_budget,
+            placement: merge_placement,
+            block_compression,
+            store_id,
+            created_at_version,
+            metrics: metrics.clone(),
+            key_comparator: key_comparator.clone(),
+            range_tombstones: range_tombstones.clone(),
+            is_bottom,
         },
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::{
+        future,
+        executor::block_on,
+        channel::oneshot,
+        StreamExt,
+    };
+
+    use std::collections::BTreeMap;
+
+    use o1::set::Set;
+
+    use alloc_pool::bytes::BytesPool;
+
+    use ero_blockwheel_fs::block;
+
+    use crate::{
+        wheels::{
+            BlockRef,
+            WheelFilename,
+        },
+        core::{
+            bin_merger::BinMerger,
+            RangeTombstone,
+            SearchRangeBounds,
+        },
+    };
+
+    use super::{
+        choose_merge,
+        merge_is_bottom,
+        range_tombstone_collectable,
+        lookup_range_retry,
+        GenServer,
+        SearchTreeRef,
+        LookupRangeRetry,
+        LookupReplyTx,
+        PinnedLookupRanges,
+    };
+
+    #[test]
+    fn choose_merge_pairs_same_sized_trees() {
+        let mut search_trees = Set::new();
+        let mut bin_merger = BinMerger::new();
+        let mut push = |items_count| {
+            let search_tree_ref = search_trees.insert(items_count);
+            bin_merger.push(SearchTreeRef { items_count, search_tree_ref, }, items_count);
+            search_tree_ref
+        };
+        let small_ref = push(3);
+        let _large_ref = push(100);
+        assert!(choose_merge(&bin_merger).is_none());
+
+        let other_small_ref = push(4);
+        let (ref_a, ref_b) = choose_merge(&bin_merger).unwrap();
+        assert!(ref_a == other_small_ref && ref_b == small_ref);
+        // deciding takes nothing out of the merger
+        assert!(choose_merge(&bin_merger).is_some());
+        let (tree_a, tree_b) = bin_merger.pop().unwrap();
+        assert!(tree_a.search_tree_ref == ref_a && tree_b.search_tree_ref == ref_b);
+        assert!(choose_merge(&bin_merger).is_none());
+    }
+
+    #[test]
+    fn bottom_merge_needs_newer_neighbours() {
+        let mut search_trees = Set::new();
+        let mut min_versions = BTreeMap::new();
+        let mut max_versions = BTreeMap::new();
+        let ref_a = search_trees.insert(());
+        min_versions.insert(ref_a, 10);
+        max_versions.insert(ref_a, 20);
+        let ref_b = search_trees.insert(());
+        min_versions.insert(ref_b, 21);
+        max_versions.insert(ref_b, 30);
+        let ref_newer = search_trees.insert(());
+        min_versions.insert(ref_newer, 31);
+        max_versions.insert(ref_newer, 40);
+        assert!(merge_is_bottom(ref_a, ref_b, &search_trees, &min_versions, &max_versions, false));
+        // a quarantined tree may be lifted later with older cells
+        assert!(!merge_is_bottom(ref_a, ref_b, &search_trees, &min_versions, &max_versions, true));
+        // the tree between them may hold cells tombstoned in `ref_newer`
+        assert!(!merge_is_bottom(ref_a, ref_newer, &search_trees, &min_versions, &max_versions, false));
+
+        let ref_loaded = search_trees.insert(());
+        max_versions.insert(ref_loaded, 5);
+        assert!(!merge_is_bottom(ref_a, ref_b, &search_trees, &min_versions, &max_versions, false));
+        // neither tree count nor the position matter, only versions outside of the inputs
+        assert!(!merge_is_bottom(ref_loaded, ref_newer, &search_trees, &min_versions, &max_versions, false));
+        search_trees.remove(ref_newer);
+        search_trees.remove(ref_b);
+        assert!(merge_is_bottom(ref_loaded, ref_a, &search_trees, &min_versions, &max_versions, false));
+    }
+
+    #[test]
+    fn range_tombstone_collected_when_nothing_older_is_left() {
+        let blocks_pool = BytesPool::new();
+        let make_range_tombstone = |wheel, version| RangeTombstone {
+            range: SearchRangeBounds::unbounded(),
+            version,
+            block_ref: BlockRef {
+                blockwheel_filename: WheelFilename::from_str(wheel, &blocks_pool),
+                block_id: block::Id::init(),
+            },
+        };
+        let range_tombstone = make_range_tombstone("wheel_a", 25);
+        let applied = vec![range_tombstone.clone()];
+
+        let mut search_trees = Set::new();
+        let mut min_versions = BTreeMap::new();
+        let ref_merged = search_trees.insert(());
+        min_versions.insert(ref_merged, 10);
+        let ref_newer = search_trees.insert(());
+        min_versions.insert(ref_newer, 30);
+        assert!(range_tombstone_collectable(&range_tombstone, &applied, Some(ref_merged), &search_trees, &min_versions, Some(30), false));
+        assert!(!range_tombstone_collectable(&range_tombstone, &applied, Some(ref_merged), &search_trees, &min_versions, Some(30), true));
+        // butcher may still hold cells written before the tombstone
+        assert!(!range_tombstone_collectable(&range_tombstone, &applied, Some(ref_merged), &search_trees, &min_versions, Some(20), false));
+        assert!(!range_tombstone_collectable(&range_tombstone, &applied, Some(ref_merged), &search_trees, &min_versions, None, false));
+        // written after the merge started, so the merged tree may still hold cells it shadows
+        let later = make_range_tombstone("wheel_b", 25);
+        assert!(!range_tombstone_collectable(&later, &applied, Some(ref_merged), &search_trees, &min_versions, Some(30), false));
+
+        // another tree holds cells older than the tombstone
+        let ref_older = search_trees.insert(());
+        min_versions.insert(ref_older, 20);
+        assert!(!range_tombstone_collectable(&range_tombstone, &applied, Some(ref_merged), &search_trees, &min_versions, Some(30), false));
+        search_trees.remove(ref_older);
+        min_versions.remove(&ref_older);
+        // versions of a tree loaded from disk are unknown
+        search_trees.insert(());
+        assert!(!range_tombstone_collectable(&range_tombstone, &applied, Some(ref_merged), &search_trees, &min_versions, Some(30), false));
+    }
+
+    #[test]
+    fn lookup_range_retries_bounded_then_pinned() {
+        let retries_limit = 2;
+        assert_eq!(lookup_range_retry(1, retries_limit, 3), LookupRangeRetry::Retry);
+        assert_eq!(lookup_range_retry(2, retries_limit, 3), LookupRangeRetry::Retry);
+        // over the limit: restarted at once only with no merge that could deprecate it again
+        assert_eq!(lookup_range_retry(3, retries_limit, 0), LookupRangeRetry::Pinned);
+        assert_eq!(lookup_range_retry(3, retries_limit, 1), LookupRangeRetry::WaitMerges);
+        assert_eq!(lookup_range_retry(1, 0, 0), LookupRangeRetry::Pinned);
+
+        // no new merges are started while a pinned scan runs
+        let mut pinned_lookup_ranges = PinnedLookupRanges::default();
+        assert_eq!(pinned_lookup_ranges.max_concurrent_merges(4), 4);
+        pinned_lookup_ranges.running += 1;
+        assert_eq!(pinned_lookup_ranges.max_concurrent_merges(4), 0);
+        pinned_lookup_ranges.running -= 1;
+        assert_eq!(pinned_lookup_ranges.max_concurrent_merges(4), 4);
+    }
+
+    #[test]
+    fn lookup_reply_canceled_once_caller_is_gone() {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let lookup_reply_tx = LookupReplyTx::Value(reply_tx);
+        assert!(!lookup_reply_tx.is_canceled());
+        drop(reply_rx);
+        assert!(lookup_reply_tx.is_canceled());
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let lookup_reply_tx = LookupReplyTx::Located(reply_tx);
+        assert!(!lookup_reply_tx.is_canceled());
+        drop(reply_rx);
+        assert!(lookup_reply_tx.is_canceled());
+    }
+
+    #[test]
+    fn request_fails_after_retries_limit() {
+        let gen_server = GenServer::new(0, 0);
+        let mut pid = gen_server.pid();
+        pid.set_request_retries_limit(3);
+
+        let GenServer { request_tx, mut fused_request_rx, flush_cache_tx, fused_flush_cache_rx, } = gen_server;
+        drop(request_tx);
+
+        let drop_requests = async move {
+            let mut dropped = 0;
+            while let Some(request) = fused_request_rx.next().await {
+                drop(request);
+                dropped += 1;
+            }
+            drop(flush_cache_tx);
+            drop(fused_flush_cache_rx);
+            dropped
+        };
+        let make_request = async move {
+            let result = pid.info().await;
+            assert!(matches!(result, Err(ero::NoProcError)));
+        };
+
+        let (dropped, ()) = block_on(future::join(drop_requests, make_request));
+        assert_eq!(dropped, 4);
+    }
+}
@@ -1,5 +1,8 @@
+use std::future::Future;
+
 use crate::{
     job,
+    trace,
 };
 
 pub mod info_butcher;
@@ -7,7 +10,11 @@ pub mod insert_butcher;
 pub mod lookup_butcher;
 pub mod lookup_range_butcher;
 pub mod remove_butcher;
+pub mod remove_many_butcher;
+pub mod remove_returning_butcher;
+pub mod write_range_tombstone;
 pub mod flush_butcher;
+pub mod flush_butcher_only;
 pub mod info_search_tree;
 pub mod lookup_search_tree;
 pub mod lookup_range_search_tree;
@@ -16,6 +23,20 @@ pub mod demolish_search_tree;
 pub mod merge_search_trees;
 pub mod merge_lookup_range;
 pub mod retrieve_value;
+pub mod bulk_load;
+pub mod truncate_wal;
+pub mod gc_orphans;
+pub mod changed_blocks;
+pub mod split_off;
+pub mod iter_raw_blocks;
+pub mod verify;
+pub mod health;
+pub mod size_histograms;
+pub mod estimate_count;
+pub mod detailed_info;
+pub mod warm_range;
+pub mod tree_stats;
+pub mod delete_range_tombstones;
 
 pub enum TaskArgs<J> where J: edeltraud::Job {
     InfoButcher(info_butcher::Args),
@@ -23,7 +44,11 @@ pub enum TaskArgs<J> where J: edeltraud::Job {
     LookupButcher(lookup_butcher::Args),
     LookupRangeButcher(lookup_range_butcher::Args),
     RemoveButcher(remove_butcher::Args),
+    RemoveManyButcher(remove_many_butcher::Args),
+    RemoveReturningButcher(remove_returning_butcher::Args),
+    WriteRangeTombstone(write_range_tombstone::Args),
     FlushButcher(flush_butcher::Args),
+    FlushButcherOnly(flush_butcher_only::Args),
     InfoSearchTree(info_search_tree::Args),
     LookupSearchTree(lookup_search_tree::Args),
     LookupRangeSearchTree(lookup_range_search_tree::Args),
@@ -32,6 +57,20 @@ pub enum TaskArgs<J> where J: edeltraud::Job {
     MergeSearchTrees(merge_search_trees::Args<J>),
     MergeLookupRange(merge_lookup_range::Args),
     RetrieveValue(retrieve_value::Args),
+    BulkLoad(bulk_load::Args<J>),
+    TruncateWal(truncate_wal::Args),
+    GcOrphans(gc_orphans::Args),
+    ChangedBlocks(changed_blocks::Args),
+    SplitOff(split_off::Args),
+    IterRawBlocks(iter_raw_blocks::Args<J>),
+    Verify(verify::Args<J>),
+    Health(health::Args),
+    SizeHistograms(size_histograms::Args),
+    EstimateCount(estimate_count::Args),
+    DetailedInfo(detailed_info::Args),
+    WarmRange(warm_range::Args),
+    TreeStats(tree_stats::Args),
+    DeleteRangeTombstones(delete_range_tombstones::Args),
 }
 
 pub enum TaskDone {
@@ -40,7 +79,11 @@ pub enum TaskDone {
     LookupButcher(lookup_butcher::Done),
     LookupRangeButcher(lookup_range_butcher::Done),
     RemoveButcher(remove_butcher::Done),
+    RemoveManyButcher(remove_many_butcher::Done),
+    RemoveReturningButcher(remove_returning_butcher::Done),
+    WriteRangeTombstone(write_range_tombstone::Done),
     FlushButcher(flush_butcher::Done),
+    FlushButcherOnly(flush_butcher_only::Done),
     InfoSearchTree(info_search_tree::Done),
     LookupSearchTree(lookup_search_tree::Done),
     LookupRangeSearchTree(lookup_range_search_tree::Done),
@@ -49,6 +92,20 @@ pub enum TaskDone {
     MergeSearchTrees(merge_search_trees::Done),
     MergeLookupRange(merge_lookup_range::Done),
     RetrieveValue(retrieve_value::Done),
+    BulkLoad(bulk_load::Done),
+    TruncateWal(truncate_wal::Done),
+    GcOrphans(gc_orphans::Done),
+    ChangedBlocks(changed_blocks::Done),
+    SplitOff(split_off::Done),
+    IterRawBlocks(iter_raw_blocks::Done),
+    Verify(verify::Done),
+    Health(health::Done),
+    SizeHistograms(size_histograms::Done),
+    EstimateCount(estimate_count::Done),
+    DetailedInfo(detailed_info::Done),
+    WarmRange(warm_range::Done),
+    TreeStats(tree_stats::Done),
+    DeleteRangeTombstones(delete_range_tombstones::Done),
 }
 
 #[derive(Debug)]
@@ -58,7 +115,11 @@ pub enum Error {
     LookupButcher(lookup_butcher::Error),
     LookupRangeButcher(lookup_range_butcher::Error),
     RemoveButcher(remove_butcher::Error),
+    RemoveManyButcher(remove_many_butcher::Error),
+    RemoveReturningButcher(remove_returning_butcher::Error),
+    WriteRangeTombstone(write_range_tombstone::Error),
     FlushButcher(flush_butcher::Error),
+    FlushButcherOnly(flush_butcher_only::Error),
     InfoSearchTree(info_search_tree::Error),
     LookupSearchTree(lookup_search_tree::Error),
     LookupRangeSearchTree(lookup_range_search_tree::Error),
@@ -67,9 +128,71 @@ pub enum Error {
     MergeSearchTrees(merge_search_trees::Error),
     MergeLookupRange(merge_lookup_range::Error),
     RetrieveValue(retrieve_value::Error),
+    BulkLoad(bulk_load::Error),
+    TruncateWal(truncate_wal::Error),
+    GcOrphans(gc_orphans::Error),
+    ChangedBlocks(changed_blocks::Error),
+    SplitOff(split_off::Error),
+    IterRawBlocks(iter_raw_blocks::Error),
+    Verify(verify::Error),
+    Health(health::Error),
+    SizeHistograms(size_histograms::Error),
+    EstimateCount(estimate_count::Error),
+    DetailedInfo(detailed_info::Error),
+    WarmRange(warm_range::Error),
+    TreeStats(tree_stats::Error),
+    DeleteRangeTombstones(delete_range_tombstones::Error),
+}
+
+impl<J> TaskArgs<J> where J: edeltraud::Job {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TaskArgs::InfoButcher(..) => "InfoButcher",
+            TaskArgs::InsertButcher(..) => "InsertButcher",
+            TaskArgs::LookupButcher(..) => "LookupButcher",
+            TaskArgs::LookupRangeButcher(..) => "LookupRangeButcher",
+            TaskArgs::RemoveButcher(..) => "RemoveButcher",
+            TaskArgs::RemoveManyButcher(..) => "RemoveManyButcher",
+            TaskArgs::RemoveReturningButcher(..) => "RemoveReturningButcher",
+            TaskArgs::WriteRangeTombstone(..) => "WriteRangeTombstone",
+            TaskArgs::FlushButcher(..) => "FlushButcher",
+            TaskArgs::FlushButcherOnly(..) => "FlushButcherOnly",
+            TaskArgs::InfoSearchTree(..) => "InfoSearchTree",
+            TaskArgs::LookupSearchTree(..) => "LookupSearchTree",
+            TaskArgs::LookupRangeSearchTree(..) => "LookupRangeSearchTree",
+            TaskArgs::FlushSearchTree(..) => "FlushSearchTree",
+            TaskArgs::DemolishSearchTree(..) => "DemolishSearchTree",
+            TaskArgs::MergeSearchTrees(..) => "MergeSearchTrees",
+            TaskArgs::MergeLookupRange(..) => "MergeLookupRange",
+            TaskArgs::RetrieveValue(..) => "RetrieveValue",
+            TaskArgs::BulkLoad(..) => "BulkLoad",
+            TaskArgs::TruncateWal(..) => "TruncateWal",
+            TaskArgs::GcOrphans(..) => "GcOrphans",
+            TaskArgs::ChangedBlocks(..) => "ChangedBlocks",
+            TaskArgs::SplitOff(..) => "SplitOff",
+            TaskArgs::IterRawBlocks(..) => "IterRawBlocks",
+            TaskArgs::Verify(..) => "Verify",
+            TaskArgs::Health(..) => "Health",
+            TaskArgs::SizeHistograms(..) => "SizeHistograms",
+            TaskArgs::EstimateCount(..) => "EstimateCount",
+            TaskArgs::DetailedInfo(..) => "DetailedInfo",
+            TaskArgs::WarmRange(..) => "WarmRange",
+            TaskArgs::TreeStats(..) => "TreeStats",
+            TaskArgs::DeleteRangeTombstones(..) => "DeleteRangeTombstones",
+        }
+    }
+}
+
+pub fn run_args<J>(args: TaskArgs<J>) -> impl Future<Output = Result<TaskDone, Error>>
+where J: edeltraud::Job + From<job::Job>,
+      J::Output: From<job::JobOutput>,
+      job::JobOutput: From<J::Output>,
+{
+    let span = trace::task_span(args.kind());
+    trace::instrument(run_args_inner(args), span)
 }
 
-pub async fn run_args<J>(args: TaskArgs<J>) -> Result<TaskDone, Error>
+async fn run_args_inner<J>(args: TaskArgs<J>) -> Result<TaskDone, Error>
 where J: edeltraud::Job + From<job::Job>,
       J::Output: From<job::JobOutput>,
       job::JobOutput: From<J::Output>,
@@ -100,11 +223,31 @@ where J: edeltraud::Job + From<job::Job>,
                 remove_butcher::run(args).await
                     .map_err(Error::RemoveButcher)?,
             ),
+        TaskArgs::RemoveManyButcher(args) =>
+            TaskDone::RemoveManyButcher(
+                remove_many_butcher::run(args).await
+                    .map_err(Error::RemoveManyButcher)?,
+            ),
+        TaskArgs::RemoveReturningButcher(args) =>
+            TaskDone::RemoveReturningButcher(
+                remove_returning_butcher::run(args).await
+                    .map_err(Error::RemoveReturningButcher)?,
+            ),
+        TaskArgs::WriteRangeTombstone(args) =>
+            TaskDone::WriteRangeTombstone(
+                write_range_tombstone::run(args).await
+                    .map_err(Error::WriteRangeTombstone)?,
+            ),
         TaskArgs::FlushButcher(args) =>
             TaskDone::FlushButcher(
                 flush_butcher::run(args).await
                     .map_err(Error::FlushButcher)?,
             ),
+        TaskArgs::FlushButcherOnly(args) =>
+            TaskDone::FlushButcherOnly(
+                flush_butcher_only::run(args).await
+                    .map_err(Error::FlushButcherOnly)?,
+            ),
         TaskArgs::InfoSearchTree(args) =>
             TaskDone::InfoSearchTree(
                 info_search_tree::run(args).await
@@ -145,5 +288,75 @@ where J: edeltraud::Job + From<job::Job>,
                 retrieve_value::run(args).await
                     .map_err(Error::RetrieveValue)?,
             ),
+        TaskArgs::BulkLoad(args) =>
+            TaskDone::BulkLoad(
+                bulk_load::run(args).await
+                    .map_err(Error::BulkLoad)?,
+            ),
+        TaskArgs::TruncateWal(args) =>
+            TaskDone::TruncateWal(
+                truncate_wal::run(args).await
+                    .map_err(Error::TruncateWal)?,
+            ),
+        TaskArgs::GcOrphans(args) =>
+            TaskDone::GcOrphans(
+                gc_orphans::run(args).await
+                    .map_err(Error::GcOrphans)?,
+            ),
+        TaskArgs::ChangedBlocks(args) =>
+            TaskDone::ChangedBlocks(
+                changed_blocks::run(args).await
+                    .map_err(Error::ChangedBlocks)?,
+            ),
+        TaskArgs::SplitOff(args) =>
+            TaskDone::SplitOff(
+                split_off::run(args).await
+                    .map_err(Error::SplitOff)?,
+            ),
+        TaskArgs::IterRawBlocks(args) =>
+            TaskDone::IterRawBlocks(
+                iter_raw_blocks::run(args).await
+                    .map_err(Error::IterRawBlocks)?,
+            ),
+        TaskArgs::Verify(args) =>
+            TaskDone::Verify(
+                verify::run(args).await
+                    .map_err(Error::Verify)?,
+            ),
+        TaskArgs::Health(args) =>
+            TaskDone::Health(
+                health::run(args).await
+                    .map_err(Error::Health)?,
+            ),
+        TaskArgs::SizeHistograms(args) =>
+            TaskDone::SizeHistograms(
+                size_histograms::run(args).await
+                    .map_err(Error::SizeHistograms)?,
+            ),
+        TaskArgs::EstimateCount(args) =>
+            TaskDone::EstimateCount(
+                estimate_count::run(args).await
+                    .map_err(Error::EstimateCount)?,
+            ),
+        TaskArgs::DetailedInfo(args) =>
+            TaskDone::DetailedInfo(
+                detailed_info::run(args).await
+                    .map_err(Error::DetailedInfo)?,
+            ),
+        TaskArgs::WarmRange(args) =>
+            TaskDone::WarmRange(
+                warm_range::run(args).await
+                    .map_err(Error::WarmRange)?,
+            ),
+        TaskArgs::TreeStats(args) =>
+            TaskDone::TreeStats(
+                tree_stats::run(args).await
+                    .map_err(Error::TreeStats)?,
+            ),
+        TaskArgs::DeleteRangeTombstones(args) =>
+            TaskDone::DeleteRangeTombstones(
+                delete_range_tombstones::run(args).await
+                    .map_err(Error::DeleteRangeTombstones)?,
+            ),
     })
 }
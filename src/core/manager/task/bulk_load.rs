@@ -0,0 +1,357 @@
+use std::{
+    cmp,
+    sync::Arc,
+    time::{
+        Instant,
+        Duration,
+    },
+};
+
+use futures::{
+    channel::{
+        oneshot,
+    },
+};
+
+use alloc_pool::{
+    pool,
+    bytes::{
+        Bytes,
+        BytesPool,
+    },
+    Unique,
+};
+
+use crate::{
+    kv,
+    job,
+    wheels,
+    storage,
+    metrics,
+    blockwheel,
+    core::{
+        manager,
+        block_sizer,
+        BlockRef,
+        level_merger::KeyRange,
+    },
+    comparator::KeyComparatorRef,
+    Inserted,
+    Compression,
+    StoragePreference,
+};
+
+pub struct Args<J> where J: edeltraud::Job {
+    pub prev_key: Option<kv::Key>,
+    pub items: Arc<Vec<(kv::Key, kv::Value)>>,
+    pub version: u64,
+    pub reply_tx: oneshot::Sender<Result<Inserted, manager::BulkLoadError>>,
+    pub thread_pool: edeltraud::Edeltraud<J>,
+    pub background_gate: job::BackgroundGate,
+    pub blocks_pool: BytesPool,
+    pub merge_blocks_pool: pool::Pool<Vec<storage::OwnedEntry>>,
+    pub wheels_pid: wheels::Pid,
+    pub block_budget: block_sizer::BlockBudget,
+    pub values_inline_size_limit: usize,
+    pub block_compression: Option<Compression>,
+    pub store_id: u32,
+    pub key_comparator: KeyComparatorRef,
+    pub metrics: metrics::MetricsRef,
+}
+
+pub enum Done {
+    Loaded(Loaded),
+    // the client is already notified and nothing is written
+    KeysNotAscending,
+}
+
+pub struct Loaded {
+    pub root_block: BlockRef,
+    pub items_count: usize,
+    pub items_bytes: usize,
+    pub key_range: Option<KeyRange>,
+    pub version: u64,
+    pub reply_tx: oneshot::Sender<Result<Inserted, manager::BulkLoadError>>,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    WheelsGone,
+    WheelsEmpty,
+    ThreadPoolGone,
+    BuildTreeUnexpectedEmptyTree,
+    BlockSerializerStart(storage::Error),
+    BlockSerializerEntry(storage::Error),
+    SerializeValueBlockStorage(storage::Error),
+    CompressBlock(storage::Error),
+    WriteBlock(blockwheel::WriteBlockError),
+}
+
+pub type JobOutput = Result<JobDone, Error>;
+
+pub struct JobArgs {
+    block_entries: Unique<Vec<storage::OwnedEntry>>,
+    node_type: storage::NodeType,
+    blocks_pool: BytesPool,
+    block_compression: Option<Compression>,
+    store_id: u32,
+}
+
+pub struct JobDone {
+    block_bytes: Bytes,
+}
+
+pub fn job(JobArgs { mut block_entries, node_type, blocks_pool, block_compression, store_id, }: JobArgs) -> JobOutput {
+    let block_bytes = blocks_pool.lend();
+    let items_count = block_entries.len();
+
+    let mut block_serializer_kont = storage::BlockSerializer::start(node_type, items_count, store_id, block_bytes)
+        .map_err(Error::BlockSerializerStart)?;
+    for ref owned_entry in block_entries.drain(..) {
+        match block_serializer_kont {
+            storage::BlockSerializerContinue::More(block_serializer) => {
+                block_serializer_kont = block_serializer.entry(owned_entry.into())
+                    .map_err(Error::BlockSerializerEntry)?;
+            },
+            storage::BlockSerializerContinue::Done(..) =>
+                unreachable!(),
+        }
+    }
+    match block_serializer_kont {
+        storage::BlockSerializerContinue::Done(block_bytes) =>
+            match block_compression {
+                None =>
+                    Ok(JobDone { block_bytes: block_bytes.freeze(), }),
+                Some(ref compression) => {
+                    let mut compressed_bytes = blocks_pool.lend();
+                    storage::block_compress(&block_bytes, compression, &mut compressed_bytes)
+                        .map_err(Error::CompressBlock)?;
+                    Ok(JobDone { block_bytes: compressed_bytes.freeze(), })
+                },
+            },
+        storage::BlockSerializerContinue::More(..) =>
+            unreachable!(),
+    }
+}
+
+pub async fn run<J>(
+    Args {
+        prev_key,
+        items,
+        version,
+        reply_tx,
+        thread_pool,
+        background_gate,
+        blocks_pool,
+        merge_blocks_pool,
+        mut wheels_pid,
+        block_budget,
+        values_inline_size_limit,
+        block_compression,
+        store_id,
+        key_comparator,
+        metrics,
+    }: Args<J>,
+)
+    -> Result<Done, Error>
+where J: edeltraud::Job + From<job::Job>,
+      J::Output: From<job::JobOutput>,
+      job::JobOutput: From<J::Output>,
+{
+    let run_start = Instant::now();
+    let mut last_key = prev_key.as_ref();
+    for (key, _value) in items.iter() {
+        if let Some(last_key) = last_key {
+            if key_comparator.compare(&last_key.key_bytes, &key.key_bytes) != cmp::Ordering::Less {
+                let error = manager::BulkLoadError::KeysNotAscending { prev_key: last_key.clone(), key: key.clone(), };
+                if let Err(_send_error) = reply_tx.send(Err(error)) {
+                    log::warn!("client canceled bulk load request");
+                }
+                return Ok(Done::KeysNotAscending);
+            }
+        }
+        last_key = Some(key);
+    }
+
+    let comparator_id = key_comparator.id();
+    let tree_items_count = items.len();
+
+    struct BlockInProgress {
+        block_entries: Unique<Vec<storage::OwnedEntry>>,
+        wheel_ref: wheels::WheelRef,
+    }
+
+    let block_writer = BlockWriter {
+        thread_pool: &thread_pool,
+        background_gate: &background_gate,
+        blocks_pool: &blocks_pool,
+        block_compression,
+        store_id,
+        metrics: &metrics,
+    };
+    let mut block_cutter = block_sizer::BlockCutter::new(block_budget);
+    let mut levels: Vec<Option<BlockInProgress>> = Vec::new();
+    let mut cut_block: Option<(usize, BlockInProgress)> = None;
+
+    for (key, value) in items.iter() {
+        // a cut block is written once an entry above all of its keys is there to jump to it
+        let child_ref = match cut_block.take() {
+            None =>
+                None,
+            Some((_level_index, BlockInProgress { block_entries, mut wheel_ref, })) => {
+                let block_ref = block_writer.write(block_entries, storage::NodeType::Leaf, &mut wheel_ref).await?;
+                Some(block_ref)
+            },
+        };
+
+        let level_index = block_cutter.next_level();
+        if levels.len() <= level_index {
+            levels.resize_with(level_index + 1, || None);
+        }
+        let BlockInProgress { mut block_entries, mut wheel_ref, } = match levels[level_index].take() {
+            Some(block) =>
+                block,
+            None => {
+                let mut block_entries = merge_blocks_pool.lend(Vec::new);
+                block_entries.clear();
+                let wheel_ref = wheels_pid.acquire().await
+                    .map_err(|ero::NoProcError| Error::WheelsGone)?
+                    .ok_or(Error::WheelsEmpty)?;
+                BlockInProgress { block_entries, wheel_ref, }
+            },
+        };
+
+        let value_ref = if storage::value_out_of_line(&value.value_bytes, StoragePreference::Auto, values_inline_size_limit) {
+            let mut value_block_bytes = blocks_pool.lend();
+            storage::value_block_serialize(&value.value_bytes, store_id, &mut value_block_bytes)
+                .map_err(Error::SerializeValueBlockStorage)?;
+            let block_size = value_block_bytes.len();
+            let block_id = wheel_ref.blockwheel_pid.write_block(value_block_bytes.freeze()).await
+                .map_err(Error::WriteBlock)?;
+            metrics.on_block_write(block_size);
+            storage::OwnedValueRef::Local(storage::LocalRef { block_id, })
+        } else {
+            storage::OwnedValueRef::Inline(value.clone())
+        };
+
+        let owned_jump_ref = match child_ref {
+            None =>
+                storage::OwnedJumpRef::None,
+            Some(BlockRef { blockwheel_filename, block_id, }) if blockwheel_filename == wheel_ref.blockwheel_filename =>
+                storage::OwnedJumpRef::Local(storage::LocalRef { block_id, }),
+            Some(block_ref) =>
+                storage::OwnedJumpRef::External(block_ref),
+        };
+        let owned_entry = storage::OwnedEntry {
+            jump_ref: owned_jump_ref,
+            key: key.clone(),
+            value_cell: kv::ValueCell {
+                version,
+                cell: kv::Cell::Value(value_ref),
+            },
+        };
+        let entry_bytes = owned_entry.serialized_size()
+            .map_err(Error::BlockSerializerEntry)?;
+        block_entries.push(owned_entry);
+        let block = BlockInProgress { block_entries, wheel_ref, };
+        if block_cutter.push(entry_bytes) {
+            cut_block = Some((level_index, block));
+        } else {
+            levels[level_index] = Some(block);
+        }
+    }
+
+    // the last cut block has no greater entry to jump to it, so its entries join the root
+    if let Some((level_index, block)) = cut_block.take() {
+        levels[level_index] = Some(block);
+    }
+    let mut maybe_root: Option<BlockInProgress> = None;
+    for BlockInProgress { mut block_entries, wheel_ref, } in levels.into_iter().rev().flatten() {
+        maybe_root = Some(match maybe_root.take() {
+            None =>
+                BlockInProgress { block_entries, wheel_ref, },
+            Some(mut root) => {
+                for owned_entry in block_entries.drain(..) {
+                    root.block_entries.push(owned_entry.retarget(&wheel_ref.blockwheel_filename, &root.wheel_ref.blockwheel_filename));
+                }
+                root
+            },
+        });
+    }
+    let BlockInProgress { block_entries, mut wheel_ref, } = maybe_root
+        .ok_or(Error::BuildTreeUnexpectedEmptyTree)?;
+    let node_type = storage::NodeType::Root {
+        tree_entries_count: tree_items_count,
+        max_version: version,
+        created_at_version: version,
+        comparator_id,
+    };
+    let root_block = block_writer.write(block_entries, node_type, &mut wheel_ref).await?;
+
+    let items_bytes = items.iter()
+        .map(|(key, value)| key.key_bytes.len() + value.value_bytes.len())
+        .sum();
+    let key_range = match (items.first(), items.last()) {
+        (Some((first, _)), Some((last, _))) =>
+            Some(KeyRange { first: first.clone(), last: last.clone(), }),
+        _ =>
+            None,
+    };
+
+    Ok(Done::Loaded(Loaded {
+        root_block,
+        items_count: tree_items_count,
+        items_bytes,
+        key_range,
+        version,
+        reply_tx,
+        elapsed: run_start.elapsed(),
+    }))
+}
+
+struct BlockWriter<'a, J> where J: edeltraud::Job {
+    thread_pool: &'a edeltraud::Edeltraud<J>,
+    background_gate: &'a job::BackgroundGate,
+    blocks_pool: &'a BytesPool,
+    block_compression: Option<Compression>,
+    store_id: u32,
+    metrics: &'a metrics::MetricsRef,
+}
+
+impl<'a, J> BlockWriter<'a, J>
+where J: edeltraud::Job + From<job::Job>,
+      J::Output: From<job::JobOutput>,
+      job::JobOutput: From<J::Output>,
+{
+    async fn write(
+        &self,
+        block_entries: Unique<Vec<storage::OwnedEntry>>,
+        node_type: storage::NodeType,
+        wheel_ref: &mut wheels::WheelRef,
+    )
+        -> Result<BlockRef, Error>
+    {
+        let job_args = JobArgs {
+            block_entries,
+            node_type,
+            blocks_pool: self.blocks_pool.clone(),
+            block_compression: self.block_compression,
+            store_id: self.store_id,
+        };
+        let job_output = self.background_gate.spawn(self.thread_pool, job::Job::BulkLoad(job_args)).await
+            .map_err(|edeltraud::SpawnError::ThreadPoolGone| Error::ThreadPoolGone)?;
+        let job_output: job::JobOutput = job_output.into();
+        let job::BulkLoadDone(job_result) = job_output.into();
+        let JobDone { block_bytes, } = job_result?;
+
+        let block_size = block_bytes.len();
+        let block_id = wheel_ref.blockwheel_pid.write_block(block_bytes).await
+            .map_err(Error::WriteBlock)?;
+        self.metrics.on_block_write(block_size);
+        Ok(BlockRef {
+            blockwheel_filename: wheel_ref.blockwheel_filename.clone(),
+            block_id,
+        })
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::{
+    HashSet,
+};
+
+use futures::{
+    channel::{
+        oneshot,
+    },
+    StreamExt,
+};
+
+use crate::{
+    kv,
+    storage,
+    core::{
+        search_tree,
+        BlockRef,
+        KeyValueRef,
+        SearchRangeBounds,
+    },
+    Flushed,
+};
+
+pub struct Args {
+    // served trees holding entries above the version floor, picked by the manager from their max versions
+    pub search_tree_pids: Vec<search_tree::Pid>,
+    pub reply_tx: oneshot::Sender<Vec<BlockRef>>,
+}
+
+pub struct Done {
+    pub changed_blocks: Vec<BlockRef>,
+    pub reply_tx: oneshot::Sender<Vec<BlockRef>>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    SearchTreeFlush(search_tree::FlushError),
+    SearchTreeIter(search_tree::IterError),
+    SearchTreeIterInterrupted,
+}
+
+// only live trees are walked, through their own (checksum verifying) block loads: blocks of demolished
+// or superseded trees still on the wheels are never reported
+pub async fn run(Args { search_tree_pids, reply_tx, }: Args) -> Result<Done, Error> {
+    let mut visited = HashSet::new();
+    let mut changed_blocks = Vec::new();
+    for mut search_tree_pid in search_tree_pids {
+        // blocks of a tree being bootstrapped are not written yet
+        let Flushed { .. } = search_tree_pid.flush().await
+            .map_err(Error::SearchTreeFlush)?;
+        let search_tree::SearchTreeIterItemsRx { mut items_rx, .. } = search_tree_pid.iter(SearchRangeBounds::unbounded()).await
+            .map_err(Error::SearchTreeIter)?;
+        loop {
+            let block_ref = match items_rx.next().await {
+                None =>
+                    return Err(Error::SearchTreeIterInterrupted),
+                Some(KeyValueRef::Item { value_cell: kv::ValueCell { cell: kv::Cell::Value(storage::OwnedValueBlockRef::Ref(block_ref)), .. }, .. }) =>
+                    block_ref,
+                Some(KeyValueRef::Item { .. }) =>
+                    continue,
+                Some(KeyValueRef::BlockFinish { block_ref, .. }) =>
+                    block_ref,
+                Some(KeyValueRef::NoMore) =>
+                    break,
+            };
+            if visited.insert(block_ref.clone()) {
+                changed_blocks.push(block_ref);
+            }
+        }
+    }
+
+    Ok(Done { changed_blocks, reply_tx, })
+}
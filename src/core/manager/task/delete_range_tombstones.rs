@@ -0,0 +1,55 @@
+use futures::{
+    channel::{
+        oneshot,
+    },
+};
+
+use crate::{
+    wheels,
+    blockwheel,
+    core::{
+        BlockRef,
+    },
+};
+
+pub struct Args {
+    pub inputs_demolished_rxs: Vec<oneshot::Receiver<()>>,
+    pub block_refs: Vec<BlockRef>,
+    pub wheels_pid: wheels::Pid,
+}
+
+pub struct Done {
+    pub blocks_count: usize,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    WheelsGone,
+    WheelNotFound {
+        blockwheel_filename: wheels::WheelFilename,
+    },
+    DeleteBlock(blockwheel::DeleteBlockError),
+}
+
+pub async fn run(Args { inputs_demolished_rxs, block_refs, mut wheels_pid, }: Args) -> Result<Done, Error> {
+    // merge inputs still on disk would be loaded again after a restart with the cells the tombstones shadow,
+    // and only the merge record tells them superseded
+    for inputs_demolished_rx in inputs_demolished_rxs {
+        if let Err(oneshot::Canceled) = inputs_demolished_rx.await {
+            log::warn!("merge input has not been demolished: keeping {} range tombstone and merge record blocks", block_refs.len());
+            return Ok(Done { blocks_count: 0, });
+        }
+    }
+
+    let blocks_count = block_refs.len();
+    for block_ref in block_refs {
+        let mut wheel_ref = wheels_pid.get(block_ref.blockwheel_filename.clone()).await
+            .map_err(|ero::NoProcError| Error::WheelsGone)?
+            .ok_or_else(|| Error::WheelNotFound {
+                blockwheel_filename: block_ref.blockwheel_filename.clone(),
+            })?;
+        let blockwheel::Deleted = wheel_ref.blockwheel_pid.delete_block(block_ref.block_id).await
+            .map_err(Error::DeleteBlock)?;
+    }
+    Ok(Done { blocks_count, })
+}
@@ -1,3 +1,9 @@
+use futures::{
+    channel::{
+        oneshot,
+    },
+};
+
 use crate::{
     core::{
         search_tree,
@@ -6,6 +12,8 @@ use crate::{
 
 pub struct Args {
     pub search_tree_pid: search_tree::Pid,
+    // fired once every block of the tree is freed
+    pub demolished_tx: Option<oneshot::Sender<()>>,
 }
 
 pub struct Done;
@@ -15,10 +23,13 @@ pub enum Error {
     SearchTreeDemolish(search_tree::DemolishError),
 }
 
-pub async fn run(Args { mut search_tree_pid, }: Args) -> Result<Done, Error> {
+pub async fn run(Args { mut search_tree_pid, demolished_tx, }: Args) -> Result<Done, Error> {
     log::debug!("spawned task, requesting demolish");
     let search_tree::Demolished = search_tree_pid.demolish().await
         .map_err(Error::SearchTreeDemolish)?;
+    if let Some(demolished_tx) = demolished_tx {
+        demolished_tx.send(()).ok();
+    }
     log::debug!("task done");
     Ok(Done)
 }
@@ -0,0 +1,41 @@
+use futures::{
+    channel::{
+        oneshot,
+    },
+};
+
+use crate::{
+    core::{
+        search_tree,
+        SearchRangeBounds,
+    },
+    DetailedInfo,
+};
+
+pub struct Args {
+    pub reply_tx: oneshot::Sender<DetailedInfo>,
+    pub detailed_info: DetailedInfo,
+    // same order as `detailed_info.search_trees`
+    pub search_tree_pids: Vec<search_tree::Pid>,
+}
+
+pub struct Done {
+    pub detailed_info: DetailedInfo,
+    pub reply_tx: oneshot::Sender<DetailedInfo>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+}
+
+pub async fn run(Args { reply_tx, mut detailed_info, search_tree_pids, }: Args) -> Result<Done, Error> {
+    for (details, mut search_tree_pid) in detailed_info.search_trees.iter_mut().zip(search_tree_pids) {
+        match search_tree_pid.info(SearchRangeBounds::from(..)).await {
+            Ok(info) =>
+                details.disk_bytes = info.disk_bytes_total,
+            Err(ero::NoProcError) =>
+                log::warn!("search tree is gone: reporting no disk bytes for it in detailed info"),
+        }
+    }
+    Ok(Done { detailed_info, reply_tx, })
+}
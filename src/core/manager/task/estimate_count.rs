@@ -0,0 +1,53 @@
+use futures::{
+    channel::{
+        oneshot,
+    },
+};
+
+use crate::{
+    core::{
+        butcher,
+        search_tree,
+        SearchRangeBounds,
+    },
+};
+
+pub struct Args {
+    pub reply_tx: oneshot::Sender<usize>,
+    pub range: SearchRangeBounds,
+    pub butcher_pid: butcher::Pid,
+    pub search_tree_pids: Vec<search_tree::Pid>,
+}
+
+pub struct Done {
+    pub estimate: usize,
+    pub reply_tx: oneshot::Sender<usize>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+}
+
+// butcher entries are counted exactly while every search tree adds its own estimate: a key living
+// in several of them is counted once per place, as are its tombstones
+pub async fn run(Args { reply_tx, range, mut butcher_pid, search_tree_pids, }: Args) -> Result<Done, Error> {
+    let mut estimate = match butcher_pid.info(range.clone()).await {
+        Ok(info) =>
+            info.butcher_entries,
+        Err(ero::NoProcError) => {
+            log::warn!("butcher is gone: skipping it in count estimate");
+            0
+        },
+    };
+
+    for mut search_tree_pid in search_tree_pids {
+        match search_tree_pid.estimate_count(range.clone()).await {
+            Ok(tree_estimate) =>
+                estimate += tree_estimate,
+            Err(ero::NoProcError) =>
+                log::warn!("search tree is gone: skipping it in count estimate"),
+        }
+    }
+
+    Ok(Done { estimate, reply_tx, })
+}
@@ -15,6 +15,7 @@ pub struct Args {
 
 pub struct Done {
     pub request_ref: Ref,
+    pub flushed: Flushed,
 }
 
 #[derive(Debug)]
@@ -23,7 +24,7 @@ pub enum Error {
 }
 
 pub async fn run(Args { request_ref, mut butcher_pid, }: Args) -> Result<Done, Error> {
-    let Flushed = butcher_pid.flush().await
+    let flushed = butcher_pid.flush().await
         .map_err(Error::ButcherFlush)?;
-    Ok(Done { request_ref, })
+    Ok(Done { request_ref, flushed, })
 }
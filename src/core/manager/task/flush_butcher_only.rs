@@ -0,0 +1,38 @@
+use futures::{
+    channel::{
+        oneshot,
+    },
+};
+
+use crate::{
+    core::{
+        butcher,
+    },
+    Flushed,
+};
+
+pub struct Args {
+    pub reply_tx: oneshot::Sender<Flushed>,
+    pub butcher_pid: butcher::Pid,
+}
+
+pub struct Done {
+    pub flushed: Flushed,
+    pub flush_generation: u64,
+    pub reply_tx: oneshot::Sender<Flushed>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    ButcherFlush(ero::NoProcError),
+    ButcherFlushGeneration(ero::NoProcError),
+}
+
+pub async fn run(Args { reply_tx, mut butcher_pid, }: Args) -> Result<Done, Error> {
+    let flushed = butcher_pid.flush().await
+        .map_err(Error::ButcherFlush)?;
+    // a concurrent flush may only move the generation forward, so waiting for it is still enough
+    let flush_generation = butcher_pid.flush_generation().await
+        .map_err(Error::ButcherFlushGeneration)?;
+    Ok(Done { flushed, flush_generation, reply_tx, })
+}
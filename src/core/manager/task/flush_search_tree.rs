@@ -23,7 +23,7 @@ pub enum Error {
 }
 
 pub async fn run(Args { request_ref, mut search_tree_pid, }: Args) -> Result<Done, Error> {
-    let Flushed = search_tree_pid.flush().await
+    let Flushed { .. } = search_tree_pid.flush().await
         .map_err(Error::SearchTreeFlush)?;
     Ok(Done { request_ref, })
 }
@@ -0,0 +1,189 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+
+use futures::{
+    StreamExt,
+};
+
+use alloc_pool::bytes::{
+    Bytes,
+    BytesPool,
+};
+
+use crate::{
+    kv,
+    wheels,
+    storage,
+    blockwheel,
+    core::{
+        search_tree,
+        BlockRef,
+    },
+    Flushed,
+    OrphansCollected,
+};
+
+pub struct Args {
+    pub search_tree_pids: Vec<search_tree::Pid>,
+    // quarantined trees are out of the manager but still hold data, so their roots are kept as well
+    pub quarantined_roots: Vec<BlockRef>,
+    pub wheels_pid: wheels::Pid,
+    pub blocks_pool: BytesPool,
+    pub store_id: u32,
+}
+
+pub struct Done {
+    pub orphans_collected: OrphansCollected,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    SearchTreeFlush(search_tree::FlushError),
+    SearchTreeRootBlock(search_tree::RootBlockError),
+    SearchTreeNotBootstrapped,
+    WheelsIterBlocks(wheels::IterBlocksError),
+    WheelsIterBlocksRxDropped,
+    WheelsGone,
+    WheelNotFound {
+        blockwheel_filename: wheels::WheelFilename,
+    },
+    DeleteBlock(blockwheel::DeleteBlockError),
+}
+
+pub enum BlockKind {
+    Tree { node_type: storage::NodeType, refs: Vec<BlockRef>, },
+    Value,
+    Other,
+}
+
+// only roots of the trees the manager serves are live: inputs a load has skipped as superseded by a
+// merge record are collected together with their blocks, as well as a merge output without a root
+pub async fn run(Args { search_tree_pids, quarantined_roots, mut wheels_pid, blocks_pool, store_id, }: Args) -> Result<Done, Error> {
+    let mut live_roots = quarantined_roots;
+    // blocks of a tree being bootstrapped are not reachable from its root yet
+    for mut search_tree_pid in search_tree_pids {
+        let Flushed { .. } = search_tree_pid.flush().await
+            .map_err(Error::SearchTreeFlush)?;
+        let root_block = search_tree_pid.root_block().await
+            .map_err(Error::SearchTreeRootBlock)?
+            .ok_or(Error::SearchTreeNotBootstrapped)?;
+        live_roots.push(root_block);
+    }
+
+    let mut iter_blocks = wheels_pid.iter_blocks().await
+        .map_err(Error::WheelsIterBlocks)?;
+
+    let mut blocks_total = 0;
+    let mut corrupt_count = 0;
+    let mut tree_blocks = HashMap::new();
+    let mut value_blocks = HashSet::new();
+    loop {
+        match iter_blocks.block_refs_rx.next().await {
+            None =>
+                return Err(Error::WheelsIterBlocksRxDropped),
+            Some(wheels::IterBlocksItem::Block { block_ref, block_bytes, }) => {
+                blocks_total += 1;
+                match classify_block(&block_ref, block_bytes, &blocks_pool, store_id) {
+                    Ok(BlockKind::Tree { refs, .. }) => {
+                        tree_blocks.insert(block_ref, refs);
+                    },
+                    Ok(BlockKind::Value) => {
+                        value_blocks.insert(block_ref);
+                    },
+                    Ok(BlockKind::Other) =>
+                        (),
+                    Err(error) => {
+                        log::warn!("corrupt block {:?} encountered during orphans collection: {:?}", block_ref, error);
+                        corrupt_count += 1;
+                    },
+                }
+            },
+            Some(wheels::IterBlocksItem::NoMoreBlocks) =>
+                break,
+        }
+    }
+
+    // refs of a corrupt block cannot be traced, so everything below it would look orphaned
+    if corrupt_count > 0 {
+        log::warn!("{} corrupt blocks found: skipping orphans removal", corrupt_count);
+        return Ok(Done { orphans_collected: OrphansCollected { blocks_total, blocks_deleted: 0, }, });
+    }
+
+    let mut reachable = HashSet::new();
+    let mut pending = live_roots;
+    while let Some(block_ref) = pending.pop() {
+        if !reachable.insert(block_ref.clone()) {
+            continue;
+        }
+        if let Some(refs) = tree_blocks.get(&block_ref) {
+            pending.extend(refs.iter().cloned());
+        }
+    }
+
+    let mut blocks_deleted = 0;
+    let orphans = tree_blocks.keys()
+        .chain(value_blocks.iter())
+        .filter(|block_ref| !reachable.contains(*block_ref));
+    for block_ref in orphans {
+        let mut wheel_ref = wheels_pid.get(block_ref.blockwheel_filename.clone()).await
+            .map_err(|ero::NoProcError| Error::WheelsGone)?
+            .ok_or_else(|| Error::WheelNotFound {
+                blockwheel_filename: block_ref.blockwheel_filename.clone(),
+            })?;
+        let blockwheel::Deleted = wheel_ref.blockwheel_pid.delete_block(block_ref.block_id.clone()).await
+            .map_err(Error::DeleteBlock)?;
+        log::debug!("orphan deleted: {:?}", block_ref);
+        blocks_deleted += 1;
+    }
+
+    Ok(Done { orphans_collected: OrphansCollected { blocks_total, blocks_deleted, }, })
+}
+
+// blocks of other stores sharing the wheels are reported as `Other`, so they are never collected
+pub fn classify_block(block_ref: &BlockRef, block_bytes: Bytes, blocks_pool: &BytesPool, store_id: u32) -> Result<BlockKind, storage::Error> {
+    let block_bytes = storage::block_decompress(block_bytes, blocks_pool)?;
+    match storage::block_verify_checksum(&block_bytes, block_ref) {
+        Ok(()) =>
+            (),
+        Err(storage::Error::UnsupportedFormatVersion { .. }) =>
+            return Ok(BlockKind::Other),
+        Err(error) =>
+            return Err(error),
+    }
+    let deserializer = match storage::block_deserialize_iter(&block_bytes) {
+        Ok(deserializer) if deserializer.store_id() != store_id =>
+            return Ok(BlockKind::Other),
+        Ok(deserializer) =>
+            deserializer,
+        Err(storage::Error::InvalidBlockMagic { provided, .. }) if storage::magic_store_id(provided, storage::VALUE_BLOCK_MAGIC) == Some(store_id) =>
+            return Ok(BlockKind::Value),
+        // wal records, range tombstones and unknown blocks are never collected
+        Err(storage::Error::InvalidBlockMagic { .. }) | Err(storage::Error::UnsupportedFormatVersion { .. }) =>
+            return Ok(BlockKind::Other),
+        Err(error) =>
+            return Err(error),
+    };
+    let node_type = deserializer.block_header().node_type;
+    let mut refs = Vec::new();
+    for maybe_entry in deserializer {
+        let entry = maybe_entry?;
+        let owned_entry = storage::OwnedEntry::from_entry(&entry, &block_bytes);
+        match owned_entry.jump_ref {
+            storage::OwnedJumpRef::None =>
+                (),
+            storage::OwnedJumpRef::Local(storage::LocalRef { block_id, }) =>
+                refs.push(BlockRef { blockwheel_filename: block_ref.blockwheel_filename.clone(), block_id, }),
+            storage::OwnedJumpRef::External(jump_block_ref) =>
+                refs.push(jump_block_ref),
+        }
+        if let kv::Cell::Value(value_ref) = owned_entry.value_cell.cell {
+            let value_block_ref = storage::OwnedValueBlockRef::from_owned_value_ref(value_ref, &block_ref.blockwheel_filename);
+            if let storage::OwnedValueBlockRef::Ref(value_block_ref) = value_block_ref {
+                refs.push(value_block_ref);
+            }
+        }
+    }
+    Ok(BlockKind::Tree { node_type, refs, })
+}
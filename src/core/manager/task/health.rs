@@ -0,0 +1,69 @@
+use futures::{
+    channel::{
+        oneshot,
+    },
+};
+
+use crate::{
+    kv,
+    core::{
+        butcher,
+        search_tree,
+    },
+    Health,
+};
+
+pub struct Args {
+    pub reply_tx: oneshot::Sender<Health>,
+    pub search_trees_count: usize,
+    pub quarantined_search_trees: usize,
+    pub butcher_pid: butcher::Pid,
+    pub search_tree_pid: Option<search_tree::Pid>,
+}
+
+pub struct Done {
+    pub health: Health,
+    pub reply_tx: oneshot::Sender<Health>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+}
+
+// unlike most tasks an unreachable peer is an outcome to report rather than a failure
+pub async fn run(Args { reply_tx, search_trees_count, quarantined_search_trees, mut butcher_pid, search_tree_pid, }: Args) -> Result<Done, Error> {
+    let mut last_error = None;
+
+    let butcher_reachable = match butcher_pid.flush_generation().await {
+        Ok(..) =>
+            true,
+        Err(ero::NoProcError) => {
+            last_error = Some("butcher is not reachable".to_string());
+            false
+        },
+    };
+
+    // looking up the lowest key walks the leftmost path from the root block
+    let search_tree_reachable = match search_tree_pid {
+        None =>
+            None,
+        Some(mut search_tree_pid) =>
+            match search_tree_pid.lookup(kv::Key::from(&b""[..])).await {
+                Ok(..) =>
+                    Some(true),
+                Err(error) => {
+                    last_error = Some(format!("search tree lookup failed: {:?}", error));
+                    Some(false)
+                },
+            },
+    };
+
+    let health = Health {
+        butcher_reachable,
+        search_trees_count,
+        search_tree_reachable,
+        last_error,
+        quarantined_search_trees,
+    };
+    Ok(Done { health, reply_tx, })
+}
@@ -5,11 +5,13 @@ use crate::{
     Info,
     core::{
         butcher,
+        SearchRangeBounds,
     },
 };
 
 pub struct Args {
     pub request_ref: Ref,
+    pub range: SearchRangeBounds,
     pub butcher_pid: butcher::Pid,
 }
 
@@ -23,8 +25,8 @@ pub enum Error {
     ButcherInfo(ero::NoProcError),
 }
 
-pub async fn run(Args { request_ref, mut butcher_pid, }: Args) -> Result<Done, Error> {
-    let info = butcher_pid.info().await
+pub async fn run(Args { request_ref, range, mut butcher_pid, }: Args) -> Result<Done, Error> {
+    let info = butcher_pid.info(range).await
         .map_err(Error::ButcherInfo)?;
     Ok(Done { request_ref, info, })
 }
@@ -4,12 +4,14 @@ use o1::set::Ref;
 use crate::{
     core::{
         search_tree,
+        SearchRangeBounds,
     },
     Info,
 };
 
 pub struct Args {
     pub request_ref: Ref,
+    pub range: SearchRangeBounds,
     pub search_tree_pid: search_tree::Pid,
 }
 
@@ -23,8 +25,8 @@ pub enum Error {
     SearchTreeInfo(ero::NoProcError),
 }
 
-pub async fn run(Args { request_ref, mut search_tree_pid, }: Args) -> Result<Done, Error> {
-    let info = search_tree_pid.info().await
+pub async fn run(Args { request_ref, range, mut search_tree_pid, }: Args) -> Result<Done, Error> {
+    let info = search_tree_pid.info(range).await
         .map_err(Error::SearchTreeInfo)?;
     Ok(Done { request_ref, info, })
 }
@@ -1,27 +1,51 @@
+use futures::{
+    channel::{
+        oneshot,
+    },
+};
+
 use crate::{
+    kv,
     core::{
         butcher,
-        RequestInsert,
+        manager,
     },
+    Inserted,
+    StoragePreference,
 };
 
 pub struct Args {
-    pub request: RequestInsert,
+    pub key: kv::Key,
+    pub value: kv::Value,
+    pub version: Option<u64>,
+    pub storage_preference: StoragePreference,
+    // `None` for fire-and-forget inserts: errors are only logged
+    pub reply_tx: Option<oneshot::Sender<Result<Inserted, manager::InsertError>>>,
     pub butcher_pid: butcher::Pid,
 }
 
-pub struct Done;
+pub struct Done {
+    pub no_reply: bool,
+}
 
 #[derive(Debug)]
 pub enum Error {
     ButcherInsert(ero::NoProcError),
 }
 
-pub async fn run(Args { request: RequestInsert { key, value, reply_tx, }, mut butcher_pid, }: Args) -> Result<Done, Error> {
-    let inserted = butcher_pid.insert(key, value).await
+pub async fn run(Args { key, value, version, storage_preference, reply_tx, mut butcher_pid, }: Args) -> Result<Done, Error> {
+    let inserted = butcher_pid.insert_cell(key, value, version, storage_preference).await
         .map_err(Error::ButcherInsert)?;
-    if let Err(_send_error) = reply_tx.send(inserted) {
-        log::warn!("client canceled insert request");
+    let no_reply = reply_tx.is_none();
+    match reply_tx {
+        Some(reply_tx) =>
+            if let Err(_send_error) = reply_tx.send(inserted) {
+                log::warn!("client canceled insert request");
+            },
+        None =>
+            if let Err(error) = inserted {
+                log::warn!("fire-and-forget insert failed: {:?}", error);
+            },
     }
-    Ok(Done)
+    Ok(Done { no_reply, })
 }
@@ -0,0 +1,204 @@
+use std::cmp::Ordering;
+
+use futures::{
+    channel::{
+        mpsc,
+    },
+    SinkExt,
+    StreamExt,
+};
+
+use alloc_pool::bytes::{
+    Bytes,
+    BytesPool,
+};
+
+use crate::{
+    job,
+    wheels,
+    storage,
+    comparator,
+    core::{
+        BlockRef,
+    },
+    RawBlocksItem,
+    RawBlockHeader,
+    RawBlockError,
+    RawNodeType,
+    StreamError,
+};
+
+pub struct Args<J> where J: edeltraud::Job {
+    pub blocks_tx: mpsc::Sender<RawBlocksItem>,
+    pub wheels_pid: wheels::Pid,
+    pub thread_pool: edeltraud::Edeltraud<J>,
+    pub background_gate: job::BackgroundGate,
+    pub blocks_pool: BytesPool,
+    pub key_comparator: comparator::KeyComparatorRef,
+}
+
+pub enum Done {
+    Finished,
+    Canceled,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    WheelsIterBlocks(wheels::IterBlocksError),
+    WheelsIterBlocksRxDropped,
+    ThreadPoolGone,
+}
+
+pub async fn run<J>(
+    Args {
+        mut blocks_tx,
+        mut wheels_pid,
+        thread_pool,
+        background_gate,
+        blocks_pool,
+        key_comparator,
+    }: Args<J>,
+)
+    -> Result<Done, Error>
+where J: edeltraud::Job + From<job::Job>,
+      J::Output: From<job::JobOutput>,
+      job::JobOutput: From<J::Output>,
+{
+    let mut iter_blocks = match wheels_pid.iter_blocks().await {
+        Ok(iter_blocks) =>
+            iter_blocks,
+        Err(error) => {
+            if let Err(_send_error) = blocks_tx.send(RawBlocksItem::Error(StreamError::WheelsGone)).await {
+                log::warn!("client dropped raw blocks iterator");
+            }
+            return Err(Error::WheelsIterBlocks(error));
+        },
+    };
+
+    loop {
+        let item = match iter_blocks.block_refs_rx.next().await {
+            None => {
+                if let Err(_send_error) = blocks_tx.send(RawBlocksItem::Error(StreamError::Interrupted)).await {
+                    log::warn!("client dropped raw blocks iterator");
+                }
+                return Err(Error::WheelsIterBlocksRxDropped);
+            },
+            Some(wheels::IterBlocksItem::Block { block_ref, block_bytes, }) => {
+                let job_args = JobArgs {
+                    block_ref: block_ref.clone(),
+                    block_bytes: block_bytes.clone(),
+                    blocks_pool: blocks_pool.clone(),
+                    key_comparator: key_comparator.clone(),
+                };
+                let header = match spawn_job(&thread_pool, &background_gate, job_args).await {
+                    Ok(header) =>
+                        header,
+                    Err(error) => {
+                        if let Err(_send_error) = blocks_tx.send(RawBlocksItem::Error(StreamError::Interrupted)).await {
+                            log::warn!("client dropped raw blocks iterator");
+                        }
+                        return Err(error);
+                    },
+                };
+                RawBlocksItem::Block { block_ref, block_bytes, header, }
+            },
+            Some(wheels::IterBlocksItem::NoMoreBlocks) =>
+                RawBlocksItem::NoMoreBlocks,
+        };
+        let is_last = matches!(item, RawBlocksItem::NoMoreBlocks);
+        if let Err(_send_error) = blocks_tx.send(item).await {
+            log::debug!("client dropped raw blocks iterator");
+            return Ok(Done::Canceled);
+        }
+        if is_last {
+            return Ok(Done::Finished);
+        }
+    }
+}
+
+pub type JobOutput = Result<RawBlockHeader, RawBlockError>;
+
+pub struct JobArgs {
+    pub block_ref: BlockRef,
+    pub block_bytes: Bytes,
+    pub blocks_pool: BytesPool,
+    pub key_comparator: comparator::KeyComparatorRef,
+}
+
+pub fn job(JobArgs { block_ref, block_bytes, blocks_pool, key_comparator, }: JobArgs) -> JobOutput {
+    verify_block(&block_ref, block_bytes, &blocks_pool, &key_comparator)
+}
+
+// decompression and checksum walk the whole block, so scans run them on the thread pool as background jobs
+pub async fn spawn_job<J>(
+    thread_pool: &edeltraud::Edeltraud<J>,
+    background_gate: &job::BackgroundGate,
+    job_args: JobArgs,
+)
+    -> Result<JobOutput, Error>
+where J: edeltraud::Job + From<job::Job>,
+      J::Output: From<job::JobOutput>,
+      job::JobOutput: From<J::Output>,
+{
+    let job_output = background_gate.spawn(thread_pool, job::Job::VerifyBlock(job_args)).await
+        .map_err(|edeltraud::SpawnError::ThreadPoolGone| Error::ThreadPoolGone)?;
+    let job_output: job::JobOutput = job_output.into();
+    let job::VerifyBlockDone(job_result) = job_output.into();
+    Ok(job_result)
+}
+
+fn verify_block(
+    block_ref: &BlockRef,
+    block_bytes: Bytes,
+    blocks_pool: &BytesPool,
+    key_comparator: &comparator::KeyComparatorRef,
+)
+    -> Result<RawBlockHeader, RawBlockError>
+{
+    let block_bytes = storage::block_decompress(block_bytes, blocks_pool)
+        .map_err(|_error| RawBlockError::Decompress)?;
+    match storage::block_verify_checksum(&block_bytes, block_ref) {
+        Ok(()) =>
+            (),
+        Err(storage::Error::UnsupportedFormatVersion { found, supported, }) =>
+            return Err(RawBlockError::UnsupportedFormatVersion { found, supported, }),
+        Err(_error) =>
+            return Err(RawBlockError::ChecksumMismatch),
+    }
+    let deserializer = match storage::block_deserialize_iter(&block_bytes) {
+        Ok(deserializer) =>
+            deserializer,
+        Err(storage::Error::InvalidBlockMagic { provided, .. }) if storage::magic_store_id(provided, storage::VALUE_BLOCK_MAGIC).is_some() =>
+            return Ok(RawBlockHeader::Value),
+        // wal records, range tombstones and unknown blocks
+        Err(storage::Error::InvalidBlockMagic { .. }) =>
+            return Ok(RawBlockHeader::Other),
+        Err(storage::Error::UnsupportedFormatVersion { found, supported, }) =>
+            return Err(RawBlockError::UnsupportedFormatVersion { found, supported, }),
+        Err(_error) =>
+            return Err(RawBlockError::HeaderDeserialize),
+    };
+    let format_version = deserializer.format_version();
+    let block_header = deserializer.block_header();
+    let entries_count = block_header.entries_count;
+    let node_type = match block_header.node_type {
+        storage::NodeType::Root { tree_entries_count, max_version, created_at_version, comparator_id, } =>
+            RawNodeType::Root { tree_entries_count, max_version, created_at_version, comparator_id, },
+        storage::NodeType::Leaf =>
+            RawNodeType::Leaf,
+    };
+
+    let mut prev_key: Option<&[u8]> = None;
+    for (entry_index, maybe_entry) in deserializer.enumerate() {
+        let entry = maybe_entry
+            .map_err(|_error| RawBlockError::EntryDeserialize { entry_index, })?;
+        if let Some(prev_key) = prev_key {
+            if key_comparator.compare(prev_key, entry.key) != Ordering::Less {
+                return Err(RawBlockError::KeysNotAscending { entry_index, });
+            }
+        }
+        prev_key = Some(entry.key);
+    }
+
+    Ok(RawBlockHeader::Tree { node_type, entries_count, format_version, })
+}
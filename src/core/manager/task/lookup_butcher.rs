@@ -18,6 +18,7 @@ pub struct Args {
 pub struct Done {
     pub request_ref: Ref,
     pub found: Option<kv::ValueCell<storage::OwnedValueBlockRef>>,
+    pub flush_generation: u64,
 }
 
 #[derive(Debug)]
@@ -26,12 +27,11 @@ pub enum Error {
 }
 
 pub async fn run(Args { request_ref, key, mut butcher_pid, }: Args) -> Result<Done, Error> {
-    match butcher_pid.lookup(key).await {
-        Ok(None) =>
-            Ok(Done { request_ref, found: None, }),
-        Ok(Some(value_cell)) =>
-            Ok(Done { request_ref, found: Some(value_cell.into()), }),
-        Err(error) =>
-            Err(Error::ButcherLookup(error)),
-    }
+    let butcher::Found { found, flush_generation, } = butcher_pid.lookup(key).await
+        .map_err(Error::ButcherLookup)?;
+    Ok(Done {
+        request_ref,
+        found: found.map(Into::into),
+        flush_generation,
+    })
 }
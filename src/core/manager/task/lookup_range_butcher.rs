@@ -1,6 +1,6 @@
 use futures::{
     channel::{
-        mpsc,
+        oneshot,
     },
 };
 
@@ -13,21 +13,34 @@ use crate::{
     kv,
     core::{
         butcher,
+        KeyValuesTx,
         SearchRangeBounds,
     },
-    KeyValueStreamItem,
+    KeyFilter,
+    VersionWindow,
 };
 
 pub struct Args {
     pub range: SearchRangeBounds,
-    pub key_values_tx: mpsc::Sender<KeyValueStreamItem>,
+    pub key_values_tx: KeyValuesTx,
+    pub key_filter: Option<KeyFilter>,
+    pub version_window: Option<VersionWindow>,
+    pub cancel_rx: oneshot::Receiver<()>,
+    // times the scan was restarted after its results got deprecated
+    pub retries: usize,
+    pub last_key: Option<kv::Key>,
     pub iter_items_pool: pool::Pool<Vec<kv::KeyValuePair<kv::Value>>>,
     pub butcher_pid: butcher::Pid,
 }
 
 pub struct Done {
     pub range: SearchRangeBounds,
-    pub key_values_tx: mpsc::Sender<KeyValueStreamItem>,
+    pub key_values_tx: KeyValuesTx,
+    pub key_filter: Option<KeyFilter>,
+    pub version_window: Option<VersionWindow>,
+    pub cancel_rx: oneshot::Receiver<()>,
+    pub retries: usize,
+    pub last_key: Option<kv::Key>,
     pub iter_items: Shared<Vec<kv::KeyValuePair<kv::Value>>>,
 }
 
@@ -36,8 +49,8 @@ pub enum Error {
     ButcherLookupRange(ero::NoProcError),
 }
 
-pub async fn run(Args { range, key_values_tx, iter_items_pool, mut butcher_pid, }: Args) -> Result<Done, Error> {
+pub async fn run(Args { range, key_values_tx, key_filter, version_window, cancel_rx, retries, last_key, iter_items_pool, mut butcher_pid, }: Args) -> Result<Done, Error> {
     let iter_items = butcher_pid.lookup_range(range.clone(), iter_items_pool).await
         .map_err(Error::ButcherLookupRange)?;
-    Ok(Done { range, key_values_tx, iter_items, })
+    Ok(Done { range, key_values_tx, key_filter, version_window, cancel_rx, retries, last_key, iter_items, })
 }
@@ -25,7 +25,7 @@ pub enum Error {
 }
 
 pub async fn run(Args { request_ref, range, mut search_tree_pid, }: Args) -> Result<Done, Error> {
-    let items_iter = search_tree_pid.iter(range).await
+    let items_iter = search_tree_pid.iter_demand_driven(range).await
         .map_err(Error::SearchTreeIter)?;
     Ok(Done { request_ref, items_iter, })
 }
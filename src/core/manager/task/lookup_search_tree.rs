@@ -6,13 +6,14 @@ use crate::{
     storage,
     core::{
         search_tree,
+        manager::replace_fold_found,
     },
 };
 
 pub struct Args {
     pub key: kv::Key,
     pub request_ref: Ref,
-    pub search_tree_pid: search_tree::Pid,
+    pub search_tree_pids: Vec<search_tree::Pid>,
 }
 
 pub struct Done {
@@ -25,8 +26,14 @@ pub enum Error {
     SearchTreeLookup(search_tree::LookupError),
 }
 
-pub async fn run(Args { request_ref, key, mut search_tree_pid, }: Args) -> Result<Done, Error> {
-    let search_tree_found = search_tree_pid.lookup(key).await
-        .map_err(Error::SearchTreeLookup)?;
-    Ok(Done { request_ref, found: search_tree_found, })
+pub async fn run(Args { request_ref, key, search_tree_pids, }: Args) -> Result<Done, Error> {
+    let mut found = None;
+    for mut search_tree_pid in search_tree_pids {
+        let search_tree_found = search_tree_pid.lookup(key.clone()).await
+            .map_err(Error::SearchTreeLookup)?;
+        if replace_fold_found(&found, &search_tree_found) {
+            found = search_tree_found;
+        }
+    }
+    Ok(Done { request_ref, found, })
 }
@@ -1,4 +1,7 @@
-use std::ops::Bound;
+use std::{
+    cmp,
+    ops::Bound,
+};
 
 use futures::{
     select,
@@ -6,8 +9,11 @@ use futures::{
     pin_mut,
     channel::{
         mpsc,
+        oneshot,
     },
+    stream::FuturesOrdered,
     SinkExt,
+    StreamExt,
     FutureExt,
 };
 
@@ -22,34 +28,60 @@ use crate::{
     kv,
     wheels,
     storage,
+    comparator::KeyComparatorRef,
     core::{
         merger,
+        search_tree,
         KeyValueRef,
+        KeyValuesTx,
+        RangeTombstones,
         SearchRangeBounds,
     },
+    KeyFilter,
+    VersionWindow,
+    KeyStreamItem,
     KeyValueStreamItem,
+    KeyValueRefStreamItem,
+    StreamError,
 };
 
 pub struct Args {
     pub range: SearchRangeBounds,
-    pub key_values_tx: mpsc::Sender<KeyValueStreamItem>,
+    pub key_values_tx: KeyValuesTx,
+    pub key_filter: Option<KeyFilter>,
+    pub version_window: Option<VersionWindow>,
+    pub cancel_rx: oneshot::Receiver<()>,
     pub butcher_iter_items: Shared<Vec<kv::KeyValuePair<kv::Value>>>,
     pub merger_iters: Unique<Vec<merger::KeyValuesIter>>,
+    pub key_comparator: KeyComparatorRef,
+    pub range_tombstones: RangeTombstones,
+    pub prefetch_depth: usize,
+    pub retries: usize,
+    // last key emitted to the client before the scan was restarted
+    pub last_key: Option<kv::Key>,
+    // trees flushed or bulk loaded after the set of trees for the scan was taken
+    pub late_trees_rx: mpsc::UnboundedReceiver<search_tree::Pid>,
     pub wheels_pid: wheels::Pid,
 }
 
 pub enum Done {
-    MergeSuccess,
+    MergeSuccess { retries: usize, },
+    Canceled { retries: usize, },
     DeprecatedResults {
         modified_range: SearchRangeBounds,
-        key_values_tx: mpsc::Sender<KeyValueStreamItem>,
+        key_values_tx: KeyValuesTx,
+        key_filter: Option<KeyFilter>,
+        version_window: Option<VersionWindow>,
+        cancel_rx: oneshot::Receiver<()>,
+        retries: usize,
+        last_key: Option<kv::Key>,
     },
 }
 
 pub enum MergeError {
     DeprecatedResultsFor {
-        key: kv::Key,
-        key_values_tx: mpsc::Sender<KeyValueStreamItem>,
+        last_key: Option<kv::Key>,
+        key_values_tx: KeyValuesTx,
     },
     Error(Error),
 }
@@ -65,10 +97,11 @@ pub enum Error {
     ValueDeserialize(storage::Error),
 }
 
-pub async fn run(Args { range, mut key_values_tx, butcher_iter_items, mut merger_iters, wheels_pid, }: Args) -> Result<Done, Error> {
+pub async fn run(Args { range, key_values_tx, key_filter, version_window, mut cancel_rx, butcher_iter_items, mut merger_iters, key_comparator, range_tombstones, prefetch_depth, retries, last_key, mut late_trees_rx, wheels_pid, }: Args) -> Result<Done, Error> {
     let (mut butcher_iter_tx, butcher_iter_rx) = mpsc::channel(0);
+    let butcher_iter_items_ref = &butcher_iter_items;
     let butcher_forward_task = async move {
-        for key_value in butcher_iter_items.iter() {
+        for key_value in butcher_iter_items_ref.iter() {
             let item = KeyValueRef::Item {
                 key: key_value.key.clone(),
                 value_cell: key_value.value_cell
@@ -86,107 +119,304 @@ pub async fn run(Args { range, mut key_values_tx, butcher_iter_items, mut merger
         Ok::<_, MergeError>(())
     };
     merger_iters.push(merger::KeyValuesIter::new(butcher_iter_rx));
-    merger_iters.shrink_to_fit();
 
-    let mut merger = merger::ItersMerger::new(merger_iters);
+    let resume_key_comparator = key_comparator.clone();
+    let late_range = range.clone();
+    let mut merger = merger::ItersMerger::new(&mut *merger_iters)
+        .applying_range_tombstones(range_tombstones, false)
+        .comparing_keys(key_comparator);
 
+    let (mut merged_tx, merged_rx) = mpsc::channel(0);
+    // merger failure is passed along so the client sees it in the stream before the task fails
+    let key_filter_ref = &key_filter;
+    let last_key_ref = &last_key;
     let merge_task = async move {
-        let maybe_merger_next = merger.next().await
-            .map_err(Error::Merger)
-            .map_err(MergeError::Error)?;
-        if let Some(mut merger_key_value) = maybe_merger_next {
-            loop {
-                let merger_future = merger.next().fuse();
-                pin_mut!(merger_future);
-                let retrieve_future = schedule_retrieve(merger_key_value, wheels_pid.clone(), key_values_tx).fuse();
-                pin_mut!(retrieve_future);
-
-                enum Event<M, R> {
-                    Merger(M),
-                    Retrieve(R),
-                }
-
-                let event = select! {
-                    result = &mut merger_future =>
-                        Event::Merger(result.map_err(Error::Merger).map_err(MergeError::Error)?),
-                    result = &mut retrieve_future =>
-                        Event::Retrieve(result?),
+        let mut merged_last_key: Option<kv::Key> = None;
+        loop {
+            // a late tree joins for the keys not merged yet: otherwise a newer cell it holds stays unseen, and
+            // once a merge frees the value block superseded the scan is deprecated and restarted
+            while let Ok(Some(mut search_tree_pid)) = late_trees_rx.try_next() {
+                let range = match &merged_last_key {
+                    None =>
+                        late_range.clone(),
+                    Some(key) =>
+                        SearchRangeBounds {
+                            range_from: Bound::Excluded(key.clone()),
+                            range_to: late_range.range_to.clone(),
+                        },
                 };
-
-                match event {
-                    Event::Merger(None) => {
-                        let (maybe_key_value, returned_key_values_tx) = retrieve_future.await?;
-                        key_values_tx = returned_key_values_tx;
-                        if let Some(key_value) = maybe_key_value {
-                            if let Err(_send_error) = key_values_tx.send(KeyValueStreamItem::KeyValue(key_value)).await {
-                                log::warn!("client dropped iterator in merger task");
-                                return Ok(());
-                            }
-                        }
-                        break;
-                    },
-                    Event::Merger(Some(next_key_value)) => {
-                        let (maybe_key_value, returned_key_values_tx) = retrieve_future.await?;
-                        key_values_tx = returned_key_values_tx;
-                        if let Some(key_value) = maybe_key_value {
-                            if let Err(_send_error) = key_values_tx.send(KeyValueStreamItem::KeyValue(key_value)).await {
-                                log::warn!("client dropped iterator in merger task");
-                                return Ok(());
-                            }
-                        }
-                        merger_key_value = next_key_value;
-                    },
-                    Event::Retrieve((maybe_key_value, returned_key_values_tx)) => {
-                        key_values_tx = returned_key_values_tx;
-                        if let Some(key_value) = maybe_key_value {
-                            if let Err(_send_error) = key_values_tx.send(KeyValueStreamItem::KeyValue(key_value)).await {
-                                log::warn!("client dropped iterator in merger task");
-                                return Ok(());
-                            }
-                        }
-                        let maybe_key_value = merger_future.await
-                            .map_err(Error::Merger)
-                            .map_err(MergeError::Error)?;
-                        match maybe_key_value {
+                match search_tree_pid.iter_demand_driven(range).await {
+                    Ok(search_tree::SearchTreeIterItemsRx { items_rx, demand_tx, }) =>
+                        merger.push_iter(match demand_tx {
+                            Some(demand_tx) =>
+                                merger::KeyValuesIter::demand_driven(items_rx, demand_tx),
                             None =>
-                                break,
-                            Some(next_key_value) =>
-                                merger_key_value = next_key_value,
-                        }
-                    },
+                                merger::KeyValuesIter::new(items_rx),
+                        }),
+                    Err(error) =>
+                        log::debug!("late search tree iter failed: {:?}, scanning without it", error),
                 }
             }
-        }
-        if let Err(_send_error) = key_values_tx.send(KeyValueStreamItem::NoMore).await {
-            log::warn!("client dropped iterator in merger task");
+
+            let maybe_item = merger.next().await;
+            if let Ok(Some(key_value)) = &maybe_item {
+                merged_last_key = Some(key_value.key.clone());
+            }
+            let item = match maybe_item {
+                Ok(None) =>
+                    break,
+                // already emitted before restart
+                Ok(Some(key_value)) if last_key_ref.as_ref().is_some_and(|last_key| {
+                    resume_key_comparator.compare(&key_value.key.key_bytes, &last_key.key_bytes) != cmp::Ordering::Greater
+                }) =>
+                    continue,
+                Ok(Some(key_value)) if key_filter_ref.as_ref().is_some_and(|key_filter| !key_filter.matches(&key_value.key)) =>
+                    continue,
+                Ok(Some(key_value)) if version_window.is_some_and(|window| !window.contains(key_value.value_cell.version)) =>
+                    continue,
+                Ok(Some(key_value)) =>
+                    Ok(key_value),
+                Err(error) =>
+                    Err(Error::Merger(error)),
+            };
+            let is_error = item.is_err();
+            if let Err(_send_error) = merged_tx.send(item).await {
+                log::debug!("retrieve task is done earlier than merger task");
+                break;
+            }
+            if is_error {
+                break;
+            }
         }
         Ok::<_, MergeError>(())
     };
 
-    match future::try_join(butcher_forward_task, merge_task).await {
-        Ok(((), ())) =>
-            Ok(Done::MergeSuccess),
-        Err(MergeError::DeprecatedResultsFor { key, key_values_tx, }) =>
+    let resume_last_key = last_key.clone();
+    let retrieve_task = async move {
+        match key_values_tx {
+            KeyValuesTx::Values(key_values_tx) =>
+                retrieve_values(merged_rx, key_values_tx, resume_last_key, prefetch_depth, wheels_pid).await,
+            KeyValuesTx::Refs(key_values_tx) =>
+                forward_refs(merged_rx, key_values_tx).await,
+            KeyValuesTx::Keys(keys_tx) =>
+                forward_keys(merged_rx, keys_tx).await,
+        }
+    };
+
+    let maybe_join_result = {
+        let join_task = future::try_join3(butcher_forward_task, merge_task, retrieve_task).fuse();
+        pin_mut!(join_task);
+        select! {
+            result = join_task =>
+                Some(result),
+            cancel_result = cancel_rx =>
+                match cancel_result {
+                    Ok(()) =>
+                        None,
+                    // sender dropped without canceling: the scan goes on until its receiver is gone
+                    Err(oneshot::Canceled) =>
+                        Some(join_task.await),
+                },
+        }
+    };
+
+    // pooled vectors are repaid on drop with their contents, so iterators are released here
+    // instead of being kept alive in the pool until the next lend
+    merger_iters.clear();
+    drop(merger_iters);
+    drop(butcher_iter_items);
+
+    let join_result = match maybe_join_result {
+        None => {
+            log::debug!("lookup range canceled by client: dropping merger");
+            return Ok(Done::Canceled { retries, });
+        },
+        Some(join_result) =>
+            join_result,
+    };
+
+    match join_result {
+        Ok(((), (), ())) =>
+            Ok(Done::MergeSuccess { retries, }),
+        Err(MergeError::DeprecatedResultsFor { last_key, key_values_tx, }) =>
             Ok(Done::DeprecatedResults {
-                modified_range: SearchRangeBounds {
-                    range_from: Bound::Included(key),
-                    ..range
+                // resume strictly after the last emitted key, or from the very start if nothing was emitted yet
+                modified_range: match &last_key {
+                    None =>
+                        range,
+                    Some(key) =>
+                        SearchRangeBounds {
+                            range_from: Bound::Excluded(key.clone()),
+                            ..range
+                        },
                 },
                 key_values_tx,
+                key_filter,
+                version_window,
+                cancel_rx,
+                retries,
+                last_key,
             }),
         Err(MergeError::Error(error)) =>
             Err(error),
     }
 }
 
-async fn schedule_retrieve(
-    key_value: kv::KeyValuePair<storage::OwnedValueBlockRef>,
+// values are retrieved concurrently for up to `prefetch_depth` items ahead, but emitted in order
+async fn retrieve_values(
+    mut merged_rx: mpsc::Receiver<MergedItem>,
+    mut key_values_tx: mpsc::Sender<KeyValueStreamItem>,
+    mut last_key: Option<kv::Key>,
+    prefetch_depth: usize,
+    wheels_pid: wheels::Pid,
+)
+    -> Result<(), MergeError>
+{
+    let mut retrieves = FuturesOrdered::new();
+    let mut merged_depleted = false;
+    loop {
+        enum Event<M, R> {
+            Merged(M),
+            Retrieved(R),
+        }
+
+        let event = if merged_depleted || retrieves.len() >= prefetch_depth {
+            match retrieves.next().await {
+                None =>
+                    break,
+                Some(result) =>
+                    Event::Retrieved(result),
+            }
+        } else if retrieves.is_empty() {
+            Event::Merged(merged_rx.next().await)
+        } else {
+            select! {
+                result = merged_rx.next() =>
+                    Event::Merged(result),
+                result = retrieves.next() => match result {
+                    None =>
+                        unreachable!(),
+                    Some(result) =>
+                        Event::Retrieved(result),
+                },
+            }
+        };
+
+        match event {
+            Event::Merged(None) =>
+                merged_depleted = true,
+            Event::Merged(Some(item)) =>
+                retrieves.push_back(retrieve_value(item, wheels_pid.clone())),
+            Event::Retrieved(Ok(key_value)) => {
+                let key = key_value.key.clone();
+                if let Err(_send_error) = key_values_tx.send(KeyValueStreamItem::KeyValue(key_value)).await {
+                    log::warn!("client dropped iterator in merger task");
+                    return Ok(());
+                }
+                last_key = Some(key);
+            },
+            Event::Retrieved(Err(RetrieveError::DeprecatedResultsFor { key, })) => {
+                log::debug!("value block for key {:?} is gone, restarting after {:?}", key, last_key);
+                return Err(MergeError::DeprecatedResultsFor { last_key, key_values_tx: KeyValuesTx::Values(key_values_tx), });
+            },
+            Event::Retrieved(Err(RetrieveError::Error(error))) =>
+                return Err(stream_failed(error, &mut key_values_tx, KeyValueStreamItem::Error).await),
+        }
+    }
+    if let Err(_send_error) = key_values_tx.send(KeyValueStreamItem::NoMore).await {
+        log::warn!("client dropped iterator in merger task");
+    }
+    Ok(())
+}
+
+async fn forward_refs(
+    mut merged_rx: mpsc::Receiver<MergedItem>,
+    mut key_values_tx: mpsc::Sender<KeyValueRefStreamItem>,
+)
+    -> Result<(), MergeError>
+{
+    while let Some(item) = merged_rx.next().await {
+        let kv::KeyValuePair { key, value_cell, } = match item {
+            Ok(key_value) =>
+                key_value,
+            Err(error) =>
+                return Err(stream_failed(error, &mut key_values_tx, KeyValueRefStreamItem::Error).await),
+        };
+        let key_value = kv::KeyValuePair { key, value_cell: value_cell.into(), };
+        if let Err(_send_error) = key_values_tx.send(KeyValueRefStreamItem::KeyValue(key_value)).await {
+            log::warn!("client dropped iterator in merger task");
+            return Ok(());
+        }
+    }
+    if let Err(_send_error) = key_values_tx.send(KeyValueRefStreamItem::NoMore).await {
+        log::warn!("client dropped iterator in merger task");
+    }
+    Ok(())
+}
+
+async fn forward_keys(
+    mut merged_rx: mpsc::Receiver<MergedItem>,
+    mut keys_tx: mpsc::Sender<KeyStreamItem>,
+)
+    -> Result<(), MergeError>
+{
+    while let Some(item) = merged_rx.next().await {
+        let kv::KeyValuePair { key, value_cell, } = match item {
+            Ok(key_value) =>
+                key_value,
+            Err(error) =>
+                return Err(stream_failed(error, &mut keys_tx, KeyStreamItem::Error).await),
+        };
+        if let kv::Cell::Tombstone { .. } = value_cell.cell {
+            continue;
+        }
+        if let Err(_send_error) = keys_tx.send(KeyStreamItem::Key(key)).await {
+            log::warn!("client dropped iterator in merger task");
+            return Ok(());
+        }
+    }
+    if let Err(_send_error) = keys_tx.send(KeyStreamItem::NoMore).await {
+        log::warn!("client dropped iterator in merger task");
+    }
+    Ok(())
+}
+
+type MergedItem = Result<kv::KeyValuePair<storage::OwnedValueBlockRef>, Error>;
+
+async fn stream_failed<T, F>(error: Error, tx: &mut mpsc::Sender<T>, make_item: F) -> MergeError where F: FnOnce(StreamError) -> T {
+    let stream_error = match &error {
+        Error::Merger(merger::Error::BackendIterPeerLost) =>
+            StreamError::BackendIterPeerLost,
+        Error::WheelsGone =>
+            StreamError::WheelsGone,
+        Error::WheelNotFound { blockwheel_filename, } =>
+            StreamError::WheelNotFound { blockwheel_filename: blockwheel_filename.clone(), },
+        Error::ReadBlock(..) =>
+            StreamError::ReadValueBlock,
+        Error::ValueDeserialize(..) =>
+            StreamError::ValueDeserialize,
+    };
+    if let Err(_send_error) = tx.send(make_item(stream_error)).await {
+        log::warn!("client dropped iterator in merger task");
+    }
+    MergeError::Error(error)
+}
+
+enum RetrieveError {
+    DeprecatedResultsFor {
+        key: kv::Key,
+    },
+    Error(Error),
+}
+
+// a merger failure is queued as an item, so it reaches the client after every value retrieved before it
+async fn retrieve_value(
+    item: MergedItem,
     mut wheels_pid: wheels::Pid,
-    key_values_tx: mpsc::Sender<KeyValueStreamItem>,
 )
-    -> Result<(Option<kv::KeyValuePair<kv::Value>>, mpsc::Sender<KeyValueStreamItem>), MergeError>
+    -> Result<kv::KeyValuePair<kv::Value>, RetrieveError>
 {
-    match key_value {
+    match item.map_err(RetrieveError::Error)? {
         kv::KeyValuePair {
             key,
             value_cell: kv::ValueCell {
@@ -194,10 +424,7 @@ async fn schedule_retrieve(
                 cell: kv::Cell::Value(storage::OwnedValueBlockRef::Inline(value)),
             },
         } =>
-            Ok((
-                Some(kv::KeyValuePair { key, value_cell: kv::ValueCell { version, cell: kv::Cell::Value(value), }, }),
-                key_values_tx,
-            )),
+            Ok(kv::KeyValuePair { key, value_cell: kv::ValueCell { version, cell: kv::Cell::Value(value), }, }),
         kv::KeyValuePair {
             key,
             value_cell: kv::ValueCell {
@@ -206,37 +433,235 @@ async fn schedule_retrieve(
             },
         } => {
             let mut wheel_ref = wheels_pid.get(block_ref.blockwheel_filename.clone()).await
-                .map_err(|ero::NoProcError| Error::WheelsGone)
-                .map_err(MergeError::Error)?
-                .ok_or_else(|| MergeError::Error(Error::WheelNotFound {
+                .map_err(|ero::NoProcError| RetrieveError::Error(Error::WheelsGone))?
+                .ok_or_else(|| RetrieveError::Error(Error::WheelNotFound {
                     blockwheel_filename: block_ref.blockwheel_filename.clone(),
                 }))?;
             let block_bytes = match wheel_ref.blockwheel_pid.read_block(block_ref.block_id.clone()).await {
                 Ok(block_bytes) =>
                     block_bytes,
                 Err(blockwheel::ReadBlockError::NotFound) =>
-                    return Err(MergeError::DeprecatedResultsFor { key, key_values_tx, }),
+                    return Err(RetrieveError::DeprecatedResultsFor { key, }),
                 Err(error) =>
-                    return Err(MergeError::Error(Error::ReadBlock(error))),
+                    return Err(RetrieveError::Error(Error::ReadBlock(error))),
             };
             let value_bytes = storage::value_block_deserialize(&block_bytes)
                 .map_err(Error::ValueDeserialize)
-                .map_err(MergeError::Error)?;
-            Ok((
-                Some(kv::KeyValuePair {
-                    key,
-                    value_cell: kv::ValueCell {
-                        version,
-                        cell: kv::Cell::Value(value_bytes.into()),
-                    },
-                }),
-                key_values_tx,
-            ))
+                .map_err(RetrieveError::Error)?;
+            Ok(kv::KeyValuePair {
+                key,
+                value_cell: kv::ValueCell {
+                    version,
+                    cell: kv::Cell::Value(value_bytes.into()),
+                },
+            })
         },
-        kv::KeyValuePair { key, value_cell: kv::ValueCell { version, cell: kv::Cell::Tombstone, }, } =>
-            Ok((
-                Some(kv::KeyValuePair { key, value_cell: kv::ValueCell { version, cell: kv::Cell::Tombstone, }, }),
-                key_values_tx,
-            )),
+        kv::KeyValuePair { key, value_cell: kv::ValueCell { version, cell: kv::Cell::Tombstone { meta, }, }, } =>
+            Ok(kv::KeyValuePair { key, value_cell: kv::ValueCell { version, cell: kv::Cell::Tombstone { meta, }, }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        sync::Arc,
+    };
+
+    use futures::{
+        channel::{
+            mpsc,
+            oneshot,
+        },
+        future,
+        executor::block_on,
+        StreamExt,
+    };
+
+    use alloc_pool::{
+        pool,
+        bytes::BytesPool,
+    };
+
+    use crate::{
+        kv,
+        wheels,
+        comparator,
+        core::{
+            KeyValuesTx,
+            SearchRangeBounds,
+        },
+        KeyFilter,
+        KeyStreamItem,
+    };
+
+    use super::{
+        run,
+        Args,
+        Done,
+    };
+
+    #[test]
+    fn pooled_vectors_are_reused() {
+        let blocks_pool = BytesPool::new();
+        let merger_iters_pool = pool::Pool::new();
+        let iter_items_pool = pool::Pool::new();
+        let merger_iters_allocated = Cell::new(0);
+        let iter_items_allocated = Cell::new(0);
+        let wheels_gen_server = wheels::GenServer::new();
+
+        for round in 0 .. 256 {
+            let mut iter_items = iter_items_pool.lend(|| {
+                iter_items_allocated.set(iter_items_allocated.get() + 1);
+                Vec::new()
+            });
+            iter_items.clear();
+            for index in 0 .. round % 8 {
+                let mut key_bytes = blocks_pool.lend();
+                key_bytes.extend_from_slice(&(index as u32).to_be_bytes());
+                let mut value_bytes = blocks_pool.lend();
+                value_bytes.extend_from_slice(b"value");
+                iter_items.push(kv::KeyValuePair {
+                    key: key_bytes.into(),
+                    value_cell: kv::ValueCell { version: 1, cell: kv::Cell::Value(value_bytes.into()), },
+                });
+            }
+
+            let merger_iters = merger_iters_pool.lend(|| {
+                merger_iters_allocated.set(merger_iters_allocated.get() + 1);
+                Vec::new()
+            });
+            // iterators of the previous scan should be released before the vector is repaid
+            assert!(merger_iters.is_empty());
+
+            let (keys_tx, keys_rx) = mpsc::channel(0);
+            let (_cancel_tx, cancel_rx) = oneshot::channel();
+            let args = Args {
+                range: SearchRangeBounds::unbounded(),
+                key_values_tx: KeyValuesTx::Keys(keys_tx),
+                key_filter: None,
+                version_window: None,
+                cancel_rx,
+                butcher_iter_items: iter_items.freeze(),
+                merger_iters,
+                key_comparator: comparator::bytewise(),
+                range_tombstones: Arc::new(Vec::new()),
+                prefetch_depth: 4,
+                retries: 0,
+                last_key: None,
+                wheels_pid: wheels_gen_server.pid(),
+            };
+            let (done, items) = block_on(future::join(run(args), keys_rx.collect::<Vec<_>>()));
+            assert!(matches!(done, Ok(Done::MergeSuccess { .. })));
+            assert_eq!(items.len(), round % 8 + 1);
+            assert!(matches!(items.last(), Some(KeyStreamItem::NoMore)));
+        }
+
+        assert_eq!(merger_iters_allocated.get(), 1);
+        assert_eq!(iter_items_allocated.get(), 1);
+    }
+
+    #[test]
+    fn key_filter_applied_in_merge() {
+        let blocks_pool = BytesPool::new();
+        let mut iter_items = pool::Pool::new().lend(Vec::new);
+        iter_items.clear();
+        for key in [&b"aa"[..], b"ab", b"b", b"ba", b"bab"] {
+            let mut key_bytes = blocks_pool.lend();
+            key_bytes.extend_from_slice(key);
+            let mut value_bytes = blocks_pool.lend();
+            value_bytes.extend_from_slice(b"value");
+            iter_items.push(kv::KeyValuePair {
+                key: key_bytes.into(),
+                value_cell: kv::ValueCell { version: 1, cell: kv::Cell::Value(value_bytes.into()), },
+            });
+        }
+        let butcher_iter_items = iter_items.freeze();
+
+        let filters = vec![
+            (KeyFilter::Prefix(b"b".to_vec()), vec![&b"b"[..], b"ba", b"bab"]),
+            (KeyFilter::Suffix(b"b".to_vec()), vec![&b"ab"[..], b"b", b"bab"]),
+            (KeyFilter::LengthRange { min: 2, max: 2, }, vec![&b"aa"[..], b"ab", b"ba"]),
+            (KeyFilter::Predicate(Arc::new(|key: &kv::Key| key.key_bytes.contains(&b'a'))), vec![&b"aa"[..], b"ab", b"ba", b"bab"]),
+        ];
+        for (key_filter, expected) in filters {
+            let (keys_tx, keys_rx) = mpsc::channel(0);
+            let (_cancel_tx, cancel_rx) = oneshot::channel();
+            let args = Args {
+                range: SearchRangeBounds::unbounded(),
+                key_values_tx: KeyValuesTx::Keys(keys_tx),
+                key_filter: Some(key_filter),
+                version_window: None,
+                cancel_rx,
+                butcher_iter_items: butcher_iter_items.clone(),
+                merger_iters: pool::Pool::new().lend(Vec::new),
+                key_comparator: comparator::bytewise(),
+                range_tombstones: Arc::new(Vec::new()),
+                prefetch_depth: 4,
+                retries: 0,
+                last_key: None,
+                wheels_pid: wheels::GenServer::new().pid(),
+            };
+            let (done, items) = block_on(future::join(run(args), keys_rx.collect::<Vec<_>>()));
+            assert!(matches!(done, Ok(Done::MergeSuccess { .. })));
+            let keys: Vec<_> = items.iter()
+                .filter_map(|item| match item {
+                    KeyStreamItem::Key(key) =>
+                        Some(&*key.key_bytes),
+                    KeyStreamItem::Error(..) | KeyStreamItem::NoMore =>
+                        None,
+                })
+                .collect();
+            assert_eq!(keys, expected);
+        }
+    }
+
+    #[test]
+    fn resumes_strictly_after_last_key() {
+        let blocks_pool = BytesPool::new();
+        let mut iter_items = pool::Pool::new().lend(Vec::new);
+        iter_items.clear();
+        for key in [&b"a"[..], b"b", b"c", b"d"] {
+            let mut key_bytes = blocks_pool.lend();
+            key_bytes.extend_from_slice(key);
+            let mut value_bytes = blocks_pool.lend();
+            value_bytes.extend_from_slice(b"value");
+            iter_items.push(kv::KeyValuePair {
+                key: key_bytes.into(),
+                value_cell: kv::ValueCell { version: 1, cell: kv::Cell::Value(value_bytes.into()), },
+            });
+        }
+        let butcher_iter_items = iter_items.freeze();
+
+        let mut last_key_bytes = blocks_pool.lend();
+        last_key_bytes.extend_from_slice(b"b");
+        let (keys_tx, keys_rx) = mpsc::channel(0);
+        let (_cancel_tx, cancel_rx) = oneshot::channel();
+        let args = Args {
+            range: SearchRangeBounds::unbounded(),
+            key_values_tx: KeyValuesTx::Keys(keys_tx),
+            key_filter: None,
+            version_window: None,
+            cancel_rx,
+            butcher_iter_items,
+            merger_iters: pool::Pool::new().lend(Vec::new),
+            key_comparator: comparator::bytewise(),
+            range_tombstones: Arc::new(Vec::new()),
+            prefetch_depth: 4,
+            retries: 1,
+            last_key: Some(last_key_bytes.into()),
+            wheels_pid: wheels::GenServer::new().pid(),
+        };
+        let (done, items) = block_on(future::join(run(args), keys_rx.collect::<Vec<_>>()));
+        assert!(matches!(done, Ok(Done::MergeSuccess { retries: 1, })));
+        let keys: Vec<_> = items.iter()
+            .filter_map(|item| match item {
+                KeyStreamItem::Key(key) =>
+                    Some(&*key.key_bytes),
+                KeyStreamItem::Error(..) | KeyStreamItem::NoMore =>
+                    None,
+            })
+            .collect();
+        assert_eq!(keys, vec![&b"c"[..], b"d"]);
     }
 }
@@ -3,10 +3,14 @@ use std::{
         Instant,
         Duration,
     },
+    hash::{
+        Hash,
+        Hasher,
+    },
+    collections::hash_map::DefaultHasher,
 };
 
 use futures::{
-    future,
     stream::{
         FuturesUnordered,
     },
@@ -15,14 +19,6 @@ use futures::{
 
 use o1::set::Ref;
 
-use bntree::{
-    sketch,
-    writer::{
-        plan,
-        fold,
-    },
-};
-
 use alloc_pool::{
     pool,
     bytes::{
@@ -37,13 +33,20 @@ use crate::{
     job,
     wheels,
     storage,
+    metrics,
     blockwheel,
+    comparator::KeyComparatorRef,
     core::{
         merger,
+        block_sizer,
         search_tree,
         BlockRef,
+        RangeTombstones,
         SearchRangeBounds,
     },
+    Compression,
+    Flushed,
+    PlacementPolicy,
 };
 
 pub struct Args<J> where J: edeltraud::Job {
@@ -52,11 +55,20 @@ pub struct Args<J> where J: edeltraud::Job {
     pub search_tree_a_pid: search_tree::Pid,
     pub search_tree_b_pid: search_tree::Pid,
     pub thread_pool: edeltraud::Edeltraud<J>,
+    pub background_gate: job::BackgroundGate,
     pub blocks_pool: BytesPool,
     pub merge_blocks_pool: pool::Pool<Vec<storage::OwnedEntry>>,
     pub merger_iters_pool: pool::Pool<Vec<merger::KeyValuesIter>>,
     pub wheels_pid: wheels::Pid,
-    pub tree_block_size: usize,
+    pub block_budget: block_sizer::BlockBudget,
+    pub placement: PlacementPolicy,
+    pub block_compression: Option<Compression>,
+    pub store_id: u32,
+    pub created_at_version: u64,
+    pub metrics: metrics::MetricsRef,
+    pub key_comparator: KeyComparatorRef,
+    pub range_tombstones: RangeTombstones,
+    pub is_bottom: bool,
 }
 
 #[derive(Default, Debug)]
@@ -72,8 +84,17 @@ pub struct Timings {
 pub struct Done {
     pub search_tree_a_ref: Ref,
     pub search_tree_b_ref: Ref,
-    pub root_block: BlockRef,
+    // to tell the merged trees apart from the ones taking over their refs after a quarantine
+    pub search_tree_a_pid: search_tree::Pid,
+    pub search_tree_b_pid: search_tree::Pid,
+    pub root_block: Option<BlockRef>,
+    // deleted once both inputs are demolished
+    pub merge_record: Option<BlockRef>,
     pub items_count: usize,
+    pub max_version: u64,
+    pub created_at_version: u64,
+    // snapshot the merge has applied: cells these shadow are not in the merged tree
+    pub range_tombstones: RangeTombstones,
     pub timings: Timings,
 }
 
@@ -83,21 +104,17 @@ pub enum Error {
         search_tree_ref: Ref,
         error: search_tree::IterError,
     },
+    SearchTreeFlush(search_tree::FlushError),
+    SearchTreeRootBlock(search_tree::RootBlockError),
+    SerializeMergeRecord(storage::Error),
     WheelsGone,
     WheelsEmpty,
     ThreadPoolGone,
-    BuildTree(fold::Error),
-    BuildTreeUnexpectedLevelSeedOnVisitBlockStart,
-    BuildTreeUnexpectedLevelSeedOnVisitItem,
-    BuildTreeUnexpectedLevelSeedOnVisitBlockFinish,
-    BuildTreeUnexpectedChildRefOnVisitBlockFinish {
-        level_index: usize,
-        block_index: usize,
-    },
     BuildTreeUnexpectedEmptyTree,
     BuildTreeMergeIterDepleted,
     BlockSerializerStart(storage::Error),
     BlockSerializerEntry(storage::Error),
+    CompressBlock(storage::Error),
     WriteBlock(blockwheel::WriteBlockError),
     Merger(merger::Error),
     WheelNotFound {
@@ -117,9 +134,8 @@ where J: edeltraud::Job + From<job::Job>,
     let run_start = Instant::now();
     let mut timings = Timings::default();
 
-    let mut remove_tasks = FuturesUnordered::new();
-
     let mut tree_items_count = 0;
+    let mut deprecated_value_blocks = Vec::new();
 
     let mut merger = merger_start(
         args.search_tree_a_ref,
@@ -127,75 +143,146 @@ where J: edeltraud::Job + From<job::Job>,
         &mut args.search_tree_a_pid,
         &mut args.search_tree_b_pid,
         &args.merger_iters_pool,
+        args.key_comparator.clone(),
+        args.range_tombstones.clone(),
+        args.is_bottom,
     ).await?;
 
-    let remove_add = |key_value| {
-        match key_value {
-            kv::KeyValuePair {
-                value_cell: kv::ValueCell {
-                    cell: kv::Cell::Value(storage::OwnedValueBlockRef::Ref(block_ref)),
-                    ..
-                },
-                ..
-            } => {
-                let mut wheels_pid = args.wheels_pid.clone();
-                remove_tasks.push(async move {
-                    let mut wheel_ref = wheels_pid.get(block_ref.blockwheel_filename.clone()).await
-                        .map_err(|ero::NoProcError| Error::WheelsGone)?
-                        .ok_or_else(|| Error::WheelNotFound {
-                            blockwheel_filename: block_ref.blockwheel_filename.clone(),
-                        })?;
-                    let blockwheel::Deleted = wheel_ref.blockwheel_pid.delete_block(block_ref.block_id.clone()).await
-                        .map_err(Error::DeleteBlock)?;
-                    log::debug!("deleted: {:?}", block_ref);
-                    Ok(())
-                });
-            },
-            _ =>
-                (),
-        }
-    };
-
-    while let Some(..) = merger.next_with_deprecated(remove_add).await.map_err(Error::Merger)? {
+    // surviving values keep their block refs as is (see `into_owned_value_ref`), so a merge never
+    // rewrites value blocks: only the deprecated ones are freed, and only after the merged tree is
+    // written, so an interrupted merge leaves both inputs readable
+    while let Some(..) = merger
+        .next_with_deprecated(|key_value| deprecated_value_blocks.extend(deprecated_value_block(key_value)))
+        .await
+        .map_err(Error::Merger)?
+    {
         tree_items_count += 1;
     }
     timings.count += run_start.elapsed();
 
     let merge_start = Instant::now();
-    let join_task = future::try_join(
-        perform_merge(args, tree_items_count, timings),
-        async move {
-            while let Some(result) = remove_tasks.next().await {
-                let () = result?;
+    let mut wheels_pid = args.wheels_pid.clone();
+    let blocks_pool = args.blocks_pool.clone();
+    let store_id = args.store_id;
+    let mut done = if tree_items_count == 0 {
+        Done {
+            search_tree_a_ref: args.search_tree_a_ref,
+            search_tree_b_ref: args.search_tree_b_ref,
+            search_tree_a_pid: args.search_tree_a_pid,
+            search_tree_b_pid: args.search_tree_b_pid,
+            root_block: None,
+            merge_record: None,
+            items_count: 0,
+            max_version: 0,
+            created_at_version: args.created_at_version,
+            range_tombstones: args.range_tombstones,
+            timings,
+        }
+    } else {
+        perform_merge(args, tree_items_count, timings).await?
+    };
+
+    if let Some(root_block) = &done.root_block {
+        let merge_record = write_merge_record(
+            root_block,
+            &mut done.search_tree_a_pid,
+            &mut done.search_tree_b_pid,
+            store_id,
+            &blocks_pool,
+            &mut wheels_pid,
+        ).await?;
+        done.merge_record = Some(merge_record);
+    }
+
+    let mut remove_tasks: FuturesUnordered<_> = deprecated_value_blocks.into_iter()
+        .map(|block_ref| {
+            let mut wheels_pid = wheels_pid.clone();
+            async move {
+                let mut wheel_ref = wheels_pid.get(block_ref.blockwheel_filename.clone()).await
+                    .map_err(|ero::NoProcError| Error::WheelsGone)?
+                    .ok_or_else(|| Error::WheelNotFound {
+                        blockwheel_filename: block_ref.blockwheel_filename.clone(),
+                    })?;
+                let blockwheel::Deleted = wheel_ref.blockwheel_pid.delete_block(block_ref.block_id.clone()).await
+                    .map_err(Error::DeleteBlock)?;
+                log::debug!("deleted: {:?}", block_ref);
+                Ok::<_, Error>(())
             }
-            Ok(())
-        },
-    );
-    let (mut done, ()) = join_task.await?;
+        })
+        .collect();
+    while let Some(result) = remove_tasks.next().await {
+        let () = result?;
+    }
     done.timings.merge += merge_start.elapsed();
 
     done.timings.total += run_start.elapsed();
     Ok(done)
 }
 
+// both inputs stay on disk until demolished, and deprecated value blocks are deleted right below: a load
+// finding the record skips the inputs instead of restoring trees with values already gone
+async fn write_merge_record(
+    root_block: &BlockRef,
+    search_tree_a_pid: &mut search_tree::Pid,
+    search_tree_b_pid: &mut search_tree::Pid,
+    store_id: u32,
+    blocks_pool: &BytesPool,
+    wheels_pid: &mut wheels::Pid,
+)
+    -> Result<BlockRef, Error>
+{
+    let mut input_roots = Vec::with_capacity(2);
+    for search_tree_pid in vec![search_tree_a_pid, search_tree_b_pid] {
+        // an input flushed from butcher may still be bootstrapping
+        let Flushed { .. } = search_tree_pid.flush().await
+            .map_err(Error::SearchTreeFlush)?;
+        let maybe_root_block = search_tree_pid.root_block().await
+            .map_err(Error::SearchTreeRootBlock)?;
+        input_roots.extend(maybe_root_block);
+    }
+    let mut block_bytes = blocks_pool.lend();
+    storage::merge_record_serialize(root_block, &input_roots, store_id, &mut block_bytes)
+        .map_err(Error::SerializeMergeRecord)?;
+    let mut wheel_ref = wheels_pid.acquire().await
+        .map_err(|ero::NoProcError| Error::WheelsGone)?
+        .ok_or(Error::WheelsEmpty)?;
+    let block_id = wheel_ref.blockwheel_pid.write_block(block_bytes.freeze()).await
+        .map_err(Error::WriteBlock)?;
+    Ok(BlockRef {
+        blockwheel_filename: wheel_ref.blockwheel_filename,
+        block_id,
+    })
+}
+
+fn deprecated_value_block(key_value: kv::KeyValuePair<storage::OwnedValueBlockRef>) -> Option<BlockRef> {
+    match key_value.value_cell.cell {
+        kv::Cell::Value(storage::OwnedValueBlockRef::Ref(block_ref)) =>
+            Some(block_ref),
+        kv::Cell::Value(storage::OwnedValueBlockRef::Inline(..)) | kv::Cell::Tombstone { .. } =>
+            None,
+    }
+}
+
 pub type JobOutput = Result<JobDone, Error>;
 
 pub struct JobArgs {
     block_entries: Unique<Vec<storage::OwnedEntry>>,
     node_type: storage::NodeType,
     blocks_pool: BytesPool,
+    block_compression: Option<Compression>,
+    store_id: u32,
 }
 
 pub struct JobDone {
     block_bytes: Bytes,
 }
 
-pub fn job(JobArgs { mut block_entries, node_type, blocks_pool, }: JobArgs) -> JobOutput {
+pub fn job(JobArgs { mut block_entries, node_type, blocks_pool, block_compression, store_id, }: JobArgs) -> JobOutput {
     let block_bytes = blocks_pool.lend();
     let items_count = block_entries.len();
     block_entries.shrink_to_fit();
 
-    let mut block_serializer_kont = storage::BlockSerializer::start(node_type, items_count, block_bytes)
+    let mut block_serializer_kont = storage::BlockSerializer::start(node_type, items_count, store_id, block_bytes)
         .map_err(Error::BlockSerializerStart)?;
     for ref owned_entry in block_entries.drain(..) {
         match block_serializer_kont {
@@ -209,7 +296,16 @@ pub fn job(JobArgs { mut block_entries, node_type, blocks_pool, }: JobArgs) -> J
     }
     match block_serializer_kont {
         storage::BlockSerializerContinue::Done(block_bytes) =>
-            Ok(JobDone { block_bytes: block_bytes.freeze(), }),
+            match block_compression {
+                None =>
+                    Ok(JobDone { block_bytes: block_bytes.freeze(), }),
+                Some(ref compression) => {
+                    let mut compressed_bytes = blocks_pool.lend();
+                    storage::block_compress(&block_bytes, compression, &mut compressed_bytes)
+                        .map_err(Error::CompressBlock)?;
+                    Ok(JobDone { block_bytes: compressed_bytes.freeze(), })
+                },
+            },
         storage::BlockSerializerContinue::More(..) =>
             unreachable!(),
     }
@@ -222,11 +318,20 @@ async fn perform_merge<J>(
         mut search_tree_a_pid,
         mut search_tree_b_pid,
         thread_pool,
+        background_gate,
         blocks_pool,
         merge_blocks_pool,
         merger_iters_pool,
         mut wheels_pid,
-        tree_block_size,
+        block_budget,
+        placement,
+        block_compression,
+        store_id,
+        created_at_version,
+        metrics,
+        key_comparator,
+        range_tombstones,
+        is_bottom,
     }: Args<J>,
     tree_items_count: usize,
     mut timings: Timings,
@@ -236,197 +341,229 @@ where J: edeltraud::Job + From<job::Job>,
       J::Output: From<job::JobOutput>,
       job::JobOutput: From<J::Output>,
 {
-    let sketch = sketch::Tree::new(tree_items_count, tree_block_size);
-    let mut fold_ctx = fold::Context::new(
-        plan::Context::new(&sketch),
-        &sketch,
-    );
-
-    enum LevelSeed {
-        Empty,
-        BlockInProgress {
-            block_entries: Unique<Vec<storage::OwnedEntry>>,
-            wheel_ref: wheels::WheelRef,
-            node_type: storage::NodeType,
-        },
+    struct BlockInProgress {
+        block_entries: Unique<Vec<storage::OwnedEntry>>,
+        // acquired on the first item, so the placement may depend on the block keys
+        wheel_ref: wheels::WheelRef,
     }
 
+    let comparator_id = key_comparator.id();
     let mut merger = merger_start(
         search_tree_a_ref,
         search_tree_b_ref,
         &mut search_tree_a_pid,
         &mut search_tree_b_pid,
         &merger_iters_pool,
+        key_comparator,
+        range_tombstones.clone(),
+        is_bottom,
     ).await?;
 
-    let mut child_ref = None;
-
-    let mut kont = fold::Script::boot();
-    let root_block = loop {
-        let kont_step = kont.step_rec(&mut fold_ctx)
-            .map_err(Error::BuildTree)?;
-        kont = match kont_step {
-            // VisitLevel
-            fold::Instruction::Op(fold::Op::VisitLevel(fold::VisitLevel { next, .. })) => {
-                next.level_ready(LevelSeed::Empty, &mut fold_ctx)
-                    .map_err(Error::BuildTree)?
+    let block_writer = BlockWriter {
+        thread_pool: &thread_pool,
+        background_gate: &background_gate,
+        blocks_pool: &blocks_pool,
+        block_compression,
+        store_id,
+        metrics: &metrics,
+    };
+    let mut block_cutter = block_sizer::BlockCutter::new(block_budget);
+    let mut levels: Vec<Option<BlockInProgress>> = Vec::new();
+    let mut cut_block: Option<(usize, BlockInProgress)> = None;
+    let mut tree_max_version = 0;
+
+    for _ in 0 .. tree_items_count {
+        let now = Instant::now();
+        let kv::KeyValuePair { key, value_cell, } = merger.next().await
+            .map_err(Error::Merger)?
+            .ok_or(Error::BuildTreeMergeIterDepleted)?;
+        timings.merge_next += now.elapsed();
+        tree_max_version = tree_max_version.max(value_cell.version);
+
+        // a cut block is written once an entry above all of its keys is there to jump to it
+        let child_ref = match cut_block.take() {
+            None =>
+                None,
+            Some((_level_index, BlockInProgress { block_entries, mut wheel_ref, })) => {
+                let block_ref = block_writer.write(block_entries, storage::NodeType::Leaf, &mut wheel_ref, &mut timings).await?;
+                Some(block_ref)
             },
+        };
 
-            // VisitBlockStart
-            fold::Instruction::Op(fold::Op::VisitBlockStart(fold::VisitBlockStart {
-                level_seed: LevelSeed::Empty,
-                level_index,
-                items_count,
-                next,
-                ..
-            })) => {
-                let wheel_ref = wheels_pid.acquire().await
-                    .map_err(|ero::NoProcError| Error::WheelsGone)?
-                    .ok_or(Error::WheelsEmpty)?;
-                let node_type = if level_index == 0 {
-                    storage::NodeType::Root { tree_entries_count: tree_items_count, }
-                } else {
-                    storage::NodeType::Leaf
-                };
+        let level_index = block_cutter.next_level();
+        if levels.len() <= level_index {
+            levels.resize_with(level_index + 1, || None);
+        }
+        let BlockInProgress { mut block_entries, wheel_ref, } = match levels[level_index].take() {
+            Some(block) =>
+                block,
+            None => {
                 let mut block_entries = merge_blocks_pool.lend(Vec::new);
                 block_entries.clear();
-                block_entries.reserve(items_count);
-                let level_seed = LevelSeed::BlockInProgress {
-                    block_entries,
-                    wheel_ref,
-                    node_type,
-                };
-                next.block_ready(level_seed, &mut fold_ctx)
-                    .map_err(Error::BuildTree)?
+                let wheel_ref = acquire_wheel(&mut wheels_pid, placement, &key).await?;
+                BlockInProgress { block_entries, wheel_ref, }
             },
-            fold::Instruction::Op(fold::Op::VisitBlockStart(fold::VisitBlockStart { .. })) =>
-                return Err(Error::BuildTreeUnexpectedLevelSeedOnVisitBlockStart),
-
-            // VisitItem
-            fold::Instruction::Op(fold::Op::VisitItem(fold::VisitItem {
-                level_seed: LevelSeed::BlockInProgress {
-                    mut block_entries,
-                    wheel_ref,
-                    node_type,
-                },
-                next,
-                ..
-            })) => {
-                let now = Instant::now();
-                let kv::KeyValuePair { key, value_cell, } = merger.next().await
-                    .map_err(Error::Merger)?
-                    .ok_or(Error::BuildTreeMergeIterDepleted)?;
-                timings.merge_next += now.elapsed();
-
-                let child_ref_taken = child_ref.take();
-                let owned_jump_ref = match child_ref_taken {
-                    None =>
-                        storage::OwnedJumpRef::None,
-                    Some(BlockRef { blockwheel_filename, block_id, }) if blockwheel_filename == wheel_ref.blockwheel_filename =>
-                        storage::OwnedJumpRef::Local(storage::LocalRef { block_id, }),
-                    Some(block_ref) =>
-                        storage::OwnedJumpRef::External(block_ref),
-                };
-                let owned_entry = storage::OwnedEntry {
-                    jump_ref: owned_jump_ref,
-                    key,
-                    value_cell: value_cell.into_owned_value_ref(&wheel_ref.blockwheel_filename),
-                };
-                block_entries.push(owned_entry);
-                let level_seed = LevelSeed::BlockInProgress {
-                    block_entries,
-                    wheel_ref,
-                    node_type,
-                };
-                next.item_ready(level_seed, &mut fold_ctx)
-                    .map_err(Error::BuildTree)?
-            },
-            fold::Instruction::Op(fold::Op::VisitItem(fold::VisitItem { level_seed: LevelSeed::Empty, .. })) =>
-                return Err(Error::BuildTreeUnexpectedLevelSeedOnVisitItem),
-
-            // VisitBlockFinish
-            fold::Instruction::Op(fold::Op::VisitBlockFinish(fold::VisitBlockFinish {
-                level_seed: LevelSeed::BlockInProgress {
-                    block_entries,
-                    mut wheel_ref,
-                    node_type,
-                },
-                level_index,
-                block_index,
-                next,
-                ..
-            })) => {
-                if let Some(..) = child_ref {
-                    return Err(Error::BuildTreeUnexpectedChildRefOnVisitBlockFinish { level_index, block_index, });
-                }
+        };
 
-                let now = Instant::now();
-                let job_args = JobArgs {
-                    block_entries,
-                    node_type,
-                    blocks_pool: blocks_pool.clone(),
-                };
-                let job_output = thread_pool.spawn(job::Job::MergeSearchTrees(job_args)).await
-                    .map_err(|edeltraud::SpawnError::ThreadPoolGone| Error::ThreadPoolGone)?;
-                let job_output: job::JobOutput = job_output.into();
-                let job::MergeSearchTreesDone(job_result) = job_output.into();
-                let JobDone { block_bytes, } = job_result?;
-                timings.block_prepare += now.elapsed();
-
-                let now = Instant::now();
-                let block_id = wheel_ref.blockwheel_pid.write_block(block_bytes).await
-                    .map_err(Error::WriteBlock)?;
-                timings.block_write += now.elapsed();
-                child_ref = Some(BlockRef {
-                    blockwheel_filename: wheel_ref.blockwheel_filename,
-                    block_id,
-                });
-
-                let level_seed = LevelSeed::Empty;
-                next.block_flushed(level_seed, &mut fold_ctx)
-                    .map_err(Error::BuildTree)?
-            },
-            fold::Instruction::Op(fold::Op::VisitBlockFinish(fold::VisitBlockFinish { level_seed: LevelSeed::Empty, .. })) =>
-                return Err(Error::BuildTreeUnexpectedLevelSeedOnVisitBlockFinish),
-
-            // Done
-            fold::Instruction::Done =>
-                match child_ref {
-                    Some(root_block_ref) =>
-                        break root_block_ref,
-                    None =>
-                        return Err(Error::BuildTreeUnexpectedEmptyTree),
-                },
+        let owned_jump_ref = match child_ref {
+            None =>
+                storage::OwnedJumpRef::None,
+            Some(BlockRef { blockwheel_filename, block_id, }) if blockwheel_filename == wheel_ref.blockwheel_filename =>
+                storage::OwnedJumpRef::Local(storage::LocalRef { block_id, }),
+            Some(block_ref) =>
+                storage::OwnedJumpRef::External(block_ref),
         };
-    };
+        let owned_entry = storage::OwnedEntry {
+            jump_ref: owned_jump_ref,
+            key,
+            value_cell: value_cell.into_owned_value_ref(&wheel_ref.blockwheel_filename),
+        };
+        let entry_bytes = owned_entry.serialized_size()
+            .map_err(Error::BlockSerializerEntry)?;
+        block_entries.push(owned_entry);
+        let block = BlockInProgress { block_entries, wheel_ref, };
+        if block_cutter.push(entry_bytes) {
+            cut_block = Some((level_index, block));
+        } else {
+            levels[level_index] = Some(block);
+        }
+    }
     assert_eq!(merger.next().await.map_err(Error::Merger)?, None);
 
+    // the last cut block has no greater entry to jump to it, so its entries join the root
+    if let Some((level_index, block)) = cut_block.take() {
+        levels[level_index] = Some(block);
+    }
+    let mut maybe_root: Option<BlockInProgress> = None;
+    for BlockInProgress { mut block_entries, wheel_ref, } in levels.into_iter().rev().flatten() {
+        maybe_root = Some(match maybe_root.take() {
+            None =>
+                BlockInProgress { block_entries, wheel_ref, },
+            Some(mut root) => {
+                for owned_entry in block_entries.drain(..) {
+                    root.block_entries.push(owned_entry.retarget(&wheel_ref.blockwheel_filename, &root.wheel_ref.blockwheel_filename));
+                }
+                root
+            },
+        });
+    }
+    let BlockInProgress { block_entries, mut wheel_ref, } = maybe_root
+        .ok_or(Error::BuildTreeUnexpectedEmptyTree)?;
+    let node_type = storage::NodeType::Root {
+        tree_entries_count: tree_items_count,
+        max_version: tree_max_version,
+        created_at_version,
+        comparator_id,
+    };
+    // the root is the last block written, so an interrupted merge never leaves a reachable tree
+    let root_block = block_writer.write(block_entries, node_type, &mut wheel_ref, &mut timings).await?;
+
     Ok(Done {
         search_tree_a_ref,
         search_tree_b_ref,
-        root_block,
+        search_tree_a_pid,
+        search_tree_b_pid,
+        root_block: Some(root_block),
+        merge_record: None,
         items_count: tree_items_count,
+        max_version: tree_max_version,
+        created_at_version,
+        range_tombstones,
         timings,
     })
 }
 
+struct BlockWriter<'a, J> where J: edeltraud::Job {
+    thread_pool: &'a edeltraud::Edeltraud<J>,
+    background_gate: &'a job::BackgroundGate,
+    blocks_pool: &'a BytesPool,
+    block_compression: Option<Compression>,
+    store_id: u32,
+    metrics: &'a metrics::MetricsRef,
+}
+
+impl<'a, J> BlockWriter<'a, J>
+where J: edeltraud::Job + From<job::Job>,
+      J::Output: From<job::JobOutput>,
+      job::JobOutput: From<J::Output>,
+{
+    async fn write(
+        &self,
+        block_entries: Unique<Vec<storage::OwnedEntry>>,
+        node_type: storage::NodeType,
+        wheel_ref: &mut wheels::WheelRef,
+        timings: &mut Timings,
+    )
+        -> Result<BlockRef, Error>
+    {
+        let now = Instant::now();
+        let job_args = JobArgs {
+            block_entries,
+            node_type,
+            blocks_pool: self.blocks_pool.clone(),
+            block_compression: self.block_compression,
+            store_id: self.store_id,
+        };
+        let job_output = self.background_gate.spawn(self.thread_pool, job::Job::MergeSearchTrees(job_args)).await
+            .map_err(|edeltraud::SpawnError::ThreadPoolGone| Error::ThreadPoolGone)?;
+        let job_output: job::JobOutput = job_output.into();
+        let job::MergeSearchTreesDone(job_result) = job_output.into();
+        let JobDone { block_bytes, } = job_result?;
+        timings.block_prepare += now.elapsed();
+
+        let now = Instant::now();
+        let block_size = block_bytes.len();
+        let block_id = wheel_ref.blockwheel_pid.write_block(block_bytes).await
+            .map_err(Error::WriteBlock)?;
+        self.metrics.on_block_write(block_size);
+        timings.block_write += now.elapsed();
+        Ok(BlockRef {
+            blockwheel_filename: wheel_ref.blockwheel_filename.clone(),
+            block_id,
+        })
+    }
+}
+
+async fn acquire_wheel(wheels_pid: &mut wheels::Pid, placement: PlacementPolicy, first_key: &kv::Key) -> Result<wheels::WheelRef, Error> {
+    let placement = match placement {
+        PlacementPolicy::Random =>
+            wheels::Placement::Random,
+        PlacementPolicy::RoundRobin =>
+            wheels::Placement::RoundRobin,
+        PlacementPolicy::LeastFull =>
+            wheels::Placement::LeastFull,
+        PlacementPolicy::KeyRangeHash => {
+            // `DefaultHasher::new` uses fixed keys, so placement is stable across merges and restarts
+            let mut hasher = DefaultHasher::new();
+            first_key.hash(&mut hasher);
+            wheels::Placement::Hash(hasher.finish())
+        },
+    };
+    wheels_pid.acquire_placed(placement).await
+        .map_err(|ero::NoProcError| Error::WheelsGone)?
+        .ok_or(Error::WheelsEmpty)
+}
+
 async fn merger_start(
     search_tree_a_ref: Ref,
     search_tree_b_ref: Ref,
     search_tree_a_pid: &mut search_tree::Pid,
     search_tree_b_pid: &mut search_tree::Pid,
     merger_iters_pool: &pool::Pool<Vec<merger::KeyValuesIter>>,
+    key_comparator: KeyComparatorRef,
+    range_tombstones: RangeTombstones,
+    is_bottom: bool,
 )
     -> Result<merger::ItersMerger<Unique<Vec<merger::KeyValuesIter>>>, Error>
 {
     let (items_a_rx, items_b_rx) = futures::future::try_join(
         async {
-            let search_tree::SearchTreeIterItemsRx { items_rx, } = search_tree_a_pid.iter(SearchRangeBounds::unbounded()).await
+            let search_tree::SearchTreeIterItemsRx { items_rx, .. } = search_tree_a_pid.iter(SearchRangeBounds::unbounded()).await
                 .map_err(|error| Error::SearchTreeIter { search_tree_ref: search_tree_a_ref.clone(), error, })?;
             Ok(items_rx)
         },
         async {
-            let search_tree::SearchTreeIterItemsRx { items_rx, } = search_tree_b_pid.iter(SearchRangeBounds::unbounded()).await
+            let search_tree::SearchTreeIterItemsRx { items_rx, .. } = search_tree_b_pid.iter(SearchRangeBounds::unbounded()).await
                 .map_err(|error| Error::SearchTreeIter { search_tree_ref: search_tree_b_ref.clone(), error, })?;
             Ok(items_rx)
         },
@@ -438,5 +575,8 @@ async fn merger_start(
     iters.push(merger::KeyValuesIter::new(items_b_rx));
     iters.shrink_to_fit();
 
-    Ok(merger::ItersMerger::new(iters))
+    Ok(merger::ItersMerger::new(iters)
+        .dropping_tombstones(is_bottom)
+        .applying_range_tombstones(range_tombstones, true)
+        .comparing_keys(key_comparator))
 }
@@ -17,8 +17,8 @@ pub enum Error {
     ButcherRemove(ero::NoProcError),
 }
 
-pub async fn run(Args { request: RequestRemove { key, reply_tx, }, mut butcher_pid, }: Args) -> Result<Done, Error> {
-    let removed = butcher_pid.remove(key).await
+pub async fn run(Args { request: RequestRemove { key, meta, reply_tx, }, mut butcher_pid, }: Args) -> Result<Done, Error> {
+    let removed = butcher_pid.remove(key, meta).await
         .map_err(Error::ButcherRemove)?;
     if let Err(_send_error) = reply_tx.send(removed) {
         log::warn!("client canceled remove request");
@@ -0,0 +1,27 @@
+use crate::{
+    core::{
+        butcher,
+        RequestRemoveMany,
+    },
+};
+
+pub struct Args {
+    pub request: RequestRemoveMany,
+    pub butcher_pid: butcher::Pid,
+}
+
+pub struct Done;
+
+#[derive(Debug)]
+pub enum Error {
+    ButcherRemoveMany(ero::NoProcError),
+}
+
+pub async fn run(Args { request: RequestRemoveMany { keys, reply_tx, }, mut butcher_pid, }: Args) -> Result<Done, Error> {
+    let removed = butcher_pid.remove_many(keys).await
+        .map_err(Error::ButcherRemoveMany)?;
+    if let Err(_send_error) = reply_tx.send(removed) {
+        log::warn!("client canceled remove many request");
+    }
+    Ok(Done)
+}
@@ -0,0 +1,67 @@
+use futures::{
+    channel::{
+        oneshot,
+    },
+};
+
+use crate::{
+    kv,
+    core::{
+        butcher,
+        manager,
+    },
+    Removed,
+};
+
+pub struct Args {
+    pub key: kv::Key,
+    pub lookup_reply_rx: oneshot::Receiver<Option<kv::ValueCell<kv::Value>>>,
+    pub flush_generation: u64,
+    pub reply_tx: oneshot::Sender<Result<(Removed, Option<kv::ValueCell<kv::Value>>), manager::RemoveError>>,
+    pub butcher_pid: butcher::Pid,
+}
+
+pub enum Done {
+    Removed,
+    Conflict {
+        key: kv::Key,
+        reply_tx: oneshot::Sender<Result<(Removed, Option<kv::ValueCell<kv::Value>>), manager::RemoveError>>,
+    },
+}
+
+#[derive(Debug)]
+pub enum Error {
+    LookupCanceled,
+    ButcherRemoveIf(ero::NoProcError),
+}
+
+pub async fn run(Args { key, lookup_reply_rx, flush_generation, reply_tx, mut butcher_pid, }: Args) -> Result<Done, Error> {
+    let found = lookup_reply_rx.await
+        .map_err(|oneshot::Canceled| Error::LookupCanceled)?;
+    let condition = butcher::RemoveCondition {
+        expected_version: found.as_ref().map(|value_cell| value_cell.version),
+        flush_generation,
+    };
+    let removed = match butcher_pid.remove_if(key.clone(), condition).await.map_err(Error::ButcherRemoveIf)? {
+        Ok(None) =>
+            return Ok(Done::Conflict { key, reply_tx, }),
+        Ok(Some(removed)) =>
+            removed,
+        Err(error) => {
+            if let Err(_send_error) = reply_tx.send(Err(error)) {
+                log::warn!("client canceled remove returning request");
+            }
+            return Ok(Done::Removed);
+        },
+    };
+    let prev_value_cell = match found {
+        Some(value_cell @ kv::ValueCell { cell: kv::Cell::Value(..), .. }) =>
+            Some(value_cell),
+        Some(kv::ValueCell { cell: kv::Cell::Tombstone { .. }, .. }) | None =>
+            None,
+    };
+    if let Err(_send_error) = reply_tx.send(Ok((removed, prev_value_cell))) {
+        log::warn!("client canceled remove returning request");
+    }
+    Ok(Done::Removed)
+}
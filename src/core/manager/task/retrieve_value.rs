@@ -1,3 +1,7 @@
+use std::{
+    time::Instant,
+};
+
 use futures::{
     channel::{
         oneshot,
@@ -16,14 +20,19 @@ pub struct Args {
     pub key: kv::Key,
     pub found_fold: Option<kv::ValueCell<storage::OwnedValueBlockRef>>,
     pub reply_tx: oneshot::Sender<Option<kv::ValueCell<kv::Value>>>,
+    pub started_at: Instant,
     pub wheels_pid: wheels::Pid,
 }
 
 pub enum Done {
-    RetrieveSuccess,
+    RetrieveSuccess {
+        found: bool,
+        started_at: Instant,
+    },
     DeprecatedResults {
         key: kv::Key,
         reply_tx: oneshot::Sender<Option<kv::ValueCell<kv::Value>>>,
+        started_at: Instant,
     },
 }
 
@@ -37,7 +46,7 @@ pub enum Error {
     ValueDeserialize(storage::Error),
 }
 
-pub async fn run(Args { key, found_fold, reply_tx, mut wheels_pid, }: Args) -> Result<Done, Error> {
+pub async fn run(Args { key, found_fold, reply_tx, started_at, mut wheels_pid, }: Args) -> Result<Done, Error> {
     let lookup_result = match found_fold {
         None =>
             None,
@@ -59,16 +68,17 @@ pub async fn run(Args { key, found_fold, reply_tx, mut wheels_pid, }: Args) -> R
                     })
                 },
                 Err(blockwheel::ReadBlockError::NotFound) =>
-                    return Ok(Done::DeprecatedResults { key, reply_tx, }),
+                    return Ok(Done::DeprecatedResults { key, reply_tx, started_at, }),
                 Err(error) =>
                     return Err(Error::ReadBlock(error)),
             }
         },
-        Some(kv::ValueCell { version, cell: kv::Cell::Tombstone, }) =>
-            Some(kv::ValueCell { version, cell: kv::Cell::Tombstone, }),
+        Some(kv::ValueCell { version, cell: kv::Cell::Tombstone { meta, }, }) =>
+            Some(kv::ValueCell { version, cell: kv::Cell::Tombstone { meta, }, }),
     };
+    let found = matches!(lookup_result, Some(kv::ValueCell { cell: kv::Cell::Value(..), .. }));
     if let Err(_send_error) = reply_tx.send(lookup_result) {
         log::warn!("client canceled lookup request");
     }
-    Ok(Done::RetrieveSuccess)
+    Ok(Done::RetrieveSuccess { found, started_at, })
 }
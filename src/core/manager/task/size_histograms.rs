@@ -0,0 +1,100 @@
+use futures::{
+    channel::{
+        oneshot,
+    },
+    StreamExt,
+};
+
+use alloc_pool::{
+    pool,
+};
+
+use crate::{
+    kv,
+    storage,
+    core::{
+        butcher,
+        search_tree::{
+            self,
+            SearchTreeIterItemsRx,
+        },
+        KeyValueRef,
+        SearchRangeBounds,
+    },
+    Histograms,
+};
+
+pub struct Args {
+    pub reply_tx: oneshot::Sender<Histograms>,
+    pub butcher_pid: butcher::Pid,
+    pub search_tree_pids: Vec<search_tree::Pid>,
+    pub iter_items_pool: pool::Pool<Vec<kv::KeyValuePair<kv::Value>>>,
+}
+
+pub struct Done {
+    pub histograms: Histograms,
+    pub reply_tx: oneshot::Sender<Histograms>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+}
+
+// a tree demolished after a merge in the meantime is skipped: this is a diagnostics snapshot, not a consistent one
+pub async fn run(Args { reply_tx, mut butcher_pid, search_tree_pids, iter_items_pool, }: Args) -> Result<Done, Error> {
+    let mut histograms = Histograms::default();
+
+    match butcher_pid.lookup_range(SearchRangeBounds::from(..), iter_items_pool).await {
+        Ok(iter_items) =>
+            for kv::KeyValuePair { key, value_cell, } in iter_items.iter() {
+                histograms.key_sizes.record(key.key_bytes.len());
+                match &value_cell.cell {
+                    kv::Cell::Value(value) =>
+                        histograms.value_sizes.record(value.value_bytes.len()),
+                    kv::Cell::Tombstone { .. } =>
+                        histograms.tombstones += 1,
+                }
+            },
+        Err(ero::NoProcError) =>
+            log::warn!("butcher is gone: skipping it in size histograms"),
+    }
+
+    for mut search_tree_pid in search_tree_pids {
+        let SearchTreeIterItemsRx { mut items_rx, .. } = match search_tree_pid.iter(SearchRangeBounds::from(..)).await {
+            Ok(iter_items_rx) =>
+                iter_items_rx,
+            Err(error) => {
+                log::warn!("search tree iter failed: {:?}, skipping it in size histograms", error);
+                continue;
+            },
+        };
+        let mut tree_histograms = Histograms::default();
+        loop {
+            match items_rx.next().await {
+                None => {
+                    log::warn!("iter items channel depleted before NoMore: skipping search tree in size histograms");
+                    tree_histograms = Histograms::default();
+                    break;
+                },
+                Some(KeyValueRef::NoMore) =>
+                    break,
+                Some(KeyValueRef::BlockFinish { .. }) =>
+                    (),
+                Some(KeyValueRef::Item { key, value_cell, }) => {
+                    tree_histograms.key_sizes.record(key.key_bytes.len());
+                    match value_cell.cell {
+                        kv::Cell::Value(storage::OwnedValueBlockRef::Inline(value)) =>
+                            tree_histograms.value_sizes.record(value.value_bytes.len()),
+                        kv::Cell::Value(storage::OwnedValueBlockRef::Ref(..)) =>
+                            tree_histograms.out_of_line_values += 1,
+                        kv::Cell::Tombstone { .. } =>
+                            tree_histograms.tombstones += 1,
+                    }
+                },
+            }
+        }
+        histograms += tree_histograms;
+    }
+
+    Ok(Done { histograms, reply_tx, })
+}
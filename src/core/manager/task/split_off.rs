@@ -0,0 +1,87 @@
+use futures::{
+    channel::{
+        mpsc,
+        oneshot,
+    },
+    StreamExt,
+};
+
+use alloc_pool::{
+    bytes::{
+        BytesPool,
+    },
+};
+
+use crate::{
+    kv,
+    wheels,
+    core::{
+        manager,
+        RangeTombstone,
+        SearchRangeBounds,
+    },
+    KeyValueStreamItem,
+    StreamError,
+};
+
+use super::write_range_tombstone;
+
+pub struct Args {
+    pub range: SearchRangeBounds,
+    pub version: u64,
+    pub key_values_rx: mpsc::Receiver<KeyValueStreamItem>,
+    pub cancel_tx: oneshot::Sender<()>,
+    pub reply_tx: oneshot::Sender<Result<Vec<kv::KeyValuePair<kv::Value>>, manager::SplitOffError>>,
+    pub store_id: u32,
+    pub blocks_pool: BytesPool,
+    pub wheels_pid: wheels::Pid,
+}
+
+pub enum Done {
+    Moved {
+        range_tombstone: RangeTombstone,
+        items: Vec<kv::KeyValuePair<kv::Value>>,
+        reply_tx: oneshot::Sender<Result<Vec<kv::KeyValuePair<kv::Value>>, manager::SplitOffError>>,
+    },
+    // the client is already notified and nothing is removed
+    ScanFailed,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    WriteRangeTombstone(write_range_tombstone::Error),
+}
+
+// only cells older than `version` are moved, and the range tombstone written with `version` shadows exactly those
+pub async fn run(Args { range, version, mut key_values_rx, cancel_tx, reply_tx, store_id, blocks_pool, mut wheels_pid, }: Args) -> Result<Done, Error> {
+    let mut items = Vec::new();
+    let scan_result = loop {
+        match key_values_rx.next().await {
+            None =>
+                break Err(StreamError::Interrupted),
+            Some(KeyValueStreamItem::KeyValue(key_value)) =>
+                match key_value.value_cell {
+                    kv::ValueCell { version: cell_version, cell: kv::Cell::Value(..), } if cell_version < version =>
+                        items.push(key_value),
+                    kv::ValueCell { .. } =>
+                        (),
+                },
+            Some(KeyValueStreamItem::Error(error)) =>
+                break Err(error),
+            Some(KeyValueStreamItem::NoMore) =>
+                break Ok(()),
+        }
+    };
+    drop(cancel_tx);
+
+    if let Err(error) = scan_result {
+        if let Err(_send_error) = reply_tx.send(Err(manager::SplitOffError::Stream(error))) {
+            log::warn!("client canceled split off request");
+        }
+        return Ok(Done::ScanFailed);
+    }
+
+    let range_tombstone = write_range_tombstone::write(range, version, store_id, &blocks_pool, &mut wheels_pid).await
+        .map_err(Error::WriteRangeTombstone)?;
+    Ok(Done::Moved { range_tombstone, items, reply_tx, })
+}
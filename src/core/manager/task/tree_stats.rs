@@ -0,0 +1,70 @@
+use futures::{
+    StreamExt,
+};
+
+use o1::set::Ref;
+
+use crate::{
+    kv,
+    core::{
+        search_tree,
+        level_merger,
+        KeyValueRef,
+        SearchRangeBounds,
+    },
+};
+
+pub struct Args {
+    pub search_tree_ref: Ref,
+    pub search_tree_pid: search_tree::Pid,
+}
+
+pub struct Done {
+    pub search_tree_ref: Ref,
+    // to tell the tree apart from the one taking over its ref after a quarantine
+    pub search_tree_pid: search_tree::Pid,
+    pub items_count: usize,
+    pub bytes: usize,
+    pub key_range: Option<level_merger::KeyRange>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    SearchTreeIter(search_tree::IterError),
+    SearchTreeIterInterrupted,
+}
+
+// root headers keep neither tree size nor keys range, so a restored tree is walked once before it is
+// queued for merges; out of line values are counted by node blocks holding their refs, they are not read
+pub async fn run(Args { search_tree_ref, mut search_tree_pid, }: Args) -> Result<Done, Error> {
+    let search_tree::SearchTreeIterItemsRx { mut items_rx, .. } = search_tree_pid.iter(SearchRangeBounds::unbounded()).await
+        .map_err(Error::SearchTreeIter)?;
+    let mut items_count = 0;
+    let mut bytes = 0;
+    let mut first_key: Option<kv::Key> = None;
+    let mut last_key: Option<kv::Key> = None;
+    loop {
+        match items_rx.next().await {
+            None =>
+                return Err(Error::SearchTreeIterInterrupted),
+            Some(KeyValueRef::Item { key, .. }) => {
+                items_count += 1;
+                if first_key.is_none() {
+                    first_key = Some(key.clone());
+                }
+                last_key = Some(key);
+            },
+            Some(KeyValueRef::BlockFinish { block_size, .. }) =>
+                bytes += block_size,
+            Some(KeyValueRef::NoMore) =>
+                break,
+        }
+    }
+    let key_range = match (first_key, last_key) {
+        (Some(first), Some(last)) =>
+            Some(level_merger::KeyRange { first, last, }),
+        _ =>
+            None,
+    };
+    Ok(Done { search_tree_ref, search_tree_pid, items_count, bytes, key_range, })
+}
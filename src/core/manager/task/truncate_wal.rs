@@ -0,0 +1,52 @@
+use futures::{
+    channel::{
+        oneshot,
+    },
+};
+
+use crate::{
+    wheels,
+    blockwheel,
+    core::{
+        BlockRef,
+    },
+};
+
+pub struct Args {
+    pub bootstrap_done_rx: oneshot::Receiver<()>,
+    pub wal_block_refs: Vec<BlockRef>,
+    pub wheels_pid: wheels::Pid,
+}
+
+pub struct Done {
+    pub records_count: usize,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    WheelsGone,
+    WheelNotFound {
+        blockwheel_filename: wheels::WheelFilename,
+    },
+    DeleteBlock(blockwheel::DeleteBlockError),
+}
+
+pub async fn run(Args { bootstrap_done_rx, wal_block_refs, mut wheels_pid, }: Args) -> Result<Done, Error> {
+    // records may be dropped only after the cache they cover is written on wheels
+    if let Err(oneshot::Canceled) = bootstrap_done_rx.await {
+        log::warn!("search tree has gone before bootstrap: keeping {} wal records", wal_block_refs.len());
+        return Ok(Done { records_count: 0, });
+    }
+
+    let records_count = wal_block_refs.len();
+    for block_ref in wal_block_refs {
+        let mut wheel_ref = wheels_pid.get(block_ref.blockwheel_filename.clone()).await
+            .map_err(|ero::NoProcError| Error::WheelsGone)?
+            .ok_or_else(|| Error::WheelNotFound {
+                blockwheel_filename: block_ref.blockwheel_filename.clone(),
+            })?;
+        let blockwheel::Deleted = wheel_ref.blockwheel_pid.delete_block(block_ref.block_id).await
+            .map_err(Error::DeleteBlock)?;
+    }
+    Ok(Done { records_count, })
+}
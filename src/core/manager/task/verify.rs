@@ -0,0 +1,159 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+
+use futures::{
+    channel::{
+        oneshot,
+    },
+    StreamExt,
+};
+
+use alloc_pool::bytes::{
+    BytesPool,
+};
+
+use crate::{
+    job,
+    wheels,
+    storage,
+    comparator,
+    core::{
+        BlockRef,
+    },
+    CorruptBlock,
+    VerifyReport,
+    RawBlockHeader,
+};
+
+use super::{
+    gc_orphans::{
+        classify_block,
+        BlockKind,
+    },
+    iter_raw_blocks,
+};
+
+pub struct Args<J> where J: edeltraud::Job {
+    pub reply_tx: oneshot::Sender<VerifyReport>,
+    pub wheels_pid: wheels::Pid,
+    pub thread_pool: edeltraud::Edeltraud<J>,
+    pub background_gate: job::BackgroundGate,
+    pub blocks_pool: BytesPool,
+    pub key_comparator: comparator::KeyComparatorRef,
+    pub store_id: u32,
+}
+
+pub struct Done {
+    pub verify_report: VerifyReport,
+    pub reply_tx: oneshot::Sender<VerifyReport>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    WheelsIterBlocks(wheels::IterBlocksError),
+    WheelsIterBlocksRxDropped,
+    VerifyBlock(iter_raw_blocks::Error),
+}
+
+pub async fn run<J>(
+    Args {
+        reply_tx,
+        mut wheels_pid,
+        thread_pool,
+        background_gate,
+        blocks_pool,
+        key_comparator,
+        store_id,
+    }: Args<J>,
+)
+    -> Result<Done, Error>
+where J: edeltraud::Job + From<job::Job>,
+      J::Output: From<job::JobOutput>,
+      job::JobOutput: From<J::Output>,
+{
+    let mut iter_blocks = wheels_pid.iter_blocks().await
+        .map_err(Error::WheelsIterBlocks)?;
+
+    let mut blocks_total = 0;
+    let mut corrupt_blocks = Vec::new();
+    let mut roots = Vec::new();
+    let mut tree_blocks = HashMap::new();
+    let mut known_blocks = HashSet::new();
+    loop {
+        match iter_blocks.block_refs_rx.next().await {
+            None =>
+                return Err(Error::WheelsIterBlocksRxDropped),
+            Some(wheels::IterBlocksItem::Block { block_ref, block_bytes, }) => {
+                blocks_total += 1;
+                known_blocks.insert(block_ref.clone());
+                let job_args = iter_raw_blocks::JobArgs {
+                    block_ref: block_ref.clone(),
+                    block_bytes: block_bytes.clone(),
+                    blocks_pool: blocks_pool.clone(),
+                    key_comparator: key_comparator.clone(),
+                };
+                let verified = iter_raw_blocks::spawn_job(&thread_pool, &background_gate, job_args).await
+                    .map_err(Error::VerifyBlock)?;
+                match verified {
+                    Ok(RawBlockHeader::Tree { .. }) =>
+                        match classify_block(&block_ref, block_bytes, &blocks_pool, store_id) {
+                            Ok(BlockKind::Tree { node_type, refs, }) => {
+                                if let storage::NodeType::Root { .. } = node_type {
+                                    roots.push(block_ref.clone());
+                                }
+                                tree_blocks.insert(block_ref, refs);
+                            },
+                            Ok(BlockKind::Value) | Ok(BlockKind::Other) =>
+                                (),
+                            Err(error) =>
+                                log::warn!("tree block {:?} verified but failed to classify: {:?}", block_ref, error),
+                        },
+                    Ok(RawBlockHeader::Value) | Ok(RawBlockHeader::Other) =>
+                        (),
+                    Err(error) =>
+                        corrupt_blocks.push(CorruptBlock { block_ref, error, }),
+                }
+            },
+            Some(wheels::IterBlocksItem::NoMoreBlocks) =>
+                break,
+        }
+    }
+
+    // merges running concurrently with the scan may make some of their blocks appear missing
+    let corrupt_refs: HashSet<_> = corrupt_blocks.iter()
+        .map(|corrupt_block| corrupt_block.block_ref.clone())
+        .collect();
+    let mut missing_blocks = HashSet::new();
+    let mut corrupt_trees = Vec::new();
+    for root_ref in roots {
+        let mut visited = HashSet::new();
+        let mut pending = vec![root_ref.clone()];
+        let mut is_corrupt = false;
+        while let Some(block_ref) = pending.pop() {
+            if !visited.insert(block_ref.clone()) {
+                continue;
+            }
+            if corrupt_refs.contains(&block_ref) {
+                is_corrupt = true;
+            } else if !known_blocks.contains(&block_ref) {
+                is_corrupt = true;
+                missing_blocks.insert(block_ref);
+            } else if let Some(refs) = tree_blocks.get(&block_ref) {
+                pending.extend(refs.iter().cloned());
+            }
+        }
+        if is_corrupt {
+            corrupt_trees.push(root_ref);
+        }
+    }
+
+    let verify_report = VerifyReport {
+        blocks_total,
+        corrupt_blocks,
+        corrupt_trees,
+        missing_blocks: missing_blocks.into_iter().collect(),
+    };
+    Ok(Done { verify_report, reply_tx, })
+}
@@ -0,0 +1,69 @@
+use futures::{
+    channel::{
+        oneshot,
+    },
+    StreamExt,
+};
+
+use crate::{
+    core::{
+        search_tree::{
+            self,
+            SearchTreeIterItemsRx,
+        },
+        KeyValueRef,
+        SearchRangeBounds,
+    },
+    Warmed,
+};
+
+pub struct Args {
+    pub reply_tx: oneshot::Sender<Warmed>,
+    pub range: SearchRangeBounds,
+    pub search_tree_pids: Vec<search_tree::Pid>,
+    pub bytes_limit: Option<usize>,
+}
+
+pub struct Done {
+    pub warmed: Warmed,
+    pub reply_tx: oneshot::Sender<Warmed>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+}
+
+// blocks are read through the wheels, which keep them in their lru caches: the items iterated are
+// dropped, and the iteration is canceled as soon as `bytes_limit` is read
+pub async fn run(Args { reply_tx, range, search_tree_pids, bytes_limit, }: Args) -> Result<Done, Error> {
+    let mut warmed = Warmed::default();
+
+    'trees: for mut search_tree_pid in search_tree_pids {
+        let SearchTreeIterItemsRx { mut items_rx, .. } = match search_tree_pid.iter(range.clone()).await {
+            Ok(iter_items_rx) =>
+                iter_items_rx,
+            Err(error) => {
+                log::warn!("search tree iter failed: {:?}, skipping it in warm range", error);
+                continue;
+            },
+        };
+        loop {
+            match items_rx.next().await {
+                None | Some(KeyValueRef::NoMore) =>
+                    break,
+                Some(KeyValueRef::Item { .. }) =>
+                    (),
+                Some(KeyValueRef::BlockFinish { block_size, .. }) => {
+                    warmed.blocks_count += 1;
+                    warmed.bytes += block_size;
+                    if bytes_limit.map_or(false, |bytes_limit| warmed.bytes >= bytes_limit) {
+                        log::debug!("warm range bytes limit reached after {} blocks", warmed.blocks_count);
+                        break 'trees;
+                    }
+                },
+            }
+        }
+    }
+
+    Ok(Done { warmed, reply_tx, })
+}
@@ -0,0 +1,75 @@
+use futures::{
+    channel::{
+        oneshot,
+    },
+};
+
+use alloc_pool::{
+    bytes::{
+        BytesPool,
+    },
+};
+
+use crate::{
+    wheels,
+    storage,
+    blockwheel,
+    core::{
+        manager,
+        BlockRef,
+        RangeTombstone,
+        SearchRangeBounds,
+    },
+    Removed,
+};
+
+pub struct Args {
+    pub range: SearchRangeBounds,
+    pub version: u64,
+    pub reply_tx: oneshot::Sender<Result<Removed, manager::RemoveError>>,
+    pub store_id: u32,
+    pub blocks_pool: BytesPool,
+    pub wheels_pid: wheels::Pid,
+}
+
+pub struct Done {
+    pub range_tombstone: RangeTombstone,
+    pub reply_tx: oneshot::Sender<Result<Removed, manager::RemoveError>>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Serialize(storage::Error),
+    WheelsGone,
+    WheelsEmpty,
+    WriteBlock(blockwheel::WriteBlockError),
+}
+
+pub async fn run(Args { range, version, reply_tx, store_id, blocks_pool, mut wheels_pid, }: Args) -> Result<Done, Error> {
+    let range_tombstone = write(range, version, store_id, &blocks_pool, &mut wheels_pid).await?;
+    Ok(Done { range_tombstone, reply_tx, })
+}
+
+pub async fn write(
+    range: SearchRangeBounds,
+    version: u64,
+    store_id: u32,
+    blocks_pool: &BytesPool,
+    wheels_pid: &mut wheels::Pid,
+)
+    -> Result<RangeTombstone, Error>
+{
+    let mut block_bytes = blocks_pool.lend();
+    storage::range_tombstone_serialize(range.range_from.as_ref(), range.range_to.as_ref(), version, store_id, &mut block_bytes)
+        .map_err(Error::Serialize)?;
+    let mut wheel_ref = wheels_pid.acquire().await
+        .map_err(|ero::NoProcError| Error::WheelsGone)?
+        .ok_or(Error::WheelsEmpty)?;
+    let block_id = wheel_ref.blockwheel_pid.write_block(block_bytes.freeze()).await
+        .map_err(Error::WriteBlock)?;
+    let block_ref = BlockRef {
+        blockwheel_filename: wheel_ref.blockwheel_filename,
+        block_id,
+    };
+    Ok(RangeTombstone { range, version, block_ref, })
+}
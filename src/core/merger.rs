@@ -1,5 +1,6 @@
 use std::{
     mem,
+    sync::Arc,
     ops::DerefMut,
     cmp::Ordering,
 };
@@ -8,19 +9,28 @@ use futures::{
     channel::{
         mpsc,
     },
+    stream::BoxStream,
+    SinkExt,
     StreamExt,
 };
 
 use crate::{
     kv,
     storage,
+    comparator::{
+        self,
+        KeyComparatorRef,
+    },
     core::{
+        range_tombstoned,
         KeyValueRef,
+        RangeTombstones,
     },
 };
 
 pub struct KeyValuesIter {
-    key_values_rx: mpsc::Receiver<KeyValueRef>,
+    key_values_rx: KeyValuesSource,
+    demand_tx: Option<mpsc::Sender<()>>,
     iter_state: IterState,
     advance_next_idx: Option<usize>,
 }
@@ -28,16 +38,55 @@ pub struct KeyValuesIter {
 impl KeyValuesIter {
     pub fn new(key_values_rx: mpsc::Receiver<KeyValueRef>) -> KeyValuesIter {
         KeyValuesIter {
-            key_values_rx,
+            key_values_rx: KeyValuesSource::Channel(key_values_rx),
+            demand_tx: None,
             iter_state: IterState::NotReady,
             advance_next_idx: None,
         }
     }
+
+    // for items coming from outside of the crate, see `merge` module
+    pub fn from_stream(key_values: BoxStream<'static, KeyValueRef>) -> KeyValuesIter {
+        KeyValuesIter {
+            key_values_rx: KeyValuesSource::Stream(key_values),
+            demand_tx: None,
+            iter_state: IterState::NotReady,
+            advance_next_idx: None,
+        }
+    }
+
+    // the peer produces its next item only when this iterator front is taken by the merger
+    pub fn demand_driven(key_values_rx: mpsc::Receiver<KeyValueRef>, demand_tx: mpsc::Sender<()>) -> KeyValuesIter {
+        KeyValuesIter {
+            demand_tx: Some(demand_tx),
+            ..KeyValuesIter::new(key_values_rx)
+        }
+    }
+}
+
+enum KeyValuesSource {
+    Channel(mpsc::Receiver<KeyValueRef>),
+    Stream(BoxStream<'static, KeyValueRef>),
+}
+
+impl KeyValuesSource {
+    async fn next(&mut self) -> Option<KeyValueRef> {
+        match self {
+            KeyValuesSource::Channel(key_values_rx) =>
+                key_values_rx.next().await,
+            KeyValuesSource::Stream(key_values) =>
+                key_values.next().await,
+        }
+    }
 }
 
 pub struct ItersMerger<V> {
     iters: V,
     advance_head_idx: Option<usize>,
+    drop_tombstones: bool,
+    range_tombstones: RangeTombstones,
+    drop_range_tombstoned: bool,
+    key_comparator: KeyComparatorRef,
 }
 
 enum IterState {
@@ -55,11 +104,38 @@ impl<V> ItersMerger<V> {
         ItersMerger {
             iters,
             advance_head_idx: None,
+            drop_tombstones: false,
+            range_tombstones: Arc::new(Vec::new()),
+            drop_range_tombstoned: false,
+            key_comparator: comparator::bytewise(),
         }
     }
+
+    pub fn dropping_tombstones(mut self, drop_tombstones: bool) -> ItersMerger<V> {
+        self.drop_tombstones = drop_tombstones;
+        self
+    }
+
+    // cells shadowed by a newer range tombstone are either dropped or replaced with a plain tombstone
+    pub fn applying_range_tombstones(mut self, range_tombstones: RangeTombstones, drop_range_tombstoned: bool) -> ItersMerger<V> {
+        self.range_tombstones = range_tombstones;
+        self.drop_range_tombstoned = drop_range_tombstoned;
+        self
+    }
+
+    pub fn comparing_keys(mut self, key_comparator: KeyComparatorRef) -> ItersMerger<V> {
+        self.key_comparator = key_comparator;
+        self
+    }
 }
 
 impl<V> ItersMerger<V> where V: DerefMut<Target = Vec<KeyValuesIter>> {
+    // joins an iterator between two `next` calls: it should yield keys above the last one merged so far only
+    pub fn push_iter(&mut self, iter: KeyValuesIter) {
+        assert!(self.advance_head_idx.is_none());
+        self.iters.push(iter);
+    }
+
     pub async fn next(&mut self) -> Result<Option<kv::KeyValuePair<storage::OwnedValueBlockRef>>, Error> {
         self.next_with_deprecated(|_| ()).await
     }
@@ -70,6 +146,41 @@ impl<V> ItersMerger<V> where V: DerefMut<Target = Vec<KeyValuesIter>> {
     )
         -> Result<Option<kv::KeyValuePair<storage::OwnedValueBlockRef>>, Error>
     where F: FnMut(kv::KeyValuePair<storage::OwnedValueBlockRef>)
+    {
+        loop {
+            let maybe_item = match self.next_merged(&mut deprecated).await? {
+                Some(item) =>
+                    match range_tombstoned(&self.range_tombstones, &item.key, item.value_cell.version, &self.key_comparator) {
+                        None =>
+                            Some(item),
+                        Some(..) if self.drop_range_tombstoned => {
+                            deprecated(item);
+                            continue;
+                        },
+                        Some(version) => {
+                            let key = item.key.clone();
+                            deprecated(item);
+                            Some(kv::KeyValuePair { key, value_cell: kv::ValueCell { version, cell: kv::Cell::Tombstone { meta: None, }, }, })
+                        },
+                    },
+                None =>
+                    None,
+            };
+            match maybe_item {
+                Some(kv::KeyValuePair { value_cell: kv::ValueCell { cell: kv::Cell::Tombstone { .. }, .. }, .. }) if self.drop_tombstones =>
+                    continue,
+                maybe_item =>
+                    return Ok(maybe_item),
+            }
+        }
+    }
+
+    async fn next_merged<F>(
+        &mut self,
+        deprecated: &mut F,
+    )
+        -> Result<Option<kv::KeyValuePair<storage::OwnedValueBlockRef>>, Error>
+    where F: FnMut(kv::KeyValuePair<storage::OwnedValueBlockRef>)
     {
         assert!(self.advance_head_idx.is_none());
         let mut cursor_idx = 0;
@@ -78,13 +189,17 @@ impl<V> ItersMerger<V> where V: DerefMut<Target = Vec<KeyValuesIter>> {
                 IterState::NotReady => {
                     let current_iter = &mut self.iters[cursor_idx];
                     loop {
+                        if let Some(demand_tx) = current_iter.demand_tx.as_mut() {
+                            // a gone peer is reported by `key_values_rx` right below
+                            demand_tx.send(()).await.ok();
+                        }
                         match current_iter.key_values_rx.next().await {
                             None =>
                                 return Err(Error::BackendIterPeerLost),
                             Some(KeyValueRef::NoMore) => {
                                 self.iters.swap_remove(cursor_idx);
                             },
-                            Some(KeyValueRef::BlockFinish(..)) =>
+                            Some(KeyValueRef::BlockFinish { .. }) =>
                                 continue,
                             Some(KeyValueRef::Item { key, value_cell, }) => {
                                 current_iter.iter_state = IterState::FrontItem(kv::KeyValuePair { key, value_cell, });
@@ -106,7 +221,7 @@ impl<V> ItersMerger<V> where V: DerefMut<Target = Vec<KeyValuesIter>> {
                                     KeyValuesIter { iter_state: IterState::FrontItem(kv::KeyValuePair { key: key_min, .. }), .. },
                                     kv::KeyValuePair { key: key_cur, .. },
                                 ) =>
-                                    match key_cur.key_bytes.cmp(&key_min.key_bytes) {
+                                    match self.key_comparator.compare(&key_cur.key_bytes, &key_min.key_bytes) {
                                         Ordering::Less => {
                                             self.iters[cursor_idx].advance_next_idx = None;
                                             self.advance_head_idx = Some(cursor_idx);
@@ -137,6 +252,10 @@ impl<V> ItersMerger<V> where V: DerefMut<Target = Vec<KeyValuesIter>> {
                     match best_item {
                         None =>
                             best_item = Some(front_item),
+                        // identical cells come from a stale tree left by an interrupted merge: they share
+                        // the very same value block, so it should not be reported as deprecated
+                        Some(prev_best) if prev_best.value_cell == front_item.value_cell =>
+                            best_item = Some(prev_best),
                         Some(prev_best) =>
                             if prev_best.value_cell.version < front_item.value_cell.version {
                                 deprecated(prev_best);
@@ -153,3 +272,260 @@ impl<V> ItersMerger<V> where V: DerefMut<Target = Vec<KeyValuesIter>> {
         Ok(best_item)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::{
+        channel::mpsc,
+        executor::block_on,
+        SinkExt,
+    };
+
+    use std::{
+        sync::Arc,
+        ops::Bound,
+    };
+
+    use alloc_pool::bytes::BytesPool;
+
+    use ero_blockwheel_fs::block;
+
+    use crate::{
+        kv,
+        storage,
+        wheels::{
+            BlockRef,
+            WheelFilename,
+        },
+        core::{
+            KeyValueRef,
+            RangeTombstone,
+            RangeTombstones,
+            SearchRangeBounds,
+        },
+    };
+
+    use super::{
+        ItersMerger,
+        KeyValuesIter,
+    };
+
+    fn make_key(blocks_pool: &BytesPool, key: &[u8]) -> kv::Key {
+        let mut key_bytes = blocks_pool.lend();
+        key_bytes.extend_from_slice(key);
+        key_bytes.into()
+    }
+
+    fn make_item(blocks_pool: &BytesPool, key: &[u8], version: u64, tombstone: bool) -> KeyValueRef {
+        KeyValueRef::Item {
+            key: make_key(blocks_pool, key),
+            value_cell: kv::ValueCell {
+                version,
+                cell: if tombstone {
+                    kv::Cell::Tombstone { meta: None, }
+                } else {
+                    let mut value_bytes = blocks_pool.lend();
+                    value_bytes.extend_from_slice(key);
+                    kv::Cell::Value(storage::OwnedValueBlockRef::Inline(value_bytes.into()))
+                },
+            },
+        }
+    }
+
+    fn merge(items_a: Vec<KeyValueRef>, items_b: Vec<KeyValueRef>, drop_tombstones: bool) -> (Vec<(kv::Key, bool)>, usize) {
+        merge_range_tombstoned(items_a, items_b, drop_tombstones, Arc::new(Vec::new()), false)
+    }
+
+    fn merge_range_tombstoned(
+        items_a: Vec<KeyValueRef>,
+        items_b: Vec<KeyValueRef>,
+        drop_tombstones: bool,
+        range_tombstones: RangeTombstones,
+        drop_range_tombstoned: bool,
+    )
+        -> (Vec<(kv::Key, bool)>, usize)
+    {
+        block_on(async move {
+            let (mut tx_a, rx_a) = mpsc::channel(items_a.len() + 1);
+            let (mut tx_b, rx_b) = mpsc::channel(items_b.len() + 1);
+            for item in items_a {
+                tx_a.send(item).await.unwrap();
+            }
+            tx_a.send(KeyValueRef::NoMore).await.unwrap();
+            for item in items_b {
+                tx_b.send(item).await.unwrap();
+            }
+            tx_b.send(KeyValueRef::NoMore).await.unwrap();
+
+            let iters = vec![KeyValuesIter::new(rx_a), KeyValuesIter::new(rx_b)];
+            let mut merger = ItersMerger::new(Box::new(iters))
+                .dropping_tombstones(drop_tombstones)
+                .applying_range_tombstones(range_tombstones, drop_range_tombstoned);
+            let mut output = Vec::new();
+            let mut deprecated_count = 0;
+            while let Some(item) = merger.next_with_deprecated(|_| deprecated_count += 1).await.unwrap() {
+                let is_tombstone = matches!(item.value_cell.cell, kv::Cell::Tombstone { .. });
+                output.push((item.key, is_tombstone));
+            }
+            (output, deprecated_count)
+        })
+    }
+
+    #[test]
+    fn tombstones_dropped_on_bottom_merge() {
+        let blocks_pool = BytesPool::new();
+        let items_a = || vec![
+            make_item(&blocks_pool, b"a", 1, false),
+            make_item(&blocks_pool, b"b", 3, true),
+            make_item(&blocks_pool, b"c", 5, true),
+        ];
+        let items_b = || vec![
+            make_item(&blocks_pool, b"a", 2, true),
+            make_item(&blocks_pool, b"b", 1, false),
+            make_item(&blocks_pool, b"d", 4, false),
+        ];
+
+        let (output, deprecated_count) = merge(items_a(), items_b(), false);
+        assert_eq!(
+            output,
+            vec![
+                (make_key(&blocks_pool, b"a"), true),
+                (make_key(&blocks_pool, b"b"), true),
+                (make_key(&blocks_pool, b"c"), true),
+                (make_key(&blocks_pool, b"d"), false),
+            ],
+        );
+        assert_eq!(deprecated_count, 2);
+
+        let (output, deprecated_count) = merge(items_a(), items_b(), true);
+        assert_eq!(output, vec![(make_key(&blocks_pool, b"d"), false)]);
+        assert_eq!(deprecated_count, 2);
+    }
+
+    #[test]
+    fn empty_key_and_value() {
+        let blocks_pool = BytesPool::new();
+        // empty key goes first and gets empty value from `make_item`
+        let items_a = vec![
+            make_item(&blocks_pool, b"", 2, false),
+            make_item(&blocks_pool, b"a", 1, false),
+        ];
+        let items_b = vec![
+            make_item(&blocks_pool, b"", 1, true),
+            make_item(&blocks_pool, b"a", 3, true),
+        ];
+
+        let (output, deprecated_count) = merge(items_a, items_b, true);
+        assert_eq!(output, vec![(make_key(&blocks_pool, b""), false)]);
+        assert_eq!(deprecated_count, 2);
+    }
+
+    #[test]
+    fn stale_duplicates_not_deprecated() {
+        let blocks_pool = BytesPool::new();
+        let items_a = vec![
+            make_item(&blocks_pool, b"a", 1, false),
+            make_item(&blocks_pool, b"b", 2, false),
+        ];
+        let items_b = vec![
+            make_item(&blocks_pool, b"a", 1, false),
+            make_item(&blocks_pool, b"b", 3, false),
+        ];
+
+        let (output, deprecated_count) = merge(items_a, items_b, false);
+        assert_eq!(
+            output,
+            vec![
+                (make_key(&blocks_pool, b"a"), false),
+                (make_key(&blocks_pool, b"b"), false),
+            ],
+        );
+        assert_eq!(deprecated_count, 1);
+    }
+
+    #[test]
+    fn iter_joined_mid_merge() {
+        let blocks_pool = BytesPool::new();
+        let output = block_on(async {
+            let (mut tx_a, rx_a) = mpsc::channel(4);
+            for item in vec![make_item(&blocks_pool, b"a", 1, false), make_item(&blocks_pool, b"c", 1, false), KeyValueRef::NoMore] {
+                tx_a.send(item).await.unwrap();
+            }
+            let mut merger = ItersMerger::new(Box::new(vec![KeyValuesIter::new(rx_a)]));
+            let mut output = Vec::new();
+            let item = merger.next().await.unwrap().unwrap();
+            output.push((item.key, item.value_cell.version));
+
+            let (mut tx_b, rx_b) = mpsc::channel(4);
+            for item in vec![make_item(&blocks_pool, b"b", 2, false), make_item(&blocks_pool, b"c", 2, true), KeyValueRef::NoMore] {
+                tx_b.send(item).await.unwrap();
+            }
+            merger.push_iter(KeyValuesIter::new(rx_b));
+            while let Some(item) = merger.next().await.unwrap() {
+                output.push((item.key, item.value_cell.version));
+            }
+            output
+        });
+        assert_eq!(
+            output,
+            vec![
+                (make_key(&blocks_pool, b"a"), 1),
+                (make_key(&blocks_pool, b"b"), 2),
+                (make_key(&blocks_pool, b"c"), 2),
+            ],
+        );
+    }
+
+    #[test]
+    fn range_tombstoned_cells() {
+        let blocks_pool = BytesPool::new();
+        let items_a = || vec![
+            make_item(&blocks_pool, b"a", 1, false),
+            make_item(&blocks_pool, b"b", 3, false),
+            make_item(&blocks_pool, b"c", 7, false),
+            make_item(&blocks_pool, b"e", 2, false),
+        ];
+        let items_b = || vec![
+            make_item(&blocks_pool, b"b", 2, false),
+            make_item(&blocks_pool, b"d", 4, true),
+        ];
+        // ["b" .. "e") removed at version 5: "c" was written after that
+        let range_tombstones = || Arc::new(vec![
+            RangeTombstone {
+                range: SearchRangeBounds {
+                    range_from: Bound::Included(make_key(&blocks_pool, b"b")),
+                    range_to: Bound::Excluded(make_key(&blocks_pool, b"e")),
+                },
+                version: 5,
+                block_ref: BlockRef {
+                    blockwheel_filename: WheelFilename::from_str("wheel", &blocks_pool),
+                    block_id: block::Id::init(),
+                },
+            },
+        ]);
+
+        let (output, deprecated_count) = merge_range_tombstoned(items_a(), items_b(), false, range_tombstones(), false);
+        assert_eq!(
+            output,
+            vec![
+                (make_key(&blocks_pool, b"a"), false),
+                (make_key(&blocks_pool, b"b"), true),
+                (make_key(&blocks_pool, b"c"), false),
+                (make_key(&blocks_pool, b"d"), true),
+                (make_key(&blocks_pool, b"e"), false),
+            ],
+        );
+        assert_eq!(deprecated_count, 3);
+
+        let (output, deprecated_count) = merge_range_tombstoned(items_a(), items_b(), false, range_tombstones(), true);
+        assert_eq!(
+            output,
+            vec![
+                (make_key(&blocks_pool, b"a"), false),
+                (make_key(&blocks_pool, b"c"), false),
+                (make_key(&blocks_pool, b"e"), false),
+            ],
+        );
+        assert_eq!(deprecated_count, 3);
+    }
+}
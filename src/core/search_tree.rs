@@ -5,7 +5,6 @@ use std::{
         Deref,
         DerefMut,
     },
-    time::Duration,
     collections::{
         hash_map,
         HashMap,
@@ -29,7 +28,6 @@ use futures::{
 use ero::{
     restart,
     ErrorSeverity,
-    RestartStrategy,
     supervisor::SupervisorPid,
 };
 
@@ -45,7 +43,10 @@ use crate::{
     job,
     wheels,
     storage,
+    metrics,
+    comparator,
     core::{
+        backoff,
         MemCache,
         BlockRef,
         KeyValueRef,
@@ -53,6 +54,9 @@ use crate::{
     },
     Info,
     Flushed,
+    Compression,
+    PoolingMode,
+    RestartBackoff,
 };
 
 pub mod task;
@@ -60,20 +64,39 @@ pub mod task;
 #[derive(Clone, Debug)]
 pub struct Params {
     pub task_restart_sec: usize,
+    pub task_restart_backoff: Option<RestartBackoff>,
     pub tree_block_size: usize,
+    pub target_block_bytes: Option<usize>,
     pub remove_tasks_limit: usize,
+    // blocks freed per second
+    pub demolish_rate_limit: Option<usize>,
     pub iter_send_buffer: usize,
     pub values_inline_size_limit: usize,
+    pub verify_checksums: bool,
+    pub block_compression: Option<Compression>,
+    pub pooling: PoolingMode,
+    pub store_id: u32,
+    pub metrics: metrics::MetricsRef,
+    pub key_comparator: comparator::KeyComparatorRef,
 }
 
 impl Default for Params {
     fn default() -> Params {
         Params {
             task_restart_sec: 1,
+            task_restart_backoff: None,
             tree_block_size: 32,
+            target_block_bytes: None,
             remove_tasks_limit: 64,
+            demolish_rate_limit: None,
             iter_send_buffer: 4,
             values_inline_size_limit: 128,
+            verify_checksums: true,
+            block_compression: None,
+            pooling: PoolingMode::Full,
+            store_id: 0,
+            metrics: metrics::no_metrics(),
+            key_comparator: comparator::bytewise(),
         }
     }
 }
@@ -134,23 +157,44 @@ impl GenServer {
         wheels_pid: wheels::Pid,
         params: Params,
         mode: Mode,
+        failures_tx: mpsc::UnboundedSender<Failure>,
     )
     where J: edeltraud::Job + From<job::Job>,
           J::Output: From<job::JobOutput>,
           job::JobOutput: From<J::Output>,
     {
-        run(State {
+        let search_tree_pid = self.pid();
+        let terminate_result = run(State {
             fused_request_rx: self.fused_request_rx,
             parent_supervisor,
             thread_pool,
             pools,
             wheels_pid,
+            restart_backoff: backoff::Backoff::new(params.task_restart_backoff),
             params,
             mode,
-        }).await
+        }).await;
+        if let Err(Error::Task { error, root_block, }) = terminate_result {
+            let failure = Failure {
+                search_tree_pid,
+                root_block,
+                error: format!("{:?}", error),
+            };
+            if let Err(_send_error) = failures_tx.unbounded_send(failure) {
+                log::warn!("search tree owner has gone, failure is not reported");
+            }
+        }
     }
 }
 
+// a tree stopped on a fatal error, reported to its owner so it is not addressed anymore;
+// `root_block` is `None` if the tree failed before its bootstrap was done
+pub struct Failure {
+    pub search_tree_pid: Pid,
+    pub root_block: Option<BlockRef>,
+    pub error: String,
+}
+
 #[derive(Debug)]
 pub enum LookupError {
     GenServer(ero::NoProcError),
@@ -166,6 +210,11 @@ pub enum IterError {
     GenServer(ero::NoProcError),
 }
 
+#[derive(Debug)]
+pub enum RootBlockError {
+    GenServer(ero::NoProcError),
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct Demolished;
 
@@ -175,18 +224,86 @@ pub enum DemolishError {
 }
 
 pub struct SearchTreeIterItemsTx {
-    pub items_tx: mpsc::Sender<KeyValueRef>,
+    items_tx: mpsc::Sender<KeyValueRef>,
+    demand: Option<IterDemand>,
+}
+
+// a demand driven iterator produces (and loads blocks for) an item only after the receiver has asked for it
+struct IterDemand {
+    demand_rx: mpsc::Receiver<()>,
+    granted: bool,
 }
 
 pub struct SearchTreeIterItemsRx {
     pub items_rx: mpsc::Receiver<KeyValueRef>,
+    // `Some` for demand driven iterators: send `()` before each `items_rx` read
+    pub demand_tx: Option<mpsc::Sender<()>>,
+}
+
+fn iter_items_channel(buffer: usize, demand_driven: bool) -> (SearchTreeIterItemsTx, SearchTreeIterItemsRx) {
+    let (items_tx, items_rx) = mpsc::channel(buffer);
+    let (demand, demand_tx) = if demand_driven {
+        let (demand_tx, demand_rx) = mpsc::channel(0);
+        (Some(IterDemand { demand_rx, granted: false, }), Some(demand_tx))
+    } else {
+        (None, None)
+    };
+    (SearchTreeIterItemsTx { items_tx, demand, }, SearchTreeIterItemsRx { items_rx, demand_tx, })
+}
+
+impl SearchTreeIterItemsTx {
+    // `false` if the receiver has gone
+    pub async fn demand(&mut self) -> bool {
+        match &mut self.demand {
+            None | Some(IterDemand { granted: true, .. }) =>
+                true,
+            Some(demand) =>
+                match demand.demand_rx.next().await {
+                    Some(()) => {
+                        demand.granted = true;
+                        true
+                    },
+                    None =>
+                        false,
+                },
+        }
+    }
+
+    pub async fn send(&mut self, item: KeyValueRef) -> Result<(), mpsc::SendError> {
+        if !self.demand().await {
+            self.items_tx.disconnect();
+        }
+        if let Some(demand) = &mut self.demand {
+            demand.granted = false;
+        }
+        self.items_tx.send(item).await
+    }
+
+    pub async fn send_all<I>(&mut self, items: I) -> Result<(), mpsc::SendError>
+    where I: IntoIterator<Item = KeyValueRef>,
+          I::IntoIter: Unpin,
+    {
+        if self.demand.is_some() {
+            for item in items {
+                self.send(item).await?;
+            }
+            Ok(())
+        } else {
+            let mut items_stream = stream::iter(items.into_iter().map(Ok));
+            self.items_tx.send_all(&mut items_stream).await
+        }
+    }
 }
 
 impl Pid {
-    pub async fn info(&mut self) -> Result<Info, ero::NoProcError> {
+    pub fn same_tree(&self, other: &Pid) -> bool {
+        self.request_tx.same_receiver(&other.request_tx)
+    }
+
+    pub async fn info(&mut self, range: SearchRangeBounds) -> Result<Info, ero::NoProcError> {
         loop {
             let (reply_tx, reply_rx) = oneshot::channel();
-            self.request_tx.send(Request::Info { reply_tx, }).await
+            self.request_tx.send(Request::Info { range: range.clone(), reply_tx, }).await
                 .map_err(|_send_error| ero::NoProcError)?;
             match reply_rx.await {
                 Ok(info) =>
@@ -197,14 +314,25 @@ impl Pid {
         }
     }
 
+    pub async fn estimate_count(&mut self, range: SearchRangeBounds) -> Result<usize, ero::NoProcError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx.send(Request::EstimateCount { range: range.clone(), reply_tx, }).await
+                .map_err(|_send_error| ero::NoProcError)?;
+            match reply_rx.await {
+                Ok(estimate) =>
+                    return Ok(estimate),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
     pub async fn lookup(&mut self, key: kv::Key) -> Result<Option<kv::ValueCell<storage::OwnedValueBlockRef>>, LookupError> {
         loop {
             let (reply_tx, reply_rx) = oneshot::channel();
             self.request_tx
-                .send(Request::Lookup(task::LookupRequest {
-                    key: key.clone(),
-                    reply_tx,
-                }))
+                .send(Request::Lookup { key: key.clone(), reply_tx, })
                 .await
                 .map_err(|_send_error| LookupError::GenServer(ero::NoProcError))?;
 
@@ -220,9 +348,17 @@ impl Pid {
     }
 
     pub async fn iter(&mut self, range: SearchRangeBounds) -> Result<SearchTreeIterItemsRx, IterError> {
+        self.iter_request(range, false).await
+    }
+
+    pub async fn iter_demand_driven(&mut self, range: SearchRangeBounds) -> Result<SearchTreeIterItemsRx, IterError> {
+        self.iter_request(range, true).await
+    }
+
+    async fn iter_request(&mut self, range: SearchRangeBounds, demand_driven: bool) -> Result<SearchTreeIterItemsRx, IterError> {
         loop {
             let (reply_tx, reply_rx) = oneshot::channel();
-            self.request_tx.send(Request::Iter { range: range.clone(), reply_tx, }).await
+            self.request_tx.send(Request::Iter { range: range.clone(), demand_driven, reply_tx, }).await
                 .map_err(|_send_error| IterError::GenServer(ero::NoProcError))?;
 
             match reply_rx.await {
@@ -241,8 +377,24 @@ impl Pid {
                 .map_err(|_send_error| FlushError::GenServer(ero::NoProcError))?;
 
             match reply_rx.await {
-                Ok(Flushed) =>
-                    return Ok(Flushed),
+                Ok(flushed) =>
+                    return Ok(flushed),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    // `None` while the tree is still being bootstrapped from its cache
+    pub async fn root_block(&mut self) -> Result<Option<BlockRef>, RootBlockError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx.send(Request::RootBlock { reply_tx, }).await
+                .map_err(|_send_error| RootBlockError::GenServer(ero::NoProcError))?;
+
+            match reply_rx.await {
+                Ok(maybe_root_block) =>
+                    return Ok(maybe_root_block),
                 Err(oneshot::Canceled) =>
                     (),
             }
@@ -273,18 +425,22 @@ struct State<J> where J: edeltraud::Job {
     wheels_pid: wheels::Pid,
     params: Params,
     mode: Mode,
+    restart_backoff: backoff::Backoff,
 }
 
 pub enum Mode {
     CacheBootstrap {
         cache: Arc<MemCache>,
+        // written into the root header, so the tree age survives restarts
+        created_at_version: u64,
+        bootstrap_done_tx: Option<oneshot::Sender<()>>,
     },
     Regular {
         root_block: BlockRef,
     },
 }
 
-async fn run<J>(state: State<J>)
+async fn run<J>(state: State<J>) -> Result<(), Error>
 where J: edeltraud::Job + From<job::Job>,
       J::Output: From<job::JobOutput>,
       job::JobOutput: From<J::Output>,
@@ -292,12 +448,12 @@ where J: edeltraud::Job + From<job::Job>,
     let terminate_result = restart::restartable(
         ero::Params {
             name: "ero-blockwheel-kv search tree task",
-            restart_strategy: RestartStrategy::Delay {
-                restart_after: Duration::from_secs(state.params.task_restart_sec as u64),
-            },
+            restart_strategy: state.restart_backoff.restart_strategy(state.params.task_restart_sec),
         },
         state,
         |mut state| async move {
+            state.restart_backoff.wait("ero-blockwheel-kv search tree task").await;
+
             let child_supervisor_gen_server = state.parent_supervisor.child_supervisor();
             let child_supervisor_pid = child_supervisor_gen_server.pid();
             state.parent_supervisor.spawn_link_temporary(
@@ -307,24 +463,33 @@ where J: edeltraud::Job + From<job::Job>,
             busyloop(child_supervisor_pid, state).await
         },
     ).await;
-    if let Err(error) = terminate_result {
+    if let Err(error) = &terminate_result {
         log::error!("fatal error: {:?}", error);
     } else {
         log::debug!("search tree terminated with result = {:?}", terminate_result);
     }
+    terminate_result
 }
 
 enum Request {
-    Info { reply_tx: oneshot::Sender<Info>, },
-    Lookup(task::LookupRequest),
-    Iter { range: SearchRangeBounds, reply_tx: oneshot::Sender<SearchTreeIterItemsRx>, },
+    Info { range: SearchRangeBounds, reply_tx: oneshot::Sender<Info>, },
+    EstimateCount { range: SearchRangeBounds, reply_tx: oneshot::Sender<usize>, },
+    Lookup {
+        key: kv::Key,
+        reply_tx: oneshot::Sender<Result<Option<kv::ValueCell<storage::OwnedValueBlockRef>>, task::SearchTreeLookupError>>,
+    },
+    Iter { range: SearchRangeBounds, demand_driven: bool, reply_tx: oneshot::Sender<SearchTreeIterItemsRx>, },
     Flush { reply_tx: oneshot::Sender<Flushed>, },
+    RootBlock { reply_tx: oneshot::Sender<Option<BlockRef>>, },
     Demolish { reply_tx: oneshot::Sender<Demolished>, },
 }
 
 #[derive(Debug)]
 enum Error {
-    Task(task::Error),
+    Task {
+        error: task::Error,
+        root_block: Option<BlockRef>,
+    },
 }
 
 async fn busyloop<J>(_child_supervisor_pid: SupervisorPid, mut state: State<J>) -> Result<(), ErrorSeverity<State<J>, Error>>
@@ -340,14 +505,19 @@ where J: edeltraud::Job + From<job::Job>,
     let mut tasks_count = 0;
 
     match &state.mode {
-        Mode::CacheBootstrap { cache, } => {
+        Mode::CacheBootstrap { cache, created_at_version, .. } => {
             tasks.push(
                 task::run_args(task::TaskArgs::Bootstrap(task::bootstrap::Args {
                     cache: cache.clone(),
                     thread_pool: state.thread_pool.clone(),
-                    blocks_pool: state.pools.blocks_pool.clone(),
+                    blocks_pool: state.params.pooling.pool_for(cache.bytes(), &state.pools.blocks_pool),
                     wheels_pid: state.wheels_pid.clone(),
                     values_inline_size_limit: state.params.values_inline_size_limit,
+                    block_compression: state.params.block_compression,
+                    store_id: state.params.store_id,
+                    created_at_version: *created_at_version,
+                    comparator_id: state.params.key_comparator.id(),
+                    metrics: state.params.metrics.clone(),
                 })),
             );
             tasks_count += 1;
@@ -413,15 +583,13 @@ where J: edeltraud::Job + From<job::Job>,
 
                 if let Some(done_reply_tx) = flush_reply_tx {
                     log::debug!("responding Flushed");
-                    if let Err(_send_error) = done_reply_tx.send(Flushed) {
+                    if let Err(_send_error) = done_reply_tx.send(Flushed::default()) {
                         log::warn!("client canceled flush request");
                     }
                 }
 
                 if let Some(done_reply_tx) = demolish_reply_tx {
-                    let (items_tx, items_rx) = mpsc::channel(state.params.iter_send_buffer);
-                    let iter_items_tx = SearchTreeIterItemsTx { items_tx, };
-                    let iter_items_rx = SearchTreeIterItemsRx { items_rx, };
+                    let (iter_items_tx, iter_items_rx) = iter_items_channel(state.params.iter_send_buffer, false);
                     let (reply_tx, reply_rx) = oneshot::channel();
                     assert!(reply_tx.send(iter_items_rx).is_ok());
 
@@ -441,6 +609,13 @@ where J: edeltraud::Job + From<job::Job>,
                             block_items_reply_rx: reply_rx,
                             wheels_pid: state.wheels_pid.clone(),
                             remove_tasks_limit: state.params.remove_tasks_limit,
+                            rate_limit: state.params.demolish_rate_limit,
+                            root_block: match &state.mode {
+                                Mode::Regular { root_block, } =>
+                                    Some(root_block.clone()),
+                                Mode::CacheBootstrap { .. } =>
+                                    None,
+                            },
                         })),
                     );
                     tasks_count += 1;
@@ -480,22 +655,69 @@ where J: edeltraud::Job + From<job::Job>,
                 return Ok(());
             },
 
-            Event::Request(Some(Request::Info { reply_tx, })) => {
+            Event::Request(Some(Request::Info { range, reply_tx, })) => {
                 assert!(!self_destruct_in_progress);
 
-                let info = Info::default();
-                if let Err(_send_error) = reply_tx.send(info) {
-                    log::warn!("client canceled info request");
-                }
+                let (iter_items_tx, iter_items_rx) = iter_items_channel(state.params.iter_send_buffer, false);
+
+                tasks.push(
+                    task::run_args(task::TaskArgs::IterDriver(task::iter_driver::Args {
+                        iter_rec_tx: iter_rec_tx.clone(),
+                        maybe_block_ref: None,
+                        range,
+                        iter_items_tx,
+                    })),
+                );
+                tasks_count += 1;
+
+                tasks.push(
+                    task::run_args(task::TaskArgs::CountCells(task::count_cells::Args {
+                        reply_tx,
+                        iter_items_rx,
+                        wheels_pid: state.wheels_pid.clone(),
+                    })),
+                );
+                tasks_count += 1;
+            },
+
+            Event::Request(Some(Request::EstimateCount { range, reply_tx, })) => {
+                assert!(!self_destruct_in_progress);
 
-                unimplemented!()
+                match &state.mode {
+                    Mode::CacheBootstrap { cache, .. } =>
+                        if let Err(_send_error) = reply_tx.send(cache.range(range).count()) {
+                            log::warn!("client canceled estimate count request");
+                        },
+                    Mode::Regular { root_block, } => {
+                        tasks.push(
+                            task::run_args(task::TaskArgs::EstimateCount(task::estimate_count::Args {
+                                reply_tx,
+                                root_block: root_block.clone(),
+                                range,
+                                key_comparator: state.params.key_comparator.clone(),
+                                wheels_pid: state.wheels_pid.clone(),
+                                thread_pool: state.thread_pool.clone(),
+                                blocks_pool: state.pools.blocks_pool.clone(),
+                                metrics: state.params.metrics.clone(),
+                                verify_checksums: state.params.verify_checksums,
+                            })),
+                        );
+                        tasks_count += 1;
+                    },
+                }
             },
 
-            Event::Request(Some(Request::Lookup(lookup_request))) => {
+            Event::Request(Some(Request::Lookup { key, reply_tx, })) => {
                 assert!(!self_destruct_in_progress);
 
+                let lookup_request = task::LookupRequest {
+                    key,
+                    key_comparator: state.params.key_comparator.clone(),
+                    reply_tx,
+                };
+
                 match &state.mode {
-                    Mode::CacheBootstrap { cache, } => {
+                    Mode::CacheBootstrap { cache, .. } => {
                         tasks.push(task::run_args(task::TaskArgs::SearchCache(task::search_cache::Args {
                             lookup_request,
                             cache: cache.clone(),
@@ -510,6 +732,10 @@ where J: edeltraud::Job + From<job::Job>,
                             &state.pools.lookup_requests_queue_pool,
                             &state.pools.iter_requests_queue_pool,
                             &state.wheels_pid,
+                            &state.thread_pool,
+                            &state.pools.blocks_pool,
+                            &state.params.metrics,
+                            state.params.verify_checksums,
                         );
                         match maybe_task_args {
                             TaskKind::None =>
@@ -523,12 +749,10 @@ where J: edeltraud::Job + From<job::Job>,
                 }
             },
 
-            Event::Request(Some(Request::Iter { range, reply_tx, })) => {
+            Event::Request(Some(Request::Iter { range, demand_driven, reply_tx, })) => {
                 assert!(!self_destruct_in_progress);
 
-                let (items_tx, items_rx) = mpsc::channel(state.params.iter_send_buffer);
-                let iter_items_tx = SearchTreeIterItemsTx { items_tx, };
-                let iter_items_rx = SearchTreeIterItemsRx { items_rx, };
+                let (iter_items_tx, iter_items_rx) = iter_items_channel(state.params.iter_send_buffer, demand_driven);
 
                 if let Err(_send_error) = reply_tx.send(iter_items_rx) {
                     log::warn!("client canceled iter request");
@@ -562,6 +786,20 @@ where J: edeltraud::Job + From<job::Job>,
                 }
             },
 
+            Event::Request(Some(Request::RootBlock { reply_tx, })) => {
+                assert!(!self_destruct_in_progress);
+
+                let maybe_root_block = match &state.mode {
+                    Mode::Regular { root_block, } =>
+                        Some(root_block.clone()),
+                    Mode::CacheBootstrap { .. } =>
+                        None,
+                };
+                if let Err(_send_error) = reply_tx.send(maybe_root_block) {
+                    log::warn!("client canceled root block request");
+                }
+            },
+
             Event::Request(Some(Request::Demolish { reply_tx, })) => {
                 assert!(!self_destruct_in_progress);
 
@@ -581,7 +819,7 @@ where J: edeltraud::Job + From<job::Job>,
 
             Event::IterRec(task::IterRecRequest { maybe_block_ref, data: iter_request_data, }) => {
                 match (&state.mode, &maybe_block_ref) {
-                    (Mode::CacheBootstrap { cache, }, None) => {
+                    (Mode::CacheBootstrap { cache, .. }, None) => {
                         tasks.push(
                             task::run_args(task::TaskArgs::IterCache(task::iter_cache::Args {
                                 cache: cache.clone(),
@@ -606,6 +844,10 @@ where J: edeltraud::Job + From<job::Job>,
                             &state.pools.lookup_requests_queue_pool,
                             &state.pools.iter_requests_queue_pool,
                             &state.wheels_pid,
+                            &state.thread_pool,
+                            &state.pools.blocks_pool,
+                            &state.params.metrics,
+                            state.params.verify_checksums,
                         );
                         match maybe_task_args {
                             TaskKind::None =>
@@ -621,8 +863,12 @@ where J: edeltraud::Job + From<job::Job>,
 
             Event::Task(Ok(task::TaskDone::Bootstrap(task::bootstrap::Done { block_ref: root_block, }))) =>
                 match mem::replace(&mut state.mode, Mode::Regular { root_block: root_block.clone(), }) {
-                    Mode::CacheBootstrap { .. } =>
-                        log::debug!("cache flushed with root_block = {:?}", root_block),
+                    Mode::CacheBootstrap { bootstrap_done_tx, .. } => {
+                        log::debug!("cache flushed with root_block = {:?}", root_block);
+                        if let Some(bootstrap_done_tx) = bootstrap_done_tx {
+                            bootstrap_done_tx.send(()).ok();
+                        }
+                    },
                     Mode::Regular { .. } =>
                         unreachable!(),
                 },
@@ -649,6 +895,7 @@ where J: edeltraud::Job + From<job::Job>,
                     tasks.push(
                         task::run_args(task::TaskArgs::IterBlock(task::iter_block::Args {
                             iter_request,
+                            key_comparator: state.params.key_comparator.clone(),
                             iter_block_entries_pool: state.pools.iter_block_entries_pool.clone(),
                             thread_pool: state.thread_pool.clone(),
                             block_bytes: block_bytes.clone(),
@@ -684,6 +931,10 @@ where J: edeltraud::Job + From<job::Job>,
                                 &state.pools.lookup_requests_queue_pool,
                                 &state.pools.iter_requests_queue_pool,
                                 &state.wheels_pid,
+                                &state.thread_pool,
+                                &state.pools.blocks_pool,
+                                &state.params.metrics,
+                                state.params.verify_checksums,
                             );
                             match maybe_task_args {
                                 TaskKind::None =>
@@ -707,6 +958,12 @@ where J: edeltraud::Job + From<job::Job>,
             Event::Task(Ok(task::TaskDone::IterBlock(task::iter_block::Done { .. }))) =>
                 (),
 
+            Event::Task(Ok(task::TaskDone::CountCells(task::count_cells::Done))) =>
+                (),
+
+            Event::Task(Ok(task::TaskDone::EstimateCount(task::estimate_count::Done))) =>
+                (),
+
             Event::Task(Ok(task::TaskDone::Demolish(task::demolish::Done { blocks_deleted, done_reply_tx, }))) => {
                 log::debug!("demolished, {} blocks actually deleted", blocks_deleted);
                 if let Err(_send_error) = done_reply_tx.send(Demolished) {
@@ -715,8 +972,15 @@ where J: edeltraud::Job + From<job::Job>,
                 return Ok(());
             },
 
-            Event::Task(Err(error)) =>
-                return Err(ErrorSeverity::Fatal(Error::Task(error))),
+            Event::Task(Err(error)) => {
+                let root_block = match &state.mode {
+                    Mode::Regular { root_block, } =>
+                        Some(root_block.clone()),
+                    Mode::CacheBootstrap { .. } =>
+                        None,
+                };
+                return Err(ErrorSeverity::Fatal(Error::Task { error, root_block, }));
+            },
         }
     }
 }
@@ -749,9 +1013,15 @@ impl AsyncTree {
         lookup_requests_queue_pool: &pool::Pool<task::LookupRequestsQueueType>,
         iter_requests_queue_pool: &pool::Pool<task::IterRequestsQueueType>,
         wheels_pid: &wheels::Pid,
+        thread_pool: &edeltraud::Edeltraud<J>,
+        blocks_pool: &BytesPool,
+        metrics: &metrics::MetricsRef,
+        verify_checksums: bool,
     )
         -> TaskKind<J>
-    where J: edeltraud::Job
+    where J: edeltraud::Job + From<job::Job>,
+          J::Output: From<job::JobOutput>,
+          job::JobOutput: From<J::Output>,
     {
         match self.tree.entry(block_ref.clone()) {
             hash_map::Entry::Occupied(mut oe) => {
@@ -771,6 +1041,10 @@ impl AsyncTree {
                 TaskKind::Local(task::TaskArgs::LoadBlock(task::load_block::Args {
                     block_ref,
                     wheels_pid: wheels_pid.clone(),
+                    thread_pool: thread_pool.clone(),
+                    blocks_pool: blocks_pool.clone(),
+                    metrics: metrics.clone(),
+                    verify_checksums,
                 }))
             },
         }
@@ -782,6 +1056,10 @@ impl AsyncTree {
         lookup_requests_queue_pool: &pool::Pool<task::LookupRequestsQueueType>,
         iter_requests_queue_pool: &pool::Pool<task::IterRequestsQueueType>,
         wheels_pid: &wheels::Pid,
+        thread_pool: &edeltraud::Edeltraud<J>,
+        blocks_pool: &BytesPool,
+        metrics: &metrics::MetricsRef,
+        verify_checksums: bool,
     )
         -> TaskKind<J>
     where J: edeltraud::Job + From<job::Job>,
@@ -807,6 +1085,10 @@ impl AsyncTree {
                 TaskKind::Local(task::TaskArgs::LoadBlock(task::load_block::Args {
                     block_ref,
                     wheels_pid: wheels_pid.clone(),
+                    thread_pool: thread_pool.clone(),
+                    blocks_pool: blocks_pool.clone(),
+                    metrics: metrics.clone(),
+                    verify_checksums,
                 }))
             },
         }
@@ -15,6 +15,7 @@ use crate::{
     kv,
     job,
     storage,
+    comparator::KeyComparatorRef,
     core::{
         search_tree::{
             SearchTreeIterItemsTx,
@@ -31,6 +32,8 @@ pub mod search_block;
 pub mod iter_cache;
 pub mod iter_block;
 pub mod iter_driver;
+pub mod count_cells;
+pub mod estimate_count;
 pub mod demolish;
 
 pub type LookupRequestsQueueType = BinaryHeap<LookupRequest>;
@@ -50,6 +53,7 @@ pub enum Outcome {
 
 pub struct LookupRequest {
     pub key: kv::Key,
+    pub key_comparator: KeyComparatorRef,
     pub reply_tx: oneshot::Sender<Result<Option<kv::ValueCell<storage::OwnedValueBlockRef>>, SearchTreeLookupError>>,
 }
 
@@ -58,6 +62,8 @@ pub type IterRequestsQueue = Unique<IterRequestsQueueType>;
 
 pub struct IterRequestData {
     pub range: SearchRangeBounds,
+    // depth of the block requested, root is at 1
+    pub depth: usize,
     pub iter_items_tx: SearchTreeIterItemsTx,
     pub repay_iter_items_tx: oneshot::Sender<SearchTreeIterItemsTx>,
 }
@@ -91,12 +97,14 @@ pub enum SearchTreeLookupError {
 
 pub enum TaskArgs<J> where J: edeltraud::Job {
     Bootstrap(bootstrap::Args<J>),
-    LoadBlock(load_block::Args),
+    LoadBlock(load_block::Args<J>),
     SearchCache(search_cache::Args<J>),
     SearchBlock(search_block::Args<J>),
     IterCache(iter_cache::Args<J>),
     IterBlock(iter_block::Args<J>),
     IterDriver(iter_driver::Args),
+    CountCells(count_cells::Args),
+    EstimateCount(estimate_count::Args<J>),
     Demolish(demolish::Args),
 }
 
@@ -108,6 +116,8 @@ pub enum TaskDone {
     IterCache(iter_cache::Done),
     IterBlock(iter_block::Done),
     IterDriver(iter_driver::Done),
+    CountCells(count_cells::Done),
+    EstimateCount(estimate_count::Done),
     Demolish(demolish::Done),
 }
 
@@ -120,6 +130,8 @@ pub enum Error {
     IterCache(iter_cache::Error),
     IterBlock(iter_block::Error),
     IterDriver(iter_driver::Error),
+    CountCells(count_cells::Error),
+    EstimateCount(estimate_count::Error),
     Demolish(demolish::Error),
 }
 
@@ -164,6 +176,16 @@ where J: edeltraud::Job + From<job::Job>,
                 iter_driver::run(args).await
                     .map_err(Error::IterDriver)?,
             ),
+        TaskArgs::CountCells(args) =>
+            TaskDone::CountCells(
+                count_cells::run(args).await
+                    .map_err(Error::CountCells)?,
+            ),
+        TaskArgs::EstimateCount(args) =>
+            TaskDone::EstimateCount(
+                estimate_count::run(args).await
+                    .map_err(Error::EstimateCount)?,
+            ),
         TaskArgs::Demolish(args) =>
             TaskDone::Demolish(
                 demolish::run(args).await
@@ -188,6 +210,6 @@ impl PartialOrd for LookupRequest {
 
 impl Ord for LookupRequest {
     fn cmp(&self, other: &LookupRequest) -> Ordering {
-        other.key.key_bytes.cmp(&self.key.key_bytes)
+        other.key_comparator.compare(&other.key.key_bytes, &self.key.key_bytes)
     }
 }
@@ -20,11 +20,13 @@ use crate::{
     job,
     wheels,
     storage,
+    metrics,
     blockwheel,
     core::{
         BlockRef,
         MemCache,
     },
+    Compression,
 };
 
 pub struct Args<J> where J: edeltraud::Job {
@@ -33,6 +35,11 @@ pub struct Args<J> where J: edeltraud::Job {
     pub blocks_pool: BytesPool,
     pub wheels_pid: wheels::Pid,
     pub values_inline_size_limit: usize,
+    pub block_compression: Option<Compression>,
+    pub store_id: u32,
+    pub created_at_version: u64,
+    pub comparator_id: u32,
+    pub metrics: metrics::MetricsRef,
 }
 
 pub struct Done {
@@ -46,6 +53,7 @@ pub enum Error {
     SerializeBlockStorage(storage::Error),
     SerializeValueBlockStorage(storage::Error),
     WriteBlock(blockwheel::WriteBlockError),
+    CompressBlock(storage::Error),
     ThreadPoolGone,
 }
 
@@ -55,6 +63,7 @@ pub struct LayoutJobArgs {
     cache: Arc<MemCache>,
     blocks_pool: BytesPool,
     values_inline_size_limit: usize,
+    store_id: u32,
 }
 
 pub struct LayoutJobDone {
@@ -70,13 +79,16 @@ enum LayoutOp {
     },
 }
 
-pub fn layout_job(LayoutJobArgs { cache, blocks_pool, values_inline_size_limit, }: LayoutJobArgs) -> LayoutJobOutput {
+pub fn layout_job(LayoutJobArgs { cache, blocks_pool, values_inline_size_limit, store_id, }: LayoutJobArgs) -> LayoutJobOutput {
     let mut layout_ops = Vec::with_capacity(cache.len());
     for (key, value_cell) in cache.iter() {
+        let storage_preference = cache.storage_preference(key);
         let layout_op = match value_cell.cell {
-            kv::Cell::Value(kv::Value { ref value_bytes, }) if value_bytes.len() > values_inline_size_limit => {
+            kv::Cell::Value(kv::Value { ref value_bytes, })
+                if storage::value_out_of_line(value_bytes, storage_preference, values_inline_size_limit) =>
+            {
                 let mut block_bytes = blocks_pool.lend();
-                storage::value_block_serialize(value_bytes, &mut block_bytes)
+                storage::value_block_serialize(value_bytes, store_id, &mut block_bytes)
                     .map_err(Error::SerializeValueBlockStorage)?;
                 LayoutOp::WriteExternalValue {
                     key: key.as_ref().clone(),
@@ -84,7 +96,7 @@ pub fn layout_job(LayoutJobArgs { cache, blocks_pool, values_inline_size_limit,
                     value_version: value_cell.version,
                 }
             },
-            kv::Cell::Value(..) | kv::Cell::Tombstone =>
+            kv::Cell::Value(..) | kv::Cell::Tombstone { .. } =>
                 LayoutOp::Ready(storage::OwnedEntry {
                     jump_ref: storage::OwnedJumpRef::None,
                     key: key.as_ref().clone(),
@@ -103,24 +115,49 @@ pub type BlockJobOutput = Result<BlockJobDone, Error>;
 pub struct BlockJobArgs {
     layout_ops: Vec<LayoutOp>,
     blocks_pool: BytesPool,
+    block_compression: Option<Compression>,
+    store_id: u32,
+    created_at_version: u64,
+    comparator_id: u32,
 }
 
 pub struct BlockJobDone {
     block_bytes: Bytes,
 }
 
-pub fn block_job(BlockJobArgs { layout_ops, blocks_pool, }: BlockJobArgs) -> BlockJobOutput {
+pub fn block_job(BlockJobArgs { layout_ops, blocks_pool, block_compression, store_id, created_at_version, comparator_id, }: BlockJobArgs) -> BlockJobOutput {
     let block_bytes = blocks_pool.lend();
+    let max_version = layout_ops.iter()
+        .map(|layout_op| match layout_op {
+            LayoutOp::Ready(owned_entry) =>
+                owned_entry.value_cell.version,
+            LayoutOp::WriteExternalValue { value_version, .. } =>
+                *value_version,
+        })
+        .max()
+        .unwrap_or(0);
     let mut kont = storage::BlockSerializer::start(
-        storage::NodeType::Root { tree_entries_count: layout_ops.len(), },
+        storage::NodeType::Root { tree_entries_count: layout_ops.len(), max_version, created_at_version, comparator_id, },
         layout_ops.len(),
+        store_id,
         block_bytes,
     ).map_err(Error::SerializeBlockStorage)?;
     let mut layout_ops_iter = layout_ops.into_iter();
     loop {
         match kont {
-            storage::BlockSerializerContinue::Done(block_bytes) =>
-                return Ok(BlockJobDone { block_bytes: block_bytes.freeze(), }),
+            storage::BlockSerializerContinue::Done(block_bytes) => {
+                let block_bytes = match block_compression {
+                    None =>
+                        block_bytes,
+                    Some(ref compression) => {
+                        let mut compressed_bytes = blocks_pool.lend();
+                        storage::block_compress(&block_bytes, compression, &mut compressed_bytes)
+                            .map_err(Error::CompressBlock)?;
+                        compressed_bytes
+                    },
+                };
+                return Ok(BlockJobDone { block_bytes: block_bytes.freeze(), });
+            },
             storage::BlockSerializerContinue::More(serializer) =>
                 match layout_ops_iter.next() {
                     Some(LayoutOp::Ready(ref owned_entry)) => {
@@ -141,6 +178,11 @@ pub async fn run<J>(
         blocks_pool,
         mut wheels_pid,
         values_inline_size_limit,
+        block_compression,
+        store_id,
+        created_at_version,
+        comparator_id,
+        metrics,
     }: Args<J>,
 )
     -> Result<Done, Error>
@@ -152,6 +194,7 @@ where J: edeltraud::Job + From<job::Job>,
         cache,
         blocks_pool: blocks_pool.clone(),
         values_inline_size_limit,
+        store_id,
     };
     let layout_job_output = thread_pool.spawn(job::Job::SearchTreeBootstrapLayout(layout_job_args)).await
         .map_err(|edeltraud::SpawnError::ThreadPoolGone| Error::ThreadPoolGone)?;
@@ -168,13 +211,16 @@ where J: edeltraud::Job + From<job::Job>,
                 let mut wheels_pid = wheels_pid.clone();
                 let block_bytes = value_block_bytes.clone();
                 let key = key.clone();
+                let metrics = metrics.clone();
                 tasks.push(async move {
                     let mut wheel_ref = wheels_pid.acquire().await
                         .map_err(|ero::NoProcError| Error::WheelsGone)?
                         .ok_or(Error::WheelsEmpty)?;
                     let blockwheel_filename = wheel_ref.blockwheel_filename.clone();
+                    let block_size = block_bytes.len();
                     let block_id = wheel_ref.blockwheel_pid.write_block(block_bytes).await
                         .map_err(Error::WriteBlock)?;
+                    metrics.on_block_write(block_size);
                     Ok::<_, Error>((layout_op_index, key, value_version, blockwheel_filename, block_id))
                 });
             },
@@ -208,14 +254,23 @@ where J: edeltraud::Job + From<job::Job>,
         assert!(matches!(prev_layout_op, LayoutOp::WriteExternalValue { .. }));
     }
 
-    let block_job_output = thread_pool.spawn(job::Job::SearchTreeBootstrapBlock(BlockJobArgs { layout_ops, blocks_pool, })).await
+    let block_job_output = thread_pool.spawn(job::Job::SearchTreeBootstrapBlock(BlockJobArgs {
+        layout_ops,
+        blocks_pool,
+        block_compression,
+        store_id,
+        created_at_version,
+        comparator_id,
+    })).await
         .map_err(|edeltraud::SpawnError::ThreadPoolGone| Error::ThreadPoolGone)?;
     let block_job_output: job::JobOutput = block_job_output.into();
     let job::SearchTreeBootstrapBlockDone(block_job_result) = block_job_output.into();
     let BlockJobDone { block_bytes, } = block_job_result?;
 
+    let block_size = block_bytes.len();
     let block_id = wheel_ref.blockwheel_pid.write_block(block_bytes).await
         .map_err(Error::WriteBlock)?;
+    metrics.on_block_write(block_size);
     Ok(Done {
         block_ref: BlockRef {
             blockwheel_filename: wheel_ref.blockwheel_filename,
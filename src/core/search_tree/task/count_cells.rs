@@ -0,0 +1,82 @@
+use futures::{
+    channel::{
+        oneshot,
+    },
+    StreamExt,
+};
+
+use ero_blockwheel_fs as blockwheel;
+
+use crate::{
+    kv,
+    wheels,
+    storage,
+    core::{
+        search_tree::{
+            KeyValueRef,
+            SearchTreeIterItemsRx,
+        },
+    },
+    Info,
+};
+
+pub struct Args {
+    pub reply_tx: oneshot::Sender<Info>,
+    pub iter_items_rx: SearchTreeIterItemsRx,
+    pub wheels_pid: wheels::Pid,
+}
+
+pub struct Done;
+
+#[derive(Debug)]
+pub enum Error {
+    WheelsGone,
+    WheelNotFound {
+        blockwheel_filename: wheels::WheelFilename,
+    },
+    ReadBlock(blockwheel::ReadBlockError),
+}
+
+// value block sizes are not kept in refs, so every out of line value in range is read to measure it
+pub async fn run(Args { reply_tx, iter_items_rx: SearchTreeIterItemsRx { mut items_rx, .. }, mut wheels_pid, }: Args) -> Result<Done, Error> {
+    let mut info = Info::default();
+    loop {
+        match items_rx.next().await {
+            None => {
+                log::warn!("iter items channel depleted before NoMore: dropping info request");
+                return Ok(Done);
+            },
+            Some(KeyValueRef::NoMore) =>
+                break,
+            Some(KeyValueRef::BlockFinish { depth, block_size, .. }) => {
+                info.depth = info.depth.max(depth);
+                info.block_count += 1;
+                info.disk_bytes_total += block_size;
+            },
+            Some(KeyValueRef::Item { value_cell: kv::ValueCell { cell: kv::Cell::Value(value_ref), .. }, .. }) => {
+                info.alive_cells_count += 1;
+                if let storage::OwnedValueBlockRef::Ref(block_ref) = value_ref {
+                    let mut wheel_ref = wheels_pid.get(block_ref.blockwheel_filename.clone()).await
+                        .map_err(|ero::NoProcError| Error::WheelsGone)?
+                        .ok_or_else(|| Error::WheelNotFound {
+                            blockwheel_filename: block_ref.blockwheel_filename.clone(),
+                        })?;
+                    match wheel_ref.blockwheel_pid.read_block(block_ref.block_id).await {
+                        Ok(block_bytes) =>
+                            info.disk_bytes_total += block_bytes.len(),
+                        Err(blockwheel::ReadBlockError::NotFound) =>
+                            log::debug!("value block freed by a merge meanwhile: skipping it in info"),
+                        Err(error) =>
+                            return Err(Error::ReadBlock(error)),
+                    }
+                }
+            },
+            Some(KeyValueRef::Item { value_cell: kv::ValueCell { cell: kv::Cell::Tombstone { .. }, .. }, .. }) =>
+                info.tombstones_count += 1,
+        }
+    }
+    if let Err(_send_error) = reply_tx.send(info) {
+        log::warn!("client canceled info request");
+    }
+    Ok(Done)
+}
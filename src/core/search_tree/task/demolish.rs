@@ -1,3 +1,9 @@
+use std::{
+    cmp,
+    convert::TryFrom,
+    time::Duration,
+};
+
 use futures::{
     select,
     stream::{
@@ -10,6 +16,8 @@ use futures::{
     StreamExt,
 };
 
+use tokio::time;
+
 use crate::{
     wheels,
     blockwheel,
@@ -19,6 +27,7 @@ use crate::{
             KeyValueRef,
             SearchTreeIterItemsRx,
         },
+        BlockRef,
     },
 };
 
@@ -27,6 +36,8 @@ pub struct Args {
     pub block_items_reply_rx: oneshot::Receiver<SearchTreeIterItemsRx>,
     pub wheels_pid: wheels::Pid,
     pub remove_tasks_limit: usize,
+    pub rate_limit: Option<usize>,
+    pub root_block: Option<BlockRef>,
 }
 
 pub struct Done {
@@ -44,10 +55,10 @@ pub enum Error {
     DeleteBlock(blockwheel::DeleteBlockError),
 }
 
-pub async fn run(Args { done_reply_tx, block_items_reply_rx, wheels_pid, remove_tasks_limit, }: Args) -> Result<Done, Error> {
-    log::debug!("spawned task with remove_tasks_limit = {:?}", remove_tasks_limit);
+pub async fn run(Args { done_reply_tx, block_items_reply_rx, mut wheels_pid, remove_tasks_limit, rate_limit, root_block, }: Args) -> Result<Done, Error> {
+    log::debug!("spawned task with remove_tasks_limit = {:?}, rate_limit = {:?}", remove_tasks_limit, rate_limit);
 
-    let SearchTreeIterItemsRx { items_rx: block_items_rx, } = block_items_reply_rx.await
+    let SearchTreeIterItemsRx { items_rx: block_items_rx, .. } = block_items_reply_rx.await
         .map_err(|oneshot::Canceled| Error::IterPeerDisconnected)?;
     let mut fused_block_items_rx = block_items_rx.fuse();
 
@@ -55,6 +66,8 @@ pub async fn run(Args { done_reply_tx, block_items_reply_rx, wheels_pid, remove_
     let mut remove_tasks_count = 0;
     let mut blocks_deleted = 0;
     let mut items_depleted = false;
+    let mut maybe_root_block = root_block.clone();
+    let mut delete_schedule = DeleteSchedule::new(rate_limit, time::Instant::now());
 
     loop {
         enum Event<B, T> {
@@ -88,6 +101,15 @@ pub async fn run(Args { done_reply_tx, block_items_reply_rx, wheels_pid, remove_
             Event::RemoveTask(remove_task)
         };
 
+        // the root is deleted before any other block so an interrupted demolish never leaves
+        // a root referring to deleted blocks; it is already loaded once any item arrives
+        if let Event::BlockItem(Some(..)) = event {
+            if let Some(root_block) = maybe_root_block.take() {
+                delete_block(&mut wheels_pid, root_block).await?;
+                blocks_deleted += 1;
+            }
+        }
+
         match event {
             Event::BlockItem(None) => {
                 assert!(items_depleted);
@@ -96,17 +118,17 @@ pub async fn run(Args { done_reply_tx, block_items_reply_rx, wheels_pid, remove_
             Event::BlockItem(Some(KeyValueRef::NoMore)) =>
                 items_depleted = true,
 
-            Event::BlockItem(Some(KeyValueRef::BlockFinish(block_ref))) => {
+            Event::BlockItem(Some(KeyValueRef::BlockFinish { block_ref, .. })) if Some(&block_ref) == root_block.as_ref() =>
+                (),
+
+            Event::BlockItem(Some(KeyValueRef::BlockFinish { block_ref, .. })) => {
+                let maybe_delete_at = delete_schedule.next_delete_at(time::Instant::now());
                 let mut wheels_pid = wheels_pid.clone();
                 remove_tasks.push(async move {
-                    let mut wheel_ref = wheels_pid.get(block_ref.blockwheel_filename.clone()).await
-                        .map_err(|ero::NoProcError| Error::WheelsGone)?
-                        .ok_or_else(|| Error::WheelNotFound {
-                            blockwheel_filename: block_ref.blockwheel_filename.clone(),
-                        })?;
-                    let blockwheel::Deleted = wheel_ref.blockwheel_pid.delete_block(block_ref.block_id).await
-                        .map_err(Error::DeleteBlock)?;
-                    Ok(())
+                    if let Some(delete_at) = maybe_delete_at {
+                        time::sleep_until(delete_at).await;
+                    }
+                    delete_block(&mut wheels_pid, block_ref).await
                 });
                 remove_tasks_count += 1;
             },
@@ -121,3 +143,71 @@ pub async fn run(Args { done_reply_tx, block_items_reply_rx, wheels_pid, remove_
         }
     }
 }
+
+// each delete is scheduled one interval after the previous one, waiting in its remove task
+struct DeleteSchedule {
+    delete_interval: Option<Duration>,
+    next_delete_at: time::Instant,
+}
+
+impl DeleteSchedule {
+    fn new(rate_limit: Option<usize>, now: time::Instant) -> DeleteSchedule {
+        DeleteSchedule {
+            delete_interval: rate_limit
+                .map(|blocks_per_sec| Duration::from_secs(1) / u32::try_from(blocks_per_sec).unwrap_or(u32::MAX)),
+            next_delete_at: now,
+        }
+    }
+
+    // `None` when unlimited: the block is deleted right away
+    fn next_delete_at(&mut self, now: time::Instant) -> Option<time::Instant> {
+        let delete_interval = self.delete_interval?;
+        let delete_at = cmp::max(self.next_delete_at, now);
+        self.next_delete_at = delete_at + delete_interval;
+        Some(delete_at)
+    }
+}
+
+async fn delete_block(wheels_pid: &mut wheels::Pid, block_ref: BlockRef) -> Result<(), Error> {
+    let mut wheel_ref = wheels_pid.get(block_ref.blockwheel_filename.clone()).await
+        .map_err(|ero::NoProcError| Error::WheelsGone)?
+        .ok_or_else(|| Error::WheelNotFound {
+            blockwheel_filename: block_ref.blockwheel_filename.clone(),
+        })?;
+    let blockwheel::Deleted = wheel_ref.blockwheel_pid.delete_block(block_ref.block_id).await
+        .map_err(Error::DeleteBlock)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time;
+
+    use super::DeleteSchedule;
+
+    #[test]
+    fn unlimited_deletes_are_not_scheduled() {
+        let now = time::Instant::now();
+        let mut delete_schedule = DeleteSchedule::new(None, now);
+        assert_eq!(delete_schedule.next_delete_at(now), None);
+        assert_eq!(delete_schedule.next_delete_at(now), None);
+    }
+
+    #[test]
+    fn deletes_paced_by_rate_limit() {
+        let start = time::Instant::now();
+        let interval = Duration::from_millis(250);
+        let mut delete_schedule = DeleteSchedule::new(Some(4), start);
+        // a burst is spread one interval apart
+        assert_eq!(delete_schedule.next_delete_at(start), Some(start));
+        assert_eq!(delete_schedule.next_delete_at(start), Some(start + interval));
+        assert_eq!(delete_schedule.next_delete_at(start), Some(start + 2 * interval));
+
+        // after an idle gap the next delete is not made up for, it goes at once
+        let later = start + Duration::from_secs(10);
+        assert_eq!(delete_schedule.next_delete_at(later), Some(later));
+        assert_eq!(delete_schedule.next_delete_at(later), Some(later + interval));
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    ops::Bound,
+    cmp::Ordering,
+};
+
+use futures::{
+    channel::{
+        oneshot,
+    },
+};
+
+use alloc_pool::bytes::BytesPool;
+
+use crate::{
+    job,
+    wheels,
+    storage,
+    metrics,
+    comparator::KeyComparatorRef,
+    core::{
+        search_tree::{
+            task::{
+                load_block,
+            },
+        },
+        BlockRef,
+        SearchRangeBounds,
+    },
+};
+
+pub struct Args<J> where J: edeltraud::Job {
+    pub reply_tx: oneshot::Sender<usize>,
+    pub root_block: BlockRef,
+    pub range: SearchRangeBounds,
+    pub key_comparator: KeyComparatorRef,
+    pub wheels_pid: wheels::Pid,
+    pub thread_pool: edeltraud::Edeltraud<J>,
+    pub blocks_pool: BytesPool,
+    pub metrics: metrics::MetricsRef,
+    pub verify_checksums: bool,
+}
+
+pub struct Done;
+
+#[derive(Debug)]
+pub enum Error {
+    LoadBlock(load_block::Error),
+    ReadBlockStorage { block_ref: BlockRef, error: storage::Error, },
+}
+
+// every entry of an inner block stands for itself and its left subtree, which is assumed to hold an equal share
+// of the block subtree: entries strictly inside the range are summed by these shares, and only the (at most two)
+// subtrees crossing a range bound are descended into, so about twice the tree depth of blocks is read
+pub async fn run<J>(
+    Args {
+        reply_tx,
+        root_block,
+        range,
+        key_comparator,
+        wheels_pid,
+        thread_pool,
+        blocks_pool,
+        metrics,
+        verify_checksums,
+    }: Args<J>,
+)
+    -> Result<Done, Error>
+where J: edeltraud::Job + From<job::Job>,
+      J::Output: From<job::JobOutput>,
+      job::JobOutput: From<J::Output>,
+{
+    let mut estimate = 0;
+    // pairs of a block and the estimated entries count of its subtree, unknown for the root until it is read
+    let mut pending = vec![(root_block, None)];
+    while let Some((block_ref, maybe_subtree_count)) = pending.pop() {
+        let load_block::Done { block_ref, block_bytes, } = load_block::run(load_block::Args {
+            block_ref,
+            wheels_pid: wheels_pid.clone(),
+            thread_pool: thread_pool.clone(),
+            blocks_pool: blocks_pool.clone(),
+            metrics: metrics.clone(),
+            verify_checksums,
+        }).await
+            .map_err(Error::LoadBlock)?;
+
+        let entries_iter = storage::block_deserialize_iter(&block_bytes)
+            .map_err(|error| Error::ReadBlockStorage { block_ref: block_ref.clone(), error, })?;
+        let entries_count = entries_iter.block_header().entries_count;
+        let subtree_count = match (maybe_subtree_count, &entries_iter.block_header().node_type) {
+            (Some(subtree_count), _) =>
+                subtree_count,
+            (None, storage::NodeType::Root { tree_entries_count, .. }) =>
+                *tree_entries_count,
+            (None, _) =>
+                entries_count,
+        };
+        let entry_share = (subtree_count / entries_count.max(1)).max(1);
+
+        let mut range_entered = false;
+        for maybe_entry in entries_iter {
+            let entry = maybe_entry
+                .map_err(|error| Error::ReadBlockStorage { block_ref: block_ref.clone(), error, })?;
+            let below_range = match &range.range_from {
+                Bound::Unbounded =>
+                    false,
+                Bound::Included(key) =>
+                    key_comparator.compare(&key.key_bytes, entry.key) == Ordering::Greater,
+                Bound::Excluded(key) =>
+                    key_comparator.compare(&key.key_bytes, entry.key) != Ordering::Less,
+            };
+            if below_range {
+                continue;
+            }
+            let above_range = match &range.range_to {
+                Bound::Unbounded =>
+                    false,
+                Bound::Included(key) =>
+                    key_comparator.compare(&key.key_bytes, entry.key) == Ordering::Less,
+                Bound::Excluded(key) =>
+                    key_comparator.compare(&key.key_bytes, entry.key) != Ordering::Greater,
+            };
+            let maybe_jump_block_ref = match storage::OwnedJumpRef::from_jump_ref(&entry.jump_ref, &block_bytes) {
+                storage::OwnedJumpRef::None =>
+                    None,
+                storage::OwnedJumpRef::Local(storage::LocalRef { block_id, }) =>
+                    Some(BlockRef {
+                        blockwheel_filename: block_ref.blockwheel_filename.clone(),
+                        block_id,
+                    }),
+                storage::OwnedJumpRef::External(block_ref) =>
+                    Some(block_ref),
+            };
+            let crosses_bound = above_range || !range_entered;
+            match maybe_jump_block_ref {
+                Some(jump_block_ref) if crosses_bound =>
+                    pending.push((jump_block_ref, Some(entry_share.saturating_sub(1)))),
+                Some(..) =>
+                    estimate += entry_share - 1,
+                None =>
+                    (),
+            }
+            if above_range {
+                break;
+            }
+            range_entered = true;
+            estimate += 1;
+        }
+    }
+
+    if let Err(_send_error) = reply_tx.send(estimate) {
+        log::warn!("client canceled estimate count request");
+    }
+    Ok(Done)
+}
@@ -24,6 +24,7 @@ use crate::{
     kv,
     job,
     storage,
+    comparator::KeyComparatorRef,
     core::{
         search_tree::{
             task::{
@@ -41,6 +42,7 @@ use crate::{
 
 pub struct Args<J> where J: edeltraud::Job {
     pub iter_request: IterRequest,
+    pub key_comparator: KeyComparatorRef,
     pub iter_block_entries_pool: pool::Pool<Vec<BlockEntry>>,
     pub thread_pool: edeltraud::Edeltraud<J>,
     pub block_bytes: Bytes,
@@ -66,6 +68,7 @@ pub struct JobArgs {
     block_ref: BlockRef,
     block_bytes: Bytes,
     search_range: SearchRangeBounds,
+    key_comparator: KeyComparatorRef,
     iter_block_entries_pool: pool::Pool<Vec<BlockEntry>>,
 }
 
@@ -73,7 +76,7 @@ pub struct JobDone {
     block_entries: Unique<Vec<BlockEntry>>,
 }
 
-pub fn job(JobArgs { block_ref, block_bytes, search_range, iter_block_entries_pool, }: JobArgs) -> JobOutput {
+pub fn job(JobArgs { block_ref, block_bytes, search_range, key_comparator, iter_block_entries_pool, }: JobArgs) -> JobOutput {
     let mut block_entries = iter_block_entries_pool.lend(Vec::new);
     block_entries.clear();
 
@@ -86,14 +89,14 @@ pub fn job(JobArgs { block_ref, block_bytes, search_range, iter_block_entries_po
             SearchRangeBounds { range_from: Bound::Unbounded, .. } =>
                 (),
             SearchRangeBounds { range_from: Bound::Excluded(key), .. } =>
-                match key.key_bytes[..].cmp(iter_entry.key) {
+                match key_comparator.compare(&key.key_bytes, iter_entry.key) {
                     Ordering::Less =>
                         (),
                     Ordering::Equal | Ordering::Greater =>
                         continue,
                 },
             SearchRangeBounds { range_from: Bound::Included(key), .. } =>
-                match key.key_bytes[..].cmp(iter_entry.key) {
+                match key_comparator.compare(&key.key_bytes, iter_entry.key) {
                     Ordering::Less | Ordering::Equal =>
                         (),
                     Ordering::Greater =>
@@ -118,14 +121,14 @@ pub fn job(JobArgs { block_ref, block_bytes, search_range, iter_block_entries_po
             SearchRangeBounds { range_to: Bound::Unbounded, .. } =>
                 false,
             SearchRangeBounds { range_to: Bound::Excluded(key), .. } =>
-                match key.key_bytes[..].cmp(iter_entry.key) {
+                match key_comparator.compare(&key.key_bytes, iter_entry.key) {
                     Ordering::Less | Ordering::Equal =>
                         true,
                     Ordering::Greater =>
                         false,
                 },
             SearchRangeBounds { range_to: Bound::Included(key), .. } =>
-                match key.key_bytes[..].cmp(iter_entry.key) {
+                match key_comparator.compare(&key.key_bytes, iter_entry.key) {
                     Ordering::Less  =>
                         true,
                     Ordering::Equal | Ordering::Greater =>
@@ -144,8 +147,8 @@ pub fn job(JobArgs { block_ref, block_bytes, search_range, iter_block_entries_po
                         &block_ref.blockwheel_filename,
                     )),
                 },
-            kv::ValueCell { version, cell: kv::Cell::Tombstone, } =>
-                kv::ValueCell { version, cell: kv::Cell::Tombstone, },
+            kv::ValueCell { version, cell: kv::Cell::Tombstone { meta, }, } =>
+                kv::ValueCell { version, cell: kv::Cell::Tombstone { meta, }, },
         };
 
         match (maybe_jump_block_ref, force_stop) {
@@ -176,10 +179,12 @@ pub async fn run<J>(
             block_ref,
             data: IterRequestData {
                 range,
+                depth,
                 mut iter_items_tx,
                 repay_iter_items_tx,
             },
         },
+        key_comparator,
         iter_block_entries_pool,
         thread_pool,
         block_bytes,
@@ -195,6 +200,7 @@ where J: edeltraud::Job + From<job::Job>,
         block_ref: block_ref.clone(),
         block_bytes: block_bytes.clone(),
         search_range: range.clone(),
+        key_comparator,
         iter_block_entries_pool,
     }));
     let job_output = job_task.await
@@ -207,11 +213,17 @@ where J: edeltraud::Job + From<job::Job>,
 
         match &block_entry_action {
             BlockEntry::OnlyJump(jump_block_ref) | BlockEntry::JumpAndEntry { jump: jump_block_ref, .. } => {
+                // jump block is loaded only when the next item is asked for
+                if !iter_items_tx.demand().await {
+                    log::warn!("client canceled iter items request before jump");
+                    return Ok(Done { block_ref, });
+                }
                 let (repay_iter_items_tx, repay_iter_items_rx) = oneshot::channel();
                 let send_result = iter_rec_tx.send(IterRecRequest {
                     maybe_block_ref: Some(jump_block_ref.clone()),
                     data: IterRequestData {
                         range: range.clone(),
+                        depth: depth + 1,
                         iter_items_tx,
                         repay_iter_items_tx,
                     },
@@ -235,7 +247,7 @@ where J: edeltraud::Job + From<job::Job>,
 
         match block_entry_action {
             BlockEntry::OnlyEntry { key, value_cell, } | BlockEntry::JumpAndEntry { key, value_cell, .. } =>
-                if let Err(_send_error) = iter_items_tx.items_tx.send(KeyValueRef::Item { key, value_cell, }).await {
+                if let Err(_send_error) = iter_items_tx.send(KeyValueRef::Item { key, value_cell, }).await {
                     log::warn!("client canceled iter items request");
                     return Ok(Done { block_ref, });
                 },
@@ -244,7 +256,7 @@ where J: edeltraud::Job + From<job::Job>,
         }
     }
 
-    if let Err(_send_error) = iter_items_tx.items_tx.send(KeyValueRef::BlockFinish(block_ref.clone())).await {
+    if let Err(_send_error) = iter_items_tx.send(KeyValueRef::BlockFinish { block_ref: block_ref.clone(), depth, block_size: block_bytes.len(), }).await {
         log::warn!("client canceled iter items request on BlockFinish");
     }
 
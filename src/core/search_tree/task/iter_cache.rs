@@ -1,10 +1,5 @@
 use std::sync::Arc;
 
-use futures::{
-    stream,
-    SinkExt,
-};
-
 use alloc_pool::{
     pool,
     Unique,
@@ -76,6 +71,7 @@ pub async fn run<J>(
             range,
             mut iter_items_tx,
             repay_iter_items_tx,
+            ..
         },
     }: Args<J>,
 )
@@ -91,12 +87,11 @@ where J: edeltraud::Job + From<job::Job>,
     let JobDone { mut items, } = job_result?;
 
     let key_value_refs = items.drain(..)
-        .map(|key_value| Ok(KeyValueRef::Item {
+        .map(|key_value| KeyValueRef::Item {
             key: key_value.key,
             value_cell: key_value.value_cell,
-        }));
-    let mut key_value_refs_stream = stream::iter(key_value_refs);
-    if let Err(_send_error) = iter_items_tx.items_tx.send_all(&mut key_value_refs_stream).await {
+        });
+    if let Err(_send_error) = iter_items_tx.send_all(key_value_refs).await {
         log::warn!("client canceled iter request");
         return Ok(Done);
     }
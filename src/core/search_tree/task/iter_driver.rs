@@ -51,6 +51,7 @@ pub async fn run(
         maybe_block_ref,
         data: IterRequestData {
             range,
+            depth: 1,
             iter_items_tx,
             repay_iter_items_tx,
         },
@@ -60,7 +61,7 @@ pub async fn run(
 
     match repay_iter_items_rx.await {
         Ok(mut iter_items_tx) =>
-            if let Err(_send_error) = iter_items_tx.items_tx.send(KeyValueRef::NoMore).await {
+            if let Err(_send_error) = iter_items_tx.send(KeyValueRef::NoMore).await {
                 log::warn!("client canceled iter request");
             },
         Err(oneshot::Canceled) =>
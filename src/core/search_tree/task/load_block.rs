@@ -1,17 +1,27 @@
-use alloc_pool::bytes::Bytes;
+use alloc_pool::bytes::{
+    Bytes,
+    BytesPool,
+};
 
 use ero_blockwheel_fs as blockwheel;
 
 use crate::{
+    job,
     wheels,
+    storage,
+    metrics,
     core::{
         BlockRef,
     },
 };
 
-pub struct Args {
+pub struct Args<J> where J: edeltraud::Job {
     pub block_ref: BlockRef,
     pub wheels_pid: wheels::Pid,
+    pub thread_pool: edeltraud::Edeltraud<J>,
+    pub blocks_pool: BytesPool,
+    pub metrics: metrics::MetricsRef,
+    pub verify_checksums: bool,
 }
 
 pub struct Done {
@@ -26,9 +36,42 @@ pub enum Error {
         blockwheel_filename: wheels::WheelFilename,
     },
     ReadBlock(blockwheel::ReadBlockError),
+    DecompressBlock {
+        block_ref: BlockRef,
+        error: storage::Error,
+    },
+    VerifyBlock(storage::Error),
+    ThreadPoolGone,
+}
+
+pub type JobOutput = Result<JobDone, Error>;
+
+pub struct JobArgs {
+    block_ref: BlockRef,
+    block_bytes: Bytes,
+    blocks_pool: BytesPool,
+    verify_checksums: bool,
 }
 
-pub async fn run(Args { block_ref, mut wheels_pid, }: Args) -> Result<Done, Error> {
+pub struct JobDone {
+    block_bytes: Bytes,
+}
+
+pub fn job(JobArgs { block_ref, block_bytes, blocks_pool, verify_checksums, }: JobArgs) -> JobOutput {
+    let block_bytes = storage::block_decompress(block_bytes, &blocks_pool)
+        .map_err(|error| Error::DecompressBlock { block_ref: block_ref.clone(), error, })?;
+    if verify_checksums {
+        storage::block_verify_checksum(&block_bytes, &block_ref)
+            .map_err(Error::VerifyBlock)?;
+    }
+    Ok(JobDone { block_bytes, })
+}
+
+pub async fn run<J>(Args { block_ref, mut wheels_pid, thread_pool, blocks_pool, metrics, verify_checksums, }: Args<J>) -> Result<Done, Error>
+where J: edeltraud::Job + From<job::Job>,
+      J::Output: From<job::JobOutput>,
+      job::JobOutput: From<J::Output>,
+{
     let mut wheel_ref = wheels_pid.get(block_ref.blockwheel_filename.clone()).await
         .map_err(|ero::NoProcError| Error::WheelsGone)?
         .ok_or_else(|| Error::WheelNotFound {
@@ -36,5 +79,17 @@ pub async fn run(Args { block_ref, mut wheels_pid, }: Args) -> Result<Done, Erro
         })?;
     let block_bytes = wheel_ref.blockwheel_pid.read_block(block_ref.block_id.clone()).await
         .map_err(Error::ReadBlock)?;
+    metrics.on_block_read(block_bytes.len());
+
+    // nothing to decompress or verify: skip the thread pool roundtrip
+    if !verify_checksums && !storage::block_is_compressed(&block_bytes) {
+        return Ok(Done { block_ref, block_bytes, });
+    }
+    let job_args = JobArgs { block_ref: block_ref.clone(), block_bytes, blocks_pool, verify_checksums, };
+    let job_output = thread_pool.spawn(job::Job::SearchTreeLoadBlock(job_args)).await
+        .map_err(|edeltraud::SpawnError::ThreadPoolGone| Error::ThreadPoolGone)?;
+    let job_output: job::JobOutput = job_output.into();
+    let job::SearchTreeLoadBlockDone(job_result) = job_output.into();
+    let JobDone { block_bytes, } = job_result?;
     Ok(Done { block_ref, block_bytes, })
 }
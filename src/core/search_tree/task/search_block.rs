@@ -88,10 +88,19 @@ pub fn job(JobArgs { search_block_ref, block_bytes, mut lookup_requests_queue, m
                                 block_ref: search_block_ref.clone(),
                                 error,
                             })?;
-                        match iter_entry.key.cmp(&request_key.key.key_bytes) {
+                        match request_key.key_comparator.compare(iter_entry.key, &request_key.key.key_bytes) {
                             Ordering::Less => {
-                                maybe_request = Some(request_key);
                                 maybe_entry = entries_iter.next();
+                                // checked on the way, so a malformed block fails instead of answering wrong
+                                if let Some(Ok(next_entry)) = &maybe_entry {
+                                    if request_key.key_comparator.compare(iter_entry.key, next_entry.key) != Ordering::Less {
+                                        return Err(Error::ReadBlockStorage {
+                                            block_ref: search_block_ref.clone(),
+                                            error: storage::Error::NonMonotonicBlock { block_ref: search_block_ref.clone(), },
+                                        });
+                                    }
+                                }
+                                maybe_request = Some(request_key);
                             },
                             Ordering::Equal => {
                                 let owned_entry = entries_iter.to_owned_entry(&iter_entry);
@@ -104,8 +113,8 @@ pub fn job(JobArgs { search_block_ref, block_bytes, mut lookup_requests_queue, m
                                                 &search_block_ref.blockwheel_filename,
                                             )),
                                         },
-                                    kv::ValueCell { version, cell: kv::Cell::Tombstone, } =>
-                                        kv::ValueCell { version, cell: kv::Cell::Tombstone, },
+                                    kv::ValueCell { version, cell: kv::Cell::Tombstone { meta, }, } =>
+                                        kv::ValueCell { version, cell: kv::Cell::Tombstone { meta, }, },
                                 };
                                 outcomes.push(SearchOutcome {
                                     request: request_key,
@@ -39,7 +39,7 @@ pub struct JobDone {
 }
 
 pub fn job(JobArgs { key, cache, }: JobArgs) -> JobOutput {
-    let result = cache.get(&*key.key_bytes)
+    let result = cache.lookup(&key)
         .cloned();
     Ok(JobDone { outcome: result.map(From::from), })
 }
@@ -56,7 +56,7 @@ where J: edeltraud::Job + From<job::Job>,
       J::Output: From<job::JobOutput>,
       job::JobOutput: From<J::Output>,
 {
-    let LookupRequest { key, reply_tx, } = lookup_request;
+    let LookupRequest { key, reply_tx, .. } = lookup_request;
     let job_output = thread_pool.spawn(job::Job::SearchTreeSearchCache(JobArgs { key, cache, })).await
         .map_err(|edeltraud::SpawnError::ThreadPoolGone| Error::ThreadPoolGone)?;
     let job_output: job::JobOutput = job_output.into();
@@ -1,3 +1,7 @@
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+
 use ero_blockwheel_fs as blockwheel;
 
 use crate::{
@@ -8,22 +12,82 @@ pub enum Job {
     BlockwheelFs(blockwheel::job::Job),
     SearchTreeBootstrapBlock(core::search_tree::task::bootstrap::BlockJobArgs),
     SearchTreeBootstrapLayout(core::search_tree::task::bootstrap::LayoutJobArgs),
+    SearchTreeLoadBlock(core::search_tree::task::load_block::JobArgs),
     SearchTreeSearchCache(core::search_tree::task::search_cache::JobArgs),
     SearchTreeSearchBlock(core::search_tree::task::search_block::JobArgs),
     SearchTreeIterCache(core::search_tree::task::iter_cache::JobArgs),
     SearchTreeIterBlock(core::search_tree::task::iter_block::JobArgs),
     MergeSearchTrees(core::manager::task::merge_search_trees::JobArgs),
+    BulkLoad(core::manager::task::bulk_load::JobArgs),
+    VerifyBlock(core::manager::task::iter_raw_blocks::JobArgs),
 }
 
 pub enum JobOutput {
     BlockwheelFs(blockwheel::job::JobOutput),
     SearchTreeBootstrapBlock(SearchTreeBootstrapBlockDone),
     SearchTreeBootstrapLayout(SearchTreeBootstrapLayoutDone),
+    SearchTreeLoadBlock(SearchTreeLoadBlockDone),
     SearchTreeSearchCache(SearchTreeSearchCacheDone),
     SearchTreeSearchBlock(SearchTreeSearchBlockDone),
     SearchTreeIterCache(SearchTreeIterCacheDone),
     SearchTreeIterBlock(SearchTreeIterBlockDone),
     MergeSearchTrees(MergeSearchTreesDone),
+    BulkLoad(BulkLoadDone),
+    VerifyBlock(VerifyBlockDone),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Priority {
+    Foreground,
+    Background,
+}
+
+impl Job {
+    pub fn priority(&self) -> Priority {
+        match self {
+            Job::MergeSearchTrees(..) | Job::BulkLoad(..) | Job::VerifyBlock(..) =>
+                Priority::Background,
+            Job::BlockwheelFs(..) |
+            Job::SearchTreeBootstrapBlock(..) |
+            Job::SearchTreeBootstrapLayout(..) |
+            Job::SearchTreeLoadBlock(..) |
+            Job::SearchTreeSearchCache(..) |
+            Job::SearchTreeSearchBlock(..) |
+            Job::SearchTreeIterCache(..) |
+            Job::SearchTreeIterBlock(..) =>
+                Priority::Foreground,
+        }
+    }
+}
+
+// edeltraud has a single queue, so background jobs are admitted to it through a separate
+// bounded one: foreground jobs never wait behind more than `background_jobs_limit` of them
+#[derive(Clone)]
+pub struct BackgroundGate {
+    maybe_semaphore: Option<Arc<Semaphore>>,
+}
+
+impl BackgroundGate {
+    pub fn new(background_jobs_limit: Option<usize>) -> BackgroundGate {
+        BackgroundGate {
+            maybe_semaphore: background_jobs_limit
+                .map(|limit| Arc::new(Semaphore::new(limit))),
+        }
+    }
+
+    pub async fn spawn<J>(&self, thread_pool: &edeltraud::Edeltraud<J>, job: Job) -> Result<J::Output, edeltraud::SpawnError>
+    where J: edeltraud::Job + From<Job>
+    {
+        match (job.priority(), &self.maybe_semaphore) {
+            (Priority::Background, Some(semaphore)) => {
+                let _permit = semaphore.acquire().await
+                    .expect("background gate semaphore is never closed");
+                thread_pool.spawn(job).await
+            },
+            (Priority::Background, None) | (Priority::Foreground, _) =>
+                thread_pool.spawn(job).await,
+        }
+    }
 }
 
 impl edeltraud::Job for Job {
@@ -41,6 +105,10 @@ impl edeltraud::Job for Job {
                 JobOutput::SearchTreeBootstrapLayout(SearchTreeBootstrapLayoutDone(
                     core::search_tree::task::bootstrap::layout_job(args),
                 )),
+            Job::SearchTreeLoadBlock(args) =>
+                JobOutput::SearchTreeLoadBlock(SearchTreeLoadBlockDone(
+                    core::search_tree::task::load_block::job(args),
+                )),
             Job::SearchTreeSearchCache(args) =>
                 JobOutput::SearchTreeSearchCache(SearchTreeSearchCacheDone(
                     core::search_tree::task::search_cache::job(args),
@@ -61,6 +129,14 @@ impl edeltraud::Job for Job {
                 JobOutput::MergeSearchTrees(MergeSearchTreesDone(
                     core::manager::task::merge_search_trees::job(args),
                 )),
+            Job::BulkLoad(args) =>
+                JobOutput::BulkLoad(BulkLoadDone(
+                    core::manager::task::bulk_load::job(args),
+                )),
+            Job::VerifyBlock(args) =>
+                JobOutput::VerifyBlock(VerifyBlockDone(
+                    core::manager::task::iter_raw_blocks::job(args),
+                )),
         }
     }
 }
@@ -95,6 +171,21 @@ impl From<JobOutput> for SearchTreeBootstrapLayoutDone {
     }
 }
 
+pub struct SearchTreeLoadBlockDone(
+    pub core::search_tree::task::load_block::JobOutput,
+);
+
+impl From<JobOutput> for SearchTreeLoadBlockDone {
+    fn from(output: JobOutput) -> Self {
+        match output {
+            JobOutput::SearchTreeLoadBlock(done) =>
+                done,
+            _other =>
+                panic!("expected JobOutput::SearchTreeLoadBlock but got other"),
+        }
+    }
+}
+
 pub struct SearchTreeSearchCacheDone(
     pub core::search_tree::task::search_cache::JobOutput,
 );
@@ -170,6 +261,36 @@ impl From<JobOutput> for MergeSearchTreesDone {
     }
 }
 
+pub struct BulkLoadDone(
+    pub core::manager::task::bulk_load::JobOutput,
+);
+
+impl From<JobOutput> for BulkLoadDone {
+    fn from(output: JobOutput) -> Self {
+        match output {
+            JobOutput::BulkLoad(done) =>
+                done,
+            _other =>
+                panic!("expected JobOutput::BulkLoad but got other"),
+        }
+    }
+}
+
+pub struct VerifyBlockDone(
+    pub core::manager::task::iter_raw_blocks::JobOutput,
+);
+
+impl From<JobOutput> for VerifyBlockDone {
+    fn from(output: JobOutput) -> Self {
+        match output {
+            JobOutput::VerifyBlock(done) =>
+                done,
+            _other =>
+                panic!("expected JobOutput::VerifyBlock but got other"),
+        }
+    }
+}
+
 impl From<blockwheel::job::Job> for Job {
     fn from(job: blockwheel::job::Job) -> Job {
         Job::BlockwheelFs(job)
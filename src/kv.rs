@@ -1,15 +1,35 @@
-use std::borrow::Borrow;
+use std::{
+    str,
+    convert::TryFrom,
+    borrow::Borrow,
+};
 
 use alloc_pool::bytes::{
     Bytes,
     BytesMut,
+    BytesPool,
+};
+
+#[cfg(feature = "kv-serde")]
+use serde_derive::{
+    Serialize,
+    Deserialize,
 };
 
+pub mod composite;
+
+// zero-length key is a regular key: with `comparator::bytewise` it is ordered before any other one
+// and can be inserted, looked up, ranged over and removed as usual
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "kv-serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "kv-serde", serde(transparent))]
 pub struct Key {
+    #[cfg_attr(feature = "kv-serde", serde(with = "bytes_serde"))]
     pub key_bytes: Bytes,
 }
 
+// the buffer is shared as is, e.g. a network one lent from a `BytesPool`: nothing is copied on the way
+// to the butcher, only serializing a flushed block does
 impl From<Bytes> for Key {
     fn from(key_bytes: Bytes) -> Key {
         Key { key_bytes, }
@@ -22,11 +42,16 @@ impl From<BytesMut> for Key {
     }
 }
 
+// zero-length value is stored as is and never confused with `Cell::Tombstone`
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "kv-serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "kv-serde", serde(transparent))]
 pub struct Value {
+    #[cfg_attr(feature = "kv-serde", serde(with = "bytes_serde"))]
     pub value_bytes: Bytes,
 }
 
+// same as `Key::from(Bytes)`
 impl From<Bytes> for Value {
     fn from(value_bytes: Bytes) -> Value {
         Value { value_bytes, }
@@ -39,19 +64,119 @@ impl From<BytesMut> for Value {
     }
 }
 
+// convenience conversions below copy into a fresh pool buffer; `Bytes` / `BytesMut` ones are the canonical form
+fn bytes_from_slice(slice: &[u8]) -> Bytes {
+    let mut bytes = BytesPool::new().lend();
+    bytes.extend_from_slice(slice);
+    bytes.freeze()
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ConvertError {
+    InvalidUtf8(str::Utf8Error),
+    InvalidLength {
+        expected: usize,
+        provided: usize,
+    },
+}
+
+macro_rules! impl_bytes_conversions {
+    ($type:ident, $field:ident) => {
+        impl From<Vec<u8>> for $type {
+            fn from(bytes: Vec<u8>) -> $type {
+                $type { $field: bytes_from_slice(&bytes), }
+            }
+        }
+
+        impl From<&[u8]> for $type {
+            fn from(bytes: &[u8]) -> $type {
+                $type { $field: bytes_from_slice(bytes), }
+            }
+        }
+
+        impl From<String> for $type {
+            fn from(string: String) -> $type {
+                $type { $field: bytes_from_slice(string.as_bytes()), }
+            }
+        }
+
+        impl From<&str> for $type {
+            fn from(string: &str) -> $type {
+                $type { $field: bytes_from_slice(string.as_bytes()), }
+            }
+        }
+
+        impl TryFrom<$type> for String {
+            type Error = ConvertError;
+
+            fn try_from(value: $type) -> Result<String, ConvertError> {
+                str::from_utf8(&value.$field)
+                    .map(String::from)
+                    .map_err(ConvertError::InvalidUtf8)
+            }
+        }
+    };
+}
+
+impl_bytes_conversions!(Key, key_bytes);
+impl_bytes_conversions!(Value, value_bytes);
+
+// big-endian, so with `comparator::bytewise` unsigned keys are ordered numerically; signed integers
+// are values only as their two's complement encoding is not
+#[cfg(feature = "int-encoding")]
+macro_rules! impl_int_conversions {
+    ($type:ident, $field:ident, $($int:ty),*) => {
+        $(
+            impl From<$int> for $type {
+                fn from(int: $int) -> $type {
+                    $type { $field: bytes_from_slice(&int.to_be_bytes()), }
+                }
+            }
+
+            impl TryFrom<$type> for $int {
+                type Error = ConvertError;
+
+                fn try_from(value: $type) -> Result<$int, ConvertError> {
+                    let mut int_bytes = [0; std::mem::size_of::<$int>()];
+                    if value.$field.len() != int_bytes.len() {
+                        return Err(ConvertError::InvalidLength {
+                            expected: int_bytes.len(),
+                            provided: value.$field.len(),
+                        });
+                    }
+                    int_bytes.copy_from_slice(&value.$field);
+                    Ok(<$int>::from_be_bytes(int_bytes))
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "int-encoding")]
+impl_int_conversions!(Key, key_bytes, u16, u32, u64, u128);
+#[cfg(feature = "int-encoding")]
+impl_int_conversions!(Value, value_bytes, u16, u32, u64, u128, i16, i32, i64, i128);
+
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "kv-serde", derive(Serialize, Deserialize))]
 pub struct ValueCell<V> {
     pub version: u64,
     pub cell: Cell<V>,
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "kv-serde", derive(Serialize, Deserialize))]
 pub enum Cell<V> {
     Value(V),
-    Tombstone,
+    // `meta` is an opaque blob attached by the remover (e.g. who or why), it goes along with the tombstone
+    // until a bottom merge drops it, and never takes part in picking the newest cell
+    Tombstone {
+        meta: Option<Value>,
+    },
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "kv-serde", derive(Serialize, Deserialize))]
 pub struct KeyValuePair<V> {
     pub key: Key,
     pub value_cell: ValueCell<V>,
@@ -62,3 +187,54 @@ impl Borrow<[u8]> for Key {
         &self.key_bytes
     }
 }
+
+#[cfg(feature = "kv-serde")]
+mod bytes_serde {
+    use std::fmt;
+
+    use serde::{
+        de,
+        Serializer,
+        Deserializer,
+    };
+
+    use alloc_pool::bytes::{
+        Bytes,
+        BytesPool,
+    };
+
+    pub fn serialize<S>(bytes: &Bytes, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Bytes, D::Error> where D: Deserializer<'de> {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> de::Visitor<'de> for BytesVisitor {
+        type Value = Bytes;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a byte array")
+        }
+
+        fn visit_bytes<E>(self, value: &[u8]) -> Result<Bytes, E> where E: de::Error {
+            let mut bytes = BytesPool::new().lend();
+            bytes.extend_from_slice(value);
+            Ok(bytes.freeze())
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Bytes, A::Error> where A: de::SeqAccess<'de> {
+            let mut bytes = BytesPool::new().lend();
+            if let Some(size_hint) = seq.size_hint() {
+                bytes.reserve(size_hint);
+            }
+            while let Some(byte) = seq.next_element::<u8>()? {
+                bytes.push(byte);
+            }
+            Ok(bytes.freeze())
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use alloc_pool::bytes::BytesPool;
+
+use crate::kv;
+
+// segments are escaped rather than length prefixed, so with `comparator::bytewise` composite keys are
+// ordered segment by segment: `0x00` inside a segment is written as `0x00 0xff` and every segment
+// ends with `0x00 0x01`, which also sorts a segment before any longer one it prefixes
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xff;
+const SEGMENT_END: u8 = 0x01;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum DecodeError {
+    UnterminatedSegment {
+        offset: usize,
+    },
+    InvalidEscape {
+        offset: usize,
+        byte: u8,
+    },
+}
+
+pub fn encode_segment(segment: &[u8], bytes: &mut Vec<u8>) {
+    for &byte in segment {
+        bytes.push(byte);
+        if byte == ESCAPE {
+            bytes.push(ESCAPED_ZERO);
+        }
+    }
+    bytes.push(ESCAPE);
+    bytes.push(SEGMENT_END);
+}
+
+pub fn encode<'a, I>(segments: I, keys_pool: &BytesPool) -> kv::Key where I: IntoIterator<Item = &'a [u8]> {
+    let mut key_bytes = keys_pool.lend();
+    for segment in segments {
+        encode_segment(segment, key_bytes.as_mut());
+    }
+    kv::Key::from(key_bytes)
+}
+
+pub fn decode(key_bytes: &[u8]) -> Result<Vec<Vec<u8>>, DecodeError> {
+    let mut segments = Vec::new();
+    let mut segment = Vec::new();
+    let mut offset = 0;
+    while offset < key_bytes.len() {
+        let byte = key_bytes[offset];
+        if byte != ESCAPE {
+            segment.push(byte);
+            offset += 1;
+            continue;
+        }
+        match key_bytes.get(offset + 1) {
+            None =>
+                return Err(DecodeError::UnterminatedSegment { offset, }),
+            Some(&ESCAPED_ZERO) =>
+                segment.push(ESCAPE),
+            Some(&SEGMENT_END) =>
+                segments.push(std::mem::take(&mut segment)),
+            Some(&byte) =>
+                return Err(DecodeError::InvalidEscape { offset: offset + 1, byte, }),
+        }
+        offset += 2;
+    }
+    if !segment.is_empty() {
+        return Err(DecodeError::UnterminatedSegment { offset: key_bytes.len() - segment.len(), });
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc_pool::bytes::BytesPool;
+
+    use super::{
+        encode,
+        decode,
+        DecodeError,
+    };
+
+    #[test]
+    fn roundtrip_with_separators_in_payload() {
+        let blocks_pool = BytesPool::new();
+        let segments = vec![&b"idx"[..], b"", b"a\x00b", b"\x00\x01\xff", b"pk"];
+        let key = encode(segments.iter().cloned(), &blocks_pool);
+        let decoded = decode(&key.key_bytes).unwrap();
+        assert_eq!(decoded, segments);
+    }
+
+    #[test]
+    fn order_is_kept_segment_by_segment() {
+        let blocks_pool = BytesPool::new();
+        let ordered = vec![
+            vec![&b"a"[..], b"z"],
+            vec![&b"a\x00"[..], b"a"],
+            vec![&b"a\x01"[..], b"a"],
+            vec![&b"ab"[..], b""],
+            vec![&b"b"[..], b""],
+        ];
+        let keys: Vec<_> = ordered.iter()
+            .map(|segments| encode(segments.iter().cloned(), &blocks_pool))
+            .collect();
+        for pair in keys.windows(2) {
+            assert!(pair[0].key_bytes[..] < pair[1].key_bytes[..]);
+        }
+    }
+
+    #[test]
+    fn malformed_keys_rejected() {
+        assert_eq!(decode(b"abc"), Err(DecodeError::UnterminatedSegment { offset: 0, }));
+        assert_eq!(decode(b"a\x00\x01b\x00"), Err(DecodeError::UnterminatedSegment { offset: 4, }));
+        assert_eq!(decode(b"a\x00\x02"), Err(DecodeError::InvalidEscape { offset: 2, byte: 0x02, }));
+    }
+}
@@ -1,23 +1,43 @@
 #[forbid(unsafe_code)]
 
 use std::{
+    mem,
+    time::Duration,
+    sync::Arc,
     ops::{
+        Bound,
         AddAssign,
         RangeBounds,
     },
 };
 
 use futures::{
+    future,
     channel::{
         mpsc,
+        oneshot,
     },
+    io::{
+        AsyncRead,
+        AsyncWrite,
+    },
+    stream,
+    Sink,
+    SinkExt,
+    Stream,
+    StreamExt,
 };
 
+use tokio::time;
+
 use ero::{
     supervisor::SupervisorPid,
 };
 
-use alloc_pool::bytes::BytesPool;
+use alloc_pool::bytes::{
+    Bytes,
+    BytesPool,
+};
 
 use ero_blockwheel_fs as blockwheel;
 
@@ -25,8 +45,15 @@ pub mod kv;
 pub mod job;
 pub mod wheels;
 pub mod version;
+pub mod metrics;
+pub mod sstable;
+pub mod comparator;
+pub mod merge;
+#[cfg(feature = "blocking")]
+pub mod blocking;
 
 mod core;
+mod trace;
 mod storage;
 
 #[cfg(test)]
@@ -35,24 +62,509 @@ mod tests;
 #[derive(Clone, Debug)]
 pub struct Params {
     pub tree_block_size: usize,
+    pub target_block_bytes: Option<usize>,
     pub butcher_task_restart_sec: usize,
+    pub butcher_flush_threshold_bytes: Option<usize>,
+    pub butcher_max_bytes: Option<usize>,
+    pub butcher_flush_interval: Option<Duration>,
+    pub butcher_flush_coalesce_window: Option<Duration>,
+    pub butcher_wal: bool,
+    pub max_key_size: Option<usize>,
+    pub namespaces: bool,
+    pub bulk_load_chunk_bytes: usize,
     pub manager_task_restart_sec: usize,
+    pub manager_task_restart_backoff: Option<RestartBackoff>,
+    pub manager_skip_corrupt_trees: bool,
+    pub manager_max_concurrent_merges: usize,
+    pub manager_background_jobs_limit: Option<usize>,
+    pub manager_lookup_range_prefetch_depth: usize,
+    pub manager_max_pending_trees: Option<usize>,
+    pub manager_lookup_fanout_limit: Option<usize>,
+    pub manager_lookup_range_retries_limit: usize,
+    pub manager_request_queue_depth: usize,
+    pub manager_flush_queue_depth: usize,
+    pub manager_compaction_strategy: CompactionStrategy,
+    pub manager_merge_placement: PlacementPolicy,
+    pub manager_gc_orphans_on_start: bool,
+    pub manager_warm_range_bytes_limit: Option<usize>,
+    pub manager_no_reply_inserts_limit: usize,
     pub search_tree_task_restart_sec: usize,
+    pub search_tree_task_restart_backoff: Option<RestartBackoff>,
     pub search_tree_remove_tasks_limit: usize,
+    pub search_tree_demolish_rate_limit: Option<usize>,
     pub search_tree_iter_send_buffer: usize,
     pub search_tree_values_inline_size_limit: usize,
+    pub search_tree_verify_checksums: bool,
+    pub block_compression: Option<Compression>,
+    pub pooling: PoolingMode,
+    pub store_id: u32,
+    pub metrics: Option<metrics::MetricsRef>,
+    pub key_comparator: Option<comparator::KeyComparatorRef>,
 }
 
 impl Default for Params {
     fn default() -> Params {
         Params {
             tree_block_size: 32,
+            target_block_bytes: None,
             butcher_task_restart_sec: 1,
+            butcher_flush_threshold_bytes: None,
+            butcher_max_bytes: None,
+            butcher_flush_interval: None,
+            butcher_flush_coalesce_window: None,
+            butcher_wal: false,
+            max_key_size: None,
+            namespaces: false,
+            bulk_load_chunk_bytes: 8 * 1024 * 1024,
             manager_task_restart_sec: 1,
+            manager_task_restart_backoff: None,
+            manager_skip_corrupt_trees: false,
+            manager_max_concurrent_merges: 4,
+            manager_background_jobs_limit: None,
+            manager_lookup_range_prefetch_depth: 4,
+            manager_max_pending_trees: None,
+            manager_lookup_fanout_limit: None,
+            manager_lookup_range_retries_limit: 4,
+            manager_request_queue_depth: 32,
+            manager_flush_queue_depth: 1,
+            manager_compaction_strategy: CompactionStrategy::SizeTiered,
+            manager_merge_placement: PlacementPolicy::Random,
+            manager_gc_orphans_on_start: false,
+            manager_warm_range_bytes_limit: None,
+            manager_no_reply_inserts_limit: 64,
             search_tree_task_restart_sec: 1,
+            search_tree_task_restart_backoff: None,
             search_tree_remove_tasks_limit: 64,
+            search_tree_demolish_rate_limit: None,
             search_tree_iter_send_buffer: 4,
             search_tree_values_inline_size_limit: 128,
+            search_tree_verify_checksums: true,
+            block_compression: None,
+            pooling: PoolingMode::Full,
+            store_id: 0,
+            metrics: None,
+            key_comparator: None,
+        }
+    }
+}
+
+impl Params {
+    pub fn builder() -> ParamsBuilder {
+        ParamsBuilder {
+            params: Params::default(),
+        }
+    }
+}
+
+pub const MAX_MANAGER_QUEUE_DEPTH: usize = 4096;
+
+#[derive(Debug)]
+pub enum ParamsError {
+    ZeroTreeBlockSize,
+    ZeroTargetBlockBytes,
+    ZeroMaxKeySize,
+    ZeroBulkLoadChunkBytes,
+    ZeroButcherTaskRestartSec,
+    ZeroButcherMaxBytes,
+    ZeroManagerTaskRestartSec,
+    InvalidManagerTaskRestartBackoff {
+        restart_backoff: RestartBackoff,
+    },
+    ZeroManagerMaxConcurrentMerges,
+    ZeroManagerBackgroundJobsLimit,
+    ZeroManagerLookupRangePrefetchDepth,
+    ZeroManagerMaxPendingTrees,
+    ZeroManagerLookupFanoutLimit,
+    ZeroManagerNoReplyInsertsLimit,
+    TooLargeManagerRequestQueueDepth {
+        request_queue_depth: usize,
+        max: usize,
+    },
+    TooLargeManagerFlushQueueDepth {
+        flush_queue_depth: usize,
+        max: usize,
+    },
+    InvalidLevelMultiplier {
+        level_multiplier: usize,
+    },
+    ZeroBaseLevelBytes,
+    ZeroSearchTreeTaskRestartSec,
+    InvalidSearchTreeTaskRestartBackoff {
+        restart_backoff: RestartBackoff,
+    },
+    ZeroSearchTreeIterSendBuffer,
+    ZeroSearchTreeDemolishRateLimit,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParamsBuilder {
+    params: Params,
+}
+
+impl ParamsBuilder {
+    pub fn tree_block_size(mut self, tree_block_size: usize) -> ParamsBuilder {
+        self.params.tree_block_size = tree_block_size;
+        self
+    }
+
+    // when set, tree blocks are cut by serialized entry bytes instead of `tree_block_size` entries
+    pub fn target_block_bytes(mut self, target_block_bytes: Option<usize>) -> ParamsBuilder {
+        self.params.target_block_bytes = target_block_bytes;
+        self
+    }
+
+    pub fn butcher_task_restart_sec(mut self, butcher_task_restart_sec: usize) -> ParamsBuilder {
+        self.params.butcher_task_restart_sec = butcher_task_restart_sec;
+        self
+    }
+
+    pub fn butcher_flush_threshold_bytes(mut self, butcher_flush_threshold_bytes: Option<usize>) -> ParamsBuilder {
+        self.params.butcher_flush_threshold_bytes = butcher_flush_threshold_bytes;
+        self
+    }
+
+    // unlike `butcher_flush_threshold_bytes` this is checked before an entry is added,
+    // so the memtable is flushed first whenever the entry would not fit
+    pub fn butcher_max_bytes(mut self, butcher_max_bytes: Option<usize>) -> ParamsBuilder {
+        self.params.butcher_max_bytes = butcher_max_bytes;
+        self
+    }
+
+    pub fn butcher_flush_interval(mut self, butcher_flush_interval: Option<Duration>) -> ParamsBuilder {
+        self.params.butcher_flush_interval = butcher_flush_interval;
+        self
+    }
+
+    // a flush threshold crossed during a burst of writes defers the flush for a random delay between half of
+    // the window and the window, so the tail of the burst lands in the same tree; `butcher_max_bytes` reached,
+    // an explicit flush or an earlier `butcher_flush_interval` deadline still flush right away
+    pub fn butcher_flush_coalesce_window(mut self, butcher_flush_coalesce_window: Option<Duration>) -> ParamsBuilder {
+        self.params.butcher_flush_coalesce_window = butcher_flush_coalesce_window;
+        self
+    }
+
+    pub fn butcher_wal(mut self, butcher_wal: bool) -> ParamsBuilder {
+        self.params.butcher_wal = butcher_wal;
+        self
+    }
+
+    pub fn max_key_size(mut self, max_key_size: Option<usize>) -> ParamsBuilder {
+        self.params.max_key_size = max_key_size;
+        self
+    }
+
+    // enables the `_ns` calls: keys starting with `NAMESPACE_MARKER` are reserved for namespaces and refused
+    // for plain writes, and `max_key_size` of a namespaced key does not count its prefix
+    pub fn namespaces(mut self, namespaces: bool) -> ParamsBuilder {
+        self.params.namespaces = namespaces;
+        self
+    }
+
+    // `Pid::bulk_load` and `Pid::import` hold at most this many key and value bytes in memory,
+    // every chunk becomes a search tree of its own
+    pub fn bulk_load_chunk_bytes(mut self, bulk_load_chunk_bytes: usize) -> ParamsBuilder {
+        self.params.bulk_load_chunk_bytes = bulk_load_chunk_bytes;
+        self
+    }
+
+    pub fn manager_task_restart_sec(mut self, manager_task_restart_sec: usize) -> ParamsBuilder {
+        self.params.manager_task_restart_sec = manager_task_restart_sec;
+        self
+    }
+
+    pub fn manager_task_restart_backoff(mut self, manager_task_restart_backoff: Option<RestartBackoff>) -> ParamsBuilder {
+        self.params.manager_task_restart_backoff = manager_task_restart_backoff;
+        self
+    }
+
+    pub fn manager_skip_corrupt_trees(mut self, manager_skip_corrupt_trees: bool) -> ParamsBuilder {
+        self.params.manager_skip_corrupt_trees = manager_skip_corrupt_trees;
+        self
+    }
+
+    pub fn manager_max_concurrent_merges(mut self, manager_max_concurrent_merges: usize) -> ParamsBuilder {
+        self.params.manager_max_concurrent_merges = manager_max_concurrent_merges;
+        self
+    }
+
+    pub fn manager_background_jobs_limit(mut self, manager_background_jobs_limit: Option<usize>) -> ParamsBuilder {
+        self.params.manager_background_jobs_limit = manager_background_jobs_limit;
+        self
+    }
+
+    pub fn manager_lookup_range_prefetch_depth(mut self, manager_lookup_range_prefetch_depth: usize) -> ParamsBuilder {
+        self.params.manager_lookup_range_prefetch_depth = manager_lookup_range_prefetch_depth;
+        self
+    }
+
+    pub fn manager_max_pending_trees(mut self, manager_max_pending_trees: Option<usize>) -> ParamsBuilder {
+        self.params.manager_max_pending_trees = manager_max_pending_trees;
+        self
+    }
+
+    // at most this many tasks per lookup query search trees, each one going through several trees in turn
+    pub fn manager_lookup_fanout_limit(mut self, manager_lookup_fanout_limit: Option<usize>) -> ParamsBuilder {
+        self.params.manager_lookup_fanout_limit = manager_lookup_fanout_limit;
+        self
+    }
+
+    // range scans restarted more than this many times in a row due to concurrent merges are retried with merges paused
+    pub fn manager_lookup_range_retries_limit(mut self, manager_lookup_range_retries_limit: usize) -> ParamsBuilder {
+        self.params.manager_lookup_range_retries_limit = manager_lookup_range_retries_limit;
+        self
+    }
+
+    // requests buffered ahead of the manager busyloop, `0` makes every submitter rendezvous with it;
+    // only taken into account by `GenServer::with_params`
+    pub fn manager_request_queue_depth(mut self, manager_request_queue_depth: usize) -> ParamsBuilder {
+        self.params.manager_request_queue_depth = manager_request_queue_depth;
+        self
+    }
+
+    // butcher memtables buffered ahead of the manager busyloop, each one held in memory until taken;
+    // only taken into account by `GenServer::with_params`
+    pub fn manager_flush_queue_depth(mut self, manager_flush_queue_depth: usize) -> ParamsBuilder {
+        self.params.manager_flush_queue_depth = manager_flush_queue_depth;
+        self
+    }
+
+    pub fn manager_compaction_strategy(mut self, manager_compaction_strategy: CompactionStrategy) -> ParamsBuilder {
+        self.params.manager_compaction_strategy = manager_compaction_strategy;
+        self
+    }
+
+    pub fn manager_merge_placement(mut self, manager_merge_placement: PlacementPolicy) -> ParamsBuilder {
+        self.params.manager_merge_placement = manager_merge_placement;
+        self
+    }
+
+    pub fn manager_gc_orphans_on_start(mut self, manager_gc_orphans_on_start: bool) -> ParamsBuilder {
+        self.params.manager_gc_orphans_on_start = manager_gc_orphans_on_start;
+        self
+    }
+
+    // `Pid::warm_range` stops once this many bytes of tree blocks are read, usually the wheels
+    // `lru_cache_size_bytes`: anything read beyond it would evict what was just warmed
+    pub fn manager_warm_range_bytes_limit(mut self, manager_warm_range_bytes_limit: Option<usize>) -> ParamsBuilder {
+        self.params.manager_warm_range_bytes_limit = manager_warm_range_bytes_limit;
+        self
+    }
+
+    // `Pid::insert_sink` inserts being applied at once, the request queue is not read past it
+    pub fn manager_no_reply_inserts_limit(mut self, manager_no_reply_inserts_limit: usize) -> ParamsBuilder {
+        self.params.manager_no_reply_inserts_limit = manager_no_reply_inserts_limit;
+        self
+    }
+
+    pub fn search_tree_task_restart_sec(mut self, search_tree_task_restart_sec: usize) -> ParamsBuilder {
+        self.params.search_tree_task_restart_sec = search_tree_task_restart_sec;
+        self
+    }
+
+    pub fn search_tree_task_restart_backoff(mut self, search_tree_task_restart_backoff: Option<RestartBackoff>) -> ParamsBuilder {
+        self.params.search_tree_task_restart_backoff = search_tree_task_restart_backoff;
+        self
+    }
+
+    pub fn search_tree_remove_tasks_limit(mut self, search_tree_remove_tasks_limit: usize) -> ParamsBuilder {
+        self.params.search_tree_remove_tasks_limit = search_tree_remove_tasks_limit;
+        self
+    }
+
+    // at most this many blocks per second are freed while demolishing a merged tree, `None` frees them as fast as possible
+    pub fn search_tree_demolish_rate_limit(mut self, search_tree_demolish_rate_limit: Option<usize>) -> ParamsBuilder {
+        self.params.search_tree_demolish_rate_limit = search_tree_demolish_rate_limit;
+        self
+    }
+
+    pub fn search_tree_iter_send_buffer(mut self, search_tree_iter_send_buffer: usize) -> ParamsBuilder {
+        self.params.search_tree_iter_send_buffer = search_tree_iter_send_buffer;
+        self
+    }
+
+    // values of at most this many bytes are stored inline in tree blocks; `0` moves every value, even an empty one, out of line
+    pub fn search_tree_values_inline_size_limit(mut self, search_tree_values_inline_size_limit: usize) -> ParamsBuilder {
+        self.params.search_tree_values_inline_size_limit = search_tree_values_inline_size_limit;
+        self
+    }
+
+    // on-read checksum verification of search tree blocks; `Pid::verify` scrubs regardless of this setting
+    pub fn search_tree_verify_checksums(mut self, search_tree_verify_checksums: bool) -> ParamsBuilder {
+        self.params.search_tree_verify_checksums = search_tree_verify_checksums;
+        self
+    }
+
+    pub fn block_compression(mut self, block_compression: Option<Compression>) -> ParamsBuilder {
+        self.params.block_compression = block_compression;
+        self
+    }
+
+    pub fn pooling(mut self, pooling: PoolingMode) -> ParamsBuilder {
+        self.params.pooling = pooling;
+        self
+    }
+
+    // stores sharing wheels must have distinct ids: `load` adopts only blocks written with its own id
+    // and orphans collection leaves the rest alone; `0` reads stores written before ids were introduced
+    pub fn store_id(mut self, store_id: u32) -> ParamsBuilder {
+        self.params.store_id = store_id;
+        self
+    }
+
+    pub fn metrics(mut self, metrics: Option<metrics::MetricsRef>) -> ParamsBuilder {
+        self.params.metrics = metrics;
+        self
+    }
+
+    pub fn key_comparator(mut self, key_comparator: Option<comparator::KeyComparatorRef>) -> ParamsBuilder {
+        self.params.key_comparator = key_comparator;
+        self
+    }
+
+    pub fn build(self) -> Result<Params, ParamsError> {
+        let params = self.params;
+        if params.tree_block_size == 0 {
+            return Err(ParamsError::ZeroTreeBlockSize);
+        }
+        if params.target_block_bytes == Some(0) {
+            return Err(ParamsError::ZeroTargetBlockBytes);
+        }
+        if params.max_key_size == Some(0) {
+            return Err(ParamsError::ZeroMaxKeySize);
+        }
+        if params.bulk_load_chunk_bytes == 0 {
+            return Err(ParamsError::ZeroBulkLoadChunkBytes);
+        }
+        if params.butcher_task_restart_sec == 0 {
+            return Err(ParamsError::ZeroButcherTaskRestartSec);
+        }
+        if params.butcher_max_bytes == Some(0) {
+            return Err(ParamsError::ZeroButcherMaxBytes);
+        }
+        if params.manager_task_restart_sec == 0 {
+            return Err(ParamsError::ZeroManagerTaskRestartSec);
+        }
+        if let Some(restart_backoff) = params.manager_task_restart_backoff {
+            if !restart_backoff.is_valid() {
+                return Err(ParamsError::InvalidManagerTaskRestartBackoff { restart_backoff, });
+            }
+        }
+        if params.manager_max_concurrent_merges == 0 {
+            return Err(ParamsError::ZeroManagerMaxConcurrentMerges);
+        }
+        if params.manager_background_jobs_limit == Some(0) {
+            return Err(ParamsError::ZeroManagerBackgroundJobsLimit);
+        }
+        if params.manager_lookup_range_prefetch_depth == 0 {
+            return Err(ParamsError::ZeroManagerLookupRangePrefetchDepth);
+        }
+        if params.manager_max_pending_trees == Some(0) {
+            return Err(ParamsError::ZeroManagerMaxPendingTrees);
+        }
+        if params.manager_lookup_fanout_limit == Some(0) {
+            return Err(ParamsError::ZeroManagerLookupFanoutLimit);
+        }
+        if params.manager_no_reply_inserts_limit == 0 {
+            return Err(ParamsError::ZeroManagerNoReplyInsertsLimit);
+        }
+        if params.manager_request_queue_depth > MAX_MANAGER_QUEUE_DEPTH {
+            return Err(ParamsError::TooLargeManagerRequestQueueDepth {
+                request_queue_depth: params.manager_request_queue_depth,
+                max: MAX_MANAGER_QUEUE_DEPTH,
+            });
+        }
+        if params.manager_flush_queue_depth > MAX_MANAGER_QUEUE_DEPTH {
+            return Err(ParamsError::TooLargeManagerFlushQueueDepth {
+                flush_queue_depth: params.manager_flush_queue_depth,
+                max: MAX_MANAGER_QUEUE_DEPTH,
+            });
+        }
+        if let CompactionStrategy::Leveled { level_multiplier, base_level_bytes, } = params.manager_compaction_strategy {
+            if level_multiplier < 2 {
+                return Err(ParamsError::InvalidLevelMultiplier { level_multiplier, });
+            }
+            if base_level_bytes == 0 {
+                return Err(ParamsError::ZeroBaseLevelBytes);
+            }
+        }
+        if params.search_tree_task_restart_sec == 0 {
+            return Err(ParamsError::ZeroSearchTreeTaskRestartSec);
+        }
+        if let Some(restart_backoff) = params.search_tree_task_restart_backoff {
+            if !restart_backoff.is_valid() {
+                return Err(ParamsError::InvalidSearchTreeTaskRestartBackoff { restart_backoff, });
+            }
+        }
+        if params.search_tree_iter_send_buffer == 0 {
+            return Err(ParamsError::ZeroSearchTreeIterSendBuffer);
+        }
+        if params.search_tree_demolish_rate_limit == Some(0) {
+            return Err(ParamsError::ZeroSearchTreeDemolishRateLimit);
+        }
+        Ok(params)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+    Zstd { level: i32, },
+}
+
+// restart delay doubles from `base` up to `max` with up to `jitter` added on top;
+// a task which runs for longer than `max` before failing again starts over from `base`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RestartBackoff {
+    pub base: Duration,
+    pub max: Duration,
+    pub jitter: Duration,
+}
+
+impl RestartBackoff {
+    fn is_valid(&self) -> bool {
+        self.base > Duration::from_secs(0) && self.max >= self.base
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CompactionStrategy {
+    SizeTiered,
+    Leveled {
+        level_multiplier: usize,
+        base_level_bytes: usize,
+    },
+}
+
+// which wheel receives each block written by a merge; `KeyRangeHash` hashes the first key of the block
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum PlacementPolicy {
+    #[default]
+    Random,
+    RoundRobin,
+    LeastFull,
+    KeyRangeHash,
+}
+
+// `Minimal` is a fast path for tiny stores where the fixed overhead of the shared pool dominates: while the butcher
+// memtable holds less than `threshold` bytes its write ahead log records and the blocks of its flushed tree are
+// allocated directly and freed once written, larger memtables are served from the shared pool as with `Full`
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum PoolingMode {
+    #[default]
+    Full,
+    Minimal {
+        threshold: usize,
+    },
+}
+
+impl PoolingMode {
+    // the pool to lend from for a memtable of `memtable_bytes`: either the shared one or a private one dropped
+    // together with its buffers
+    pub fn pool_for(&self, memtable_bytes: usize, shared_pool: &BytesPool) -> BytesPool {
+        match *self {
+            PoolingMode::Minimal { threshold, } if memtable_bytes < threshold =>
+                BytesPool::new(),
+            PoolingMode::Full | PoolingMode::Minimal { .. } =>
+                shared_pool.clone(),
         }
     }
 }
@@ -60,29 +572,64 @@ impl Default for Params {
 pub struct GenServer {
     manager_gen_server: core::manager::GenServer,
     manager_pid: core::manager::Pid,
+    bulk_load_chunk_bytes: usize,
+    namespaces: bool,
+    namespaces_supported: bool,
 }
 
 #[derive(Clone)]
 pub struct Pid {
     manager_pid: core::manager::Pid,
+    keys_pool: BytesPool,
+    bulk_load_chunk_bytes: usize,
+    namespaces: bool,
+    namespaces_supported: bool,
 }
 
 impl GenServer {
     pub fn new() -> GenServer {
-        let manager_gen_server = core::manager::GenServer::new();
+        GenServer::with_params(&Params::default())
+    }
+
+    // request channels exist before `run`, so their capacities (and the bulk load chunk size used by pids)
+    // are taken from params here
+    pub fn with_params(params: &Params) -> GenServer {
+        let manager_gen_server = core::manager::GenServer::new(
+            params.manager_request_queue_depth,
+            params.manager_flush_queue_depth,
+        );
         let manager_pid = manager_gen_server.pid();
+        let namespaces_supported = params.key_comparator
+            .as_ref()
+            .map_or(true, |key_comparator| key_comparator.id() == comparator::BYTEWISE_ID);
         GenServer {
             manager_gen_server,
             manager_pid,
+            bulk_load_chunk_bytes: params.bulk_load_chunk_bytes,
+            namespaces: params.namespaces,
+            namespaces_supported,
         }
     }
 
     pub fn pid(&self) -> Pid {
         Pid {
             manager_pid: self.manager_pid.clone(),
+            keys_pool: BytesPool::new(),
+            bulk_load_chunk_bytes: self.bulk_load_chunk_bytes,
+            namespaces: self.namespaces,
+            namespaces_supported: self.namespaces_supported,
         }
     }
 
+    // dropping the returned future (e.g. a losing `select!` branch) is safe in the sense that
+    // the on-disk state stays consistent: a merge output root block is written only after all of
+    // its descendants, so a restart never links a partially written tree. A merge cancelled after
+    // its merge record is written is finished on load: the superseded inputs are skipped and left
+    // to the orphans collection. One cancelled between its root and its record is restored along
+    // with its inputs, which only duplicates cells until these trees are merged again. Blocks of an
+    // interrupted merge become orphans, use `manager_gc_orphans_on_start` or `Pid::gc_orphans` to
+    // free them. Nothing is flushed on cancellation: use `Pid::flush` before dropping to persist
+    // the butcher contents.
     pub async fn run<J>(
         self,
         mut parent_supervisor: SupervisorPid,
@@ -98,19 +645,56 @@ impl GenServer {
     {
         let butcher_gen_server = core::butcher::GenServer::new();
         let butcher_pid = butcher_gen_server.pid();
+        let key_comparator = params.key_comparator
+            .unwrap_or_else(comparator::bytewise);
         let butcher_params = core::butcher::Params {
-            tree_block_size: params.tree_block_size,
+            block_budget: core::block_sizer::BlockBudget::new(params.tree_block_size, params.target_block_bytes),
             task_restart_sec: params.butcher_task_restart_sec,
+            flush_threshold_bytes: params.butcher_flush_threshold_bytes,
+            max_bytes: params.butcher_max_bytes,
+            flush_interval: params.butcher_flush_interval,
+            flush_coalesce_window: params.butcher_flush_coalesce_window,
+            wal: params.butcher_wal,
+            max_key_size: params.max_key_size,
+            namespaces: params.namespaces,
+            pooling: params.pooling,
+            store_id: params.store_id,
+            key_comparator: key_comparator.clone(),
         };
 
+        let metrics = params.metrics
+            .unwrap_or_else(metrics::no_metrics);
         let manager_params = core::manager::Params {
             task_restart_sec: params.manager_task_restart_sec,
+            task_restart_backoff: params.manager_task_restart_backoff,
+            skip_corrupt_trees: params.manager_skip_corrupt_trees,
+            max_concurrent_merges: params.manager_max_concurrent_merges,
+            background_jobs_limit: params.manager_background_jobs_limit,
+            lookup_range_prefetch_depth: params.manager_lookup_range_prefetch_depth,
+            max_pending_trees: params.manager_max_pending_trees,
+            lookup_fanout_limit: params.manager_lookup_fanout_limit,
+            lookup_range_retries_limit: params.manager_lookup_range_retries_limit,
+            compaction_strategy: params.manager_compaction_strategy,
+            merge_placement: params.manager_merge_placement,
+            gc_orphans_on_start: params.manager_gc_orphans_on_start,
+            warm_range_bytes_limit: params.manager_warm_range_bytes_limit,
+            no_reply_inserts_limit: params.manager_no_reply_inserts_limit,
+            metrics: metrics.clone(),
             search_tree_params: core::search_tree::Params {
                 task_restart_sec: params.search_tree_task_restart_sec,
+                task_restart_backoff: params.search_tree_task_restart_backoff,
                 tree_block_size: params.tree_block_size,
+                target_block_bytes: params.target_block_bytes,
                 remove_tasks_limit: params.search_tree_remove_tasks_limit,
+                demolish_rate_limit: params.search_tree_demolish_rate_limit,
                 iter_send_buffer: params.search_tree_iter_send_buffer,
                 values_inline_size_limit: params.search_tree_values_inline_size_limit,
+                verify_checksums: params.search_tree_verify_checksums,
+                block_compression: params.block_compression,
+                pooling: params.pooling,
+                store_id: params.store_id,
+                metrics,
+                key_comparator,
             },
         };
 
@@ -119,10 +703,14 @@ impl GenServer {
         parent_supervisor.spawn_link_permanent(
             child_supervisor_gen_server.run(),
         );
+        let version_watermark = version::Watermark::default();
         parent_supervisor.spawn_link_permanent(
             butcher_gen_server.run(
                 version_provider.clone(),
+                version_watermark.clone(),
                 self.manager_pid.clone(),
+                wheels_pid.clone(),
+                blocks_pool.clone(),
                 butcher_params,
             ),
         );
@@ -131,6 +719,8 @@ impl GenServer {
             child_supervisor_pid.clone(),
             thread_pool,
             blocks_pool,
+            version_provider,
+            version_watermark,
             butcher_pid,
             wheels_pid,
             manager_params,
@@ -142,21 +732,80 @@ impl GenServer {
 #[derive(Debug)]
 pub enum InsertError {
     GenServer(ero::NoProcError),
+    KeyTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    // a plain write of a key starting with `NAMESPACE_MARKER` while `namespaces` are enabled
+    KeyReserved,
+    // `version::Provider` returned a version not above the last one assigned
+    VersionRegression {
+        version: u64,
+        last_version: u64,
+    },
+}
+
+#[derive(Debug)]
+pub enum InsertVersionedError {
+    GenServer(ero::NoProcError),
+    VersionReserved {
+        version: u64,
+    },
+    KeyTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    KeyReserved,
+    // the write was rejected by the butcher watermark check (external versions themselves are taken as is)
+    VersionRegression {
+        version: u64,
+        last_version: u64,
+    },
 }
 
 #[derive(Debug)]
 pub enum LookupError {
     GenServer(ero::NoProcError),
+    TimedOut,
 }
 
 #[derive(Debug)]
 pub enum LookupRangeError {
     GenServer(ero::NoProcError),
+    ZeroBuffer,
+}
+
+#[derive(Debug)]
+pub enum LookupRangePagedError {
+    LookupRange(LookupRangeError),
+    ZeroLimit,
+    Stream(StreamError),
+}
+
+#[derive(Debug)]
+pub enum NamespaceError {
+    // `Params::namespaces` is not set
+    Disabled,
+    // a custom `key_comparator` may interleave keys of different namespaces
+    NotBytewiseComparator,
+    Insert(InsertError),
+    Lookup(LookupError),
+    LookupRange(LookupRangeError),
+    Remove(RemoveError),
 }
 
 #[derive(Debug)]
 pub enum RemoveError {
     GenServer(ero::NoProcError),
+    KeyTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    KeyReserved,
+    VersionRegression {
+        version: u64,
+        last_version: u64,
+    },
 }
 
 #[derive(Debug)]
@@ -164,70 +813,1174 @@ pub enum FlushError {
     GenServer(ero::NoProcError),
 }
 
+#[derive(Debug)]
+pub enum GcOrphansError {
+    GenServer(ero::NoProcError),
+}
+
+#[derive(Debug)]
+pub enum ChangedBlocksError {
+    GenServer(ero::NoProcError),
+}
+
+#[derive(Debug)]
+pub enum IterRawBlocksError {
+    GenServer(ero::NoProcError),
+}
+
+#[derive(Debug)]
+pub enum VerifyError {
+    GenServer(ero::NoProcError),
+}
+
+#[derive(Debug)]
+pub enum SplitOffError {
+    GenServer(ero::NoProcError),
+    Stream(StreamError),
+    VersionRegression {
+        version: u64,
+        last_version: u64,
+    },
+}
+
+#[derive(Debug)]
+pub enum BulkLoadError {
+    GenServer(ero::NoProcError),
+    KeyReserved {
+        key: kv::Key,
+    },
+    KeysNotAscending {
+        prev_key: kv::Key,
+        key: kv::Key,
+    },
+    VersionRegression {
+        version: u64,
+        last_version: u64,
+    },
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    LookupRange(LookupRangeError),
+    RangeInterrupted,
+    Stream(StreamError),
+    Sstable(sstable::Error),
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Sstable(sstable::Error),
+    BulkLoad(BulkLoadError),
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct Inserted {
     pub version: u64,
 }
 
+// whether a value is stored inline in a tree block or in a separate value block;
+// `Auto` decides by `search_tree_values_inline_size_limit`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub enum StoragePreference {
+    Inline,
+    OutOfLine,
+    #[default]
+    Auto,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct Removed {
     pub version: u64,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
-pub struct Flushed;
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+pub struct Flushed {
+    pub cache_entries_flushed: usize,
+    pub new_search_trees: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+pub struct OrphansCollected {
+    pub blocks_total: usize,
+    pub blocks_deleted: usize,
+}
+
+// opaque identity of a search tree, valid while the tree is alive
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct SearchTreeId(o1::set::Ref);
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct CompactionTimings {
+    pub count: Duration,
+    pub merge: Duration,
+    pub merge_next: Duration,
+    pub block_prepare: Duration,
+    pub block_write: Duration,
+    pub total: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct CompactionEvent {
+    pub items_count: usize,
+    pub timings: CompactionTimings,
+    pub input_refs: [SearchTreeId; 2],
+    // `None` when nothing survived the merge
+    pub output_ref: Option<SearchTreeId>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Consistency {
+    // butcher memtable is folded in, so every acknowledged insert is seen
+    Strong,
+    // only search trees are read: writes still sitting in the butcher are missed, so results may be
+    // as stale as the time since the latest flush (bounded by the flush interval or the butcher size limit)
+    Trees,
+}
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
 pub struct Info {
     pub alive_cells_count: usize,
     pub tombstones_count: usize,
+    // max depth and total blocks of the search trees visited for the range requested
+    pub depth: usize,
+    pub block_count: usize,
+    // butcher memtable not flushed yet; its values are held in memory whatever their size,
+    // they only turn into block refs once flushed
+    pub butcher_entries: usize,
+    pub butcher_bytes: usize,
+    // sizes of the node blocks visited and of the value blocks they refer to
+    pub disk_bytes_total: usize,
+    // range scans started and not yet finished or canceled, retries of the same scan included once
+    pub active_range_scans: usize,
+}
+
+// `next_cursor` is the last key of a full page followed by more items, it is passed as `start_after`
+// for the next page; `None` means the range is exhausted
+#[derive(Debug)]
+pub struct Page {
+    pub items: Vec<kv::KeyValuePair<kv::Value>>,
+    pub next_cursor: Option<kv::Key>,
 }
 
+// a scan is torn down promptly only on an explicit `cancel`, a dropped `cancel_tx` leaves it
+// running until `key_values_rx` is dropped as well
 pub struct LookupRange {
     pub key_values_rx: mpsc::Receiver<KeyValueStreamItem>,
+    pub cancel_tx: oneshot::Sender<()>,
+}
+
+impl LookupRange {
+    pub fn cancel(self) {
+        self.cancel_tx.send(()).ok();
+    }
+
+    // a failed or interrupted scan yields one error and then ends
+    pub fn into_stream(self) -> impl Stream<Item = Result<kv::KeyValuePair<kv::Value>, StreamError>> {
+        stream::unfold(Some(self), |maybe_lookup_range| async move {
+            let mut lookup_range = maybe_lookup_range?;
+            match lookup_range.key_values_rx.next().await {
+                None =>
+                    Some((Err(StreamError::Interrupted), None)),
+                Some(KeyValueStreamItem::KeyValue(key_value_pair)) =>
+                    Some((Ok(key_value_pair), Some(lookup_range))),
+                Some(KeyValueStreamItem::Error(error)) =>
+                    Some((Err(error), None)),
+                Some(KeyValueStreamItem::NoMore) =>
+                    None,
+            }
+        })
+    }
+}
+
+pub type ValueLocation = ValueRef;
+
+// inclusive on both ends
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VersionWindow {
+    pub lo: u64,
+    pub hi: u64,
+}
+
+impl VersionWindow {
+    pub fn contains(&self, version: u64) -> bool {
+        self.lo <= version && version <= self.hi
+    }
+}
+
+pub type KeyPredicateRef = Arc<dyn Fn(&kv::Key) -> bool + Send + Sync>;
+
+#[derive(Clone)]
+pub enum KeyFilter {
+    Prefix(Vec<u8>),
+    Suffix(Vec<u8>),
+    // both bounds are inclusive
+    LengthRange { min: usize, max: usize, },
+    Predicate(KeyPredicateRef),
+}
+
+impl KeyFilter {
+    pub fn matches(&self, key: &kv::Key) -> bool {
+        match self {
+            KeyFilter::Prefix(prefix) =>
+                key.key_bytes.starts_with(prefix),
+            KeyFilter::Suffix(suffix) =>
+                key.key_bytes.ends_with(suffix),
+            KeyFilter::LengthRange { min, max, } =>
+                (*min ..= *max).contains(&key.key_bytes.len()),
+            KeyFilter::Predicate(predicate) =>
+                predicate(key),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub enum KeyValueStreamItem {
     KeyValue(kv::KeyValuePair<kv::Value>),
+    // the scan is truncated: no more items follow
+    Error(StreamError),
+    NoMore,
+}
+
+#[derive(Clone, Debug)]
+pub enum StreamError {
+    // stream channel closed without `NoMore`
+    Interrupted,
+    BackendIterPeerLost,
+    WheelsGone,
+    WheelNotFound {
+        blockwheel_filename: wheels::WheelFilename,
+    },
+    ReadValueBlock,
+    ValueDeserialize,
+    CompositeKeyDecode(kv::composite::DecodeError),
+}
+
+pub struct RawBlocks {
+    pub blocks_rx: mpsc::Receiver<RawBlocksItem>,
+}
+
+pub enum RawBlocksItem {
+    Block {
+        block_ref: wheels::BlockRef,
+        block_bytes: Bytes,
+        header: Result<RawBlockHeader, RawBlockError>,
+    },
+    Error(StreamError),
+    NoMoreBlocks,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RawBlockHeader {
+    Tree {
+        node_type: RawNodeType,
+        entries_count: usize,
+        format_version: u16,
+    },
+    Value,
+    // wal records, range tombstones and blocks of unknown formats
+    Other,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RawNodeType {
+    Root {
+        tree_entries_count: usize,
+        max_version: u64,
+        created_at_version: u64,
+        comparator_id: u32,
+    },
+    Leaf,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RawBlockError {
+    Decompress,
+    ChecksumMismatch,
+    UnsupportedFormatVersion {
+        found: u16,
+        supported: u16,
+    },
+    HeaderDeserialize,
+    EntryDeserialize {
+        entry_index: usize,
+    },
+    KeysNotAscending {
+        entry_index: usize,
+    },
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CorruptBlock {
+    pub block_ref: wheels::BlockRef,
+    pub error: RawBlockError,
+}
+
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct VerifyReport {
+    pub blocks_total: usize,
+    pub corrupt_blocks: Vec<CorruptBlock>,
+    // roots of trees reaching a corrupt or missing block
+    pub corrupt_trees: Vec<wheels::BlockRef>,
+    // referenced by tree blocks but absent from wheels
+    pub missing_blocks: Vec<wheels::BlockRef>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Warmed {
+    pub blocks_count: usize,
+    pub bytes: usize,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Health {
+    pub butcher_reachable: bool,
+    pub search_trees_count: usize,
+    // one search tree is probed with a lookup, `None` when there are no trees yet
+    pub search_tree_reachable: Option<bool>,
+    // latest non fatal failure seen by the manager: a failed probe, a corrupt block skipped on load
+    // or a quarantined search tree
+    pub last_error: Option<String>,
+    // trees stopped on a fatal error and left out of lookups until `Pid::verify` finds them intact
+    pub quarantined_search_trees: usize,
+}
+
+// power of two buckets: bucket `i` counts sizes in `[2^(i - 1), 2^i)`, bucket 0 counts empty ones
+// and the last bucket everything which does not fit below it
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct SizeHistogram {
+    pub buckets: [usize; 32],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Histograms {
+    pub key_sizes: SizeHistogram,
+    // butcher values and values stored inline in search trees
+    pub value_sizes: SizeHistogram,
+    // values in their own blocks are not read, so only their count is known
+    pub out_of_line_values: usize,
+    pub tombstones: usize,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DetailedInfo {
+    pub current_version: u64,
+    // the oldest tree first
+    pub search_trees: Vec<SearchTreeDetails>,
+    pub quarantined_search_trees: Vec<QuarantinedSearchTree>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct QuarantinedSearchTree {
+    // `None` for a tree failed before its bootstrap was done, it is never lifted
+    pub root_block: Option<wheels::BlockRef>,
+    pub created_at_version: u64,
+    pub error: String,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SearchTreeDetails {
+    pub id: SearchTreeId,
+    // store version when the tree was flushed, merged or bulk loaded: 0 for trees written before it was recorded
+    pub created_at_version: u64,
+    // versions handed out since then; with `version::Provider::from_unix_epoch_seed` every restart also adds
+    // the seconds elapsed shifted left by 24 bits
+    pub age: u64,
+    // `None` when the versions of the tree are unknown
+    pub max_version: Option<u64>,
+    // node and value blocks of the whole tree, 0 for a tree demolished while it was measured
+    pub disk_bytes: usize,
+}
+
+// value location as stored in search trees: out of line values are not read from wheels
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ValueRef {
+    Inline(kv::Value),
+    Block(wheels::BlockRef),
+}
+
+pub struct LookupRangeRefs {
+    pub key_values_rx: mpsc::Receiver<KeyValueRefStreamItem>,
+    pub cancel_tx: oneshot::Sender<()>,
+}
+
+impl LookupRangeRefs {
+    pub fn cancel(self) {
+        self.cancel_tx.send(()).ok();
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = Result<kv::KeyValuePair<ValueRef>, StreamError>> {
+        stream::unfold(Some(self), |maybe_lookup_range_refs| async move {
+            let mut lookup_range_refs = maybe_lookup_range_refs?;
+            match lookup_range_refs.key_values_rx.next().await {
+                None =>
+                    Some((Err(StreamError::Interrupted), None)),
+                Some(KeyValueRefStreamItem::KeyValue(key_value_pair)) =>
+                    Some((Ok(key_value_pair), Some(lookup_range_refs))),
+                Some(KeyValueRefStreamItem::Error(error)) =>
+                    Some((Err(error), None)),
+                Some(KeyValueRefStreamItem::NoMore) =>
+                    None,
+            }
+        })
+    }
+}
+
+#[derive(Clone)]
+pub enum KeyValueRefStreamItem {
+    KeyValue(kv::KeyValuePair<ValueRef>),
+    Error(StreamError),
+    NoMore,
+}
+
+pub struct LookupRangeKeys {
+    pub keys_rx: mpsc::Receiver<KeyStreamItem>,
+    pub cancel_tx: oneshot::Sender<()>,
+}
+
+impl LookupRangeKeys {
+    pub fn cancel(self) {
+        self.cancel_tx.send(()).ok();
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = Result<kv::Key, StreamError>> {
+        stream::unfold(Some(self), |maybe_lookup_range_keys| async move {
+            let mut lookup_range_keys = maybe_lookup_range_keys?;
+            match lookup_range_keys.keys_rx.next().await {
+                None =>
+                    Some((Err(StreamError::Interrupted), None)),
+                Some(KeyStreamItem::Key(key)) =>
+                    Some((Ok(key), Some(lookup_range_keys))),
+                Some(KeyStreamItem::Error(error)) =>
+                    Some((Err(error), None)),
+                Some(KeyStreamItem::NoMore) =>
+                    None,
+            }
+        })
+    }
+}
+
+#[derive(Clone)]
+pub enum KeyStreamItem {
+    Key(kv::Key),
+    Error(StreamError),
     NoMore,
 }
 
 impl Pid {
+    pub fn set_request_retries_limit(&mut self, request_retries_limit: usize) {
+        self.manager_pid.set_request_retries_limit(request_retries_limit);
+    }
+
     pub async fn info(&mut self) -> Result<Info, ero::NoProcError> {
         self.manager_pid.info().await
     }
 
+    pub async fn info_range<R>(&mut self, range: R) -> Result<Info, ero::NoProcError> where R: RangeBounds<kv::Key> {
+        self.manager_pid.info_range(range).await
+    }
+
     pub async fn insert(&mut self, key: kv::Key, value: kv::Value) -> Result<Inserted, InsertError> {
+        if self.key_reserved(&key) {
+            return Err(InsertError::KeyReserved);
+        }
+        self.insert_unchecked(key, value).await
+    }
+
+    async fn insert_unchecked(&mut self, key: kv::Key, value: kv::Value) -> Result<Inserted, InsertError> {
         self.manager_pid.insert(key, value).await
-            .map_err(|core::manager::InsertError::GenServer(ero::NoProcError)| InsertError::GenServer(ero::NoProcError))
+            .map_err(|error| match error {
+                core::manager::InsertError::GenServer(ero::NoProcError) =>
+                    InsertError::GenServer(ero::NoProcError),
+                core::manager::InsertError::KeyTooLarge { size, limit, } =>
+                    InsertError::KeyTooLarge { size, limit, },
+                core::manager::InsertError::VersionRegression { version, last_version, } =>
+                    InsertError::VersionRegression { version, last_version, },
+            })
+    }
+
+    // fire-and-forget inserts with the manager request queue as backpressure: the queue is not read while
+    // `manager_no_reply_inserts_limit` of them are being applied; rejected inserts (e.g. a key over
+    // `max_key_size`) are only logged. Each insert is applied by its own task, so the same key sent twice
+    // in a row may end up with either value: use `insert` for those. `Pid::flush` issued after the sink
+    // is flushed covers everything sent through it.
+    pub fn insert_sink(&mut self) -> impl Sink<(kv::Key, kv::Value), Error = InsertError> {
+        let namespaces = self.namespaces;
+        self.manager_pid.insert_sink()
+            .sink_map_err(|ero::NoProcError| InsertError::GenServer(ero::NoProcError))
+            .with(move |(key, value): (kv::Key, kv::Value)| future::ready(if key_reserved(namespaces, &key) {
+                Err(InsertError::KeyReserved)
+            } else {
+                Ok((key, value))
+            }))
+    }
+
+    pub async fn insert_with_storage_preference(
+        &mut self,
+        key: kv::Key,
+        value: kv::Value,
+        storage_preference: StoragePreference,
+    )
+        -> Result<Inserted, InsertError>
+    {
+        if self.key_reserved(&key) {
+            return Err(InsertError::KeyReserved);
+        }
+        self.manager_pid.insert_with_storage_preference(key, value, storage_preference).await
+            .map_err(|error| match error {
+                core::manager::InsertError::GenServer(ero::NoProcError) =>
+                    InsertError::GenServer(ero::NoProcError),
+                core::manager::InsertError::KeyTooLarge { size, limit, } =>
+                    InsertError::KeyTooLarge { size, limit, },
+                core::manager::InsertError::VersionRegression { version, last_version, } =>
+                    InsertError::VersionRegression { version, last_version, },
+            })
+    }
+
+    // external versions must stay below `version::EXTERNAL_VERSIONS_LIMIT`
+    // in order not to collide with the ones assigned by `version::Provider`
+    pub async fn insert_versioned(&mut self, key: kv::Key, value: kv::Value, version: u64) -> Result<Inserted, InsertVersionedError> {
+        if version >= version::EXTERNAL_VERSIONS_LIMIT {
+            return Err(InsertVersionedError::VersionReserved { version, });
+        }
+        if self.key_reserved(&key) {
+            return Err(InsertVersionedError::KeyReserved);
+        }
+        self.manager_pid.insert_versioned(key, value, version).await
+            .map_err(|error| match error {
+                core::manager::InsertError::GenServer(ero::NoProcError) =>
+                    InsertVersionedError::GenServer(ero::NoProcError),
+                core::manager::InsertError::KeyTooLarge { size, limit, } =>
+                    InsertVersionedError::KeyTooLarge { size, limit, },
+                core::manager::InsertError::VersionRegression { version, last_version, } =>
+                    InsertVersionedError::VersionRegression { version, last_version, },
+            })
     }
 
+    // a removed key yields its `Tombstone` cell carrying the removal version and meta, while `None` means it was never written
+    // (or its tombstone has already been dropped by compaction)
     pub async fn lookup(&mut self, key: kv::Key) -> Result<Option<kv::ValueCell<kv::Value>>, LookupError> {
-        self.manager_pid.lookup(key).await
+        self.lookup_with_consistency(key, Consistency::Strong).await
+    }
+
+    pub async fn lookup_with_consistency(
+        &mut self,
+        key: kv::Key,
+        consistency: Consistency,
+    )
+        -> Result<Option<kv::ValueCell<kv::Value>>, LookupError>
+    {
+        self.manager_pid.lookup_with_consistency(key, consistency).await
             .map_err(|core::manager::LookupError::GenServer(ero::NoProcError)| LookupError::GenServer(ero::NoProcError))
     }
 
+    // same as `lookup` with removed keys reported as absent
+    pub async fn lookup_value(&mut self, key: kv::Key) -> Result<Option<kv::Value>, LookupError> {
+        let maybe_value_cell = self.lookup(key).await?;
+        Ok(maybe_value_cell.and_then(|kv::ValueCell { cell, .. }| match cell {
+            kv::Cell::Value(value) =>
+                Some(value),
+            kv::Cell::Tombstone { .. } =>
+                None,
+        }))
+    }
+
+    // the value block is not read, so a `Block` location may already be deprecated by a concurrent merge
+    pub async fn lookup_located(&mut self, key: kv::Key) -> Result<Option<kv::ValueCell<ValueLocation>>, LookupError> {
+        self.manager_pid.lookup_located(key).await
+            .map_err(|core::manager::LookupError::GenServer(ero::NoProcError)| LookupError::GenServer(ero::NoProcError))
+    }
+
+    // only the caller is released on deadline: the request already sent keeps running in the manager
+    pub async fn lookup_timeout(&mut self, key: kv::Key, timeout: Duration) -> Result<Option<kv::ValueCell<kv::Value>>, LookupError> {
+        time::timeout(timeout, self.lookup(key)).await
+            .map_err(|_elapsed| LookupError::TimedOut)?
+    }
+
     pub async fn lookup_range<R>(&mut self, range: R) -> Result<LookupRange, LookupRangeError> where R: RangeBounds<kv::Key> {
-        self.manager_pid.lookup_range(range).await
+        self.lookup_range_buffered(range, None).await
+    }
+
+    // every page is a separate scan over `(start_after, end)`, so keys written or flushed between pages are
+    // seen by later pages if they sort after the cursor, and no key is returned twice
+    pub async fn lookup_range_paged(
+        &mut self,
+        start_after: kv::Key,
+        end: Bound<kv::Key>,
+        limit: usize,
+    )
+        -> Result<Page, LookupRangePagedError>
+    {
+        if limit == 0 {
+            return Err(LookupRangePagedError::ZeroLimit);
+        }
+        let mut lookup_range = self.lookup_range((Bound::Excluded(start_after), end)).await
+            .map_err(LookupRangePagedError::LookupRange)?;
+        let mut items = Vec::with_capacity(limit);
+        loop {
+            match lookup_range.key_values_rx.next().await {
+                None =>
+                    return Err(LookupRangePagedError::Stream(StreamError::Interrupted)),
+                Some(KeyValueStreamItem::KeyValue(..)) if items.len() >= limit => {
+                    // one more item is peeked so that a page ending exactly at the range end has no cursor
+                    lookup_range.cancel();
+                    let next_cursor = items.last().map(|key_value_pair| key_value_pair.key.clone());
+                    return Ok(Page { items, next_cursor, });
+                },
+                Some(KeyValueStreamItem::KeyValue(key_value_pair)) =>
+                    items.push(key_value_pair),
+                Some(KeyValueStreamItem::Error(error)) =>
+                    return Err(LookupRangePagedError::Stream(error)),
+                Some(KeyValueStreamItem::NoMore) =>
+                    return Ok(Page { items, next_cursor: None, }),
+            }
+        }
+    }
+
+    pub async fn lookup_range_buffered<R>(
+        &mut self,
+        range: R,
+        buffer: Option<usize>,
+    )
+        -> Result<LookupRange, LookupRangeError>
+    where R: RangeBounds<kv::Key>
+    {
+        if buffer == Some(0) {
+            return Err(LookupRangeError::ZeroBuffer);
+        }
+        self.manager_pid.lookup_range(range, buffer, None, None).await
             .map_err(|core::manager::LookupRangeError::GenServer(ero::NoProcError)| LookupRangeError::GenServer(ero::NoProcError))
     }
 
+    // keys rejected by `key_filter` are dropped inside the merge and never reach the stream
+    pub async fn lookup_range_filtered<R>(&mut self, range: R, key_filter: KeyFilter) -> Result<LookupRange, LookupRangeError> where R: RangeBounds<kv::Key> {
+        self.manager_pid.lookup_range(range, None, Some(key_filter), None).await
+            .map_err(|core::manager::LookupRangeError::GenServer(ero::NoProcError)| LookupRangeError::GenServer(ero::NoProcError))
+    }
+
+    // only the latest cell of each key is considered, and it is yielded if its version is within the window;
+    // search trees with every version below `version_lo` are not queried at all
+    pub async fn lookup_range_versions<R>(
+        &mut self,
+        range: R,
+        version_lo: u64,
+        version_hi: u64,
+    )
+        -> Result<LookupRange, LookupRangeError>
+    where R: RangeBounds<kv::Key>
+    {
+        let version_window = VersionWindow { lo: version_lo, hi: version_hi, };
+        self.manager_pid.lookup_range(range, None, None, Some(version_window)).await
+            .map_err(|core::manager::LookupRangeError::GenServer(ero::NoProcError)| LookupRangeError::GenServer(ero::NoProcError))
+    }
+
+    // block refs are only valid until the next merge or gc_orphans run reclaims them
+    pub async fn lookup_range_refs<R>(&mut self, range: R) -> Result<LookupRangeRefs, LookupRangeError> where R: RangeBounds<kv::Key> {
+        self.manager_pid.lookup_range_refs(range, None).await
+            .map_err(|core::manager::LookupRangeError::GenServer(ero::NoProcError)| LookupRangeError::GenServer(ero::NoProcError))
+    }
+
+    // tombstoned keys are skipped
+    pub async fn lookup_range_keys_only<R>(&mut self, range: R) -> Result<LookupRangeKeys, LookupRangeError> where R: RangeBounds<kv::Key> {
+        self.manager_pid.lookup_range_keys_only(range, None).await
+            .map_err(|core::manager::LookupRangeError::GenServer(ero::NoProcError)| LookupRangeError::GenServer(ero::NoProcError))
+    }
+
+    pub async fn iter_all(
+        &mut self,
+    )
+        -> Result<impl Stream<Item = Result<kv::KeyValuePair<kv::Value>, StreamError>>, LookupRangeError>
+    {
+        let lookup_range = self.lookup_range(..).await?;
+        Ok(lookup_range.into_stream())
+    }
+
+    // prefix upper bound is derived bytewise, so results are only meaningful with `comparator::bytewise` ordering
+    pub async fn scan_prefix(&mut self, prefix: Vec<u8>) -> Result<LookupRange, LookupRangeError> {
+        let range_to = match prefix.iter().rposition(|&byte| byte != 0xff) {
+            None =>
+                Bound::Unbounded,
+            Some(position) => {
+                let mut upper_bytes = self.keys_pool.lend();
+                upper_bytes.extend_from_slice(&prefix[..= position]);
+                upper_bytes[position] += 1;
+                Bound::Excluded(kv::Key::from(upper_bytes))
+            },
+        };
+        let mut lower_bytes = self.keys_pool.lend();
+        lower_bytes.extend_from_slice(&prefix);
+        self.lookup_range((Bound::Included(kv::Key::from(lower_bytes)), range_to)).await
+    }
+
+    // keys are decoded with `kv::composite` and stripped of the prefix segments; a key under the prefix
+    // which is not a composite one yields an error item without ending the stream
+    pub async fn scan_composite_prefix(
+        &mut self,
+        prefix_segments: &[&[u8]],
+    )
+        -> Result<impl Stream<Item = Result<(Vec<Vec<u8>>, kv::ValueCell<kv::Value>), StreamError>>, LookupRangeError>
+    {
+        let mut prefix = Vec::new();
+        for segment in prefix_segments {
+            kv::composite::encode_segment(segment, &mut prefix);
+        }
+        let prefix_segments_count = prefix_segments.len();
+        let lookup_range = self.scan_prefix(prefix).await?;
+        Ok(lookup_range.into_stream().map(move |maybe_key_value| {
+            let kv::KeyValuePair { key, value_cell, } = maybe_key_value?;
+            let mut segments = kv::composite::decode(&key.key_bytes)
+                .map_err(StreamError::CompositeKeyDecode)?;
+            segments.drain(.. prefix_segments_count);
+            Ok((segments, value_cell))
+        }))
+    }
+
+    // namespaces share the butcher, search trees, flushes and merges: a namespace is only a fixed
+    // `NAMESPACE_PREFIX_LEN` bytes prefix of the stored key, so namespaced calls are refused unless
+    // `Params::namespaces` is set and keys are ordered by `comparator::bytewise`; range removals, split
+    // offs and scans of plain ranges are not checked and may cover namespaces as well
+    pub async fn insert_ns(&mut self, ns: u64, key: kv::Key, value: kv::Value) -> Result<Inserted, NamespaceError> {
+        let key = self.namespace_key(ns, &key)?;
+        self.insert_unchecked(key, value).await
+            .map_err(NamespaceError::Insert)
+    }
+
+    pub async fn lookup_ns(&mut self, ns: u64, key: kv::Key) -> Result<Option<kv::ValueCell<kv::Value>>, NamespaceError> {
+        let key = self.namespace_key(ns, &key)?;
+        self.lookup(key).await
+            .map_err(NamespaceError::Lookup)
+    }
+
+    pub async fn remove_ns(&mut self, ns: u64, key: kv::Key) -> Result<Removed, NamespaceError> {
+        let key = self.namespace_key(ns, &key)?;
+        self.remove_unchecked(key, None).await
+            .map_err(NamespaceError::Remove)
+    }
+
+    // yielded keys are stripped of the namespace prefix
+    pub async fn iter_ns(
+        &mut self,
+        ns: u64,
+    )
+        -> Result<impl Stream<Item = Result<kv::KeyValuePair<kv::Value>, StreamError>>, NamespaceError>
+    {
+        let range = self.namespace_range(ns)?;
+        let lookup_range = self.lookup_range(range).await
+            .map_err(NamespaceError::LookupRange)?;
+        let keys_pool = self.keys_pool.clone();
+        Ok(lookup_range.into_stream().map(move |maybe_key_value| {
+            maybe_key_value.map(|kv::KeyValuePair { key, value_cell, }| {
+                let mut key_bytes = keys_pool.lend();
+                key_bytes.extend_from_slice(&key.key_bytes[NAMESPACE_PREFIX_LEN ..]);
+                kv::KeyValuePair { key: kv::Key::from(key_bytes), value_cell, }
+            })
+        }))
+    }
+
+    // drops the whole namespace with a single range tombstone
+    pub async fn remove_namespace(&mut self, ns: u64) -> Result<Removed, NamespaceError> {
+        let range = self.namespace_range(ns)?;
+        self.remove_range(range).await
+            .map_err(NamespaceError::Remove)
+    }
+
+    fn namespace_key(&self, ns: u64, key: &kv::Key) -> Result<kv::Key, NamespaceError> {
+        self.namespaces_check()?;
+        Ok(namespace_key(ns, key, &self.keys_pool))
+    }
+
+    fn namespace_range(&self, ns: u64) -> Result<(Bound<kv::Key>, Bound<kv::Key>), NamespaceError> {
+        self.namespaces_check()?;
+        Ok(namespace_range(ns, &self.keys_pool))
+    }
+
+    fn namespaces_check(&self) -> Result<(), NamespaceError> {
+        if !self.namespaces {
+            return Err(NamespaceError::Disabled);
+        }
+        if !self.namespaces_supported {
+            return Err(NamespaceError::NotBytewiseComparator);
+        }
+        Ok(())
+    }
+
+    fn key_reserved(&self, key: &kv::Key) -> bool {
+        key_reserved(self.namespaces, key)
+    }
+
     pub async fn remove(&mut self, key: kv::Key) -> Result<Removed, RemoveError> {
-        self.manager_pid.remove(key).await
-            .map_err(|core::manager::RemoveError::GenServer(ero::NoProcError)| RemoveError::GenServer(ero::NoProcError))
+        self.remove_cell(key, None).await
+    }
+
+    // `meta` is returned with the tombstone by `lookup` and range scans with tombstones, e.g. for change auditing
+    pub async fn remove_with_meta(&mut self, key: kv::Key, meta: kv::Value) -> Result<Removed, RemoveError> {
+        self.remove_cell(key, Some(meta)).await
+    }
+
+    async fn remove_cell(&mut self, key: kv::Key, meta: Option<kv::Value>) -> Result<Removed, RemoveError> {
+        if self.key_reserved(&key) {
+            return Err(RemoveError::KeyReserved);
+        }
+        self.remove_unchecked(key, meta).await
+    }
+
+    async fn remove_unchecked(&mut self, key: kv::Key, meta: Option<kv::Value>) -> Result<Removed, RemoveError> {
+        self.manager_pid.remove(key, meta).await
+            .map_err(|error| match error {
+                core::manager::RemoveError::GenServer(ero::NoProcError) =>
+                    RemoveError::GenServer(ero::NoProcError),
+                core::manager::RemoveError::KeyTooLarge { size, limit, } =>
+                    RemoveError::KeyTooLarge { size, limit, },
+                core::manager::RemoveError::VersionRegression { version, last_version, } =>
+                    RemoveError::VersionRegression { version, last_version, },
+            })
+    }
+
+    // tombstones are written in one butcher request with a single flush threshold check afterwards,
+    // results follow the order of `keys`
+    pub async fn remove_many(&mut self, keys: Vec<kv::Key>) -> Result<Vec<Removed>, RemoveError> {
+        if keys.iter().any(|key| self.key_reserved(key)) {
+            return Err(RemoveError::KeyReserved);
+        }
+        self.manager_pid.remove_many(keys).await
+            .map_err(|error| match error {
+                core::manager::RemoveError::GenServer(ero::NoProcError) =>
+                    RemoveError::GenServer(ero::NoProcError),
+                core::manager::RemoveError::KeyTooLarge { size, limit, } =>
+                    RemoveError::KeyTooLarge { size, limit, },
+                core::manager::RemoveError::VersionRegression { version, last_version, } =>
+                    RemoveError::VersionRegression { version, last_version, },
+            })
+    }
+
+    pub async fn remove_returning(&mut self, key: kv::Key) -> Result<(Removed, Option<kv::ValueCell<kv::Value>>), RemoveError> {
+        if self.key_reserved(&key) {
+            return Err(RemoveError::KeyReserved);
+        }
+        self.manager_pid.remove_returning(key).await
+            .map_err(|error| match error {
+                core::manager::RemoveError::GenServer(ero::NoProcError) =>
+                    RemoveError::GenServer(ero::NoProcError),
+                core::manager::RemoveError::KeyTooLarge { size, limit, } =>
+                    RemoveError::KeyTooLarge { size, limit, },
+                core::manager::RemoveError::VersionRegression { version, last_version, } =>
+                    RemoveError::VersionRegression { version, last_version, },
+            })
+    }
+
+    pub async fn remove_range<R>(&mut self, range: R) -> Result<Removed, RemoveError> where R: RangeBounds<kv::Key> {
+        self.manager_pid.remove_range(range).await
+            .map_err(|error| match error {
+                core::manager::RemoveError::GenServer(ero::NoProcError) =>
+                    RemoveError::GenServer(ero::NoProcError),
+                core::manager::RemoveError::KeyTooLarge { size, limit, } =>
+                    RemoveError::KeyTooLarge { size, limit, },
+                core::manager::RemoveError::VersionRegression { version, last_version, } =>
+                    RemoveError::VersionRegression { version, last_version, },
+            })
+    }
+
+    // returns live cells of the range and removes them with a single range tombstone
+    pub async fn split_off<R>(&mut self, range: R) -> Result<Vec<kv::KeyValuePair<kv::Value>>, SplitOffError> where R: RangeBounds<kv::Key> {
+        self.manager_pid.split_off(range).await
+            .map_err(|error| match error {
+                core::manager::SplitOffError::GenServer(ero::NoProcError) =>
+                    SplitOffError::GenServer(ero::NoProcError),
+                core::manager::SplitOffError::Stream(error) =>
+                    SplitOffError::Stream(error),
+                core::manager::SplitOffError::VersionRegression { version, last_version, } =>
+                    SplitOffError::VersionRegression { version, last_version, },
+            })
+    }
+
+    // the stream is read in chunks of `bulk_load_chunk_bytes`, each one loaded as a separate search tree,
+    // so the load is not atomic: chunks loaded before an out of order key is found stay in the store;
+    // the version of the last loaded chunk is returned
+    pub async fn bulk_load<S>(&mut self, sorted: S) -> Result<Inserted, BulkLoadError> where S: Stream<Item = (kv::Key, kv::Value)> {
+        let mut sorted = Box::pin(sorted);
+        let mut chunker = BulkLoadChunker::new(self.bulk_load_chunk_bytes);
+        let mut inserted = None;
+        while let Some((key, value)) = sorted.next().await {
+            if let Some(chunk) = chunker.push(key, value) {
+                inserted = Some(self.bulk_load_chunk(&mut chunker, chunk).await?);
+            }
+        }
+        self.bulk_load_finish(&mut chunker, inserted).await
+    }
+
+    // a stream ending right on a chunk boundary leaves nothing to load, so the last loaded chunk version stands;
+    // an empty chunk is sent only for an empty stream
+    async fn bulk_load_finish(&mut self, chunker: &mut BulkLoadChunker, inserted: Option<Inserted>) -> Result<Inserted, BulkLoadError> {
+        let chunk = chunker.take();
+        match inserted {
+            Some(inserted) if chunk.is_empty() =>
+                Ok(inserted),
+            Some(..) | None =>
+                self.bulk_load_chunk(chunker, chunk).await,
+        }
+    }
+
+    async fn bulk_load_chunk(&mut self, chunker: &mut BulkLoadChunker, chunk: Vec<(kv::Key, kv::Value)>) -> Result<Inserted, BulkLoadError> {
+        if let Some((key, _value)) = chunk.iter().find(|(key, _value)| self.key_reserved(key)) {
+            return Err(BulkLoadError::KeyReserved { key: key.clone(), });
+        }
+        // keys order is checked against configured comparator by the manager bulk load task
+        let last_key = chunk.last().map(|(key, _value)| key.clone());
+        let inserted = self.manager_pid.bulk_load(chunker.prev_key.clone(), chunk).await
+            .map_err(|error| match error {
+                core::manager::BulkLoadError::GenServer(ero::NoProcError) =>
+                    BulkLoadError::GenServer(ero::NoProcError),
+                core::manager::BulkLoadError::KeysNotAscending { prev_key, key, } =>
+                    BulkLoadError::KeysNotAscending { prev_key, key, },
+                core::manager::BulkLoadError::VersionRegression { version, last_version, } =>
+                    BulkLoadError::VersionRegression { version, last_version, },
+            })?;
+        if last_key.is_some() {
+            chunker.prev_key = last_key;
+        }
+        Ok(inserted)
+    }
+
+    pub async fn export<W>(&mut self, writer: W) -> Result<Info, ExportError> where W: AsyncWrite + Unpin {
+        let mut lookup_range = self.lookup_range(..).await
+            .map_err(ExportError::LookupRange)?;
+        let mut sstable_writer = sstable::Writer::start(writer).await
+            .map_err(ExportError::Sstable)?;
+        loop {
+            match lookup_range.key_values_rx.next().await {
+                None =>
+                    return Err(ExportError::RangeInterrupted),
+                Some(KeyValueStreamItem::KeyValue(kv::KeyValuePair { key, value_cell: kv::ValueCell { cell: kv::Cell::Value(value), .. }, })) =>
+                    sstable_writer.entry(&key, &value).await
+                        .map_err(ExportError::Sstable)?,
+                Some(KeyValueStreamItem::KeyValue(kv::KeyValuePair { value_cell: kv::ValueCell { cell: kv::Cell::Tombstone { .. }, .. }, .. })) =>
+                    (),
+                Some(KeyValueStreamItem::Error(error)) =>
+                    return Err(ExportError::Stream(error)),
+                Some(KeyValueStreamItem::NoMore) =>
+                    break,
+            }
+        }
+        let entries_count = sstable_writer.finish().await
+            .map_err(ExportError::Sstable)?;
+        Ok(Info {
+            alive_cells_count: entries_count as usize,
+            ..Info::default()
+        })
+    }
+
+    // loaded chunk by chunk as `bulk_load` does: a file found corrupted midway leaves the chunks before it in the store
+    pub async fn import<R>(&mut self, reader: R) -> Result<Inserted, ImportError> where R: AsyncRead + Unpin {
+        let mut sstable_reader = sstable::Reader::start(reader, self.keys_pool.clone()).await
+            .map_err(ImportError::Sstable)?;
+        let mut chunker = BulkLoadChunker::new(self.bulk_load_chunk_bytes);
+        let mut inserted = None;
+        while let Some((key, value)) = sstable_reader.next().await.map_err(ImportError::Sstable)? {
+            if let Some(chunk) = chunker.push(key, value) {
+                inserted = Some(
+                    self.bulk_load_chunk(&mut chunker, chunk).await
+                        .map_err(ImportError::BulkLoad)?,
+                );
+            }
+        }
+        self.bulk_load_finish(&mut chunker, inserted).await
+            .map_err(ImportError::BulkLoad)
     }
 
     pub async fn flush(&mut self) -> Result<Flushed, FlushError> {
         self.manager_pid.flush_all().await
             .map_err(|core::manager::FlushError::GenServer(ero::NoProcError)| FlushError::GenServer(ero::NoProcError))
     }
+
+    // turns the butcher memtable into a search tree and returns once it is registered, without flushing
+    // other trees or waiting for merges; the new tree blocks are written in background, so unless
+    // `butcher_wal` is set the data is durable only after `flush`
+    pub async fn flush_butcher_only(&mut self) -> Result<Flushed, FlushError> {
+        self.manager_pid.flush_butcher_only().await
+            .map_err(|core::manager::FlushError::GenServer(ero::NoProcError)| FlushError::GenServer(ero::NoProcError))
+    }
+
+    // blocks unreachable from the roots of served search trees (e.g. left by a merge interrupted by a crash, or
+    // merge inputs a restart has skipped as superseded) are deleted; lookups and inserts are served meanwhile,
+    // while bulk loads, flushes and verification wait for the collection to finish
+    pub async fn gc_orphans(&mut self) -> Result<OrphansCollected, GcOrphansError> {
+        self.manager_pid.gc_orphans().await
+            .map_err(|core::manager::GcOrphansError::GenServer(ero::NoProcError)| GcOrphansError::GenServer(ero::NoProcError))
+    }
+
+    // trees with any entry version above `version_floor` are listed in full, together with their value blocks;
+    // trees written before version ranges were recorded always count as changed
+    pub async fn changed_blocks_since(&mut self, version_floor: u64) -> Result<Vec<wheels::BlockRef>, ChangedBlocksError> {
+        self.manager_pid.changed_blocks_since(version_floor).await
+            .map_err(|core::manager::ChangedBlocksError::GenServer(ero::NoProcError)| ChangedBlocksError::GenServer(ero::NoProcError))
+    }
+
+    // the latest version handed out by `version::Provider`; externally assigned versions are not tracked
+    pub async fn current_version(&mut self) -> Result<u64, ero::NoProcError> {
+        self.manager_pid.current_version().await
+    }
+
+    // ages of the search trees alive, telling whether old data gets compacted down as expected
+    pub async fn detailed_info(&mut self) -> Result<DetailedInfo, ero::NoProcError> {
+        self.manager_pid.detailed_info().await
+    }
+
+    // every block of every wheel with its decoded header; runs alongside regular requests, so blocks written
+    // or removed meanwhile may or may not be listed
+    pub async fn iter_raw_blocks(&mut self) -> Result<RawBlocks, IterRawBlocksError> {
+        self.manager_pid.iter_raw_blocks().await
+            .map_err(|core::manager::IterRawBlocksError::GenServer(ero::NoProcError)| IterRawBlocksError::GenServer(ero::NoProcError))
+    }
+
+    // scrub: checksums and key order of every block plus reachability of every tree; writes are not blocked,
+    // so blocks of trees merged during the scan may be reported missing
+    pub async fn verify(&mut self) -> Result<VerifyReport, VerifyError> {
+        self.manager_pid.verify().await
+            .map_err(|core::manager::VerifyError::GenServer(ero::NoProcError)| VerifyError::GenServer(ero::NoProcError))
+    }
+
+    // answered only after the butcher and a search tree have replied to a probe, so a hung pipeline
+    // shows up as a request which never completes: callers should apply their own deadline
+    pub async fn health(&mut self) -> Result<Health, ero::NoProcError> {
+        self.manager_pid.health().await
+    }
+
+    // walks the butcher and every search tree in full, so it costs as much as a complete scan
+    pub async fn size_histograms(&mut self) -> Result<Histograms, ero::NoProcError> {
+        self.manager_pid.size_histograms().await
+    }
+
+    // sums entries counts of the search tree blocks over the range instead of scanning it, reading about
+    // twice the tree depth of blocks per search tree: it is an estimate which may over-count keys
+    // superseded or removed, since every version and tombstone still kept is counted
+    pub async fn estimate_count<R>(&mut self, range: R) -> Result<usize, ero::NoProcError> where R: RangeBounds<kv::Key> {
+        self.manager_pid.estimate_count(range).await
+    }
+
+    // reads the search tree blocks over the range so the wheels cache them ahead of a heavy scan,
+    // nothing is emitted: spawn the returned future to keep on meanwhile
+    pub async fn warm_range<R>(&mut self, range: R) -> Result<Warmed, ero::NoProcError> where R: RangeBounds<kv::Key> {
+        self.manager_pid.warm_range(range).await
+    }
+
+    // events are dropped for a subscriber which does not keep up
+    pub async fn subscribe_compactions(&mut self) -> Result<mpsc::Receiver<CompactionEvent>, ero::NoProcError> {
+        self.manager_pid.subscribe_compactions().await
+    }
+
+    pub async fn shutdown(&mut self) -> Result<(), ero::NoProcError> {
+        self.manager_pid.shutdown().await
+    }
 }
 
+// collects a sorted stream into chunks of about `chunk_bytes` key and value bytes,
+// `prev_key` is the last key of the chunk loaded before, so the order is checked across chunks too
+struct BulkLoadChunker {
+    chunk: Vec<(kv::Key, kv::Value)>,
+    chunk_bytes: usize,
+    chunk_bytes_limit: usize,
+    prev_key: Option<kv::Key>,
+}
+
+impl BulkLoadChunker {
+    fn new(chunk_bytes_limit: usize) -> BulkLoadChunker {
+        BulkLoadChunker {
+            chunk: Vec::new(),
+            chunk_bytes: 0,
+            chunk_bytes_limit,
+            prev_key: None,
+        }
+    }
+
+    fn push(&mut self, key: kv::Key, value: kv::Value) -> Option<Vec<(kv::Key, kv::Value)>> {
+        self.chunk_bytes += key.key_bytes.len() + value.value_bytes.len();
+        self.chunk.push((key, value));
+        if self.chunk_bytes >= self.chunk_bytes_limit {
+            Some(self.take())
+        } else {
+            None
+        }
+    }
+
+    fn take(&mut self) -> Vec<(kv::Key, kv::Value)> {
+        self.chunk_bytes = 0;
+        mem::take(&mut self.chunk)
+    }
+}
+
+// saturating: a miscounted part must not bring down the manager folding an info request
 impl AddAssign for Info {
     fn add_assign(&mut self, rhs: Info) {
-        self.alive_cells_count += rhs.alive_cells_count;
-        self.tombstones_count += rhs.tombstones_count;
+        self.alive_cells_count = self.alive_cells_count.saturating_add(rhs.alive_cells_count);
+        self.tombstones_count = self.tombstones_count.saturating_add(rhs.tombstones_count);
+        self.depth = self.depth.max(rhs.depth);
+        self.block_count = self.block_count.saturating_add(rhs.block_count);
+        self.butcher_entries = self.butcher_entries.saturating_add(rhs.butcher_entries);
+        self.butcher_bytes = self.butcher_bytes.saturating_add(rhs.butcher_bytes);
+        self.disk_bytes_total = self.disk_bytes_total.saturating_add(rhs.disk_bytes_total);
+        self.active_range_scans = self.active_range_scans.saturating_add(rhs.active_range_scans);
+    }
+}
+
+// a namespaced key is stored as `NAMESPACE_MARKER`, the big endian namespace and the key itself
+pub const NAMESPACE_MARKER: u8 = 0xff;
+pub const NAMESPACE_PREFIX_LEN: usize = 9;
+
+fn key_reserved(namespaces: bool, key: &kv::Key) -> bool {
+    namespaces && key.key_bytes.first() == Some(&NAMESPACE_MARKER)
+}
+
+fn namespace_key(ns: u64, key: &kv::Key, keys_pool: &BytesPool) -> kv::Key {
+    let mut key_bytes = keys_pool.lend();
+    key_bytes.push(NAMESPACE_MARKER);
+    key_bytes.extend_from_slice(&ns.to_be_bytes());
+    key_bytes.extend_from_slice(&key.key_bytes);
+    kv::Key::from(key_bytes)
+}
+
+fn namespace_range(ns: u64, keys_pool: &BytesPool) -> (Bound<kv::Key>, Bound<kv::Key>) {
+    let mut lower_bytes = keys_pool.lend();
+    lower_bytes.push(NAMESPACE_MARKER);
+    lower_bytes.extend_from_slice(&ns.to_be_bytes());
+    let range_to = match ns.checked_add(1) {
+        // plain keys never start with the marker, so nothing but namespaces sorts after the last one
+        None =>
+            Bound::Unbounded,
+        Some(next_ns) => {
+            let mut upper_bytes = keys_pool.lend();
+            upper_bytes.push(NAMESPACE_MARKER);
+            upper_bytes.extend_from_slice(&next_ns.to_be_bytes());
+            Bound::Excluded(kv::Key::from(upper_bytes))
+        },
+    };
+    (Bound::Included(kv::Key::from(lower_bytes)), range_to)
+}
+
+impl SizeHistogram {
+    pub fn record(&mut self, size: usize) {
+        // significant bits count: 0 for an empty one, `n` for sizes in `[2^(n - 1), 2^n)`
+        let bits = (0usize.leading_zeros() - size.leading_zeros()) as usize;
+        self.buckets[bits.min(self.buckets.len() - 1)] += 1;
+    }
+
+    pub fn total(&self) -> usize {
+        self.buckets.iter().sum()
+    }
+}
+
+impl AddAssign for SizeHistogram {
+    fn add_assign(&mut self, rhs: SizeHistogram) {
+        for (bucket, rhs_bucket) in self.buckets.iter_mut().zip(rhs.buckets.iter()) {
+            *bucket = bucket.saturating_add(*rhs_bucket);
+        }
+    }
+}
+
+impl AddAssign for Histograms {
+    fn add_assign(&mut self, rhs: Histograms) {
+        self.key_sizes += rhs.key_sizes;
+        self.value_sizes += rhs.value_sizes;
+        self.out_of_line_values = self.out_of_line_values.saturating_add(rhs.out_of_line_values);
+        self.tombstones = self.tombstones.saturating_add(rhs.tombstones);
     }
 }
 
@@ -235,5 +1988,11 @@ impl Info {
     pub fn reset(&mut self) {
         self.alive_cells_count = 0;
         self.tombstones_count = 0;
+        self.depth = 0;
+        self.block_count = 0;
+        self.butcher_entries = 0;
+        self.butcher_bytes = 0;
+        self.disk_bytes_total = 0;
+        self.active_range_scans = 0;
     }
 }
@@ -0,0 +1,167 @@
+use futures::{
+    channel::{
+        mpsc,
+    },
+    StreamExt,
+};
+
+use alloc_pool::{
+    pool,
+    Unique,
+};
+
+use crate::{
+    kv,
+    core::{
+        self,
+        merger,
+    },
+    comparator::KeyComparatorRef,
+    ValueRef,
+};
+
+// items of every iterator are expected in ascending key order with respect to the merger comparator,
+// and each iterator should be finished with `NoMore`: a channel closed without it fails the merge
+pub enum KeyValueRef {
+    Item {
+        key: kv::Key,
+        value_cell: kv::ValueCell<ValueRef>,
+    },
+    NoMore,
+}
+
+pub struct KeyValuesIter {
+    iter: merger::KeyValuesIter,
+}
+
+impl KeyValuesIter {
+    pub fn new(key_values_rx: mpsc::Receiver<KeyValueRef>) -> KeyValuesIter {
+        let key_values = key_values_rx
+            .map(|item| match item {
+                KeyValueRef::Item { key, value_cell, } =>
+                    core::KeyValueRef::Item { key, value_cell: value_cell.into(), },
+                KeyValueRef::NoMore =>
+                    core::KeyValueRef::NoMore,
+            });
+        KeyValuesIter {
+            iter: merger::KeyValuesIter::from_stream(key_values.boxed()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    IterPeerLost,
+}
+
+// k-way merge used for range lookups and compaction: cells sharing a key are folded into the one
+// with the highest version, the others are reported to `next_with_deprecated` callback
+pub struct ItersMerger {
+    merger: merger::ItersMerger<Unique<Vec<merger::KeyValuesIter>>>,
+}
+
+impl ItersMerger {
+    pub fn new(iters: Vec<KeyValuesIter>) -> ItersMerger {
+        let mut merger_iters = pool::Pool::new().lend(Vec::new);
+        merger_iters.clear();
+        merger_iters.extend(iters.into_iter().map(|KeyValuesIter { iter, }| iter));
+        ItersMerger {
+            merger: merger::ItersMerger::new(merger_iters),
+        }
+    }
+
+    pub fn dropping_tombstones(self, drop_tombstones: bool) -> ItersMerger {
+        ItersMerger {
+            merger: self.merger.dropping_tombstones(drop_tombstones),
+        }
+    }
+
+    // `comparator::bytewise` is used by default
+    pub fn comparing_keys(self, key_comparator: KeyComparatorRef) -> ItersMerger {
+        ItersMerger {
+            merger: self.merger.comparing_keys(key_comparator),
+        }
+    }
+
+    pub async fn next(&mut self) -> Result<Option<kv::KeyValuePair<ValueRef>>, Error> {
+        self.next_with_deprecated(|_| ()).await
+    }
+
+    pub async fn next_with_deprecated<F>(
+        &mut self,
+        mut deprecated: F,
+    )
+        -> Result<Option<kv::KeyValuePair<ValueRef>>, Error>
+    where F: FnMut(kv::KeyValuePair<ValueRef>)
+    {
+        let maybe_item = self.merger
+            .next_with_deprecated(|kv::KeyValuePair { key, value_cell, }| {
+                deprecated(kv::KeyValuePair { key, value_cell: value_cell.into(), })
+            })
+            .await
+            .map_err(|merger::Error::BackendIterPeerLost| Error::IterPeerLost)?;
+        Ok(maybe_item.map(|kv::KeyValuePair { key, value_cell, }| kv::KeyValuePair { key, value_cell: value_cell.into(), }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{
+        channel::mpsc,
+        executor::block_on,
+        SinkExt,
+    };
+
+    use alloc_pool::bytes::BytesPool;
+
+    use crate::{
+        kv,
+        ValueRef,
+    };
+
+    use super::{
+        ItersMerger,
+        KeyValueRef,
+        KeyValuesIter,
+    };
+
+    fn make_item(blocks_pool: &BytesPool, key: &[u8], version: u64) -> KeyValueRef {
+        let mut key_bytes = blocks_pool.lend();
+        key_bytes.extend_from_slice(key);
+        let mut value_bytes = blocks_pool.lend();
+        value_bytes.extend_from_slice(&version.to_be_bytes());
+        KeyValueRef::Item {
+            key: key_bytes.into(),
+            value_cell: kv::ValueCell { version, cell: kv::Cell::Value(ValueRef::Inline(value_bytes.into())), },
+        }
+    }
+
+    #[test]
+    fn newest_version_wins() {
+        let blocks_pool = BytesPool::new();
+        let outcome = block_on(async {
+            let streams = vec![
+                vec![make_item(&blocks_pool, b"a", 1), make_item(&blocks_pool, b"c", 4)],
+                vec![make_item(&blocks_pool, b"a", 2), make_item(&blocks_pool, b"b", 3)],
+            ];
+            let mut iters = Vec::new();
+            for items in streams {
+                let (mut tx, rx) = mpsc::channel(items.len() + 1);
+                for item in items {
+                    tx.send(item).await.unwrap();
+                }
+                tx.send(KeyValueRef::NoMore).await.unwrap();
+                iters.push(KeyValuesIter::new(rx));
+            }
+
+            let mut merger = ItersMerger::new(iters);
+            let mut merged = Vec::new();
+            let mut deprecated = Vec::new();
+            while let Some(item) = merger.next_with_deprecated(|item| deprecated.push(item.value_cell.version)).await.unwrap() {
+                merged.push((item.key.key_bytes.to_vec(), item.value_cell.version));
+            }
+            (merged, deprecated)
+        });
+        assert_eq!(outcome, (vec![(b"a".to_vec(), 2), (b"b".to_vec(), 3), (b"c".to_vec(), 4)], vec![1]));
+    }
+}
@@ -0,0 +1,32 @@
+use std::{
+    fmt,
+    sync::Arc,
+    time::Duration,
+};
+
+pub trait Metrics {
+    fn on_insert(&self) { }
+    fn on_lookup_hit(&self, _latency: Duration) { }
+    fn on_lookup_miss(&self, _latency: Duration) { }
+    fn on_flush(&self, _items_count: usize) { }
+    fn on_merge_start(&self, _items_count: usize) { }
+    fn on_merge_end(&self, _items_count: usize, _duration: Duration) { }
+    fn on_block_read(&self, _block_size: usize) { }
+    fn on_block_write(&self, _block_size: usize) { }
+}
+
+pub type MetricsRef = Arc<dyn Metrics + Send + Sync>;
+
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics { }
+
+pub fn no_metrics() -> MetricsRef {
+    Arc::new(NoMetrics)
+}
+
+impl fmt::Debug for dyn Metrics + Send + Sync {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Metrics")
+    }
+}
@@ -0,0 +1,258 @@
+use std::io;
+
+use futures::io::{
+    AsyncRead,
+    AsyncReadExt,
+    AsyncWrite,
+    AsyncWriteExt,
+};
+
+use alloc_pool::bytes::{
+    BytesMut,
+    BytesPool,
+};
+
+use crate::{
+    kv,
+    storage,
+};
+
+const SSTABLE_MAGIC: u64 = 0x5e3a9d17c04b6f21;
+const SSTABLE_VERSION: u16 = 1;
+// keys and values never exceed a block, so a longer chunk may only come from a corrupted file
+const MAX_CHUNK_SIZE: usize = storage::MAX_BLOCK_SIZE;
+
+const TAG_ENTRY: u8 = 1;
+const TAG_TRAILER: u8 = 0;
+
+#[derive(Debug)]
+pub enum Error {
+    Write(io::Error),
+    Read(io::Error),
+    InvalidMagic {
+        expected: u64,
+        provided: u64,
+    },
+    UnsupportedVersion {
+        found: u16,
+        supported: u16,
+    },
+    UnexpectedTag(u8),
+    EntryTooLarge {
+        size: usize,
+    },
+    EntriesCountMismatch {
+        expected: u64,
+        read: u64,
+    },
+    ChecksumMismatch {
+        expected: u32,
+        calculated: u32,
+    },
+}
+
+pub struct Writer<W> {
+    writer: W,
+    crc: u32,
+    entries_count: u64,
+}
+
+impl<W> Writer<W> where W: AsyncWrite + Unpin {
+    pub async fn start(writer: W) -> Result<Writer<W>, Error> {
+        let mut sstable_writer = Writer { writer, crc: 0, entries_count: 0, };
+        sstable_writer.write(&SSTABLE_MAGIC.to_be_bytes()).await?;
+        sstable_writer.write(&SSTABLE_VERSION.to_be_bytes()).await?;
+        Ok(sstable_writer)
+    }
+
+    pub async fn entry(&mut self, key: &kv::Key, value: &kv::Value) -> Result<(), Error> {
+        self.write(&[TAG_ENTRY]).await?;
+        self.write_chunk(&key.key_bytes).await?;
+        self.write_chunk(&value.value_bytes).await?;
+        self.entries_count += 1;
+        Ok(())
+    }
+
+    pub async fn finish(mut self) -> Result<u64, Error> {
+        self.write(&[TAG_TRAILER]).await?;
+        let entries_count = self.entries_count;
+        self.write(&entries_count.to_be_bytes()).await?;
+        let crc = self.crc;
+        self.writer.write_all(&crc.to_be_bytes()).await
+            .map_err(Error::Write)?;
+        self.writer.flush().await
+            .map_err(Error::Write)?;
+        Ok(entries_count)
+    }
+
+    async fn write_chunk(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if bytes.len() > u32::MAX as usize {
+            return Err(Error::EntryTooLarge { size: bytes.len(), });
+        }
+        self.write(&(bytes.len() as u32).to_be_bytes()).await?;
+        self.write(bytes).await
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.writer.write_all(bytes).await
+            .map_err(Error::Write)?;
+        self.crc = crc32c::crc32c_append(self.crc, bytes);
+        Ok(())
+    }
+}
+
+pub struct Reader<R> {
+    reader: R,
+    crc: u32,
+    entries_count: u64,
+    bytes_pool: BytesPool,
+}
+
+impl<R> Reader<R> where R: AsyncRead + Unpin {
+    pub async fn start(reader: R, bytes_pool: BytesPool) -> Result<Reader<R>, Error> {
+        let mut sstable_reader = Reader { reader, crc: 0, entries_count: 0, bytes_pool, };
+        let magic = u64::from_be_bytes(sstable_reader.read_array().await?);
+        if magic != SSTABLE_MAGIC {
+            return Err(Error::InvalidMagic { expected: SSTABLE_MAGIC, provided: magic, });
+        }
+        let version = u16::from_be_bytes(sstable_reader.read_array().await?);
+        if version != SSTABLE_VERSION {
+            return Err(Error::UnsupportedVersion { found: version, supported: SSTABLE_VERSION, });
+        }
+        Ok(sstable_reader)
+    }
+
+    pub async fn next(&mut self) -> Result<Option<(kv::Key, kv::Value)>, Error> {
+        let [tag] = self.read_array().await?;
+        match tag {
+            TAG_ENTRY => {
+                let key = self.read_chunk().await?;
+                let value = self.read_chunk().await?;
+                self.entries_count += 1;
+                Ok(Some((key.freeze().into(), value.freeze().into())))
+            },
+            TAG_TRAILER => {
+                let entries_count = u64::from_be_bytes(self.read_array().await?);
+                if entries_count != self.entries_count {
+                    return Err(Error::EntriesCountMismatch { expected: entries_count, read: self.entries_count, });
+                }
+                let calculated = self.crc;
+                let mut crc_bytes = [0; 4];
+                self.reader.read_exact(&mut crc_bytes).await
+                    .map_err(Error::Read)?;
+                let expected = u32::from_be_bytes(crc_bytes);
+                if expected != calculated {
+                    return Err(Error::ChecksumMismatch { expected, calculated, });
+                }
+                Ok(None)
+            },
+            other =>
+                Err(Error::UnexpectedTag(other)),
+        }
+    }
+
+    async fn read_chunk(&mut self) -> Result<BytesMut, Error> {
+        let size = u32::from_be_bytes(self.read_array().await?) as usize;
+        if size > MAX_CHUNK_SIZE {
+            return Err(Error::EntryTooLarge { size, });
+        }
+        let mut bytes = self.bytes_pool.lend();
+        bytes.resize(size, 0);
+        self.reader.read_exact(&mut bytes[..]).await
+            .map_err(Error::Read)?;
+        self.crc = crc32c::crc32c_append(self.crc, &bytes);
+        Ok(bytes)
+    }
+
+    async fn read_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut array = [0; N];
+        self.reader.read_exact(&mut array).await
+            .map_err(Error::Read)?;
+        self.crc = crc32c::crc32c_append(self.crc, &array);
+        Ok(array)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{
+        executor::block_on,
+        io::Cursor,
+    };
+
+    use alloc_pool::bytes::BytesPool;
+
+    use crate::kv;
+
+    use super::{
+        Error,
+        Reader,
+        Writer,
+    };
+
+    fn make_pair(blocks_pool: &BytesPool, key: &[u8], value: &[u8]) -> (kv::Key, kv::Value) {
+        let mut key_bytes = blocks_pool.lend();
+        key_bytes.extend_from_slice(key);
+        let mut value_bytes = blocks_pool.lend();
+        value_bytes.extend_from_slice(value);
+        (key_bytes.into(), value_bytes.into())
+    }
+
+    #[test]
+    fn roundtrip() {
+        let blocks_pool = BytesPool::new();
+        let pairs = vec![
+            make_pair(&blocks_pool, b"alpha", b"1"),
+            make_pair(&blocks_pool, b"beta", b""),
+            make_pair(&blocks_pool, b"gamma", &[0xff; 300]),
+        ];
+
+        let mut sstable_bytes = Vec::new();
+        let entries_count = block_on(async {
+            let mut writer = Writer::start(&mut sstable_bytes).await.unwrap();
+            for (key, value) in &pairs {
+                writer.entry(key, value).await.unwrap();
+            }
+            writer.finish().await.unwrap()
+        });
+        assert_eq!(entries_count, 3);
+
+        let read_pairs = block_on(async {
+            let mut reader = Reader::start(Cursor::new(&sstable_bytes[..]), blocks_pool.clone()).await.unwrap();
+            let mut read_pairs = Vec::new();
+            while let Some(pair) = reader.next().await.unwrap() {
+                read_pairs.push(pair);
+            }
+            read_pairs
+        });
+        assert_eq!(read_pairs, pairs);
+
+        let mut corrupted = sstable_bytes.clone();
+        corrupted[16] ^= 0x01;
+        let result = block_on(async {
+            let mut reader = Reader::start(Cursor::new(&corrupted[..]), blocks_pool.clone()).await?;
+            while let Some(..) = reader.next().await? { }
+            Ok::<_, Error>(())
+        });
+        assert!(matches!(result, Err(Error::ChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn oversized_chunk_rejected() {
+        let blocks_pool = BytesPool::new();
+        let mut sstable_bytes = Vec::new();
+        block_on(async {
+            let mut writer = Writer::start(&mut sstable_bytes).await.unwrap();
+            let (key, value) = make_pair(&blocks_pool, b"key", b"value");
+            writer.entry(&key, &value).await.unwrap();
+            writer.finish().await.unwrap();
+        });
+        // magic, version and entry tag are followed by the key length
+        sstable_bytes[11 .. 15].copy_from_slice(&u32::MAX.to_be_bytes());
+        let result = block_on(async {
+            let mut reader = Reader::start(Cursor::new(&sstable_bytes[..]), blocks_pool.clone()).await?;
+            reader.next().await
+        });
+        assert!(matches!(result, Err(Error::EntryTooLarge { size, }) if size == u32::MAX as usize));
+    }
+}
@@ -3,10 +3,18 @@ use serde_derive::{
     Deserialize,
 };
 
+use std::{
+    io,
+    ops::Bound,
+};
+
 use bincode::Options;
 
 use alloc_pool::{
-    bytes::Bytes,
+    bytes::{
+        Bytes,
+        BytesPool,
+    },
 };
 
 use ero_blockwheel_fs::{
@@ -19,9 +27,40 @@ use crate::{
         BlockRef,
         WheelFilename,
     },
+    comparator,
+    Compression,
+    StoragePreference,
 };
 
-pub const BLOCK_MAGIC: u64 = 0xbde78ba3966ca503;
+pub const BLOCK_MAGIC: u64 = 0x7b3f1c9a64d2e805;
+// blocks written before checksums were introduced carry the legacy magic and no trailing crc;
+// they are still read as `LEGACY_FORMAT_VERSION`, only checksum verification is skipped for them
+pub const LEGACY_BLOCK_MAGIC: u64 = 0xbde78ba3966ca503;
+pub const LEGACY_FORMAT_VERSION: u16 = 0;
+pub const FIXINT_FORMAT_VERSION: u16 = 1;
+pub const VARINT_FORMAT_VERSION: u16 = 2;
+pub const MAX_VERSION_FORMAT_VERSION: u16 = 3;
+pub const CREATED_AT_FORMAT_VERSION: u16 = 4;
+pub const FORMAT_VERSION: u16 = 5;
+pub const COMPRESSED_BLOCK_MAGIC: u64 = 0x2c51ed0e4f8b9a17;
+// upper bound of a block before compression: a compressed block header declaring more is corrupted
+pub const MAX_BLOCK_SIZE: usize = 64 * 1024 * 1024;
+
+// store id is mixed into the lower half of a magic, so stores sharing wheels tell their blocks apart;
+// store id 0 keeps the magic values written before store ids were introduced
+pub fn store_magic(magic: u64, store_id: u32) -> u64 {
+    magic ^ u64::from(store_id)
+}
+
+// store id of a `magic` variant, `None` when `provided` is not one of them
+pub fn magic_store_id(provided: u64, magic: u64) -> Option<u32> {
+    let store_id = provided ^ magic;
+    if store_id >> 32 == 0 {
+        Some(store_id as u32)
+    } else {
+        None
+    }
+}
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct BlockHeader {
@@ -31,10 +70,119 @@ pub struct BlockHeader {
 
 #[derive(Clone, Copy, Serialize, Deserialize, Debug)]
 pub enum NodeType {
+    // `max_version` is the highest entry version over the whole tree, `created_at_version` is the store
+    // version when the tree was built: 0 for trees written before it was recorded; `comparator_id` is
+    // the id of the key comparator the tree is ordered with
+    Root { tree_entries_count: usize, max_version: u64, created_at_version: u64, comparator_id: u32, },
+    Leaf,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct LegacyBlockHeader {
+    node_type: LegacyNodeType,
+    entries_count: usize,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+enum LegacyNodeType {
     Root { tree_entries_count: usize, },
     Leaf,
 }
 
+// writes a root in the pre max version layout, for restoring old stores in tests
+#[cfg(test)]
+pub fn legacy_root_block_serialize<B>(entries: &[Entry], store_id: u32, mut block_bytes: B) -> Result<B, Error> where B: AsMut<Vec<u8>> {
+    block_bytes.as_mut().clear();
+    bincode_options()
+        .serialize_into(block_bytes.as_mut(), &store_magic(BLOCK_MAGIC, store_id))
+        .map_err(Error::BlockMagicSerialize)?;
+    bincode_options()
+        .serialize_into(block_bytes.as_mut(), &VARINT_FORMAT_VERSION)
+        .map_err(Error::FormatVersionSerialize)?;
+    let legacy_header = LegacyBlockHeader {
+        node_type: LegacyNodeType::Root { tree_entries_count: entries.len(), },
+        entries_count: entries.len(),
+    };
+    bincode_options()
+        .serialize_into(block_bytes.as_mut(), &legacy_header)
+        .map_err(Error::BlockHeaderSerialize)?;
+    for entry in entries {
+        varint_bincode_options()
+            .serialize_into(block_bytes.as_mut(), entry)
+            .map_err(Error::EntrySerialize)?;
+    }
+    block_checksum_append(block_bytes.as_mut())?;
+    Ok(block_bytes)
+}
+
+impl From<LegacyBlockHeader> for BlockHeader {
+    fn from(legacy_header: LegacyBlockHeader) -> BlockHeader {
+        BlockHeader {
+            node_type: match legacy_header.node_type {
+                // version range was not recorded before: `u64::MAX` makes the tree count as changed for any floor,
+                // `BlockDeserializeIter::root_max_version` reports it as unknown
+                LegacyNodeType::Root { tree_entries_count, } =>
+                    NodeType::Root { tree_entries_count, max_version: u64::MAX, created_at_version: 0, comparator_id: comparator::BYTEWISE_ID, },
+                LegacyNodeType::Leaf =>
+                    NodeType::Leaf,
+            },
+            entries_count: legacy_header.entries_count,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct MaxVersionBlockHeader {
+    node_type: MaxVersionNodeType,
+    entries_count: usize,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+enum MaxVersionNodeType {
+    Root { tree_entries_count: usize, max_version: u64, },
+    Leaf,
+}
+
+impl From<MaxVersionBlockHeader> for BlockHeader {
+    fn from(max_version_header: MaxVersionBlockHeader) -> BlockHeader {
+        BlockHeader {
+            node_type: match max_version_header.node_type {
+                MaxVersionNodeType::Root { tree_entries_count, max_version, } =>
+                    NodeType::Root { tree_entries_count, max_version, created_at_version: 0, comparator_id: comparator::BYTEWISE_ID, },
+                MaxVersionNodeType::Leaf =>
+                    NodeType::Leaf,
+            },
+            entries_count: max_version_header.entries_count,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct CreatedAtBlockHeader {
+    node_type: CreatedAtNodeType,
+    entries_count: usize,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+enum CreatedAtNodeType {
+    Root { tree_entries_count: usize, max_version: u64, created_at_version: u64, },
+    Leaf,
+}
+
+impl From<CreatedAtBlockHeader> for BlockHeader {
+    fn from(created_at_header: CreatedAtBlockHeader) -> BlockHeader {
+        BlockHeader {
+            node_type: match created_at_header.node_type {
+                CreatedAtNodeType::Root { tree_entries_count, max_version, created_at_version, } =>
+                    NodeType::Root { tree_entries_count, max_version, created_at_version, comparator_id: comparator::BYTEWISE_ID, },
+                CreatedAtNodeType::Leaf =>
+                    NodeType::Leaf,
+            },
+            entries_count: created_at_header.entries_count,
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Entry<'a> {
     #[serde(borrow)]
@@ -56,6 +204,8 @@ pub enum Cell<'a> {
     #[serde(borrow)]
     Value(ValueRef<'a>),
     Tombstone,
+    // appended last so entries and wal records written before tombstone metadata keep their encoding
+    TombstoneMeta(&'a [u8]),
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -90,8 +240,10 @@ impl<'a> From<&'a kv::ValueCell<kv::Value>> for ValueCell<'a> {
         ValueCell {
             version: value_cell.version,
             cell: match value_cell.cell {
-                kv::Cell::Tombstone =>
+                kv::Cell::Tombstone { meta: None, } =>
                     Cell::Tombstone,
+                kv::Cell::Tombstone { meta: Some(kv::Value { ref value_bytes, }), } =>
+                    Cell::TombstoneMeta(value_bytes),
                 kv::Cell::Value(kv::Value { ref value_bytes, }) =>
                     Cell::Value(ValueRef::Inline(value_bytes)),
             },
@@ -107,26 +259,55 @@ pub enum Error {
     ValueBlockSerialize(bincode::Error),
     BlockMagicDeserialize(bincode::Error),
     InvalidBlockMagic { expected: u64, provided: u64, },
+    FormatVersionSerialize(bincode::Error),
+    FormatVersionDeserialize(bincode::Error),
+    UnsupportedFormatVersion { found: u16, supported: u16, },
     BlockHeaderDeserialize(bincode::Error),
     EntryDeserialize(bincode::Error),
     ValueBlockDeserialize(bincode::Error),
+    CompressedBlockHeaderSerialize(bincode::Error),
+    CompressedBlockHeaderDeserialize(bincode::Error),
+    BlockCompress(io::Error),
+    BlockDecompress(io::Error),
+    BlockTooLarge { size: usize, max: usize, },
+    UncompressedSizeMismatch { expected: usize, provided: usize, },
+    ChecksumSerialize(bincode::Error),
+    ChecksumMismatch { block_ref: BlockRef, },
+    // keys of a tree block are not strictly ascending
+    NonMonotonicBlock { block_ref: BlockRef, },
+    WalRecordSerialize(bincode::Error),
+    WalRecordDeserialize(bincode::Error),
+    WalRecordChecksumMismatch,
+    WalRecordValueNotInline,
+    RangeTombstoneSerialize(bincode::Error),
+    RangeTombstoneDeserialize(bincode::Error),
+    RangeTombstoneChecksumMismatch,
+    MergeRecordSerialize(bincode::Error),
+    MergeRecordDeserialize(bincode::Error),
+    MergeRecordChecksumMismatch,
 }
 
+const CHECKSUM_SIZE: usize = 4;
+
 pub struct BlockSerializer<B> {
     block_bytes: B,
     entries_left: usize,
 }
 
 impl<B> BlockSerializer<B> where B: AsMut<Vec<u8>> {
-    pub fn start(node_type: NodeType, entries_count: usize, mut block_bytes: B) -> Result<BlockSerializerContinue<B>, Error> {
+    pub fn start(node_type: NodeType, entries_count: usize, store_id: u32, mut block_bytes: B) -> Result<BlockSerializerContinue<B>, Error> {
         block_bytes.as_mut().clear();
         bincode_options()
-            .serialize_into(block_bytes.as_mut(), &BLOCK_MAGIC)
+            .serialize_into(block_bytes.as_mut(), &store_magic(BLOCK_MAGIC, store_id))
             .map_err(Error::BlockMagicSerialize)?;
+        bincode_options()
+            .serialize_into(block_bytes.as_mut(), &FORMAT_VERSION)
+            .map_err(Error::FormatVersionSerialize)?;
         bincode_options()
             .serialize_into(block_bytes.as_mut(), &BlockHeader { node_type, entries_count, })
             .map_err(Error::BlockHeaderSerialize)?;
         Ok(if entries_count == 0 {
+            block_checksum_append(block_bytes.as_mut())?;
             BlockSerializerContinue::Done(block_bytes)
         } else {
             BlockSerializerContinue::More(BlockSerializer { block_bytes, entries_left: entries_count, })
@@ -134,11 +315,12 @@ impl<B> BlockSerializer<B> where B: AsMut<Vec<u8>> {
     }
 
     pub fn entry(mut self, entry: Entry) -> Result<BlockSerializerContinue<B>, Error> {
-        bincode_options()
+        varint_bincode_options()
             .serialize_into(self.block_bytes.as_mut(), &entry)
             .map_err(Error::EntrySerialize)?;
         self.entries_left -= 1;
         Ok(if self.entries_left == 0 {
+            block_checksum_append(self.block_bytes.as_mut())?;
             BlockSerializerContinue::Done(self.block_bytes)
         } else {
             BlockSerializerContinue::More(self)
@@ -151,39 +333,137 @@ pub enum BlockSerializerContinue<B> {
     More(BlockSerializer<B>),
 }
 
-pub struct BlockDeserializeIter<'a, R, O> where O: Options {
-    deserializer: bincode::Deserializer<R, O>,
+fn block_checksum_append(block_bytes: &mut Vec<u8>) -> Result<(), Error> {
+    let checksum = crc32c::crc32c(block_bytes);
+    bincode_options()
+        .serialize_into(block_bytes, &checksum)
+        .map_err(Error::ChecksumSerialize)
+}
+
+pub fn block_verify_checksum(block_bytes: &[u8], block_ref: &BlockRef) -> Result<(), Error> {
+    let mut reader = block_bytes;
+    let magic: u64 = bincode_options()
+        .deserialize_from(&mut reader)
+        .map_err(Error::BlockMagicDeserialize)?;
+    if magic_store_id(magic, BLOCK_MAGIC).is_none() {
+        return Ok(());
+    }
+    let format_version: u16 = bincode_options()
+        .deserialize_from(&mut reader)
+        .map_err(Error::FormatVersionDeserialize)?;
+    check_format_version(format_version)?;
+    if block_bytes.len() < CHECKSUM_SIZE {
+        return Err(Error::ChecksumMismatch { block_ref: block_ref.clone(), });
+    }
+    let (checked_bytes, mut checksum_bytes) = block_bytes.split_at(block_bytes.len() - CHECKSUM_SIZE);
+    let checksum: u32 = bincode_options()
+        .deserialize_from(&mut checksum_bytes)
+        .map_err(|_error| Error::ChecksumMismatch { block_ref: block_ref.clone(), })?;
+    if crc32c::crc32c(checked_bytes) != checksum {
+        return Err(Error::ChecksumMismatch { block_ref: block_ref.clone(), });
+    }
+    Ok(())
+}
+
+pub struct BlockDeserializeIter<'a> {
     block_bytes: &'a Bytes,
     block_header: BlockHeader,
+    format_version: u16,
+    store_id: u32,
+    offset: usize,
     entries_read: usize,
 }
 
-pub fn block_deserialize_iter<'a>(
-    block_bytes: &'a Bytes,
-)
-    -> Result<BlockDeserializeIter<'a, impl bincode::BincodeRead<'a>, impl Options>, Error>
-{
-    let mut deserializer = bincode::Deserializer::from_slice(block_bytes, bincode_options());
-    let magic: u64 = serde::Deserialize::deserialize(&mut deserializer)
+pub fn block_deserialize_iter<'a>(block_bytes: &'a Bytes) -> Result<BlockDeserializeIter<'a>, Error> {
+    let mut reader: &'a [u8] = block_bytes;
+    let magic: u64 = bincode_options()
+        .deserialize_from(&mut reader)
         .map_err(Error::BlockMagicDeserialize)?;
-    if magic != BLOCK_MAGIC {
-        return Err(Error::InvalidBlockMagic { expected: BLOCK_MAGIC, provided: magic, });
-    }
-    let block_header: BlockHeader = serde::Deserialize::deserialize(&mut deserializer)
-        .map_err(Error::BlockHeaderDeserialize)?;
+    let (format_version, store_id) = match magic_store_id(magic, BLOCK_MAGIC) {
+        Some(store_id) => {
+            let format_version = bincode_options()
+                .deserialize_from(&mut reader)
+                .map_err(Error::FormatVersionDeserialize)?;
+            (format_version, store_id)
+        },
+        None if magic == LEGACY_BLOCK_MAGIC =>
+            (LEGACY_FORMAT_VERSION, 0),
+        None =>
+            return Err(Error::InvalidBlockMagic { expected: BLOCK_MAGIC, provided: magic, }),
+    };
+    check_format_version(format_version)?;
+    let block_header: BlockHeader = match format_version {
+        LEGACY_FORMAT_VERSION | FIXINT_FORMAT_VERSION | VARINT_FORMAT_VERSION =>
+            bincode_options()
+                .deserialize_from::<_, LegacyBlockHeader>(&mut reader)
+                .map_err(Error::BlockHeaderDeserialize)?
+                .into(),
+        MAX_VERSION_FORMAT_VERSION =>
+            bincode_options()
+                .deserialize_from::<_, MaxVersionBlockHeader>(&mut reader)
+                .map_err(Error::BlockHeaderDeserialize)?
+                .into(),
+        CREATED_AT_FORMAT_VERSION =>
+            bincode_options()
+                .deserialize_from::<_, CreatedAtBlockHeader>(&mut reader)
+                .map_err(Error::BlockHeaderDeserialize)?
+                .into(),
+        _ =>
+            bincode_options()
+                .deserialize_from(&mut reader)
+                .map_err(Error::BlockHeaderDeserialize)?,
+    };
     Ok(BlockDeserializeIter {
-        deserializer,
         block_bytes,
         block_header,
+        format_version,
+        store_id,
+        offset: block_bytes.len() - reader.len(),
         entries_read: 0,
     })
 }
 
-impl<'a, R, O> BlockDeserializeIter<'a, R, O> where O: Options {
+fn check_format_version(format_version: u16) -> Result<(), Error> {
+    match format_version {
+        LEGACY_FORMAT_VERSION |
+        FIXINT_FORMAT_VERSION |
+        VARINT_FORMAT_VERSION |
+        MAX_VERSION_FORMAT_VERSION |
+        CREATED_AT_FORMAT_VERSION |
+        FORMAT_VERSION =>
+            Ok(()),
+        found =>
+            Err(Error::UnsupportedFormatVersion { found, supported: FORMAT_VERSION, }),
+    }
+}
+
+impl<'a> BlockDeserializeIter<'a> {
     pub fn block_header(&self) -> &BlockHeader {
         &self.block_header
     }
 
+    pub fn format_version(&self) -> u16 {
+        self.format_version
+    }
+
+    pub fn store_id(&self) -> u32 {
+        self.store_id
+    }
+
+    // false for blocks written before the tree version range was recorded
+    pub fn max_version_recorded(&self) -> bool {
+        self.format_version >= MAX_VERSION_FORMAT_VERSION
+    }
+
+    pub fn root_max_version(&self) -> Option<u64> {
+        match self.block_header.node_type {
+            NodeType::Root { max_version, .. } if self.max_version_recorded() =>
+                Some(max_version),
+            NodeType::Root { .. } | NodeType::Leaf =>
+                None,
+        }
+    }
+
     pub fn to_owned_entry(&'a self, entry: &Entry<'a>) -> OwnedEntry {
         OwnedEntry::from_entry(entry, &self.block_bytes)
     }
@@ -211,7 +491,11 @@ impl OwnedEntry {
                 version: entry.value_cell.version,
                 cell: match &entry.value_cell.cell {
                     Cell::Tombstone =>
-                        kv::Cell::Tombstone,
+                        kv::Cell::Tombstone { meta: None, },
+                    Cell::TombstoneMeta(meta) =>
+                        kv::Cell::Tombstone {
+                            meta: Some(kv::Value { value_bytes: block_bytes.clone_subslice(meta), }),
+                        },
                     Cell::Value(ValueRef::Inline(value)) => {
                         kv::Cell::Value(OwnedValueRef::Inline(kv::Value {
                             value_bytes: block_bytes.clone_subslice(value),
@@ -228,6 +512,48 @@ impl OwnedEntry {
             },
         }
     }
+
+    // local refs of an entry moved to a block on another wheel become external, and vice versa
+    pub fn retarget(self, from_blockwheel_filename: &WheelFilename, to_blockwheel_filename: &WheelFilename) -> OwnedEntry {
+        if from_blockwheel_filename == to_blockwheel_filename {
+            return self;
+        }
+        let jump_ref = match self.jump_ref {
+            OwnedJumpRef::None =>
+                OwnedJumpRef::None,
+            OwnedJumpRef::Local(LocalRef { block_id, }) =>
+                OwnedJumpRef::External(BlockRef {
+                    blockwheel_filename: from_blockwheel_filename.clone(),
+                    block_id,
+                }),
+            OwnedJumpRef::External(BlockRef { blockwheel_filename, block_id, }) if &blockwheel_filename == to_blockwheel_filename =>
+                OwnedJumpRef::Local(LocalRef { block_id, }),
+            OwnedJumpRef::External(block_ref) =>
+                OwnedJumpRef::External(block_ref),
+        };
+        let value_cell = kv::ValueCell {
+            version: self.value_cell.version,
+            cell: match self.value_cell.cell {
+                kv::Cell::Value(value_ref) =>
+                    kv::Cell::Value(OwnedValueBlockRef::from_owned_value_ref(value_ref, from_blockwheel_filename)),
+                kv::Cell::Tombstone { meta, } =>
+                    kv::Cell::Tombstone { meta, },
+            },
+        };
+        OwnedEntry {
+            jump_ref,
+            key: self.key,
+            value_cell: value_cell.into_owned_value_ref(to_blockwheel_filename),
+        }
+    }
+
+    pub fn serialized_size(&self) -> Result<usize, Error> {
+        let entry: Entry = self.into();
+        varint_bincode_options()
+            .serialized_size(&entry)
+            .map(|size| size as usize)
+            .map_err(Error::EntrySerialize)
+    }
 }
 
 impl<'a> From<&'a OwnedEntry> for Entry<'a> {
@@ -302,8 +628,10 @@ impl<'a> From<&'a kv::Cell<OwnedValueRef>> for Cell<'a> {
         match cell {
             kv::Cell::Value(value_ref) =>
                 Cell::Value(value_ref.into()),
-            kv::Cell::Tombstone =>
+            kv::Cell::Tombstone { meta: None, } =>
                 Cell::Tombstone,
+            kv::Cell::Tombstone { meta: Some(meta), } =>
+                Cell::TombstoneMeta(&meta.value_bytes),
         }
     }
 }
@@ -313,8 +641,8 @@ impl From<kv::Cell<kv::Value>> for kv::Cell<OwnedValueRef> {
         match cell {
             kv::Cell::Value(value) =>
                 kv::Cell::Value(OwnedValueRef::Inline(value)),
-            kv::Cell::Tombstone =>
-                kv::Cell::Tombstone,
+            kv::Cell::Tombstone { meta, } =>
+                kv::Cell::Tombstone { meta, },
         }
     }
 }
@@ -376,8 +704,8 @@ impl kv::ValueCell<OwnedValueBlockRef> {
                     kv::Cell::Value(OwnedValueRef::Local(LocalRef { block_id, })),
                 kv::Cell::Value(OwnedValueBlockRef::Ref(block_ref)) =>
                     kv::Cell::Value(OwnedValueRef::External(block_ref)),
-                kv::Cell::Tombstone =>
-                    kv::Cell::Tombstone,
+                kv::Cell::Tombstone { meta, } =>
+                    kv::Cell::Tombstone { meta, },
             },
         }
     }
@@ -397,13 +725,46 @@ impl From<kv::Cell<kv::Value>> for kv::Cell<OwnedValueBlockRef> {
         match cell {
             kv::Cell::Value(value) =>
                 kv::Cell::Value(OwnedValueBlockRef::Inline(value)),
-            kv::Cell::Tombstone =>
-                kv::Cell::Tombstone,
+            kv::Cell::Tombstone { meta, } =>
+                kv::Cell::Tombstone { meta, },
+        }
+    }
+}
+
+// public `crate::ValueRef` as opposed to the serialized `ValueRef` of this module
+impl From<kv::ValueCell<OwnedValueBlockRef>> for kv::ValueCell<crate::ValueRef> {
+    fn from(value_cell: kv::ValueCell<OwnedValueBlockRef>) -> kv::ValueCell<crate::ValueRef> {
+        kv::ValueCell {
+            version: value_cell.version,
+            cell: match value_cell.cell {
+                kv::Cell::Value(OwnedValueBlockRef::Inline(value)) =>
+                    kv::Cell::Value(crate::ValueRef::Inline(value)),
+                kv::Cell::Value(OwnedValueBlockRef::Ref(block_ref)) =>
+                    kv::Cell::Value(crate::ValueRef::Block(block_ref)),
+                kv::Cell::Tombstone { meta, } =>
+                    kv::Cell::Tombstone { meta, },
+            },
+        }
+    }
+}
+
+impl From<kv::ValueCell<crate::ValueRef>> for kv::ValueCell<OwnedValueBlockRef> {
+    fn from(value_cell: kv::ValueCell<crate::ValueRef>) -> kv::ValueCell<OwnedValueBlockRef> {
+        kv::ValueCell {
+            version: value_cell.version,
+            cell: match value_cell.cell {
+                kv::Cell::Value(crate::ValueRef::Inline(value)) =>
+                    kv::Cell::Value(OwnedValueBlockRef::Inline(value)),
+                kv::Cell::Value(crate::ValueRef::Block(block_ref)) =>
+                    kv::Cell::Value(OwnedValueBlockRef::Ref(block_ref)),
+                kv::Cell::Tombstone { meta, } =>
+                    kv::Cell::Tombstone { meta, },
+            },
         }
     }
 }
 
-impl<'a, R, O> Iterator for BlockDeserializeIter<'a, R, O> where R: bincode::BincodeRead<'a>, O: Options {
+impl<'a> Iterator for BlockDeserializeIter<'a> {
     type Item = Result<Entry<'a>, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -411,29 +772,65 @@ impl<'a, R, O> Iterator for BlockDeserializeIter<'a, R, O> where R: bincode::Bin
             None
         } else {
             self.entries_read += 1;
-            let maybe_entry: Result<Entry<'_>, Error> = serde::Deserialize::deserialize(&mut self.deserializer)
-                .map_err(Error::EntryDeserialize);
+            let block_bytes: &'a [u8] = self.block_bytes;
+            let entry_bytes = &block_bytes[self.offset ..];
+            let maybe_entry = match self.format_version {
+                LEGACY_FORMAT_VERSION | FIXINT_FORMAT_VERSION =>
+                    entry_deserialize(entry_bytes, bincode_options),
+                _ =>
+                    entry_deserialize(entry_bytes, varint_bincode_options),
+            };
             match maybe_entry {
-                Ok(entry) =>
-                    Some(Ok(entry)),
-                Err(error) =>
-                    Some(Err(error)),
+                Ok((entry, entry_size)) => {
+                    self.offset += entry_size;
+                    Some(Ok(entry))
+                },
+                Err(error) => {
+                    self.entries_read = self.block_header.entries_count;
+                    Some(Err(error))
+                },
             }
         }
     }
 }
 
+fn entry_deserialize<'a, F, O>(entry_bytes: &'a [u8], options: F) -> Result<(Entry<'a>, usize), Error>
+where F: Fn() -> O,
+      O: Options,
+{
+    let entry: Entry<'a> = options()
+        .deserialize(entry_bytes)
+        .map_err(Error::EntryDeserialize)?;
+    let entry_size = options()
+        .serialized_size(&entry)
+        .map_err(Error::EntryDeserialize)?;
+    Ok((entry, entry_size as usize))
+}
+
 pub const VALUE_BLOCK_MAGIC: u64 = 0x5df58182f2741b7a;
 
+// values of up to `values_inline_size_limit` bytes are inlined, except for a zero limit which sends
+// every value out of line, empty ones included
+pub fn value_out_of_line(value_bytes: &[u8], storage_preference: StoragePreference, values_inline_size_limit: usize) -> bool {
+    match storage_preference {
+        StoragePreference::Inline =>
+            false,
+        StoragePreference::OutOfLine =>
+            true,
+        StoragePreference::Auto =>
+            values_inline_size_limit == 0 || value_bytes.len() > values_inline_size_limit,
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 struct ValueBlock<'a> {
     value_block: &'a [u8],
 }
 
-pub fn value_block_serialize<B>(value_block: &[u8], mut block_bytes: B) -> Result<(), Error> where B: AsMut<Vec<u8>> {
+pub fn value_block_serialize<B>(value_block: &[u8], store_id: u32, mut block_bytes: B) -> Result<(), Error> where B: AsMut<Vec<u8>> {
     block_bytes.as_mut().clear();
     bincode_options()
-        .serialize_into(block_bytes.as_mut(), &VALUE_BLOCK_MAGIC)
+        .serialize_into(block_bytes.as_mut(), &store_magic(VALUE_BLOCK_MAGIC, store_id))
         .map_err(Error::BlockMagicSerialize)?;
     bincode_options()
         .serialize_into(block_bytes.as_mut(), &ValueBlock {
@@ -447,7 +844,7 @@ pub fn value_block_deserialize(block_bytes: &Bytes) -> Result<Bytes, Error> {
     let mut deserializer = bincode::Deserializer::from_slice(block_bytes, bincode_options());
     let magic: u64 = serde::Deserialize::deserialize(&mut deserializer)
         .map_err(Error::BlockMagicDeserialize)?;
-    if magic != VALUE_BLOCK_MAGIC {
+    if magic_store_id(magic, VALUE_BLOCK_MAGIC).is_none() {
         return Err(Error::InvalidBlockMagic { expected: VALUE_BLOCK_MAGIC, provided: magic, });
     }
     let value_block: ValueBlock<'_> = serde::Deserialize::deserialize(&mut deserializer)
@@ -455,6 +852,309 @@ pub fn value_block_deserialize(block_bytes: &Bytes) -> Result<Bytes, Error> {
     Ok(block_bytes.clone_subslice(value_block.value_block))
 }
 
+pub const WAL_RECORD_MAGIC: u64 = 0x94c2e07b1d6a5f38;
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct WalRecord<'a> {
+    key: &'a [u8],
+    #[serde(borrow)]
+    value_cell: ValueCell<'a>,
+}
+
+pub fn wal_record_serialize<B>(
+    key: &kv::Key,
+    value_cell: &kv::ValueCell<kv::Value>,
+    store_id: u32,
+    mut block_bytes: B,
+)
+    -> Result<(), Error>
+where B: AsMut<Vec<u8>>
+{
+    block_bytes.as_mut().clear();
+    bincode_options()
+        .serialize_into(block_bytes.as_mut(), &store_magic(WAL_RECORD_MAGIC, store_id))
+        .map_err(Error::BlockMagicSerialize)?;
+    varint_bincode_options()
+        .serialize_into(block_bytes.as_mut(), &WalRecord {
+            key: &key.key_bytes,
+            value_cell: value_cell.into(),
+        })
+        .map_err(Error::WalRecordSerialize)?;
+    block_checksum_append(block_bytes.as_mut())
+}
+
+pub fn wal_record_deserialize(block_bytes: &Bytes) -> Result<(kv::Key, kv::ValueCell<kv::Value>), Error> {
+    if block_bytes.len() < CHECKSUM_SIZE {
+        return Err(Error::WalRecordChecksumMismatch);
+    }
+    let (checked_bytes, mut checksum_bytes) = block_bytes.split_at(block_bytes.len() - CHECKSUM_SIZE);
+    let checksum: u32 = bincode_options()
+        .deserialize_from(&mut checksum_bytes)
+        .map_err(|_error| Error::WalRecordChecksumMismatch)?;
+    if crc32c::crc32c(checked_bytes) != checksum {
+        return Err(Error::WalRecordChecksumMismatch);
+    }
+
+    let mut reader = checked_bytes;
+    let magic: u64 = bincode_options()
+        .deserialize_from(&mut reader)
+        .map_err(Error::BlockMagicDeserialize)?;
+    if magic_store_id(magic, WAL_RECORD_MAGIC).is_none() {
+        return Err(Error::InvalidBlockMagic { expected: WAL_RECORD_MAGIC, provided: magic, });
+    }
+    let wal_record: WalRecord<'_> = varint_bincode_options()
+        .deserialize(reader)
+        .map_err(Error::WalRecordDeserialize)?;
+    let key = kv::Key {
+        key_bytes: block_bytes.clone_subslice(wal_record.key),
+    };
+    let value_cell = kv::ValueCell {
+        version: wal_record.value_cell.version,
+        cell: match wal_record.value_cell.cell {
+            Cell::Value(ValueRef::Inline(value)) =>
+                kv::Cell::Value(kv::Value { value_bytes: block_bytes.clone_subslice(value), }),
+            Cell::Value(..) =>
+                return Err(Error::WalRecordValueNotInline),
+            Cell::Tombstone =>
+                kv::Cell::Tombstone { meta: None, },
+            Cell::TombstoneMeta(meta) =>
+                kv::Cell::Tombstone {
+                    meta: Some(kv::Value { value_bytes: block_bytes.clone_subslice(meta), }),
+                },
+        },
+    };
+    Ok((key, value_cell))
+}
+
+pub const RANGE_TOMBSTONE_MAGIC: u64 = 0x2d7f0c98e4a1b653;
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct RangeTombstoneRecord<'a> {
+    #[serde(borrow)]
+    range_from: Bound<&'a [u8]>,
+    #[serde(borrow)]
+    range_to: Bound<&'a [u8]>,
+    version: u64,
+}
+
+pub fn range_tombstone_serialize<B>(
+    range_from: Bound<&kv::Key>,
+    range_to: Bound<&kv::Key>,
+    version: u64,
+    store_id: u32,
+    mut block_bytes: B,
+)
+    -> Result<(), Error>
+where B: AsMut<Vec<u8>>
+{
+    block_bytes.as_mut().clear();
+    bincode_options()
+        .serialize_into(block_bytes.as_mut(), &store_magic(RANGE_TOMBSTONE_MAGIC, store_id))
+        .map_err(Error::BlockMagicSerialize)?;
+    varint_bincode_options()
+        .serialize_into(block_bytes.as_mut(), &RangeTombstoneRecord {
+            range_from: bound_key_bytes(range_from),
+            range_to: bound_key_bytes(range_to),
+            version,
+        })
+        .map_err(Error::RangeTombstoneSerialize)?;
+    block_checksum_append(block_bytes.as_mut())
+}
+
+fn bound_key_bytes(bound: Bound<&kv::Key>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Unbounded =>
+            Bound::Unbounded,
+        Bound::Included(key) =>
+            Bound::Included(&key.key_bytes),
+        Bound::Excluded(key) =>
+            Bound::Excluded(&key.key_bytes),
+    }
+}
+
+pub fn range_tombstone_deserialize(block_bytes: &Bytes) -> Result<(Bound<kv::Key>, Bound<kv::Key>, u64), Error> {
+    if block_bytes.len() < CHECKSUM_SIZE {
+        return Err(Error::RangeTombstoneChecksumMismatch);
+    }
+    let (checked_bytes, mut checksum_bytes) = block_bytes.split_at(block_bytes.len() - CHECKSUM_SIZE);
+    let checksum: u32 = bincode_options()
+        .deserialize_from(&mut checksum_bytes)
+        .map_err(|_error| Error::RangeTombstoneChecksumMismatch)?;
+    if crc32c::crc32c(checked_bytes) != checksum {
+        return Err(Error::RangeTombstoneChecksumMismatch);
+    }
+
+    let mut reader = checked_bytes;
+    let magic: u64 = bincode_options()
+        .deserialize_from(&mut reader)
+        .map_err(Error::BlockMagicDeserialize)?;
+    if magic_store_id(magic, RANGE_TOMBSTONE_MAGIC).is_none() {
+        return Err(Error::InvalidBlockMagic { expected: RANGE_TOMBSTONE_MAGIC, provided: magic, });
+    }
+    let record: RangeTombstoneRecord<'_> = varint_bincode_options()
+        .deserialize(reader)
+        .map_err(Error::RangeTombstoneDeserialize)?;
+    let owned_key = |bound: Bound<&[u8]>| match bound {
+        Bound::Unbounded =>
+            Bound::Unbounded,
+        Bound::Included(key) =>
+            Bound::Included(kv::Key { key_bytes: block_bytes.clone_subslice(key), }),
+        Bound::Excluded(key) =>
+            Bound::Excluded(kv::Key { key_bytes: block_bytes.clone_subslice(key), }),
+    };
+    Ok((owned_key(record.range_from), owned_key(record.range_to), record.version))
+}
+
+// written by a merge once its output root is on disk and deleted after the inputs are demolished:
+// roots listed as inputs are superseded when the output root is found as well
+pub const MERGE_RECORD_MAGIC: u64 = 0x5b13e2a7c90d46f8;
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct MergeRecord<'a> {
+    #[serde(borrow)]
+    output_root: ExternalRef<'a>,
+    #[serde(borrow)]
+    input_roots: Vec<ExternalRef<'a>>,
+}
+
+pub fn merge_record_serialize<B>(
+    output_root: &BlockRef,
+    input_roots: &[BlockRef],
+    store_id: u32,
+    mut block_bytes: B,
+)
+    -> Result<(), Error>
+where B: AsMut<Vec<u8>>
+{
+    let external_ref = |block_ref: &BlockRef| ExternalRef {
+        filename: &*block_ref.blockwheel_filename,
+        block_id: block_ref.block_id.clone(),
+    };
+    block_bytes.as_mut().clear();
+    bincode_options()
+        .serialize_into(block_bytes.as_mut(), &store_magic(MERGE_RECORD_MAGIC, store_id))
+        .map_err(Error::BlockMagicSerialize)?;
+    varint_bincode_options()
+        .serialize_into(block_bytes.as_mut(), &MergeRecord {
+            output_root: external_ref(output_root),
+            input_roots: input_roots.iter().map(external_ref).collect(),
+        })
+        .map_err(Error::MergeRecordSerialize)?;
+    block_checksum_append(block_bytes.as_mut())
+}
+
+pub fn merge_record_deserialize(block_bytes: &Bytes) -> Result<(BlockRef, Vec<BlockRef>), Error> {
+    if block_bytes.len() < CHECKSUM_SIZE {
+        return Err(Error::MergeRecordChecksumMismatch);
+    }
+    let (checked_bytes, mut checksum_bytes) = block_bytes.split_at(block_bytes.len() - CHECKSUM_SIZE);
+    let checksum: u32 = bincode_options()
+        .deserialize_from(&mut checksum_bytes)
+        .map_err(|_error| Error::MergeRecordChecksumMismatch)?;
+    if crc32c::crc32c(checked_bytes) != checksum {
+        return Err(Error::MergeRecordChecksumMismatch);
+    }
+
+    let mut reader = checked_bytes;
+    let magic: u64 = bincode_options()
+        .deserialize_from(&mut reader)
+        .map_err(Error::BlockMagicDeserialize)?;
+    if magic_store_id(magic, MERGE_RECORD_MAGIC).is_none() {
+        return Err(Error::InvalidBlockMagic { expected: MERGE_RECORD_MAGIC, provided: magic, });
+    }
+    let record: MergeRecord<'_> = varint_bincode_options()
+        .deserialize(reader)
+        .map_err(Error::MergeRecordDeserialize)?;
+    let owned_ref = |external_ref: &ExternalRef<'_>| BlockRef {
+        blockwheel_filename: block_bytes.clone_subslice(external_ref.filename).into(),
+        block_id: external_ref.block_id.clone(),
+    };
+    Ok((owned_ref(&record.output_root), record.input_roots.iter().map(owned_ref).collect()))
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+enum CompressionAlgorithm {
+    Zstd,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct CompressedBlockHeader {
+    algorithm: CompressionAlgorithm,
+    uncompressed_size: usize,
+}
+
+pub fn block_compress<B>(block_bytes: &[u8], compression: &Compression, mut compressed_bytes: B) -> Result<(), Error> where B: AsMut<Vec<u8>> {
+    if block_bytes.len() > MAX_BLOCK_SIZE {
+        return Err(Error::BlockTooLarge { size: block_bytes.len(), max: MAX_BLOCK_SIZE, });
+    }
+    let compressed_bytes = compressed_bytes.as_mut();
+    compressed_bytes.clear();
+    bincode_options()
+        .serialize_into(&mut *compressed_bytes, &COMPRESSED_BLOCK_MAGIC)
+        .map_err(Error::BlockMagicSerialize)?;
+    let (algorithm, level) = match compression {
+        &Compression::Zstd { level, } =>
+            (CompressionAlgorithm::Zstd, level),
+    };
+    bincode_options()
+        .serialize_into(&mut *compressed_bytes, &CompressedBlockHeader {
+            algorithm,
+            uncompressed_size: block_bytes.len(),
+        })
+        .map_err(Error::CompressedBlockHeaderSerialize)?;
+    match algorithm {
+        CompressionAlgorithm::Zstd =>
+            zstd::stream::copy_encode(block_bytes, &mut *compressed_bytes, level)
+                .map_err(Error::BlockCompress)?,
+    }
+    Ok(())
+}
+
+pub fn block_is_compressed(block_bytes: &[u8]) -> bool {
+    let mut reader = block_bytes;
+    match bincode_options().deserialize_from::<_, u64>(&mut reader) {
+        Ok(magic) =>
+            magic == COMPRESSED_BLOCK_MAGIC,
+        Err(..) =>
+            false,
+    }
+}
+
+pub fn block_decompress(block_bytes: Bytes, blocks_pool: &BytesPool) -> Result<Bytes, Error> {
+    if !block_is_compressed(&block_bytes) {
+        return Ok(block_bytes);
+    }
+    let mut reader: &[u8] = &block_bytes;
+    let _magic: u64 = bincode_options()
+        .deserialize_from(&mut reader)
+        .map_err(Error::BlockMagicDeserialize)?;
+    let compressed_block_header: CompressedBlockHeader = bincode_options()
+        .deserialize_from(&mut reader)
+        .map_err(Error::CompressedBlockHeaderDeserialize)?;
+    let uncompressed_size = compressed_block_header.uncompressed_size;
+    if uncompressed_size > MAX_BLOCK_SIZE {
+        return Err(Error::BlockTooLarge { size: uncompressed_size, max: MAX_BLOCK_SIZE, });
+    }
+    let mut decompressed_bytes = blocks_pool.lend();
+    decompressed_bytes.reserve(uncompressed_size);
+    match compressed_block_header.algorithm {
+        CompressionAlgorithm::Zstd => {
+            // one byte over the declared size is enough to detect a lying header without inflating further
+            let decoder = zstd::stream::read::Decoder::new(reader)
+                .map_err(Error::BlockDecompress)?;
+            io::copy(&mut io::Read::take(decoder, uncompressed_size as u64 + 1), decompressed_bytes.as_mut())
+                .map_err(Error::BlockDecompress)?;
+        },
+    }
+    if decompressed_bytes.len() != uncompressed_size {
+        return Err(Error::UncompressedSizeMismatch {
+            expected: uncompressed_size,
+            provided: decompressed_bytes.len(),
+        });
+    }
+    Ok(decompressed_bytes.freeze())
+}
+
 fn bincode_options() -> impl Options {
     bincode::DefaultOptions::new()
         .with_no_limit()
@@ -462,3 +1162,548 @@ fn bincode_options() -> impl Options {
         .with_fixint_encoding()
         .allow_trailing_bytes()
 }
+
+fn varint_bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_no_limit()
+        .with_big_endian()
+        .with_varint_encoding()
+        .allow_trailing_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use rand::Rng;
+
+    use bincode::Options;
+
+    use alloc_pool::bytes::BytesPool;
+
+    use ero_blockwheel_fs::block;
+
+    use crate::{
+        kv,
+        wheels::{
+            BlockRef,
+            WheelFilename,
+        },
+        comparator,
+        Compression,
+        StoragePreference,
+    };
+
+    use super::{
+        block_deserialize_iter,
+        value_out_of_line,
+        BlockSerializer,
+        BlockSerializerContinue,
+        NodeType,
+        LegacyBlockHeader,
+        LegacyNodeType,
+        MaxVersionBlockHeader,
+        MaxVersionNodeType,
+        CreatedAtBlockHeader,
+        CreatedAtNodeType,
+        CREATED_AT_FORMAT_VERSION,
+        OwnedEntry,
+        OwnedJumpRef,
+        OwnedValueRef,
+        BLOCK_MAGIC,
+        VALUE_BLOCK_MAGIC,
+        FORMAT_VERSION,
+        VARINT_FORMAT_VERSION,
+        MAX_VERSION_FORMAT_VERSION,
+        bincode_options,
+        block_checksum_append,
+        block_verify_checksum,
+        LEGACY_BLOCK_MAGIC,
+        wal_record_serialize,
+        wal_record_deserialize,
+        range_tombstone_serialize,
+        range_tombstone_deserialize,
+        merge_record_serialize,
+        merge_record_deserialize,
+        value_block_serialize,
+        value_block_deserialize,
+        magic_store_id,
+        block_compress,
+        block_decompress,
+        block_is_compressed,
+        CompressedBlockHeader,
+        CompressionAlgorithm,
+        COMPRESSED_BLOCK_MAGIC,
+        MAX_BLOCK_SIZE,
+        Error,
+    };
+
+    fn random_bytes(blocks_pool: &BytesPool, len: usize) -> alloc_pool::bytes::Bytes {
+        let mut rng = rand::thread_rng();
+        let mut bytes = blocks_pool.lend();
+        bytes.extend((0 .. len).map(|_| rng.gen::<u8>()));
+        bytes.freeze()
+    }
+
+    fn roundtrip(blocks_pool: &BytesPool, sizes: &[(usize, usize, u64)]) {
+        let entries: Vec<_> = sizes.iter()
+            .map(|&(key_len, value_len, version)| OwnedEntry {
+                jump_ref: OwnedJumpRef::None,
+                key: kv::Key { key_bytes: random_bytes(blocks_pool, key_len), },
+                value_cell: kv::ValueCell {
+                    version,
+                    cell: if value_len % 7 == 3 {
+                        kv::Cell::Tombstone { meta: None, }
+                    } else if value_len % 7 == 5 {
+                        kv::Cell::Tombstone {
+                            meta: Some(kv::Value { value_bytes: random_bytes(blocks_pool, value_len), }),
+                        }
+                    } else {
+                        kv::Cell::Value(OwnedValueRef::Inline(kv::Value {
+                            value_bytes: random_bytes(blocks_pool, value_len),
+                        }))
+                    },
+                },
+            })
+            .collect();
+
+        let mut kont = BlockSerializer::start(NodeType::Leaf, entries.len(), 0, blocks_pool.lend()).unwrap();
+        let mut entries_iter = entries.iter();
+        let block_bytes = loop {
+            match kont {
+                BlockSerializerContinue::Done(block_bytes) =>
+                    break block_bytes.freeze(),
+                BlockSerializerContinue::More(serializer) =>
+                    kont = serializer.entry(entries_iter.next().unwrap().into()).unwrap(),
+            }
+        };
+        assert!(entries_iter.next().is_none());
+
+        let deserializer = block_deserialize_iter(&block_bytes).unwrap();
+        assert_eq!(deserializer.format_version(), FORMAT_VERSION);
+        assert_eq!(deserializer.block_header().entries_count, entries.len());
+        let mut entries_count = 0;
+        for (maybe_entry, expected) in deserializer.zip(entries.iter()) {
+            let entry = maybe_entry.unwrap();
+            assert_eq!(entry.key, &*expected.key.key_bytes);
+            assert_eq!(entry.value_cell.version, expected.value_cell.version);
+            match (&entry.value_cell.cell, &expected.value_cell.cell) {
+                (super::Cell::Value(super::ValueRef::Inline(value)), kv::Cell::Value(OwnedValueRef::Inline(expected_value))) =>
+                    assert_eq!(*value, &*expected_value.value_bytes),
+                (super::Cell::Tombstone, kv::Cell::Tombstone { meta: None, }) =>
+                    (),
+                (super::Cell::TombstoneMeta(meta), kv::Cell::Tombstone { meta: Some(expected_meta), }) =>
+                    assert_eq!(*meta, &*expected_meta.value_bytes),
+                _ =>
+                    panic!("value cell mismatch"),
+            }
+            entries_count += 1;
+        }
+        assert_eq!(entries_count, entries.len());
+    }
+
+    #[test]
+    fn varint_roundtrip_random() {
+        let blocks_pool = BytesPool::new();
+        let mut rng = rand::thread_rng();
+        for _ in 0 .. 64 {
+            let sizes: Vec<(usize, usize, u64)> = (0 .. rng.gen_range(0 .. 64))
+                .map(|_| (rng.gen_range(0 .. 512), rng.gen_range(0 .. 512), rng.gen()))
+                .collect();
+            roundtrip(&blocks_pool, &sizes);
+        }
+    }
+
+    #[test]
+    fn varint_roundtrip_edge_cases() {
+        let blocks_pool = BytesPool::new();
+        roundtrip(&blocks_pool, &[]);
+        roundtrip(&blocks_pool, &[(0, 0, 0)]);
+        roundtrip(&blocks_pool, &[(0, 1, u64::MAX), (1, 0, 0)]);
+        roundtrip(&blocks_pool, &[(250, 251, 250), (251, 250, 251), (65535, 65536, 65536)]);
+        roundtrip(&blocks_pool, &[(1 << 20, 4 << 20, 1 << 40)]);
+    }
+
+    #[test]
+    fn root_header_max_version() {
+        let blocks_pool = BytesPool::new();
+        let node_type = NodeType::Root { tree_entries_count: 7, max_version: 42, created_at_version: 17, comparator_id: 3, };
+        let block_bytes = match BlockSerializer::start(node_type, 0, 0, blocks_pool.lend()).unwrap() {
+            BlockSerializerContinue::Done(block_bytes) =>
+                block_bytes.freeze(),
+            BlockSerializerContinue::More(..) =>
+                unreachable!(),
+        };
+        let deserializer = block_deserialize_iter(&block_bytes).unwrap();
+        assert!(matches!(
+            deserializer.block_header().node_type,
+            NodeType::Root { tree_entries_count: 7, max_version: 42, created_at_version: 17, comparator_id: 3, },
+        ));
+
+        let mut created_at_bytes = blocks_pool.lend();
+        bincode_options().serialize_into(created_at_bytes.as_mut(), &BLOCK_MAGIC).unwrap();
+        bincode_options().serialize_into(created_at_bytes.as_mut(), &CREATED_AT_FORMAT_VERSION).unwrap();
+        let created_at_header = CreatedAtBlockHeader {
+            node_type: CreatedAtNodeType::Root { tree_entries_count: 7, max_version: 42, created_at_version: 17, },
+            entries_count: 0,
+        };
+        bincode_options().serialize_into(created_at_bytes.as_mut(), &created_at_header).unwrap();
+        block_checksum_append(created_at_bytes.as_mut()).unwrap();
+        let created_at_bytes = created_at_bytes.freeze();
+        let deserializer = block_deserialize_iter(&created_at_bytes).unwrap();
+        assert_eq!(deserializer.format_version(), CREATED_AT_FORMAT_VERSION);
+        assert!(matches!(
+            deserializer.block_header().node_type,
+            NodeType::Root { tree_entries_count: 7, max_version: 42, created_at_version: 17, comparator_id: comparator::BYTEWISE_ID, },
+        ));
+
+        let mut max_version_bytes = blocks_pool.lend();
+        bincode_options().serialize_into(max_version_bytes.as_mut(), &BLOCK_MAGIC).unwrap();
+        bincode_options().serialize_into(max_version_bytes.as_mut(), &MAX_VERSION_FORMAT_VERSION).unwrap();
+        let max_version_header = MaxVersionBlockHeader {
+            node_type: MaxVersionNodeType::Root { tree_entries_count: 7, max_version: 42, },
+            entries_count: 0,
+        };
+        bincode_options().serialize_into(max_version_bytes.as_mut(), &max_version_header).unwrap();
+        block_checksum_append(max_version_bytes.as_mut()).unwrap();
+        let max_version_bytes = max_version_bytes.freeze();
+        let deserializer = block_deserialize_iter(&max_version_bytes).unwrap();
+        assert_eq!(deserializer.format_version(), MAX_VERSION_FORMAT_VERSION);
+        assert_eq!(deserializer.root_max_version(), Some(42));
+        assert!(matches!(
+            deserializer.block_header().node_type,
+            NodeType::Root { tree_entries_count: 7, max_version: 42, created_at_version: 0, comparator_id: comparator::BYTEWISE_ID, },
+        ));
+
+        let mut legacy_bytes = blocks_pool.lend();
+        bincode_options().serialize_into(legacy_bytes.as_mut(), &BLOCK_MAGIC).unwrap();
+        bincode_options().serialize_into(legacy_bytes.as_mut(), &VARINT_FORMAT_VERSION).unwrap();
+        let legacy_header = LegacyBlockHeader {
+            node_type: LegacyNodeType::Root { tree_entries_count: 7, },
+            entries_count: 0,
+        };
+        bincode_options().serialize_into(legacy_bytes.as_mut(), &legacy_header).unwrap();
+        block_checksum_append(legacy_bytes.as_mut()).unwrap();
+        let legacy_bytes = legacy_bytes.freeze();
+        let deserializer = block_deserialize_iter(&legacy_bytes).unwrap();
+        assert_eq!(deserializer.format_version(), VARINT_FORMAT_VERSION);
+        assert_eq!(deserializer.root_max_version(), None);
+        assert!(matches!(
+            deserializer.block_header().node_type,
+            NodeType::Root { tree_entries_count: 7, max_version: u64::MAX, created_at_version: 0, comparator_id: comparator::BYTEWISE_ID, },
+        ));
+    }
+
+    #[test]
+    fn corrupted_block_detected() {
+        let blocks_pool = BytesPool::new();
+        let block_ref = BlockRef {
+            blockwheel_filename: WheelFilename::from_str("wheel", &blocks_pool),
+            block_id: block::Id::init(),
+        };
+        let entry = OwnedEntry {
+            jump_ref: OwnedJumpRef::None,
+            key: kv::Key { key_bytes: random_bytes(&blocks_pool, 16), },
+            value_cell: kv::ValueCell {
+                version: 1,
+                cell: kv::Cell::Value(OwnedValueRef::Inline(kv::Value { value_bytes: random_bytes(&blocks_pool, 32), })),
+            },
+        };
+        let block_bytes = match BlockSerializer::start(NodeType::Leaf, 1, 0, blocks_pool.lend()).unwrap() {
+            BlockSerializerContinue::More(serializer) =>
+                match serializer.entry((&entry).into()).unwrap() {
+                    BlockSerializerContinue::Done(block_bytes) =>
+                        block_bytes,
+                    BlockSerializerContinue::More(..) =>
+                        unreachable!(),
+                },
+            BlockSerializerContinue::Done(..) =>
+                unreachable!(),
+        };
+        block_verify_checksum(&block_bytes, &block_ref).unwrap();
+
+        for offset in &[20, block_bytes.len() / 2, block_bytes.len() - 1] {
+            let mut corrupted = blocks_pool.lend();
+            corrupted.extend_from_slice(&block_bytes);
+            corrupted[*offset] ^= 0x01;
+            assert!(matches!(
+                block_verify_checksum(&corrupted, &block_ref),
+                Err(Error::ChecksumMismatch { block_ref: ref corrupted_ref, }) if corrupted_ref == &block_ref,
+            ));
+        }
+
+        // legacy blocks have no checksum to verify
+        let mut legacy_bytes = blocks_pool.lend();
+        bincode_options().serialize_into(legacy_bytes.as_mut(), &LEGACY_BLOCK_MAGIC).unwrap();
+        let legacy_header = LegacyBlockHeader {
+            node_type: LegacyNodeType::Leaf,
+            entries_count: 0,
+        };
+        bincode_options().serialize_into(legacy_bytes.as_mut(), &legacy_header).unwrap();
+        block_verify_checksum(&legacy_bytes, &block_ref).unwrap();
+        assert_eq!(block_deserialize_iter(&legacy_bytes.freeze()).unwrap().block_header().entries_count, 0);
+    }
+
+    #[test]
+    fn store_id_mixed_into_magic() {
+        let blocks_pool = BytesPool::new();
+        for &store_id in &[0, 1, u32::MAX] {
+            let block_bytes = match BlockSerializer::start(NodeType::Leaf, 0, store_id, blocks_pool.lend()).unwrap() {
+                BlockSerializerContinue::Done(block_bytes) =>
+                    block_bytes.freeze(),
+                BlockSerializerContinue::More(..) =>
+                    unreachable!(),
+            };
+            let deserializer = block_deserialize_iter(&block_bytes).unwrap();
+            assert_eq!(deserializer.store_id(), store_id);
+
+            let mut value_bytes = blocks_pool.lend();
+            value_block_serialize(b"value", store_id, &mut value_bytes).unwrap();
+            let value_bytes = value_bytes.freeze();
+            assert_eq!(&*value_block_deserialize(&value_bytes).unwrap(), b"value");
+            match block_deserialize_iter(&value_bytes) {
+                Err(Error::InvalidBlockMagic { provided, .. }) => {
+                    assert_eq!(magic_store_id(provided, VALUE_BLOCK_MAGIC), Some(store_id));
+                    assert_eq!(magic_store_id(provided, BLOCK_MAGIC), None);
+                },
+                _ =>
+                    panic!("value block taken for a tree block"),
+            }
+        }
+    }
+
+    #[test]
+    fn wal_record_roundtrip() {
+        let blocks_pool = BytesPool::new();
+        let key = kv::Key { key_bytes: random_bytes(&blocks_pool, 16), };
+        let value_cell = kv::ValueCell {
+            version: 17,
+            cell: kv::Cell::Value(kv::Value { value_bytes: random_bytes(&blocks_pool, 64), }),
+        };
+        let mut record_bytes = blocks_pool.lend();
+        wal_record_serialize(&key, &value_cell, 0, &mut record_bytes).unwrap();
+        let record_bytes = record_bytes.freeze();
+        let (read_key, read_value_cell) = wal_record_deserialize(&record_bytes).unwrap();
+        assert_eq!(read_key, key);
+        assert_eq!(read_value_cell, value_cell);
+
+        let tombstone = kv::ValueCell { version: 18, cell: kv::Cell::Tombstone { meta: None, }, };
+        let mut record_bytes = blocks_pool.lend();
+        wal_record_serialize(&key, &tombstone, 0, &mut record_bytes).unwrap();
+        let mut corrupted = blocks_pool.lend();
+        corrupted.extend_from_slice(&record_bytes);
+        let record_bytes = record_bytes.freeze();
+        assert_eq!(wal_record_deserialize(&record_bytes).unwrap().1, tombstone);
+
+        corrupted[12] ^= 0x01;
+        assert!(matches!(wal_record_deserialize(&corrupted.freeze()), Err(Error::WalRecordChecksumMismatch)));
+
+        let tombstone_meta = kv::ValueCell {
+            version: 19,
+            cell: kv::Cell::Tombstone { meta: Some(kv::Value { value_bytes: random_bytes(&blocks_pool, 8), }), },
+        };
+        let mut record_bytes = blocks_pool.lend();
+        wal_record_serialize(&key, &tombstone_meta, 0, &mut record_bytes).unwrap();
+        assert_eq!(wal_record_deserialize(&record_bytes.freeze()).unwrap().1, tombstone_meta);
+    }
+
+    #[test]
+    fn empty_key_and_value_roundtrip() {
+        let blocks_pool = BytesPool::new();
+        let key = kv::Key { key_bytes: random_bytes(&blocks_pool, 0), };
+        let value_cell = kv::ValueCell {
+            version: 1,
+            cell: kv::Cell::Value(kv::Value { value_bytes: random_bytes(&blocks_pool, 0), }),
+        };
+        let mut record_bytes = blocks_pool.lend();
+        wal_record_serialize(&key, &value_cell, 0, &mut record_bytes).unwrap();
+        let (read_key, read_value_cell) = wal_record_deserialize(&record_bytes.freeze()).unwrap();
+        assert_eq!(read_key, key);
+        assert_eq!(read_value_cell, value_cell);
+
+        let tombstone = kv::ValueCell { version: 2, cell: kv::Cell::Tombstone { meta: None, }, };
+        let mut record_bytes = blocks_pool.lend();
+        wal_record_serialize(&key, &tombstone, 0, &mut record_bytes).unwrap();
+        let (_, read_tombstone) = wal_record_deserialize(&record_bytes.freeze()).unwrap();
+        assert_eq!(read_tombstone, tombstone);
+        assert_ne!(read_tombstone.cell, value_cell.cell);
+
+        let mut block_bytes = blocks_pool.lend();
+        value_block_serialize(&[], 0, &mut block_bytes).unwrap();
+        let value_bytes = value_block_deserialize(&block_bytes.freeze()).unwrap();
+        assert!(value_bytes.is_empty());
+
+        // tree block: an empty inline value is read back as a value, the next entry as a tombstone
+        let entries = vec![
+            OwnedEntry {
+                jump_ref: OwnedJumpRef::None,
+                key: key.clone(),
+                value_cell: kv::ValueCell {
+                    version: 1,
+                    cell: kv::Cell::Value(OwnedValueRef::Inline(kv::Value { value_bytes: random_bytes(&blocks_pool, 0), })),
+                },
+            },
+            OwnedEntry {
+                jump_ref: OwnedJumpRef::None,
+                key: kv::Key { key_bytes: random_bytes(&blocks_pool, 1), },
+                value_cell: kv::ValueCell { version: 2, cell: kv::Cell::Tombstone { meta: None, }, },
+            },
+        ];
+        let mut kont = BlockSerializer::start(NodeType::Leaf, entries.len(), 0, blocks_pool.lend()).unwrap();
+        let mut entries_iter = entries.iter();
+        let block_bytes = loop {
+            match kont {
+                BlockSerializerContinue::Done(block_bytes) =>
+                    break block_bytes.freeze(),
+                BlockSerializerContinue::More(serializer) =>
+                    kont = serializer.entry(entries_iter.next().unwrap().into()).unwrap(),
+            }
+        };
+        let mut deserializer = block_deserialize_iter(&block_bytes).unwrap();
+        let entry = deserializer.next().unwrap().unwrap();
+        assert!(entry.key.is_empty());
+        assert!(matches!(entry.value_cell.cell, super::Cell::Value(super::ValueRef::Inline(value)) if value.is_empty()));
+        let entry = deserializer.next().unwrap().unwrap();
+        assert_eq!(entry.key, &*entries[1].key.key_bytes);
+        assert!(matches!(entry.value_cell.cell, super::Cell::Tombstone));
+        assert!(deserializer.next().is_none());
+    }
+
+    #[test]
+    fn range_tombstone_roundtrip() {
+        let blocks_pool = BytesPool::new();
+        let key_a = kv::Key { key_bytes: random_bytes(&blocks_pool, 16), };
+        let key_b = kv::Key { key_bytes: random_bytes(&blocks_pool, 24), };
+        let bounds = vec![
+            (Bound::Unbounded, Bound::Unbounded),
+            (Bound::Included(&key_a), Bound::Excluded(&key_b)),
+            (Bound::Excluded(&key_a), Bound::Included(&key_b)),
+            (Bound::Unbounded, Bound::Included(&key_b)),
+        ];
+        for (version, (range_from, range_to)) in bounds.into_iter().enumerate() {
+            let mut record_bytes = blocks_pool.lend();
+            range_tombstone_serialize(range_from, range_to, version as u64, 0, &mut record_bytes).unwrap();
+            let record_bytes = record_bytes.freeze();
+            let (read_range_from, read_range_to, read_version) = range_tombstone_deserialize(&record_bytes).unwrap();
+            assert_eq!(read_range_from.as_ref(), range_from);
+            assert_eq!(read_range_to.as_ref(), range_to);
+            assert_eq!(read_version, version as u64);
+            assert!(matches!(wal_record_deserialize(&record_bytes), Err(Error::InvalidBlockMagic { .. })));
+        }
+    }
+
+    #[test]
+    fn merge_record_roundtrip() {
+        let blocks_pool = BytesPool::new();
+        let output_root = BlockRef {
+            blockwheel_filename: WheelFilename::from_str("wheel_a", &blocks_pool),
+            block_id: block::Id::init(),
+        };
+        let input_roots = vec![
+            BlockRef {
+                blockwheel_filename: WheelFilename::from_str("wheel_b", &blocks_pool),
+                block_id: block::Id::init(),
+            },
+            BlockRef {
+                blockwheel_filename: WheelFilename::from_str("wheel_c", &blocks_pool),
+                block_id: block::Id::init(),
+            },
+        ];
+        let mut record_bytes = blocks_pool.lend();
+        merge_record_serialize(&output_root, &input_roots, 3, &mut record_bytes).unwrap();
+        let record_bytes = record_bytes.freeze();
+        let (read_output_root, read_input_roots) = merge_record_deserialize(&record_bytes).unwrap();
+        assert_eq!(read_output_root, output_root);
+        assert_eq!(read_input_roots, input_roots);
+        assert!(matches!(range_tombstone_deserialize(&record_bytes), Err(Error::InvalidBlockMagic { .. })));
+    }
+
+    #[test]
+    fn compress_decompress_roundtrip() {
+        let blocks_pool = BytesPool::new();
+        for &len in &[0, 1, 4096, 1 << 20] {
+            let block_bytes = random_bytes(&blocks_pool, len);
+            let mut compressed_bytes = blocks_pool.lend();
+            block_compress(&block_bytes, &Compression::Zstd { level: 3, }, &mut compressed_bytes).unwrap();
+            let compressed_bytes = compressed_bytes.freeze();
+            assert!(block_is_compressed(&compressed_bytes));
+            let decompressed_bytes = block_decompress(compressed_bytes, &blocks_pool).unwrap();
+            assert_eq!(&*decompressed_bytes, &*block_bytes);
+        }
+
+        let plain_bytes = random_bytes(&blocks_pool, 64);
+        assert_eq!(&*block_decompress(plain_bytes.clone(), &blocks_pool).unwrap(), &*plain_bytes);
+    }
+
+    fn compressed_with_header(blocks_pool: &BytesPool, uncompressed_size: usize, payload: &[u8]) -> alloc_pool::bytes::Bytes {
+        let mut block_bytes = blocks_pool.lend();
+        bincode_options().serialize_into(block_bytes.as_mut(), &COMPRESSED_BLOCK_MAGIC).unwrap();
+        let header = CompressedBlockHeader { algorithm: CompressionAlgorithm::Zstd, uncompressed_size, };
+        bincode_options().serialize_into(block_bytes.as_mut(), &header).unwrap();
+        zstd::stream::copy_encode(payload, block_bytes.as_mut(), 3).unwrap();
+        block_bytes.freeze()
+    }
+
+    #[test]
+    fn decompress_corrupt_header() {
+        let blocks_pool = BytesPool::new();
+        let payload = [7; 4096];
+
+        let huge = compressed_with_header(&blocks_pool, usize::MAX, &payload);
+        assert!(matches!(
+            block_decompress(huge, &blocks_pool),
+            Err(Error::BlockTooLarge { size: usize::MAX, max: MAX_BLOCK_SIZE, }),
+        ));
+
+        let understated = compressed_with_header(&blocks_pool, 16, &payload);
+        assert!(matches!(
+            block_decompress(understated, &blocks_pool),
+            Err(Error::UncompressedSizeMismatch { expected: 16, provided: 17, }),
+        ));
+
+        let overstated = compressed_with_header(&blocks_pool, 8192, &payload);
+        assert!(matches!(
+            block_decompress(overstated, &blocks_pool),
+            Err(Error::UncompressedSizeMismatch { expected: 8192, provided: 4096, }),
+        ));
+
+        let mut garbage = blocks_pool.lend();
+        bincode_options().serialize_into(garbage.as_mut(), &COMPRESSED_BLOCK_MAGIC).unwrap();
+        let header = CompressedBlockHeader { algorithm: CompressionAlgorithm::Zstd, uncompressed_size: 4096, };
+        bincode_options().serialize_into(garbage.as_mut(), &header).unwrap();
+        garbage.extend_from_slice(&[0xff; 32]);
+        assert!(matches!(block_decompress(garbage.freeze(), &blocks_pool), Err(Error::BlockDecompress(..))));
+
+        let oversized = vec![0; MAX_BLOCK_SIZE + 1];
+        assert!(matches!(
+            block_compress(&oversized, &Compression::Zstd { level: 1, }, blocks_pool.lend()),
+            Err(Error::BlockTooLarge { .. }),
+        ));
+    }
+
+    #[test]
+    fn value_out_of_line_honors_preference() {
+        let small = [0; 16];
+        let large = [0; 256];
+        assert!(!value_out_of_line(&small, StoragePreference::Auto, 128));
+        assert!(value_out_of_line(&large, StoragePreference::Auto, 128));
+        assert!(value_out_of_line(&small, StoragePreference::OutOfLine, 128));
+        assert!(!value_out_of_line(&large, StoragePreference::Inline, 128));
+    }
+
+    #[test]
+    fn value_out_of_line_limit_boundary() {
+        let limit = 16;
+        assert!(!value_out_of_line(&[0; 15], StoragePreference::Auto, limit));
+        assert!(!value_out_of_line(&[0; 16], StoragePreference::Auto, limit));
+        assert!(value_out_of_line(&[0; 17], StoragePreference::Auto, limit));
+        assert!(!value_out_of_line(&[], StoragePreference::Auto, limit));
+
+        assert!(value_out_of_line(&[], StoragePreference::Auto, 0));
+        assert!(value_out_of_line(&[0; 1], StoragePreference::Auto, 0));
+        assert!(!value_out_of_line(&[], StoragePreference::Inline, 0));
+
+        assert!(!value_out_of_line(&[0; 1], StoragePreference::Auto, 1));
+        assert!(value_out_of_line(&[0; 2], StoragePreference::Auto, 1));
+    }
+}
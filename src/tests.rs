@@ -1,5 +1,8 @@
 use std::{
     fs,
+    cmp::Ordering,
+    ops::Bound,
+    sync::Arc,
     path::{
         PathBuf,
     },
@@ -10,15 +13,22 @@ use std::{
 
 use futures::{
     Future,
+    FutureExt,
     SinkExt,
     StreamExt,
+    TryStreamExt,
     select,
     pin_mut,
-    channel::mpsc,
+    channel::{
+        mpsc,
+        oneshot,
+    },
 };
 
 use rand::Rng;
 
+use tokio::time;
+
 use ero::{
     supervisor::{
         SupervisorPid,
@@ -33,6 +43,7 @@ use alloc_pool::bytes::{
 use super::{
     kv,
     job,
+    comparator,
     wheels,
     version,
     storage,
@@ -121,6 +132,275 @@ fn stress() {
     fs::remove_file(&wheel_filename_b).ok();
 }
 
+#[test]
+fn cancel_run() {
+    run_small("cancel", cancel_run_loop);
+}
+
+#[test]
+fn compaction_frees_value_blocks() {
+    run_small("values", compaction_frees_value_blocks_loop);
+}
+
+#[test]
+fn bottom_merge_drops_tombstones() {
+    run_small("bottom", bottom_merge_drops_tombstones_loop);
+}
+
+#[test]
+fn excluded_lower_bound() {
+    run_small("excluded", excluded_lower_bound_loop);
+}
+
+#[test]
+fn paged_lookup_range() {
+    run_small("paged", paged_lookup_range_loop);
+}
+
+#[test]
+fn shared_wheels_store_ids() {
+    run_small("store_ids", shared_wheels_store_ids_loop);
+}
+
+#[test]
+fn bulk_load_custom_comparator() {
+    run_small("bulk_load", bulk_load_custom_comparator_loop);
+}
+
+#[test]
+fn remove_errors_reported() {
+    run_small("remove_errors", remove_errors_reported_loop);
+}
+
+#[test]
+fn split_off_concurrent_inserts() {
+    run_small("split_off_concurrent", split_off_concurrent_inserts_loop);
+}
+
+#[test]
+fn empty_key_and_value_flush_merge() {
+    run_small("empty_key_value", empty_key_and_value_flush_merge_loop);
+}
+
+#[test]
+fn lookups_served_while_writes_throttled() {
+    run_small("writes_throttled", lookups_served_while_writes_throttled_loop);
+}
+
+#[test]
+fn target_block_bytes_cuts_blocks() {
+    run_small("target_block_bytes", target_block_bytes_cuts_blocks_loop);
+}
+
+#[test]
+fn regressed_versions_rejected_after_restart() {
+    run_small("regressed_versions", regressed_versions_rejected_after_restart_loop);
+}
+
+#[test]
+fn insert_sink_bounded() {
+    run_small("insert_sink_bounded", insert_sink_bounded_loop);
+}
+
+#[test]
+fn concurrent_flushes() {
+    run_small("concurrent_flushes", concurrent_flushes_loop);
+}
+
+#[test]
+fn flush_during_range_scan() {
+    run_small("flush_during_range_scan", flush_during_range_scan_loop);
+}
+
+#[test]
+fn namespaces_round_trip() {
+    run_small("namespaces_round_trip", namespaces_round_trip_loop);
+}
+
+#[test]
+fn flush_butcher_only_registers_tree() {
+    run_small("flush_butcher_only", flush_butcher_only_registers_tree_loop);
+}
+
+#[test]
+fn butcher_max_bytes_bounds_memtable() {
+    run_small("butcher_max_bytes", butcher_max_bytes_bounds_memtable_loop);
+}
+
+#[test]
+fn lookup_located_reports_placement() {
+    run_small("lookup_located", lookup_located_reports_placement_loop);
+}
+
+#[test]
+fn size_histograms_fold_butcher_and_trees() {
+    run_small("size_histograms", size_histograms_fold_butcher_and_trees_loop);
+}
+
+// blocking calls are made from the test thread, while the store runs on the runtime workers
+#[cfg(feature = "blocking")]
+#[test]
+fn blocking_pid_round_trip() {
+    // the guard is dropped after the runtime, so wheel files are removed once the store is down
+    let (params, _wheel_files) = small_params("blocking_pid");
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .build()
+        .unwrap();
+
+    let (wheel_kv_pid, blocks_pool) = runtime.block_on(async {
+        let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+            spawn_wheels(params.wheel_a, params.wheel_b)?;
+        let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+        let wheel_kv_pid = wheel_kv_gen_server.pid();
+        supervisor_pid.spawn_link_permanent(
+            wheel_kv_gen_server.run(
+                supervisor_pid.clone(),
+                thread_pool.clone(),
+                blocks_pool.clone(),
+                version::Provider::from_unix_epoch_seed(),
+                wheels_pid.clone(),
+                params.kv.clone(),
+            ),
+        );
+        Ok::<_, Error>((wheel_kv_pid, blocks_pool))
+    }).unwrap();
+
+    let keys_count = 8;
+    let mut blocking_pids = vec![
+        blockwheel_kv::blocking::BlockingPid::new(wheel_kv_pid.clone()),
+        blockwheel_kv::blocking::BlockingPid::with_handle(wheel_kv_pid, runtime.handle().clone()),
+    ];
+    for (round, blocking_pid) in blocking_pids.iter_mut().enumerate() {
+        for index in 0 .. keys_count {
+            let (key, value) = make_key_value(index, round, &blocks_pool);
+            let blockwheel_kv::Inserted { .. } = blocking_pid.insert(key, value).unwrap();
+        }
+        let (removed_key, _value) = make_key_value(0, round, &blocks_pool);
+        let blockwheel_kv::Removed { .. } = blocking_pid.remove(removed_key.clone()).unwrap();
+        let blockwheel_kv::Flushed { .. } = blocking_pid.flush().unwrap();
+
+        let found = blocking_pid.lookup(removed_key).unwrap();
+        assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Tombstone { .. }, .. })));
+        let (key, value) = make_key_value(1, round, &blocks_pool);
+        let found = blocking_pid.lookup(key).unwrap();
+        assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Value(ref found_value), .. }) if found_value == &value));
+
+        // the removed key is left out of the scan
+        let found: Vec<_> = blocking_pid.lookup_range(..).unwrap()
+            .map(|maybe_key_value| maybe_key_value.unwrap())
+            .collect();
+        let expected: Vec<_> = (1 .. keys_count)
+            .map(|index| make_key_value(index, round, &blocks_pool))
+            .collect();
+        assert_eq!(found.len(), expected.len());
+        for (kv::KeyValuePair { key, value_cell, }, (expected_key, expected_value)) in found.into_iter().zip(expected) {
+            assert_eq!(key, expected_key);
+            assert_eq!(value_cell.cell, kv::Cell::Value(expected_value));
+        }
+    }
+
+    drop(blocking_pids);
+}
+
+#[test]
+fn active_range_scans_released() {
+    run_small("active_range_scans", active_range_scans_released_loop);
+}
+
+#[test]
+fn burst_flushes_coalesce() {
+    // the coalescing window is a timer
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    run_small_on(runtime, "burst_flushes_coalesce", burst_flushes_coalesce_loop);
+}
+
+#[test]
+fn legacy_root_restored_for_inserts() {
+    run_small("legacy_root", legacy_root_restored_for_inserts_loop);
+}
+
+#[test]
+fn minimal_pooling_round_trip() {
+    run_small("minimal_pooling", minimal_pooling_round_trip_loop);
+}
+
+#[test]
+fn bulk_load_chunk_boundary_version() {
+    run_small("bulk_load_chunk_boundary", bulk_load_chunk_boundary_version_loop);
+}
+
+fn run_small<F, Fut>(tag: &str, f: F) where F: FnOnce(Params) -> Fut, Fut: Future<Output = Result<(), Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    run_small_on(runtime, tag, f);
+}
+
+fn run_small_on<F, Fut>(runtime: tokio::runtime::Runtime, tag: &str, f: F) where F: FnOnce(Params) -> Fut, Fut: Future<Output = Result<(), Error>> {
+    let (params, _wheel_files) = small_params(tag);
+    runtime.block_on(f(params)).unwrap();
+}
+
+// wheel files of a test are removed up front and once the guard is dropped, a failed test included
+struct WheelFiles {
+    wheel_filename_a: PathBuf,
+    wheel_filename_b: PathBuf,
+}
+
+impl WheelFiles {
+    fn new(wheel_filename_a: PathBuf, wheel_filename_b: PathBuf) -> WheelFiles {
+        fs::remove_file(&wheel_filename_a).ok();
+        fs::remove_file(&wheel_filename_b).ok();
+        WheelFiles { wheel_filename_a, wheel_filename_b, }
+    }
+}
+
+impl Drop for WheelFiles {
+    fn drop(&mut self) {
+        fs::remove_file(&self.wheel_filename_a).ok();
+        fs::remove_file(&self.wheel_filename_b).ok();
+    }
+}
+
+fn small_params(tag: &str) -> (Params, WheelFiles) {
+    let kv = blockwheel_kv::Params {
+        tree_block_size: 4,
+        ..Default::default()
+    };
+    let init_wheel_size_bytes = 4 * 1024 * 1024;
+    let work_block_size_bytes = 64 * 1024;
+
+    let wheel_filename_a: PathBuf = format!("/tmp/blockwheel_kv_a_{}", tag).into();
+    let wheel_filename_b: PathBuf = format!("/tmp/blockwheel_kv_b_{}", tag).into();
+
+    let params = Params {
+        wheel_a: blockwheel::Params {
+            interpreter: blockwheel::InterpreterParams::FixedFile(blockwheel::FixedFileInterpreterParams {
+                wheel_filename: wheel_filename_a.clone(),
+                init_wheel_size_bytes,
+            }),
+            work_block_size_bytes,
+            lru_cache_size_bytes: 0,
+            ..Default::default()
+        },
+        wheel_b: blockwheel::Params {
+            interpreter: blockwheel::InterpreterParams::FixedFile(blockwheel::FixedFileInterpreterParams {
+                wheel_filename: wheel_filename_b.clone(),
+                init_wheel_size_bytes,
+            }),
+            work_block_size_bytes,
+            lru_cache_size_bytes: 0,
+            ..Default::default()
+        },
+        kv,
+    };
+
+    (params, WheelFiles::new(wheel_filename_a, wheel_filename_b))
+}
+
 #[derive(Clone)]
 struct Params {
     wheel_a: blockwheel::Params,
@@ -170,9 +450,14 @@ enum Error {
     Insert(blockwheel_kv::InsertError),
     Lookup(blockwheel_kv::LookupError),
     LookupRange(blockwheel_kv::LookupRangeError),
+    LookupRangePaged(blockwheel_kv::LookupRangePagedError),
     Remove(blockwheel_kv::RemoveError),
+    BulkLoad(blockwheel_kv::BulkLoadError),
+    SplitOff(blockwheel_kv::SplitOffError),
     Flush(blockwheel_kv::FlushError),
+    Namespace(blockwheel_kv::NamespaceError),
     UnexpectedLookupRangeRxFinish,
+    LookupRangeStream(blockwheel_kv::StreamError),
     ExpectedValueNotFound {
         key: kv::Key,
         value_cell: kv::ValueCell<kv::Value>,
@@ -189,10 +474,25 @@ enum Error {
     WheelAGoneDuringInfo,
     WheelBGoneDuringInfo,
     WheelsGoneDuringFlush,
+    WheelsGoneDuringAcquire,
+    WriteBlock(blockwheel::WriteBlockError),
     WheelsIterBlocks(wheels::IterBlocksError),
     WheelsIterBlocksRxDropped,
     Storage(storage::Error),
     BackwardIterKeyNotFound,
+    GcOrphans(blockwheel_kv::GcOrphansError),
+    IterRawBlocks(blockwheel_kv::IterRawBlocksError),
+    RawBlocksStream(blockwheel_kv::StreamError),
+    Verify(blockwheel_kv::VerifyError),
+    Health(ero::NoProcError),
+    EstimateCount(ero::NoProcError),
+    DetailedInfo(ero::NoProcError),
+    SizeHistograms(ero::NoProcError),
+    WarmRange(ero::NoProcError),
+    UnflushedValueSurvivedCancel {
+        key: kv::Key,
+        found_value_cell: kv::ValueCell<kv::Value>,
+    },
 }
 
 fn make_wheel_ref(
@@ -367,7 +667,7 @@ async fn stress_loop(
                     key: key.clone(),
                     value_cell: kv::ValueCell {
                         version,
-                        cell: kv::Cell::Tombstone,
+                        cell: kv::Cell::Tombstone { meta: None, },
                     },
                 };
                 let &offset = data.index.get(&key).unwrap();
@@ -425,8 +725,9 @@ async fn stress_loop(
                 // insert task
                 let mut wheel_kv_pid = wheel_kv_pid.clone();
                 let blocks_pool = blocks_pool.clone();
-                let key_amount = rng.gen_range(1 .. limits.key_size_bytes);
-                let value_amount = rng.gen_range(1 .. limits.value_size_bytes);
+                // zero-length keys and values are valid as well
+                let key_amount = rng.gen_range(0 .. limits.key_size_bytes);
+                let value_amount = rng.gen_range(0 .. limits.value_size_bytes);
 
                 log::debug!(
                     "{}. performing INSERT with {} bytes key and {} bytes value (dice = {:.3}, prob = {:.3}) | {:?}, active = {:?}",
@@ -474,7 +775,7 @@ async fn stress_loop(
                     match &value_cell.cell {
                         kv::Cell::Value(value) =>
                             break (key, value),
-                        kv::Cell::Tombstone =>
+                        kv::Cell::Tombstone { .. } =>
                             continue,
                     }
                 };
@@ -522,7 +823,7 @@ async fn stress_loop(
                 match &value_cell.cell {
                     kv::Cell::Value(value) =>
                         format!("{} bytes", value.value_bytes.len()),
-                    kv::Cell::Tombstone =>
+                    kv::Cell::Tombstone { .. } =>
                         "tombstone".to_string(),
                 },
                 counter,
@@ -561,6 +862,8 @@ async fn stress_loop(
                                     version_snapshot,
                                     lookup_kind: LookupKind::Range,
                                 },
+                            Some(blockwheel_kv::KeyValueStreamItem::Error(error)) =>
+                                return Err(Error::LookupRangeStream(error)),
                             Some(blockwheel_kv::KeyValueStreamItem::NoMore) =>
                                 return Err(Error::ExpectedValueNotFound { key, value_cell, }),
                         };
@@ -569,6 +872,8 @@ async fn stress_loop(
                                 return Err(Error::UnexpectedLookupRangeRxFinish),
                             Some(blockwheel_kv::KeyValueStreamItem::KeyValue(key_value_pair)) =>
                                 return Err(Error::UnexpectedValueForLookupRange { key, key_value_pair, }),
+                            Some(blockwheel_kv::KeyValueStreamItem::Error(error)) =>
+                                return Err(Error::LookupRangeStream(error)),
                             Some(blockwheel_kv::KeyValueStreamItem::NoMore) =>
                                 ()
                         }
@@ -584,7 +889,7 @@ async fn stress_loop(
 
     assert!(done_rx.next().await.is_none());
 
-    let blockwheel_kv::Flushed = wheel_kv_pid.flush().await
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
         .map_err(Error::Flush)?;
     let wheels::Flushed = wheels_pid.flush().await
         .map_err(|ero::NoProcError| Error::WheelsGoneDuringFlush)?;
@@ -598,8 +903,12 @@ async fn stress_loop(
         match iter_blocks.block_refs_rx.next().await {
             None =>
                 return Err(Error::WheelsIterBlocksRxDropped),
-            Some(wheels::IterBlocksItem::Block { block_bytes, .. }) => {
+            Some(wheels::IterBlocksItem::Block { block_ref, block_bytes, }) => {
                 checked_blocks += 1;
+                let block_bytes = storage::block_decompress(block_bytes, &blocks_pool)
+                    .map_err(Error::Storage)?;
+                storage::block_verify_checksum(&block_bytes, &block_ref)
+                    .map_err(Error::Storage)?;
                 match storage::block_deserialize_iter(&block_bytes) {
                     Ok(deserializer) =>
                         for maybe_entry in deserializer {
@@ -627,3 +936,1845 @@ async fn stress_loop(
     log::info!("FINISHED: blocks checked = {}, entries = {} | {:?}", checked_blocks, checked_entries, counter);
     Ok::<_, Error>(())
 }
+
+async fn cancel_run_loop(params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+    let version_provider = version::Provider::from_unix_epoch_seed();
+
+    // first instance: several flushed trees merged, then some unflushed inserts, then cancel
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    let run = wheel_kv_gen_server.run(
+        supervisor_pid.clone(),
+        thread_pool.clone(),
+        blocks_pool.clone(),
+        version_provider.clone(),
+        wheels_pid.clone(),
+        params.kv.clone(),
+    );
+    let (cancel_tx, cancel_rx) = oneshot::channel();
+    supervisor_pid.spawn_link_temporary(async move {
+        let run = run.fuse();
+        pin_mut!(run);
+        select! {
+            () = run =>
+                (),
+            _ = cancel_rx.fuse() =>
+                log::debug!("run future dropped"),
+        }
+    });
+
+    let mut flushed = Vec::new();
+    for index in 0 .. 256 {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let blockwheel_kv::Inserted { version, } = wheel_kv_pid.insert(key.clone(), value.clone()).await
+            .map_err(Error::Insert)?;
+        flushed.push((key, kv::ValueCell { version, cell: kv::Cell::Value(value), }));
+        if index % 64 == 63 {
+            let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+                .map_err(Error::Flush)?;
+        }
+    }
+    let mut unflushed = Vec::new();
+    for index in 256 .. 320 {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key.clone(), value).await
+            .map_err(Error::Insert)?;
+        unflushed.push(key);
+    }
+    cancel_tx.send(()).ok();
+
+    // second instance over the same wheels must see exactly the flushed state
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version_provider.clone(),
+            wheels_pid.clone(),
+            blockwheel_kv::Params {
+                manager_gc_orphans_on_start: true,
+                ..params.kv
+            },
+        ),
+    );
+
+    for (key, value_cell) in flushed {
+        match wheel_kv_pid.lookup(key.clone()).await.map_err(Error::Lookup)? {
+            None =>
+                return Err(Error::ExpectedValueNotFound { key, value_cell, }),
+            Some(found_value_cell) if found_value_cell != value_cell =>
+                return Err(Error::UnexpectedValueFound { key, expected_value_cell: value_cell, found_value_cell, }),
+            Some(..) =>
+                (),
+        }
+    }
+    for key in unflushed {
+        if let Some(found_value_cell) = wheel_kv_pid.lookup(key.clone()).await.map_err(Error::Lookup)? {
+            return Err(Error::UnflushedValueSurvivedCancel { key, found_value_cell, });
+        }
+    }
+
+    let blockwheel_kv::OrphansCollected { .. } = wheel_kv_pid.gc_orphans().await
+        .map_err(Error::GcOrphans)?;
+
+    Ok(())
+}
+
+async fn shared_wheels_store_ids_loop(params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+    let version_provider = version::Provider::from_unix_epoch_seed();
+    let store_ids = [1, 2];
+
+    // same keys with different values are written by both stores, their blocks interleaved on the wheels
+    let mut cancel_txs = Vec::new();
+    let mut wheel_kv_pids = Vec::new();
+    for &store_id in &store_ids {
+        let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+        wheel_kv_pids.push(wheel_kv_gen_server.pid());
+        let run = wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version_provider.clone(),
+            wheels_pid.clone(),
+            blockwheel_kv::Params { store_id, ..params.kv.clone() },
+        );
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        supervisor_pid.spawn_link_temporary(async move {
+            let run = run.fuse();
+            pin_mut!(run);
+            select! {
+                () = run =>
+                    (),
+                _ = cancel_rx.fuse() =>
+                    log::debug!("run future dropped"),
+            }
+        });
+        cancel_txs.push(cancel_tx);
+    }
+    let keys_count = 64;
+    for index in 0 .. keys_count {
+        for (round, wheel_kv_pid) in wheel_kv_pids.iter_mut().enumerate() {
+            let (key, value) = make_key_value(index, round, &blocks_pool);
+            let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key, value).await
+                .map_err(Error::Insert)?;
+        }
+    }
+    for wheel_kv_pid in &mut wheel_kv_pids {
+        let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+            .map_err(Error::Flush)?;
+    }
+    for cancel_tx in cancel_txs {
+        cancel_tx.send(()).ok();
+    }
+
+    // each store reloaded sees only its own values, and collecting orphans in one leaves the other intact
+    for (round, &store_id) in store_ids.iter().enumerate() {
+        let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+        let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+        supervisor_pid.spawn_link_permanent(
+            wheel_kv_gen_server.run(
+                supervisor_pid.clone(),
+                thread_pool.clone(),
+                blocks_pool.clone(),
+                version_provider.clone(),
+                wheels_pid.clone(),
+                blockwheel_kv::Params { store_id, manager_gc_orphans_on_start: true, ..params.kv.clone() },
+            ),
+        );
+        for index in 0 .. keys_count {
+            let (key, value) = make_key_value(index, round, &blocks_pool);
+            let found = wheel_kv_pid.lookup_value(key).await
+                .map_err(Error::Lookup)?;
+            assert_eq!(found, Some(value));
+        }
+    }
+
+    Ok(())
+}
+
+async fn compaction_frees_value_blocks_loop(params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    // values are well above `search_tree_values_inline_size_limit`, so each one gets its own block
+    let keys_count = 32;
+    let mut value_blocks_counts = Vec::new();
+    for round in 0 .. 2 {
+        for index in 0 .. keys_count {
+            let (key, value) = make_key_value(index, round, &blocks_pool);
+            let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key, value).await
+                .map_err(Error::Insert)?;
+        }
+        // two trees of the same size are merged before flush returns
+        let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+            .map_err(Error::Flush)?;
+        value_blocks_counts.push(count_value_blocks(&mut wheel_kv_pid).await?);
+    }
+
+    assert_eq!(value_blocks_counts, vec![keys_count, keys_count]);
+
+    let verify_report = wheel_kv_pid.verify().await
+        .map_err(Error::Verify)?;
+    assert!(verify_report.blocks_total > 0);
+    assert_eq!(verify_report.corrupt_blocks, vec![]);
+    assert_eq!(verify_report.corrupt_trees, vec![]);
+    assert_eq!(verify_report.missing_blocks, vec![]);
+
+    let health = wheel_kv_pid.health().await
+        .map_err(Error::Health)?;
+    assert!(health.butcher_reachable);
+    assert!(health.search_trees_count > 0);
+    assert_eq!(health.search_tree_reachable, Some(true));
+    assert_eq!(health.last_error, None);
+    Ok(())
+}
+
+async fn bottom_merge_drops_tombstones_loop(params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    let keys_count = 16;
+    for index in 0 .. keys_count {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key, value).await
+            .map_err(Error::Insert)?;
+    }
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    let info = wheel_kv_pid.info().await
+        .map_err(|ero::NoProcError| Error::WheelAGoneDuringInfo)?;
+    assert_eq!((info.alive_cells_count, info.tombstones_count), (keys_count, 0));
+
+    for index in 0 .. keys_count {
+        let (key, _value) = make_key_value(index, 0, &blocks_pool);
+        let blockwheel_kv::Removed { .. } = wheel_kv_pid.remove(key).await
+            .map_err(Error::Remove)?;
+    }
+    let info = wheel_kv_pid.info().await
+        .map_err(|ero::NoProcError| Error::WheelAGoneDuringInfo)?;
+    assert_eq!(info.tombstones_count, keys_count);
+
+    // tombstones tree pairs with the values tree of the same size, nothing older exists beneath both
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    let info = wheel_kv_pid.info().await
+        .map_err(|ero::NoProcError| Error::WheelAGoneDuringInfo)?;
+    assert_eq!((info.alive_cells_count, info.tombstones_count), (0, 0));
+
+    Ok(())
+}
+
+struct ReversedComparator;
+
+impl comparator::KeyComparator for ReversedComparator {
+    fn compare(&self, key_a: &[u8], key_b: &[u8]) -> Ordering {
+        key_b.cmp(key_a)
+    }
+
+    fn id(&self) -> u32 {
+        1
+    }
+}
+
+async fn bulk_load_custom_comparator_loop(mut params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    // a couple of pairs per chunk, so both the order check and the trees span several chunks
+    params.kv.key_comparator = Some(Arc::new(ReversedComparator));
+    params.kv.bulk_load_chunk_bytes = 1024;
+    params.kv.namespaces = true;
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::with_params(&params.kv);
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    // descending bytes are ascending for the reversed comparator
+    let keys_count = 32;
+    let pairs: Vec<_> = (0 .. keys_count).rev()
+        .map(|index| make_key_value(index, 0, &blocks_pool))
+        .collect();
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.bulk_load(futures::stream::iter(pairs.clone())).await
+        .map_err(Error::BulkLoad)?;
+    for (key, value) in &pairs {
+        let found = wheel_kv_pid.lookup_value(key.clone()).await
+            .map_err(Error::Lookup)?;
+        assert_eq!(found.as_ref(), Some(value));
+    }
+    let found = collect_range_keys(&mut wheel_kv_pid, (Bound::Unbounded, Bound::Unbounded)).await?;
+    let expected: Vec<_> = pairs.iter().map(|(key, _value)| key.clone()).collect();
+    assert_eq!(found, expected);
+
+    // every root records the comparator it was ordered with
+    let mut raw_blocks = wheel_kv_pid.iter_raw_blocks().await
+        .map_err(Error::IterRawBlocks)?;
+    let mut roots_count = 0;
+    loop {
+        match raw_blocks.blocks_rx.next().await {
+            None =>
+                return Err(Error::WheelsIterBlocksRxDropped),
+            Some(blockwheel_kv::RawBlocksItem::Block {
+                header: Ok(blockwheel_kv::RawBlockHeader::Tree {
+                    node_type: blockwheel_kv::RawNodeType::Root { comparator_id, .. },
+                    ..
+                }),
+                ..
+            }) => {
+                assert_eq!(comparator_id, 1);
+                roots_count += 1;
+            },
+            Some(blockwheel_kv::RawBlocksItem::Block { .. }) =>
+                (),
+            Some(blockwheel_kv::RawBlocksItem::Error(error)) =>
+                return Err(Error::RawBlocksStream(error)),
+            Some(blockwheel_kv::RawBlocksItem::NoMoreBlocks) =>
+                break,
+        }
+    }
+    assert!(roots_count > 0);
+
+    // bytewise ascending pair sits across a chunk boundary: it is caught against the previous chunk last key
+    let pairs: Vec<_> = [105, 104, 103, 102, 101, 100, 200].iter()
+        .map(|&index| make_key_value(index, 0, &blocks_pool))
+        .collect();
+    match wheel_kv_pid.bulk_load(futures::stream::iter(pairs.clone())).await {
+        Err(blockwheel_kv::BulkLoadError::KeysNotAscending { prev_key, key, }) => {
+            assert_eq!(prev_key, pairs[5].0);
+            assert_eq!(key, pairs[6].0);
+        },
+        other =>
+            panic!("expected KeysNotAscending, got {:?}", other),
+    }
+    // chunks loaded before the failure stay, the failed one is not loaded at all
+    let found = wheel_kv_pid.lookup_value(pairs[0].0.clone()).await
+        .map_err(Error::Lookup)?;
+    assert!(found.is_some());
+    let found = wheel_kv_pid.lookup_value(pairs[6].0.clone()).await
+        .map_err(Error::Lookup)?;
+    assert!(found.is_none());
+
+    // namespaces are prefixes, so they are refused for a custom ordering
+    match wheel_kv_pid.insert_ns(1, kv::Key::from("a"), kv::Value::from("a")).await {
+        Err(blockwheel_kv::NamespaceError::NotBytewiseComparator) =>
+            (),
+        other =>
+            panic!("expected NotBytewiseComparator, got {:?}", other),
+    }
+
+    Ok(())
+}
+
+async fn remove_errors_reported_loop(mut params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let (key, value) = make_key_value(0, 0, &blocks_pool);
+    params.kv.max_key_size = Some(key.key_bytes.len());
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    let inserted = wheel_kv_pid.insert(key.clone(), value.clone()).await
+        .map_err(Error::Insert)?;
+    let (removed, prev_value_cell) = wheel_kv_pid.remove_returning(key.clone()).await
+        .map_err(Error::Remove)?;
+    assert!(removed.version > inserted.version);
+    assert_eq!(prev_value_cell, Some(kv::ValueCell { version: inserted.version, cell: kv::Cell::Value(value), }));
+    let (_removed, prev_value_cell) = wheel_kv_pid.remove_returning(key.clone()).await
+        .map_err(Error::Remove)?;
+    assert_eq!(prev_value_cell, None);
+
+    // oversized keys are rejected by the butcher and reported to the caller on every remove path
+    let mut too_long_bytes = key.key_bytes.to_vec();
+    too_long_bytes.push(b'!');
+    let too_long_key = kv::Key::from(too_long_bytes);
+    let expected_size = key.key_bytes.len() + 1;
+    let expected_limit = key.key_bytes.len();
+    let result = wheel_kv_pid.remove(too_long_key.clone()).await;
+    assert!(matches!(result, Err(blockwheel_kv::RemoveError::KeyTooLarge { size, limit, }) if size == expected_size && limit == expected_limit));
+    let result = wheel_kv_pid.remove_with_meta(too_long_key.clone(), kv::Value::from("meta")).await;
+    assert!(matches!(result, Err(blockwheel_kv::RemoveError::KeyTooLarge { .. })));
+    let result = wheel_kv_pid.remove_returning(too_long_key).await;
+    assert!(matches!(result, Err(blockwheel_kv::RemoveError::KeyTooLarge { .. })));
+
+    // the store keeps serving after the rejected removes
+    let found = wheel_kv_pid.lookup(key).await
+        .map_err(Error::Lookup)?;
+    assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Tombstone { .. }, .. })));
+
+    Ok(())
+}
+
+async fn split_off_concurrent_inserts_loop(params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    let keys_count = 64;
+    let (split_lo, split_hi) = (16, 48);
+    for index in 0 .. keys_count {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key, value).await
+            .map_err(Error::Insert)?;
+    }
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+
+    // every key of the range is overwritten while the split is running
+    let mut insert_pid = wheel_kv_pid.clone();
+    let insert_blocks_pool = blocks_pool.clone();
+    let inserts = tokio::spawn(async move {
+        let mut inserted = HashMap::new();
+        for index in split_lo .. split_hi {
+            let (key, value) = make_key_value(index, 1, &insert_blocks_pool);
+            let blockwheel_kv::Inserted { version, } = insert_pid.insert(key.clone(), value.clone()).await
+                .map_err(Error::Insert)?;
+            inserted.insert(key, kv::ValueCell { version, cell: kv::Cell::Value(value), });
+        }
+        Ok::<_, Error>(inserted)
+    });
+    let range = (
+        Bound::Included(make_key_value(split_lo, 0, &blocks_pool).0),
+        Bound::Excluded(make_key_value(split_hi, 0, &blocks_pool).0),
+    );
+    let moved = wheel_kv_pid.split_off(range).await
+        .map_err(Error::SplitOff)?;
+    let inserted = inserts.await
+        .map_err(Error::GenTaskJoin)??;
+
+    // the latest cell of a key either moves out (and is shadowed in the source) or stays, never both:
+    // an insert which has landed before the split is moved itself, a later one survives it
+    let moved: HashMap<_, _> = moved.into_iter()
+        .map(|key_value| (key_value.key, key_value.value_cell))
+        .collect();
+    assert!(moved.keys().all(|key| inserted.contains_key(key)));
+    let mut moved_latest = 0;
+    for index in split_lo .. split_hi {
+        let (key, old_value) = make_key_value(index, 0, &blocks_pool);
+        let new_value_cell = &inserted[&key];
+        let found = wheel_kv_pid.lookup(key.clone()).await
+            .map_err(Error::Lookup)?;
+        match moved.get(&key) {
+            Some(moved_value_cell) if moved_value_cell == new_value_cell => {
+                assert!(!matches!(found, Some(kv::ValueCell { cell: kv::Cell::Value(..), .. })));
+                moved_latest += 1;
+            },
+            Some(moved_value_cell) => {
+                assert_eq!(moved_value_cell.cell, kv::Cell::Value(old_value));
+                assert!(moved_value_cell.version < new_value_cell.version);
+                assert_eq!(found.as_ref(), Some(new_value_cell));
+            },
+            None =>
+                assert_eq!(found.as_ref(), Some(new_value_cell)),
+        }
+    }
+
+    // keys outside of the range are neither moved nor touched
+    for index in (0 .. split_lo).chain(split_hi .. keys_count) {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let found = wheel_kv_pid.lookup(key.clone()).await
+            .map_err(Error::Lookup)?;
+        assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Value(ref found_value), .. }) if found_value == &value));
+    }
+
+    // the range tombstone shadows only moved cells: concurrent inserts survive a flush and merges
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    let found = collect_range_keys(&mut wheel_kv_pid, (Bound::Unbounded, Bound::Unbounded)).await?;
+    assert_eq!(found.len(), keys_count - moved_latest);
+
+    Ok(())
+}
+
+async fn empty_key_and_value_flush_merge_loop(params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    let empty_key = kv::Key::from("");
+    let key_a = kv::Key::from("a");
+    let key_b = kv::Key::from("b");
+    let empty_value = kv::Value::from("");
+
+    // first tree: the empty key with an empty value next to a regular pair
+    let blockwheel_kv::Inserted { version: empty_key_version, } = wheel_kv_pid.insert(empty_key.clone(), empty_value.clone()).await
+        .map_err(Error::Insert)?;
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key_a.clone(), kv::Value::from("value a")).await
+        .map_err(Error::Insert)?;
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+
+    // second tree of the same size: an empty value for another key and a tombstone, merged with the first one
+    let blockwheel_kv::Inserted { version: key_b_version, } = wheel_kv_pid.insert(key_b.clone(), empty_value.clone()).await
+        .map_err(Error::Insert)?;
+    let blockwheel_kv::Removed { .. } = wheel_kv_pid.remove(key_a.clone()).await
+        .map_err(Error::Remove)?;
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    let health = wheel_kv_pid.health().await
+        .map_err(Error::Health)?;
+    assert_eq!(health.search_trees_count, 1);
+
+    let found = wheel_kv_pid.lookup(empty_key.clone()).await
+        .map_err(Error::Lookup)?;
+    assert_eq!(found, Some(kv::ValueCell { version: empty_key_version, cell: kv::Cell::Value(empty_value.clone()), }));
+    let found = wheel_kv_pid.lookup(key_b.clone()).await
+        .map_err(Error::Lookup)?;
+    assert_eq!(found, Some(kv::ValueCell { version: key_b_version, cell: kv::Cell::Value(empty_value.clone()), }));
+    let found = wheel_kv_pid.lookup(key_a).await
+        .map_err(Error::Lookup)?;
+    assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Tombstone { .. }, .. })));
+    let found = collect_range_keys(&mut wheel_kv_pid, (Bound::Unbounded, Bound::Unbounded)).await?;
+    assert_eq!(found, vec![empty_key.clone(), key_b.clone()]);
+
+    // the empty key is removed as any other one
+    let blockwheel_kv::Removed { .. } = wheel_kv_pid.remove(empty_key.clone()).await
+        .map_err(Error::Remove)?;
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    let found = wheel_kv_pid.lookup(empty_key.clone()).await
+        .map_err(Error::Lookup)?;
+    assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Tombstone { .. }, .. })));
+    let found = collect_range_keys(&mut wheel_kv_pid, (Bound::Included(empty_key), Bound::Unbounded)).await?;
+    assert_eq!(found, vec![key_b]);
+
+    Ok(())
+}
+
+async fn insert_sink_bounded_loop(mut params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    // far more inserts than may be applied at once
+    params.kv.manager_no_reply_inserts_limit = 2;
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::with_params(&params.kv);
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    let keys_count = 256;
+    let pairs: Vec<_> = (0 .. keys_count)
+        .map(|index| make_key_value(index, 0, &blocks_pool))
+        .collect();
+    {
+        let insert_sink = wheel_kv_pid.insert_sink();
+        pin_mut!(insert_sink);
+        let mut pairs_stream = futures::stream::iter(pairs.clone().into_iter().map(Ok));
+        insert_sink.send_all(&mut pairs_stream).await
+            .map_err(Error::Insert)?;
+        insert_sink.flush().await
+            .map_err(Error::Insert)?;
+    }
+
+    // a flush after the sink one covers every insert sent
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    for (key, value) in &pairs {
+        let found = wheel_kv_pid.lookup_value(key.clone()).await
+            .map_err(Error::Lookup)?;
+        assert_eq!(found.as_ref(), Some(value));
+    }
+    let found = collect_range_keys(&mut wheel_kv_pid, (Bound::Unbounded, Bound::Unbounded)).await?;
+    assert_eq!(found.len(), keys_count);
+
+    Ok(())
+}
+
+async fn concurrent_flushes_loop(mut params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    // the butcher holds every insert until flushed
+    params.kv.tree_block_size = 1024;
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::with_params(&params.kv);
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    let keys_count = 32;
+    for index in 0 .. keys_count {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key, value).await
+            .map_err(Error::Insert)?;
+    }
+
+    // flushes arriving together are coalesced, while more inserts than may be parked during a flush keep
+    // coming
+    let flushes = (0 .. 8)
+        .map(|_| {
+            let mut wheel_kv_pid = wheel_kv_pid.clone();
+            async move { wheel_kv_pid.flush().await }
+        });
+    let inserts = (keys_count .. keys_count * 8)
+        .map(|index| {
+            let mut wheel_kv_pid = wheel_kv_pid.clone();
+            let (key, value) = make_key_value(index, 0, &blocks_pool);
+            async move { wheel_kv_pid.insert(key, value).await }
+        });
+    let (flushes_results, inserts_results) = futures::future::join(
+        futures::future::join_all(flushes),
+        futures::future::join_all(inserts),
+    ).await;
+    let mut cache_entries_flushed = 0;
+    for flush_result in flushes_results {
+        let blockwheel_kv::Flushed { cache_entries_flushed: flushed, .. } = flush_result
+            .map_err(Error::Flush)?;
+        cache_entries_flushed = cache_entries_flushed.max(flushed);
+    }
+    assert!(cache_entries_flushed >= keys_count);
+    for insert_result in inserts_results {
+        let blockwheel_kv::Inserted { .. } = insert_result
+            .map_err(Error::Insert)?;
+    }
+
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    let info = wheel_kv_pid.info().await
+        .map_err(Error::DetailedInfo)?;
+    assert_eq!(info.butcher_entries, 0);
+    let found = collect_range_keys(&mut wheel_kv_pid, (Bound::Unbounded, Bound::Unbounded)).await?;
+    assert_eq!(found.len(), keys_count * 8);
+
+    Ok(())
+}
+
+async fn flush_during_range_scan_loop(params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    // even keys in a single tree
+    let keys_count = 128;
+    let pairs: Vec<_> = (0 .. keys_count).step_by(2)
+        .map(|index| make_key_value(index, 0, &blocks_pool))
+        .collect();
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.bulk_load(futures::stream::iter(pairs.clone())).await
+        .map_err(Error::BulkLoad)?;
+
+    let mut lookup_range = wheel_kv_pid.lookup_range_buffered(.., Some(1)).await
+        .map_err(Error::LookupRange)?;
+    let mut found = Vec::new();
+    while found.len() < 4 {
+        match lookup_range.key_values_rx.next().await {
+            Some(blockwheel_kv::KeyValueStreamItem::KeyValue(key_value_pair)) =>
+                found.push(key_value_pair),
+            Some(blockwheel_kv::KeyValueStreamItem::Error(error)) =>
+                return Err(Error::LookupRangeStream(error)),
+            Some(blockwheel_kv::KeyValueStreamItem::NoMore) | None =>
+                return Err(Error::UnexpectedLookupRangeRxFinish),
+        }
+    }
+
+    // far ahead of the scan: a new key and a newer value, both only in a tree flushed after the scan
+    // has taken its trees and butcher contents; the tiny tree is not merged with the loaded one, so the
+    // scan cannot be deprecated and restarted
+    let (new_key, new_value) = make_key_value(keys_count - 7, 1, &blocks_pool);
+    let (updated_key, updated_value) = make_key_value(keys_count - 8, 1, &blocks_pool);
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(new_key.clone(), new_value.clone()).await
+        .map_err(Error::Insert)?;
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(updated_key.clone(), updated_value.clone()).await
+        .map_err(Error::Insert)?;
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush_butcher_only().await
+        .map_err(Error::Flush)?;
+
+    loop {
+        match lookup_range.key_values_rx.next().await {
+            Some(blockwheel_kv::KeyValueStreamItem::KeyValue(key_value_pair)) =>
+                found.push(key_value_pair),
+            Some(blockwheel_kv::KeyValueStreamItem::Error(error)) =>
+                return Err(Error::LookupRangeStream(error)),
+            Some(blockwheel_kv::KeyValueStreamItem::NoMore) =>
+                break,
+            None =>
+                return Err(Error::UnexpectedLookupRangeRxFinish),
+        }
+    }
+    let mut expected: Vec<_> = pairs.iter().map(|(key, _value)| key.clone()).collect();
+    expected.push(new_key.clone());
+    expected.sort_by(|key_a, key_b| key_a.key_bytes.cmp(&key_b.key_bytes));
+    let found_keys: Vec<_> = found.iter().map(|key_value_pair| key_value_pair.key.clone()).collect();
+    assert_eq!(found_keys, expected);
+    for kv::KeyValuePair { key, value_cell, } in found {
+        if key == new_key {
+            assert_eq!(value_cell.cell, kv::Cell::Value(new_value.clone()));
+        } else if key == updated_key {
+            assert_eq!(value_cell.cell, kv::Cell::Value(updated_value.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+async fn namespaces_round_trip_loop(mut params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    params.kv.namespaces = true;
+    params.kv.max_key_size = Some(4);
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::with_params(&params.kv);
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    // the same keys in both namespaces, the last one has no upper bound
+    let ns_a = 1;
+    let ns_b = u64::MAX;
+    let key_a = kv::Key::from("a");
+    let key_b = kv::Key::from("b");
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert_ns(ns_a, key_a.clone(), kv::Value::from("ns a value a")).await
+        .map_err(Error::Namespace)?;
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert_ns(ns_b, key_a.clone(), kv::Value::from("ns b value a")).await
+        .map_err(Error::Namespace)?;
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert_ns(ns_a, key_b.clone(), kv::Value::from("ns a value b")).await
+        .map_err(Error::Namespace)?;
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert_ns(ns_b, key_b.clone(), kv::Value::from("ns b value b")).await
+        .map_err(Error::Namespace)?;
+    // a plain key is outside of every namespace
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(kv::Key::from("pl"), kv::Value::from("plain value")).await
+        .map_err(Error::Insert)?;
+
+    let found = collect_namespace(&mut wheel_kv_pid, ns_a).await?;
+    assert_eq!(found, vec![
+        (key_a.clone(), kv::Value::from("ns a value a")),
+        (key_b.clone(), kv::Value::from("ns a value b")),
+    ]);
+    let found = collect_namespace(&mut wheel_kv_pid, ns_b).await?;
+    assert_eq!(found, vec![
+        (key_a.clone(), kv::Value::from("ns b value a")),
+        (key_b.clone(), kv::Value::from("ns b value b")),
+    ]);
+
+    // dropping one namespace keeps the other one and the plain key
+    let blockwheel_kv::Removed { .. } = wheel_kv_pid.remove_namespace(ns_a).await
+        .map_err(Error::Namespace)?;
+    let found = collect_namespace(&mut wheel_kv_pid, ns_a).await?;
+    assert_eq!(found, vec![]);
+    let found = wheel_kv_pid.lookup_ns(ns_a, key_a.clone()).await
+        .map_err(Error::Namespace)?;
+    assert!(!matches!(found, Some(kv::ValueCell { cell: kv::Cell::Value(..), .. })));
+    let found = collect_namespace(&mut wheel_kv_pid, ns_b).await?;
+    assert_eq!(found.len(), 2);
+    let found = wheel_kv_pid.lookup_value(kv::Key::from("pl")).await
+        .map_err(Error::Lookup)?;
+    assert_eq!(found, Some(kv::Value::from("plain value")));
+
+    // the namespace prefix does not count against `max_key_size`
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert_ns(ns_a, kv::Key::from("abcd"), kv::Value::from("ns a value abcd")).await
+        .map_err(Error::Namespace)?;
+    let result = wheel_kv_pid.insert_ns(ns_a, kv::Key::from("abcde"), kv::Value::from("ns a value abcde")).await;
+    assert!(matches!(result, Err(blockwheel_kv::NamespaceError::Insert(blockwheel_kv::InsertError::KeyTooLarge { size: 5, limit: 4, }))));
+
+    // plain writes cannot reach into the namespaces key space
+    let mut reserved_key_bytes = blocks_pool.lend();
+    reserved_key_bytes.push(blockwheel_kv::NAMESPACE_MARKER);
+    reserved_key_bytes.extend_from_slice(&ns_b.to_be_bytes());
+    reserved_key_bytes.extend_from_slice(b"a");
+    let reserved_key = kv::Key::from(reserved_key_bytes);
+    let result = wheel_kv_pid.insert(reserved_key.clone(), kv::Value::from("plain value")).await;
+    assert!(matches!(result, Err(blockwheel_kv::InsertError::KeyReserved)));
+    let result = wheel_kv_pid.remove(reserved_key).await;
+    assert!(matches!(result, Err(blockwheel_kv::RemoveError::KeyReserved)));
+    let found = wheel_kv_pid.lookup_ns(ns_b, key_a.clone()).await
+        .map_err(Error::Namespace)?;
+    assert_eq!(found.map(|value_cell| value_cell.cell), Some(kv::Cell::Value(kv::Value::from("ns b value a"))));
+
+    Ok(())
+}
+
+async fn collect_namespace(wheel_kv_pid: &mut blockwheel_kv::Pid, ns: u64) -> Result<Vec<(kv::Key, kv::Value)>, Error> {
+    let key_values = wheel_kv_pid.iter_ns(ns).await
+        .map_err(Error::Namespace)?;
+    let key_values: Vec<_> = key_values.try_collect().await
+        .map_err(Error::LookupRangeStream)?;
+    Ok(key_values.into_iter()
+        .filter_map(|kv::KeyValuePair { key, value_cell, }| match value_cell.cell {
+            kv::Cell::Value(value) =>
+                Some((key, value)),
+            kv::Cell::Tombstone { .. } =>
+                None,
+        })
+        .collect())
+}
+
+async fn target_block_bytes_cuts_blocks_loop(mut params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let target_block_bytes = 256;
+    params.kv.target_block_bytes = Some(target_block_bytes);
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::with_params(&params.kv);
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    // two bulk loaded trees merged into one: values go out of line, so far more than `tree_block_size`
+    // entries fit a block
+    let keys_count = 128;
+    let pairs: Vec<_> = (0 .. keys_count)
+        .map(|index| make_key_value(index, 0, &blocks_pool))
+        .collect();
+    for chunk in pairs.chunks(keys_count / 2) {
+        let blockwheel_kv::Inserted { .. } = wheel_kv_pid.bulk_load(futures::stream::iter(chunk.to_vec())).await
+            .map_err(Error::BulkLoad)?;
+    }
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    let health = wheel_kv_pid.health().await
+        .map_err(Error::Health)?;
+    assert_eq!(health.search_trees_count, 1);
+
+    for (key, value) in &pairs {
+        let found = wheel_kv_pid.lookup_value(key.clone()).await
+            .map_err(Error::Lookup)?;
+        assert_eq!(found.as_ref(), Some(value));
+    }
+    let found = collect_range_keys(&mut wheel_kv_pid, (Bound::Unbounded, Bound::Unbounded)).await?;
+    let expected: Vec<_> = pairs.iter().map(|(key, _value)| key.clone()).collect();
+    assert_eq!(found, expected);
+
+    // every block below the root is cut right after its entries reach the budget
+    let mut raw_blocks = wheel_kv_pid.iter_raw_blocks().await
+        .map_err(Error::IterRawBlocks)?;
+    let mut leaves_count = 0;
+    loop {
+        match raw_blocks.blocks_rx.next().await {
+            None =>
+                return Err(Error::WheelsIterBlocksRxDropped),
+            Some(blockwheel_kv::RawBlocksItem::Block {
+                block_bytes,
+                header: Ok(blockwheel_kv::RawBlockHeader::Tree {
+                    node_type: blockwheel_kv::RawNodeType::Leaf,
+                    entries_count,
+                    ..
+                }),
+                ..
+            }) => {
+                assert!(entries_count > params.kv.tree_block_size);
+                assert!(block_bytes.len() >= target_block_bytes);
+                assert!(block_bytes.len() < target_block_bytes + 128);
+                leaves_count += 1;
+            },
+            Some(blockwheel_kv::RawBlocksItem::Block { .. }) =>
+                (),
+            Some(blockwheel_kv::RawBlocksItem::Error(error)) =>
+                return Err(Error::RawBlocksStream(error)),
+            Some(blockwheel_kv::RawBlocksItem::NoMoreBlocks) =>
+                break,
+        }
+    }
+    assert!(leaves_count > 0);
+
+    Ok(())
+}
+
+async fn regressed_versions_rejected_after_restart_loop(params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    let run = wheel_kv_gen_server.run(
+        supervisor_pid.clone(),
+        thread_pool.clone(),
+        blocks_pool.clone(),
+        version::Provider::from_unix_epoch_seed(),
+        wheels_pid.clone(),
+        params.kv.clone(),
+    );
+    let (cancel_tx, cancel_rx) = oneshot::channel();
+    supervisor_pid.spawn_link_temporary(async move {
+        let run = run.fuse();
+        pin_mut!(run);
+        select! {
+            () = run =>
+                (),
+            _ = cancel_rx.fuse() =>
+                log::debug!("run future dropped"),
+        }
+    });
+    let (key, value) = make_key_value(0, 0, &blocks_pool);
+    let blockwheel_kv::Inserted { version: stored_version, } = wheel_kv_pid.insert(key.clone(), value.clone()).await
+        .map_err(Error::Insert)?;
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    cancel_tx.send(()).ok();
+
+    // restarted with a provider behind the versions already on disk, e.g. after a wall clock regression
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_seed(version::EXTERNAL_VERSIONS_LIMIT),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+    let (_key, regressed_value) = make_key_value(0, 1, &blocks_pool);
+    match wheel_kv_pid.insert(key.clone(), regressed_value.clone()).await {
+        Err(blockwheel_kv::InsertError::VersionRegression { version, last_version, }) => {
+            assert!(version < stored_version);
+            assert!(last_version >= stored_version);
+        },
+        other =>
+            panic!("expected VersionRegression, got {:?}", other),
+    }
+    // versions the manager obtains itself go through the same check
+    let (other_key, other_value) = make_key_value(1, 1, &blocks_pool);
+    match wheel_kv_pid.bulk_load(futures::stream::iter(vec![(other_key.clone(), other_value)])).await {
+        Err(blockwheel_kv::BulkLoadError::VersionRegression { .. }) =>
+            (),
+        other =>
+            panic!("expected VersionRegression, got {:?}", other),
+    }
+
+    let found = wheel_kv_pid.lookup(key).await
+        .map_err(Error::Lookup)?;
+    assert_eq!(found, Some(kv::ValueCell { version: stored_version, cell: kv::Cell::Value(value), }));
+    let found = wheel_kv_pid.lookup(other_key).await
+        .map_err(Error::Lookup)?;
+    assert_eq!(found, None);
+
+    Ok(())
+}
+
+async fn lookups_served_while_writes_throttled_loop(mut params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    params.kv.manager_max_pending_trees = Some(1);
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    // two trees flushed without waiting for merges: the second one starts a merge and holds
+    // writes back as one tree over `max_pending_trees`
+    let tree_items = 512;
+    let mut expected = Vec::new();
+    for round in 0 .. 2 {
+        for index in round * tree_items .. (round + 1) * tree_items {
+            let (key, value) = make_key_value(index, round, &blocks_pool);
+            let blockwheel_kv::Inserted { version, } = wheel_kv_pid.insert(key.clone(), value.clone()).await
+                .map_err(Error::Insert)?;
+            expected.push((key, kv::ValueCell { version, cell: kv::Cell::Value(value), }));
+        }
+        let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush_butcher_only().await
+            .map_err(Error::Flush)?;
+    }
+
+    // a throttled insert waits for the merge, lookups going through butcher are answered meanwhile
+    let (late_key, late_value) = make_key_value(2 * tree_items, 2, &blocks_pool);
+    let mut insert_pid = wheel_kv_pid.clone();
+    let insert = insert_pid.insert(late_key.clone(), late_value.clone());
+    let lookups = async {
+        for (key, value_cell) in &expected {
+            match wheel_kv_pid.lookup(key.clone()).await.map_err(Error::Lookup)? {
+                None =>
+                    return Err(Error::ExpectedValueNotFound { key: key.clone(), value_cell: value_cell.clone(), }),
+                Some(found_value_cell) if &found_value_cell != value_cell =>
+                    return Err(Error::UnexpectedValueFound {
+                        key: key.clone(),
+                        expected_value_cell: value_cell.clone(),
+                        found_value_cell,
+                    }),
+                Some(..) =>
+                    (),
+            }
+        }
+        Ok(())
+    };
+    let (insert_result, lookups_result) = futures::future::join(insert, lookups).await;
+    let () = lookups_result?;
+    let blockwheel_kv::Inserted { version: late_version, } = insert_result
+        .map_err(Error::Insert)?;
+
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    let found = wheel_kv_pid.lookup(late_key).await
+        .map_err(Error::Lookup)?;
+    assert_eq!(found, Some(kv::ValueCell { version: late_version, cell: kv::Cell::Value(late_value), }));
+
+    Ok(())
+}
+
+async fn excluded_lower_bound_loop(params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    let keys: Vec<_> = (0 .. 3)
+        .map(|index| make_key_value(index, 0, &blocks_pool).0)
+        .collect();
+    for index in 0 .. keys.len() {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key, value).await
+            .map_err(Error::Insert)?;
+    }
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+
+    // boundary key lives in a search tree only
+    let found = collect_range_keys(&mut wheel_kv_pid, (Bound::Excluded(keys[0].clone()), Bound::Unbounded)).await?;
+    assert_eq!(found, keys[1 ..].to_vec());
+
+    // flushed keys are seen by lookups skipping the butcher
+    let found = wheel_kv_pid.lookup_with_consistency(keys[1].clone(), blockwheel_kv::Consistency::Trees).await
+        .map_err(Error::Lookup)?;
+    assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Value(..), .. })));
+
+    // a single block search tree is estimated exactly
+    let estimate = wheel_kv_pid.estimate_count((Bound::Excluded(keys[0].clone()), Bound::Unbounded)).await
+        .map_err(Error::EstimateCount)?;
+    assert_eq!(estimate, 2);
+
+    // the only tree block is read once for warming
+    let warmed = wheel_kv_pid.warm_range(..).await
+        .map_err(Error::WarmRange)?;
+    assert_eq!(warmed.blocks_count, 1);
+
+    // the flushed tree is recorded with the version it was built at
+    let detailed_info = wheel_kv_pid.detailed_info().await
+        .map_err(Error::DetailedInfo)?;
+    assert_eq!(detailed_info.search_trees.len(), 1);
+    let tree_details = &detailed_info.search_trees[0];
+    assert!(tree_details.created_at_version > 0);
+    assert_eq!(tree_details.age, detailed_info.current_version - tree_details.created_at_version);
+    assert!(tree_details.disk_bytes > 0);
+    let info = wheel_kv_pid.info().await
+        .map_err(|ero::NoProcError| Error::WheelAGoneDuringInfo)?;
+    assert_eq!(info.disk_bytes_total, tree_details.disk_bytes);
+
+    // boundary key is a butcher tombstone shadowing a search tree value, then the tombstone gets flushed
+    let blockwheel_kv::Removed { .. } = wheel_kv_pid.remove(keys[1].clone()).await
+        .map_err(Error::Remove)?;
+    for flush in [false, true] {
+        if flush {
+            let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+                .map_err(Error::Flush)?;
+        }
+        let found = collect_range_keys(&mut wheel_kv_pid, (Bound::Excluded(keys[1].clone()), Bound::Unbounded)).await?;
+        assert_eq!(found, keys[2 ..].to_vec());
+        let found = collect_range_keys(&mut wheel_kv_pid, (Bound::Excluded(keys[0].clone()), Bound::Unbounded)).await?;
+        assert_eq!(found, keys[2 ..].to_vec());
+        let found = collect_range_keys(&mut wheel_kv_pid, (Bound::Excluded(keys[2].clone()), Bound::Excluded(keys[2].clone()))).await?;
+        assert_eq!(found, vec![]);
+
+        // removed key is reported with its tombstone until a bottom merge drops it
+        if !flush {
+            let found = wheel_kv_pid.lookup(keys[1].clone()).await
+                .map_err(Error::Lookup)?;
+            assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Tombstone { .. }, .. })));
+        }
+        let found = wheel_kv_pid.lookup_value(keys[1].clone()).await
+            .map_err(Error::Lookup)?;
+        assert!(found.is_none());
+        let found = wheel_kv_pid.lookup_value(keys[2].clone()).await
+            .map_err(Error::Lookup)?;
+        assert!(found.is_some());
+    }
+
+    // batch removal reports versions in keys order
+    let removed = wheel_kv_pid.remove_many(vec![keys[2].clone(), keys[0].clone()]).await
+        .map_err(Error::Remove)?;
+    assert_eq!(removed.len(), 2);
+    assert!(removed[0].version < removed[1].version);
+    let found = collect_range_keys(&mut wheel_kv_pid, (Bound::Unbounded, Bound::Unbounded)).await?;
+    assert_eq!(found, vec![]);
+
+    // newer tombstone carries the removal metadata
+    let meta = kv::Value::from("removed by test");
+    let blockwheel_kv::Removed { .. } = wheel_kv_pid.remove_with_meta(keys[1].clone(), meta.clone()).await
+        .map_err(Error::Remove)?;
+    let found = wheel_kv_pid.lookup(keys[1].clone()).await
+        .map_err(Error::Lookup)?;
+    assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Tombstone { meta: Some(found_meta), }, .. }) if found_meta == meta));
+
+    Ok(())
+}
+
+async fn paged_lookup_range_loop(params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    // even keys are written up front, odd ones while paging
+    for index in (0 .. 20).step_by(2) {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key, value).await
+            .map_err(Error::Insert)?;
+    }
+
+    let end = Bound::Included(make_key_value(18, 0, &blocks_pool).0);
+    let mut start_after = kv::Key::from("key");
+    let mut paged = Vec::new();
+    let mut round = 0;
+    loop {
+        let blockwheel_kv::Page { items, next_cursor, } = wheel_kv_pid.lookup_range_paged(start_after, end.clone(), 3).await
+            .map_err(Error::LookupRangePaged)?;
+        assert!(items.len() <= 3);
+        round += 1;
+        // cells behind the cursor get newer versions, then everything is flushed (and maybe merged) between pages
+        for kv::KeyValuePair { key, .. } in &items {
+            let value = make_key_value(0, round, &blocks_pool).1;
+            let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key.clone(), value).await
+                .map_err(Error::Insert)?;
+        }
+        if round == 1 {
+            // one key ahead of the cursor and one past the inclusive end
+            for index in [17, 19] {
+                let (key, value) = make_key_value(index, round, &blocks_pool);
+                let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key, value).await
+                    .map_err(Error::Insert)?;
+            }
+        }
+        let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+            .map_err(Error::Flush)?;
+        paged.extend(items.into_iter().map(|key_value_pair| key_value_pair.key));
+        match next_cursor {
+            None =>
+                break,
+            Some(cursor) =>
+                start_after = cursor,
+        }
+    }
+
+    let expected: Vec<_> = [0, 2, 4, 6, 8, 10, 12, 14, 16, 17, 18].iter()
+        .map(|&index| make_key_value(index, 0, &blocks_pool).0)
+        .collect();
+    assert_eq!(paged, expected);
+
+    Ok(())
+}
+
+async fn flush_butcher_only_registers_tree_loop(mut params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    // the butcher holds every insert until flushed
+    params.kv.tree_block_size = 1024;
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::with_params(&params.kv);
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    let keys_count = 16;
+    for index in 0 .. keys_count {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key, value).await
+            .map_err(Error::Insert)?;
+    }
+
+    // the memtable becomes a single tree, registered by the time the call returns
+    let flushed = wheel_kv_pid.flush_butcher_only().await
+        .map_err(Error::Flush)?;
+    assert_eq!(flushed, blockwheel_kv::Flushed { cache_entries_flushed: keys_count, new_search_trees: 1, });
+    let health = wheel_kv_pid.health().await
+        .map_err(Error::Health)?;
+    assert_eq!(health.search_trees_count, 1);
+    let info = wheel_kv_pid.info().await
+        .map_err(Error::DetailedInfo)?;
+    assert_eq!((info.butcher_entries, info.alive_cells_count), (0, keys_count));
+
+    // nothing to flush: no tree is made
+    let flushed = wheel_kv_pid.flush_butcher_only().await
+        .map_err(Error::Flush)?;
+    assert_eq!(flushed, blockwheel_kv::Flushed { cache_entries_flushed: 0, new_search_trees: 0, });
+    let health = wheel_kv_pid.health().await
+        .map_err(Error::Health)?;
+    assert_eq!(health.search_trees_count, 1);
+
+    for index in 0 .. keys_count {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let found = wheel_kv_pid.lookup_value(key).await
+            .map_err(Error::Lookup)?;
+        assert_eq!(found, Some(value));
+    }
+
+    Ok(())
+}
+
+async fn butcher_max_bytes_bounds_memtable_loop(mut params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    // room for two entries: the third one flushes the memtable before it is added
+    let (key, value) = make_key_value(0, 0, &blocks_pool);
+    let entry_bytes = key.key_bytes.len() + value.value_bytes.len();
+    let max_bytes = 2 * entry_bytes + entry_bytes / 2;
+    params.kv.tree_block_size = 1024;
+    params.kv.butcher_max_bytes = Some(max_bytes);
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::with_params(&params.kv);
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    let keys_count = 8;
+    for index in 0 .. keys_count {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key, value).await
+            .map_err(Error::Insert)?;
+        let info = wheel_kv_pid.info().await
+            .map_err(Error::DetailedInfo)?;
+        assert_eq!(info.butcher_entries, index % 2 + 1);
+        assert_eq!(info.butcher_bytes, info.butcher_entries * entry_bytes);
+        assert!(info.butcher_bytes <= max_bytes);
+    }
+
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    let info = wheel_kv_pid.info().await
+        .map_err(Error::DetailedInfo)?;
+    assert_eq!((info.butcher_entries, info.butcher_bytes, info.alive_cells_count), (0, 0, keys_count));
+    for index in 0 .. keys_count {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let found = wheel_kv_pid.lookup_value(key).await
+            .map_err(Error::Lookup)?;
+        assert_eq!(found, Some(value));
+    }
+
+    Ok(())
+}
+
+async fn lookup_located_reports_placement_loop(params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::with_params(&params.kv);
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    // `make_key_value` values are over `search_tree_values_inline_size_limit`
+    let (large_key, large_value) = make_key_value(0, 0, &blocks_pool);
+    let small_key = kv::Key::from("small");
+    let small_value = kv::Value::from("tiny");
+    let forced_key = kv::Key::from("forced");
+    let removed_key = kv::Key::from("removed");
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(large_key.clone(), large_value.clone()).await
+        .map_err(Error::Insert)?;
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(small_key.clone(), small_value.clone()).await
+        .map_err(Error::Insert)?;
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid
+        .insert_with_storage_preference(forced_key.clone(), small_value.clone(), blockwheel_kv::StoragePreference::OutOfLine)
+        .await
+        .map_err(Error::Insert)?;
+    let blockwheel_kv::Removed { .. } = wheel_kv_pid.remove(removed_key.clone()).await
+        .map_err(Error::Remove)?;
+
+    // butcher holds every value in memory
+    let found = wheel_kv_pid.lookup_located(large_key.clone()).await
+        .map_err(Error::Lookup)?;
+    assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Value(blockwheel_kv::ValueLocation::Inline(ref value)), .. }) if value == &large_value));
+
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    let found = wheel_kv_pid.lookup_located(large_key.clone()).await
+        .map_err(Error::Lookup)?;
+    assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Value(blockwheel_kv::ValueLocation::Block(..)), .. })));
+    let found = wheel_kv_pid.lookup_located(small_key.clone()).await
+        .map_err(Error::Lookup)?;
+    assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Value(blockwheel_kv::ValueLocation::Inline(ref value)), .. }) if value == &small_value));
+    let found = wheel_kv_pid.lookup_located(forced_key.clone()).await
+        .map_err(Error::Lookup)?;
+    assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Value(blockwheel_kv::ValueLocation::Block(..)), .. })));
+    let found = wheel_kv_pid.lookup_located(removed_key).await
+        .map_err(Error::Lookup)?;
+    assert!(matches!(found, Some(kv::ValueCell { cell: kv::Cell::Tombstone { .. }, .. })));
+    let found = wheel_kv_pid.lookup_located(kv::Key::from("missing")).await
+        .map_err(Error::Lookup)?;
+    assert_eq!(found, None);
+
+    // the value behind a block location is the one stored
+    let found = wheel_kv_pid.lookup_value(large_key).await
+        .map_err(Error::Lookup)?;
+    assert_eq!(found, Some(large_value));
+
+    Ok(())
+}
+
+async fn size_histograms_fold_butcher_and_trees_loop(mut params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    // the butcher holds every insert until flushed
+    params.kv.tree_block_size = 1024;
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::with_params(&params.kv);
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    // a tree with an inline value, two values in their own blocks and a tombstone
+    let (large_key_a, large_value_a) = make_key_value(0, 0, &blocks_pool);
+    let (large_key_b, large_value_b) = make_key_value(1, 0, &blocks_pool);
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(kv::Key::from("a"), kv::Value::from("tiny")).await
+        .map_err(Error::Insert)?;
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(large_key_a.clone(), large_value_a).await
+        .map_err(Error::Insert)?;
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(large_key_b, large_value_b).await
+        .map_err(Error::Insert)?;
+    let blockwheel_kv::Removed { .. } = wheel_kv_pid.remove(kv::Key::from("r")).await
+        .map_err(Error::Remove)?;
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    // and the butcher with a value held in memory
+    let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(kv::Key::from("bb"), kv::Value::from("xy")).await
+        .map_err(Error::Insert)?;
+
+    let histograms = wheel_kv_pid.size_histograms().await
+        .map_err(Error::SizeHistograms)?;
+    let mut expected = blockwheel_kv::Histograms::default();
+    for key_size in vec![1, 1, 2, large_key_a.key_bytes.len(), large_key_a.key_bytes.len()] {
+        expected.key_sizes.record(key_size);
+    }
+    expected.value_sizes.record(4);
+    expected.value_sizes.record(2);
+    expected.out_of_line_values = 2;
+    expected.tombstones = 1;
+    assert_eq!(histograms, expected);
+    assert_eq!(histograms.key_sizes.buckets[1], 2);
+    assert_eq!(histograms.key_sizes.total(), 5);
+
+    Ok(())
+}
+
+async fn active_range_scans_released_loop(params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::with_params(&params.kv);
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    let keys_count = 32;
+    for index in 0 .. keys_count {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key, value).await
+            .map_err(Error::Insert)?;
+    }
+
+    // a drained scan
+    let found = collect_range_keys(&mut wheel_kv_pid, (Bound::Unbounded, Bound::Unbounded)).await?;
+    assert_eq!(found.len(), keys_count);
+    wait_active_range_scans(&mut wheel_kv_pid, 0).await?;
+
+    // and one dropped by the client half way
+    let mut lookup_range = wheel_kv_pid.lookup_range_buffered(.., Some(1)).await
+        .map_err(Error::LookupRange)?;
+    match lookup_range.key_values_rx.next().await {
+        Some(blockwheel_kv::KeyValueStreamItem::KeyValue(..)) =>
+            (),
+        Some(blockwheel_kv::KeyValueStreamItem::Error(error)) =>
+            return Err(Error::LookupRangeStream(error)),
+        Some(blockwheel_kv::KeyValueStreamItem::NoMore) | None =>
+            return Err(Error::UnexpectedLookupRangeRxFinish),
+    }
+    let info = wheel_kv_pid.info().await
+        .map_err(Error::DetailedInfo)?;
+    assert_eq!(info.active_range_scans, 1);
+    drop(lookup_range);
+    wait_active_range_scans(&mut wheel_kv_pid, 0).await?;
+
+    Ok(())
+}
+
+// a finished scan is accounted once its merge task is done, which may be after the client has seen it end
+async fn wait_active_range_scans(wheel_kv_pid: &mut blockwheel_kv::Pid, expected: usize) -> Result<(), Error> {
+    for _ in 0 .. 1024 {
+        let info = wheel_kv_pid.info().await
+            .map_err(Error::DetailedInfo)?;
+        if info.active_range_scans == expected {
+            return Ok(());
+        }
+        tokio::task::yield_now().await;
+    }
+    panic!("active_range_scans never got back to {}", expected)
+}
+
+async fn burst_flushes_coalesce_loop(mut params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    // the threshold is crossed by the second insert of the burst, the window holds the rest of it
+    let (key, value) = make_key_value(0, 0, &blocks_pool);
+    let entry_bytes = key.key_bytes.len() + value.value_bytes.len();
+    let window = time::Duration::from_secs(1);
+    params.kv.tree_block_size = 1024;
+    params.kv.butcher_flush_threshold_bytes = Some(2 * entry_bytes);
+    params.kv.butcher_flush_coalesce_window = Some(window);
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::with_params(&params.kv);
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    let keys_count = 16;
+    for index in 0 .. keys_count {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let blockwheel_kv::Inserted { .. } = wheel_kv_pid.insert(key, value).await
+            .map_err(Error::Insert)?;
+    }
+    let info = wheel_kv_pid.info().await
+        .map_err(Error::DetailedInfo)?;
+    assert_eq!(info.butcher_entries, keys_count);
+    let health = wheel_kv_pid.health().await
+        .map_err(Error::Health)?;
+    assert_eq!(health.search_trees_count, 0);
+
+    // the whole burst lands in a single tree once the window elapses
+    time::sleep(window).await;
+    let mut search_trees_count = 0;
+    for _ in 0 .. 64 {
+        let health = wheel_kv_pid.health().await
+            .map_err(Error::Health)?;
+        search_trees_count = health.search_trees_count;
+        if search_trees_count > 0 {
+            break;
+        }
+        time::sleep(window / 16).await;
+    }
+    assert_eq!(search_trees_count, 1);
+    let info = wheel_kv_pid.info().await
+        .map_err(Error::DetailedInfo)?;
+    assert_eq!((info.butcher_entries, info.alive_cells_count), (0, keys_count));
+
+    Ok(())
+}
+
+async fn legacy_root_restored_for_inserts_loop(params: Params) -> Result<(), Error> {
+    let (mut supervisor_pid, blocks_pool, thread_pool, mut wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    // a root written before trees recorded their version range
+    let legacy_version = version::Provider::from_unix_epoch_seed().obtain();
+    let entries = vec![
+        storage::Entry {
+            jump_ref: storage::JumpRef::None,
+            key: b"key legacy",
+            value_cell: storage::ValueCell {
+                version: legacy_version,
+                cell: storage::Cell::Value(storage::ValueRef::Inline(b"value legacy")),
+            },
+        },
+    ];
+    let block_bytes = storage::legacy_root_block_serialize(&entries, params.kv.store_id, blocks_pool.lend())
+        .map_err(Error::Storage)?;
+    let mut wheel_ref = wheels_pid.acquire().await
+        .map_err(|ero::NoProcError| Error::WheelsGoneDuringAcquire)?
+        .unwrap();
+    wheel_ref.blockwheel_pid.write_block(block_bytes.freeze()).await
+        .map_err(Error::WriteBlock)?;
+    let wheels::Flushed = wheels_pid.flush().await
+        .map_err(|ero::NoProcError| Error::WheelsGoneDuringFlush)?;
+
+    // the watermark is seeded from the legacy cells, not from the unknown tree max version
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    let run = wheel_kv_gen_server.run(
+        supervisor_pid.clone(),
+        thread_pool.clone(),
+        blocks_pool.clone(),
+        version::Provider::from_seed(version::EXTERNAL_VERSIONS_LIMIT),
+        wheels_pid.clone(),
+        params.kv.clone(),
+    );
+    let (cancel_tx, cancel_rx) = oneshot::channel();
+    supervisor_pid.spawn_link_temporary(async move {
+        let run = run.fuse();
+        pin_mut!(run);
+        select! {
+            () = run =>
+                (),
+            _ = cancel_rx.fuse() =>
+                log::debug!("run future dropped"),
+        }
+    });
+    let (key, value) = make_key_value(0, 0, &blocks_pool);
+    match wheel_kv_pid.insert(key.clone(), value.clone()).await {
+        Err(blockwheel_kv::InsertError::VersionRegression { last_version, .. }) =>
+            assert_eq!(last_version, legacy_version),
+        other =>
+            panic!("expected VersionRegression, got {:?}", other),
+    }
+    cancel_tx.send(()).ok();
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::new();
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+    let blockwheel_kv::Inserted { version, } = wheel_kv_pid.insert(key.clone(), value.clone()).await
+        .map_err(Error::Insert)?;
+    assert!(version > legacy_version);
+    let found = wheel_kv_pid.lookup(key).await
+        .map_err(Error::Lookup)?;
+    assert_eq!(found, Some(kv::ValueCell { version, cell: kv::Cell::Value(value), }));
+
+    let mut legacy_key_bytes = blocks_pool.lend();
+    legacy_key_bytes.extend_from_slice(b"key legacy");
+    let found = wheel_kv_pid.lookup(kv::Key { key_bytes: legacy_key_bytes.freeze(), }).await
+        .map_err(Error::Lookup)?;
+    assert!(matches!(found, Some(kv::ValueCell { version, .. }) if version == legacy_version));
+
+    Ok(())
+}
+
+async fn minimal_pooling_round_trip_loop(mut params: Params) -> Result<(), Error> {
+    // the whole memtable stays under the threshold, so every wal record and flushed block is allocated directly
+    params.kv.pooling = blockwheel_kv::PoolingMode::Minimal { threshold: 1 << 20, };
+    params.kv.butcher_wal = true;
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::with_params(&params.kv);
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    let mut inserted = Vec::new();
+    for index in 0 .. 16 {
+        let (key, value) = make_key_value(index, 0, &blocks_pool);
+        let blockwheel_kv::Inserted { version, } = wheel_kv_pid.insert(key.clone(), value.clone()).await
+            .map_err(Error::Insert)?;
+        inserted.push((key, kv::ValueCell { version, cell: kv::Cell::Value(value), }));
+    }
+    let blockwheel_kv::Flushed { .. } = wheel_kv_pid.flush().await
+        .map_err(Error::Flush)?;
+    for (key, value_cell) in inserted {
+        let found = wheel_kv_pid.lookup(key).await
+            .map_err(Error::Lookup)?;
+        assert_eq!(found, Some(value_cell));
+    }
+
+    Ok(())
+}
+
+async fn bulk_load_chunk_boundary_version_loop(mut params: Params) -> Result<(), Error> {
+    // exactly two pairs per chunk, so four pairs end the stream right on a chunk boundary
+    let (key, value) = make_key_value(0, 0, &BytesPool::new());
+    params.kv.bulk_load_chunk_bytes = 2 * (key.key_bytes.len() + value.value_bytes.len());
+    let (mut supervisor_pid, blocks_pool, thread_pool, wheels_pid) =
+        spawn_wheels(params.wheel_a, params.wheel_b)?;
+
+    let wheel_kv_gen_server = blockwheel_kv::GenServer::with_params(&params.kv);
+    let mut wheel_kv_pid = wheel_kv_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheel_kv_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            version::Provider::from_unix_epoch_seed(),
+            wheels_pid.clone(),
+            params.kv.clone(),
+        ),
+    );
+
+    let pairs: Vec<_> = (0 .. 4)
+        .map(|index| make_key_value(index, 0, &blocks_pool))
+        .collect();
+    let blockwheel_kv::Inserted { version, } = wheel_kv_pid.bulk_load(futures::stream::iter(pairs.clone())).await
+        .map_err(Error::BulkLoad)?;
+    let found = wheel_kv_pid.lookup(pairs[3].0.clone()).await
+        .map_err(Error::Lookup)?;
+    assert_eq!(found, Some(kv::ValueCell { version, cell: kv::Cell::Value(pairs[3].1.clone()), }));
+    let found = wheel_kv_pid.lookup(pairs[0].0.clone()).await
+        .map_err(Error::Lookup)?;
+    assert!(matches!(found, Some(kv::ValueCell { version: first_version, .. }) if first_version < version));
+
+    Ok(())
+}
+
+async fn collect_range_keys(
+    wheel_kv_pid: &mut blockwheel_kv::Pid,
+    range: (Bound<kv::Key>, Bound<kv::Key>),
+)
+    -> Result<Vec<kv::Key>, Error>
+{
+    let mut lookup_range = wheel_kv_pid.lookup_range(range).await
+        .map_err(Error::LookupRange)?;
+    let mut keys = Vec::new();
+    loop {
+        match lookup_range.key_values_rx.next().await {
+            None =>
+                return Err(Error::UnexpectedLookupRangeRxFinish),
+            Some(blockwheel_kv::KeyValueStreamItem::KeyValue(key_value_pair)) =>
+                keys.push(key_value_pair.key),
+            Some(blockwheel_kv::KeyValueStreamItem::Error(error)) =>
+                return Err(Error::LookupRangeStream(error)),
+            Some(blockwheel_kv::KeyValueStreamItem::NoMore) =>
+                return Ok(keys),
+        }
+    }
+}
+
+fn spawn_wheels(
+    wheel_a: blockwheel::Params,
+    wheel_b: blockwheel::Params,
+)
+    -> Result<(SupervisorPid, BytesPool, edeltraud::Edeltraud<job::Job>, wheels::Pid), Error>
+{
+    let supervisor_gen_server = SupervisorGenServer::new();
+    let mut supervisor_pid = supervisor_gen_server.pid();
+    tokio::spawn(supervisor_gen_server.run());
+
+    let blocks_pool = BytesPool::new();
+    let thread_pool: edeltraud::Edeltraud<job::Job> = edeltraud::Builder::new()
+        .build()
+        .map_err(Error::ThreadPool)?;
+
+    let wheel_ref_a = make_wheel_ref(wheel_a, &blocks_pool, &mut supervisor_pid, &thread_pool);
+    let wheel_ref_b = make_wheel_ref(wheel_b, &blocks_pool, &mut supervisor_pid, &thread_pool);
+
+    let wheels_gen_server = wheels::GenServer::new();
+    let wheels_pid = wheels_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        wheels_gen_server.run(vec![wheel_ref_a, wheel_ref_b], wheels::Params::default()),
+    );
+
+    Ok((supervisor_pid, blocks_pool, thread_pool, wheels_pid))
+}
+
+fn make_key_value(index: usize, round: usize, blocks_pool: &BytesPool) -> (kv::Key, kv::Value) {
+    let mut key_bytes = blocks_pool.lend();
+    key_bytes.extend_from_slice(format!("key {:08}", index).as_bytes());
+    let mut value_bytes = blocks_pool.lend();
+    value_bytes.extend_from_slice(format!("value {:08} round {:04} ", index, round).repeat(16).as_bytes());
+    (kv::Key { key_bytes: key_bytes.freeze(), }, kv::Value { value_bytes: value_bytes.freeze(), })
+}
+
+async fn count_value_blocks(wheel_kv_pid: &mut blockwheel_kv::Pid) -> Result<usize, Error> {
+    let mut raw_blocks = wheel_kv_pid.iter_raw_blocks().await
+        .map_err(Error::IterRawBlocks)?;
+    let mut count = 0;
+    loop {
+        match raw_blocks.blocks_rx.next().await {
+            None =>
+                return Err(Error::WheelsIterBlocksRxDropped),
+            Some(blockwheel_kv::RawBlocksItem::Block { header: Ok(blockwheel_kv::RawBlockHeader::Value), .. }) =>
+                count += 1,
+            Some(blockwheel_kv::RawBlocksItem::Block { .. }) =>
+                (),
+            Some(blockwheel_kv::RawBlocksItem::Error(error)) =>
+                return Err(Error::RawBlocksStream(error)),
+            Some(blockwheel_kv::RawBlocksItem::NoMoreBlocks) =>
+                return Ok(count),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::future::Future;
+
+#[cfg(feature = "tracing")]
+pub use tracing::Span;
+
+#[cfg(feature = "tracing")]
+pub fn request_span(kind: &'static str) -> Span {
+    tracing::info_span!("request", kind)
+}
+
+#[cfg(feature = "tracing")]
+pub fn task_span(kind: &'static str) -> Span {
+    tracing::debug_span!("task", kind)
+}
+
+#[cfg(feature = "tracing")]
+pub fn instrument<F>(future: F, span: Span) -> impl Future<Output = F::Output> where F: Future {
+    tracing::Instrument::instrument(future, span)
+}
+
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone, Debug)]
+pub struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub fn current() -> Span {
+        Span
+    }
+
+    pub fn enter(&self) -> Entered {
+        Entered
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+pub struct Entered;
+
+#[cfg(not(feature = "tracing"))]
+pub fn request_span(_kind: &'static str) -> Span {
+    Span
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn task_span(_kind: &'static str) -> Span {
+    Span
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn instrument<F>(future: F, _span: Span) -> F where F: Future {
+    future
+}
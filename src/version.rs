@@ -2,6 +2,8 @@ use std::{
     time,
     sync::{
         Arc,
+        Mutex,
+        PoisonError,
         atomic::{
             Ordering,
             AtomicU64,
@@ -9,24 +11,124 @@ use std::{
     },
 };
 
+// versions assigned by `Provider` are seeded from unix epoch seconds shifted left by 24 bits,
+// so everything below this limit is left free for externally assigned versions
+pub const EXTERNAL_VERSIONS_LIMIT: u64 = 1 << 54;
+
+// every `obtain` has to return a version above all the previously obtained ones: merges and lookups keep
+// the cell with the highest version, so a regressed version would silently lose a newer write
 #[derive(Clone)]
 pub struct Provider {
     counter: Arc<AtomicU64>,
 }
 
 impl Provider {
+    pub fn from_seed(initial_counter: u64) -> Provider {
+        Provider {
+            counter: Arc::new(AtomicU64::new(initial_counter)),
+        }
+    }
+
     pub fn from_unix_epoch_seed() -> Provider {
         let seconds_since_epoch = time::SystemTime::now()
             .duration_since(time::SystemTime::UNIX_EPOCH)
             .map(|duration| duration.as_secs())
             .unwrap_or(0);
-        let initial_counter = seconds_since_epoch << 24;
-        Provider {
-            counter: Arc::new(AtomicU64::new(initial_counter)),
-        }
+        Provider::from_seed(seconds_since_epoch << 24)
     }
 
     pub fn obtain(&self) -> u64 {
         self.counter.fetch_add(1, Ordering::Relaxed)
     }
+
+    // every version obtained so far is not above this one
+    pub fn current(&self) -> u64 {
+        self.counter.load(Ordering::Relaxed).saturating_sub(1)
+    }
+}
+
+// highest version obtained or found on disk so far, used to detect `Provider` contract violations (e.g. the
+// epoch seed after a wall clock regression across restarts); shared by every task obtaining versions
+#[derive(Clone, Default, Debug)]
+pub struct Watermark {
+    last_version: Arc<Mutex<Option<u64>>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Regression {
+    pub version: u64,
+    pub last_version: u64,
+}
+
+impl Watermark {
+    // versions of restored trees and wal records: everything obtained afterwards has to be above them
+    pub fn observe(&self, version: u64) {
+        let mut last_version = self.last_version.lock().unwrap_or_else(PoisonError::into_inner);
+        *last_version = Some(last_version.map_or(version, |last_version| last_version.max(version)));
+    }
+
+    // the lock is held around `obtain`, so versions obtained concurrently are checked in their order
+    pub fn obtain(&self, provider: &Provider) -> Result<u64, Regression> {
+        let mut last_version = self.last_version.lock().unwrap_or_else(PoisonError::into_inner);
+        let version = provider.obtain();
+        advance(&mut last_version, version)?;
+        Ok(version)
+    }
+
+    pub fn advance(&self, version: u64) -> Result<(), Regression> {
+        let mut last_version = self.last_version.lock().unwrap_or_else(PoisonError::into_inner);
+        advance(&mut last_version, version)
+    }
+}
+
+fn advance(last_version: &mut Option<u64>, version: u64) -> Result<(), Regression> {
+    match *last_version {
+        Some(last_version) if version <= last_version =>
+            Err(Regression { version, last_version, }),
+        _ => {
+            *last_version = Some(version);
+            Ok(())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Provider,
+        Watermark,
+        Regression,
+    };
+
+    #[test]
+    fn regressing_provider_detected() {
+        let watermark = Watermark::default();
+        let provider = Provider::from_seed(100);
+        assert_eq!(watermark.advance(provider.obtain()), Ok(()));
+        assert_eq!(watermark.advance(provider.obtain()), Ok(()));
+
+        // e.g. reseeded from a wall clock which went backwards
+        let regressed = Provider::from_seed(50);
+        assert_eq!(watermark.advance(regressed.obtain()), Err(Regression { version: 50, last_version: 101, }));
+        let repeated = Provider::from_seed(101);
+        assert_eq!(watermark.advance(repeated.obtain()), Err(Regression { version: 101, last_version: 101, }));
+
+        assert_eq!(watermark.advance(provider.obtain()), Ok(()));
+    }
+
+    #[test]
+    fn seeded_from_disk() {
+        let watermark = Watermark::default();
+        watermark.observe(200);
+        // an older observed version does not lower it
+        watermark.observe(150);
+        let provider = Provider::from_seed(100);
+        assert_eq!(watermark.obtain(&provider), Err(Regression { version: 100, last_version: 200, }));
+
+        let provider = Provider::from_seed(201);
+        assert_eq!(watermark.obtain(&provider), Ok(201));
+        // shared between clones
+        let cloned = watermark.clone();
+        assert_eq!(cloned.advance(201), Err(Regression { version: 201, last_version: 201, }));
+    }
 }
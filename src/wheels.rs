@@ -3,7 +3,10 @@ use std::{
     str,
     path,
     ops::Deref,
-    time::Duration,
+    time::{
+        Instant,
+        Duration,
+    },
     collections::{
         HashMap,
         hash_map,
@@ -15,9 +18,14 @@ use futures::{
         mpsc,
         oneshot,
     },
+    future::{
+        BoxFuture,
+    },
     stream::{
         FuturesUnordered,
     },
+    select,
+    FutureExt,
     StreamExt,
     SinkExt,
 };
@@ -101,6 +109,15 @@ pub struct BlockRef {
     pub block_id: block::Id,
 }
 
+// how a wheel is picked for a new block
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Placement {
+    Random,
+    RoundRobin,
+    LeastFull,
+    Hash(u64),
+}
+
 #[derive(Clone, Debug)]
 pub struct Params {
     pub task_restart_sec: usize,
@@ -168,6 +185,9 @@ impl GenServer {
                 request_rx: self.request_rx,
                 wheels,
                 index,
+                round_robin_offset: 0,
+                bytes_free: Vec::new(),
+                bytes_free_sampled_at: None,
             },
             |state| busyloop(state),
         ).await;
@@ -181,8 +201,15 @@ struct State {
     request_rx: mpsc::Receiver<Request>,
     wheels: Vec<WheelRef>,
     index: HashMap<WheelFilename, usize>,
+    round_robin_offset: usize,
+    // sampled in background for least full placement, `None` for wheels that did not respond
+    bytes_free: Vec<Option<usize>>,
+    bytes_free_sampled_at: Option<Instant>,
 }
 
+// least full placement answers from free space sampled at most this often instead of asking every wheel per acquire
+const BYTES_FREE_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct Flushed;
 
@@ -202,9 +229,13 @@ pub enum IterBlocksItem {
 
 impl Pid {
     pub async fn acquire(&mut self) -> Result<Option<WheelRef>, ero::NoProcError> {
+        self.acquire_placed(Placement::Random).await
+    }
+
+    pub async fn acquire_placed(&mut self, placement: Placement) -> Result<Option<WheelRef>, ero::NoProcError> {
         loop {
             let (reply_tx, reply_rx) = oneshot::channel();
-            self.request_tx.send(Request::Acquire { reply_tx, }).await
+            self.request_tx.send(Request::Acquire { placement, reply_tx, }).await
                 .map_err(|_send_error| ero::NoProcError)?;
             match reply_rx.await {
                 Ok(maybe_wheel_ref) =>
@@ -259,7 +290,7 @@ impl Pid {
 }
 
 enum Request {
-    Acquire { reply_tx: oneshot::Sender<Option<WheelRef>>, },
+    Acquire { placement: Placement, reply_tx: oneshot::Sender<Option<WheelRef>>, },
     Get { blockwheel_filename: WheelFilename, reply_tx: oneshot::Sender<Option<WheelRef>>, },
     Flush { reply_tx: oneshot::Sender<Flushed>, },
     IterBlocks { reply_tx: oneshot::Sender<IterBlocks>, },
@@ -280,14 +311,77 @@ enum Error {
 }
 
 async fn busyloop(mut state: State) -> Result<(), ErrorSeverity<State, Error>> {
-    while let Some(request) = state.request_rx.next().await {
+    let mut info_tasks: FuturesUnordered<BoxFuture<'static, (usize, Result<usize, ero::NoProcError>)>> =
+        FuturesUnordered::new();
+    let mut bytes_free_sample = Vec::new();
+
+    loop {
+        enum Event<R, I> {
+            Request(Option<R>),
+            Info(I),
+        }
+
+        let event = select! {
+            result = state.request_rx.next() =>
+                Event::Request(result),
+            result = info_tasks.select_next_some() =>
+                Event::Info(result),
+        };
+
+        let request = match event {
+            Event::Request(None) =>
+                break,
+            Event::Request(Some(request)) =>
+                request,
+            Event::Info((offset, info_result)) => {
+                match info_result {
+                    Ok(bytes_free) =>
+                        bytes_free_sample[offset] = Some(bytes_free),
+                    Err(ero::NoProcError) =>
+                        log::warn!("wheel {:?} gone while sampling its free space", state.wheels[offset].blockwheel_filename),
+                }
+                if info_tasks.is_empty() {
+                    state.bytes_free = std::mem::take(&mut bytes_free_sample);
+                }
+                continue;
+            },
+        };
+
         match request {
-            Request::Acquire { reply_tx, } => {
+            Request::Acquire { placement, reply_tx, } => {
                 let maybe_wheel_ref = if state.wheels.is_empty() {
                     None
                 } else {
-                    let mut rng = rand::thread_rng();
-                    let offset = rng.gen_range(0 .. state.wheels.len());
+                    let offset = match placement {
+                        Placement::Random => {
+                            let mut rng = rand::thread_rng();
+                            rng.gen_range(0 .. state.wheels.len())
+                        },
+                        Placement::RoundRobin => {
+                            let offset = state.round_robin_offset % state.wheels.len();
+                            state.round_robin_offset = offset + 1;
+                            offset
+                        },
+                        Placement::LeastFull => {
+                            let sample_stale = state.bytes_free_sampled_at
+                                .map_or(true, |sampled_at| sampled_at.elapsed() >= BYTES_FREE_SAMPLE_INTERVAL);
+                            if sample_stale && info_tasks.is_empty() {
+                                state.bytes_free_sampled_at = Some(Instant::now());
+                                bytes_free_sample = vec![None; state.wheels.len()];
+                                for (offset, WheelRef { blockwheel_pid, .. }) in state.wheels.iter().enumerate() {
+                                    let mut blockwheel_pid = blockwheel_pid.clone();
+                                    info_tasks.push(async move {
+                                        let info_result = blockwheel_pid.info().await
+                                            .map(|info| info.bytes_free as usize);
+                                        (offset, info_result)
+                                    }.boxed());
+                                }
+                            }
+                            least_full_offset(&state.bytes_free)
+                        },
+                        Placement::Hash(hash) =>
+                            (hash % state.wheels.len() as u64) as usize,
+                    };
                     Some(state.wheels[offset].clone())
                 };
                 if let Err(_send_error) = reply_tx.send(maybe_wheel_ref) {
@@ -392,3 +486,12 @@ async fn busyloop(mut state: State) -> Result<(), ErrorSeverity<State, Error>> {
 
     Ok(())
 }
+
+// unresponsive wheels are skipped; the first one is used if none responded or nothing is sampled yet
+fn least_full_offset(bytes_free: &[Option<usize>]) -> usize {
+    bytes_free.iter()
+        .enumerate()
+        .filter_map(|(offset, maybe_bytes_free)| maybe_bytes_free.map(|bytes_free| (offset, bytes_free)))
+        .max_by_key(|&(_, bytes_free)| bytes_free)
+        .map_or(0, |(offset, _)| offset)
+}